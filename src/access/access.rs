@@ -0,0 +1,218 @@
+use std::fmt;
+use std::net::IpAddr;
+
+use crate::http::request::HTTPRequest;
+
+pub const CIDR_INVALID_FORMAT: &str = "Invalid address range format";
+
+// Where a request comes from. Peers on a unix socket have no address.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClientAddr {
+    Ip(IpAddr),
+    Unix,
+}
+
+impl fmt::Display for ClientAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientAddr::Ip(ip) => write!(f, "{}", ip),
+            ClientAddr::Unix => write!(f, "unix:"),
+        }
+    }
+}
+
+// An address range such as `10.0.0.0/8`, `2001:db8::/32`, `unix:` for
+// unix socket peers or `all`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cidr {
+    All,
+    Unix,
+    V4(u32, u8),
+    V6(u128, u8),
+}
+
+impl Cidr {
+    pub fn parse(raw: &str) -> Result<Cidr, String> {
+        match raw {
+            "all" => return Ok(Cidr::All),
+            "unix:" => return Ok(Cidr::Unix),
+            _ => {},
+        }
+
+        let mut parts = raw.splitn(2, '/');
+        let addr: IpAddr = match parts.next().unwrap().parse() {
+            Ok(addr) => addr,
+            Err(_) => return Err(String::from(CIDR_INVALID_FORMAT)),
+        };
+        let prefix: Option<u8> = match parts.next() {
+            Some(p) => match p.parse() {
+                Ok(p) => Some(p),
+                Err(_) => return Err(String::from(CIDR_INVALID_FORMAT)),
+            },
+            None => None,
+        };
+
+        match addr {
+            IpAddr::V4(v4) => {
+                let prefix = prefix.unwrap_or(32);
+                if prefix > 32 {
+                    return Err(String::from(CIDR_INVALID_FORMAT));
+                }
+                Ok(Cidr::V4(u32::from(v4) & mask_v4(prefix), prefix))
+            },
+            IpAddr::V6(v6) => {
+                let prefix = prefix.unwrap_or(128);
+                if prefix > 128 {
+                    return Err(String::from(CIDR_INVALID_FORMAT));
+                }
+                Ok(Cidr::V6(u128::from(v6) & mask_v6(prefix), prefix))
+            },
+        }
+    }
+
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self, normalize(addr)) {
+            (Cidr::All, _) => true,
+            (Cidr::V4(net, prefix), IpAddr::V4(v4)) => u32::from(v4) & mask_v4(*prefix) == *net,
+            (Cidr::V6(net, prefix), IpAddr::V6(v6)) => u128::from(v6) & mask_v6(*prefix) == *net,
+            _ => false,
+        }
+    }
+
+    // Unix socket peers only match `unix:` and `all`, never a loopback
+    // range.
+    pub fn matches(&self, addr: ClientAddr) -> bool {
+        match (self, addr) {
+            (Cidr::All, _) | (Cidr::Unix, ClientAddr::Unix) => true,
+            (_, ClientAddr::Ip(ip)) => self.contains(ip),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rule {
+    Allow(Cidr),
+    Deny(Cidr),
+}
+
+// Ordered `allow`/`deny` rules, the first matching rule decides. An address
+// that matches no rule is allowed.
+#[derive(Debug, Clone, Default)]
+pub struct AccessList {
+    pub rules: Vec<Rule>,
+}
+
+impl AccessList {
+    // Accepts an `allow <range>` or `deny <range>` directive, returns false
+    // for any other directive.
+    pub fn parse_directive(&mut self, tokens: &[String]) -> Result<bool, String> {
+        let make: fn(Cidr) -> Rule = match tokens[0].as_str() {
+            "allow" => Rule::Allow,
+            "deny" => Rule::Deny,
+            _ => return Ok(false),
+        };
+
+        if tokens.len() != 2 {
+            return Err(String::from(CIDR_INVALID_FORMAT));
+        }
+
+        self.rules.push(make(Cidr::parse(&tokens[1])?));
+        Ok(true)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn is_allowed(&self, addr: ClientAddr) -> bool {
+        for rule in &self.rules {
+            match rule {
+                Rule::Allow(cidr) if cidr.matches(addr) => return true,
+                Rule::Deny(cidr) if cidr.matches(addr) => return false,
+                _ => {},
+            }
+        }
+
+        true
+    }
+}
+
+// Which peers may report the client address through a forwarding header.
+#[derive(Debug, Clone)]
+pub struct RealIp {
+    pub trusted: Vec<Cidr>,
+    pub header: String,
+}
+
+impl Default for RealIp {
+    fn default() -> RealIp {
+        RealIp {
+            trusted: Vec::new(),
+            header: String::from("X-Forwarded-For"),
+        }
+    }
+}
+
+impl RealIp {
+    fn is_trusted(&self, addr: ClientAddr) -> bool {
+        self.trusted.iter().any(|cidr| cidr.matches(addr))
+    }
+
+    // Walks the forwarding header from the right, skipping trusted proxies,
+    // so a client cannot spoof its address by prepending entries.
+    pub fn client_addr(&self, peer: ClientAddr, req: &HTTPRequest) -> ClientAddr {
+        let peer = match peer {
+            ClientAddr::Ip(ip) => ClientAddr::Ip(normalize(ip)),
+            ClientAddr::Unix => ClientAddr::Unix,
+        };
+        if !self.is_trusted(peer) {
+            return peer;
+        }
+
+        let forwarded = match req.header(&self.header) {
+            Some(value) => value,
+            None => return peer,
+        };
+
+        let mut client = peer;
+        for hop in forwarded.rsplit(',') {
+            match hop.trim().parse::<IpAddr>() {
+                Ok(addr) => {
+                    client = ClientAddr::Ip(normalize(addr));
+                    if !self.is_trusted(client) {
+                        break;
+                    }
+                },
+                Err(_) => break,
+            }
+        }
+
+        client
+    }
+}
+
+// IPv4-mapped IPv6 peers (dual-stack sockets) are matched as IPv4.
+fn normalize(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => addr,
+        },
+        _ => addr,
+    }
+}
+
+fn mask_v4(prefix: u8) -> u32 {
+    match prefix {
+        0 => 0,
+        p => u32::MAX << (32 - p),
+    }
+}
+
+fn mask_v6(prefix: u8) -> u128 {
+    match prefix {
+        0 => 0,
+        p => u128::MAX << (128 - p),
+    }
+}
@@ -0,0 +1,105 @@
+use std::net::IpAddr;
+
+use super::access::{AccessList, Cidr, ClientAddr, RealIp, Rule};
+use crate::http::request::HTTPRequest;
+
+fn ip(raw: &str) -> IpAddr {
+    raw.parse().unwrap()
+}
+
+fn addr(raw: &str) -> ClientAddr {
+    ClientAddr::Ip(ip(raw))
+}
+
+fn list(rules: &[(&str, &str)]) -> AccessList {
+    let mut access = AccessList::default();
+    for (directive, range) in rules {
+        let tokens = vec![directive.to_string(), range.to_string()];
+        assert_eq!(access.parse_directive(&tokens), Ok(true));
+    }
+    access
+}
+
+#[test]
+fn cidr_v4_contains() {
+    let cidr = Cidr::parse("192.168.10.0/24").unwrap();
+    assert!(cidr.contains(ip("192.168.10.77")));
+    assert!(!cidr.contains(ip("192.168.11.1")));
+    assert!(!cidr.contains(ip("::1")));
+}
+
+#[test]
+fn cidr_v6_contains() {
+    let cidr = Cidr::parse("2001:db8::/32").unwrap();
+    assert!(cidr.contains(ip("2001:db8:1::5")));
+    assert!(!cidr.contains(ip("2001:db9::1")));
+}
+
+#[test]
+fn cidr_single_host_and_mapped() {
+    let cidr = Cidr::parse("10.1.2.3").unwrap();
+    assert_eq!(cidr, Cidr::V4(0x0a010203, 32));
+    assert!(cidr.contains(ip("::ffff:10.1.2.3")));
+}
+
+#[test]
+fn cidr_invalid() {
+    assert!(Cidr::parse("10.0.0.0/33").is_err());
+    assert!(Cidr::parse("office").is_err());
+    assert!(Cidr::parse("10.0.0.0/x").is_err());
+}
+
+#[test]
+fn first_matching_rule_wins() {
+    let access = list(&[("deny", "192.168.1.1"), ("allow", "192.168.1.0/24"), ("deny", "all")]);
+    assert!(!access.is_allowed(addr("192.168.1.1")));
+    assert!(access.is_allowed(addr("192.168.1.2")));
+    assert!(!access.is_allowed(addr("8.8.8.8")));
+}
+
+#[test]
+fn no_rules_allow_everyone() {
+    assert!(AccessList::default().is_allowed(addr("8.8.8.8")));
+}
+
+#[test]
+fn other_directive_is_not_consumed() {
+    let mut access = AccessList::default();
+    assert_eq!(access.parse_directive(&["root".to_string(), "/".to_string()]), Ok(false));
+    assert_eq!(access.rules, Vec::<Rule>::new());
+}
+
+#[test]
+fn real_ip_from_trusted_proxy() {
+    let real_ip = RealIp {
+        trusted: vec![Cidr::parse("10.0.0.0/8").unwrap()],
+        ..Default::default()
+    };
+    let req = HTTPRequest::parse(b"GET / HTTP/1.1\r\nX-Forwarded-For: 1.1.1.1, 2.2.2.2, 10.0.0.7\r\n\r\n").unwrap();
+
+    assert_eq!(real_ip.client_addr(addr("10.0.0.1"), &req), addr("2.2.2.2"));
+    assert_eq!(real_ip.client_addr(addr("3.3.3.3"), &req), addr("3.3.3.3"));
+}
+
+#[test]
+fn unix_peers_are_not_loopback() {
+    let access = list(&[("allow", "127.0.0.1"), ("deny", "all")]);
+    assert!(access.is_allowed(addr("127.0.0.1")));
+    assert!(!access.is_allowed(ClientAddr::Unix));
+    let access = list(&[("allow", "unix:"), ("deny", "all")]);
+    assert!(access.is_allowed(ClientAddr::Unix));
+    assert!(!access.is_allowed(addr("127.0.0.1")));
+
+    let req = HTTPRequest::parse(b"GET / HTTP/1.1\r\nX-Forwarded-For: 1.1.1.1\r\n\r\n").unwrap();
+    let loopback = RealIp {
+        trusted: vec![Cidr::parse("127.0.0.0/8").unwrap()],
+        ..Default::default()
+    };
+    assert_eq!(loopback.client_addr(ClientAddr::Unix, &req), ClientAddr::Unix);
+    let unix = RealIp {
+        trusted: vec![Cidr::parse("unix:").unwrap()],
+        ..Default::default()
+    };
+    assert_eq!(unix.client_addr(ClientAddr::Unix, &req), addr("1.1.1.1"));
+    assert_eq!(ClientAddr::Unix.to_string(), "unix:");
+}
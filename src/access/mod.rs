@@ -0,0 +1,3 @@
+pub mod access;
+#[cfg(test)]
+mod access_test;
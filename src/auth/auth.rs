@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use crate::http::request::HTTPRequest;
+
+pub const AUTH_INVALID_FORMAT: &str = "Invalid auth_basic format";
+pub const AUTH_NO_USER_FILE: &str = "auth_basic requires auth_basic_user_file";
+
+// Used when the user is unknown or has an unsupported hash so that the
+// response takes as long as for a wrong password.
+const DUMMY_HASH: &str = "$2b$10$05X094U3M94yRWA3F.CWVee1s7sE81UlBtA8mA3bULosf1C8cAr9m";
+
+// Users and password hashes from an htpasswd file. The file is re-read when
+// its modification time or size changes.
+pub struct Htpasswd {
+    path: String,
+    state: Mutex<HtpasswdState>,
+}
+
+struct HtpasswdState {
+    stamp: Option<(SystemTime, u64)>,
+    users: HashMap<String, String>,
+}
+
+impl fmt::Debug for Htpasswd {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Htpasswd").field("path", &self.path).finish()
+    }
+}
+
+impl Htpasswd {
+    pub fn open(path: &str) -> Result<Htpasswd, String> {
+        let htpasswd = Htpasswd {
+            path: path.to_owned(),
+            state: Mutex::new(HtpasswdState {
+                stamp: None,
+                users: HashMap::new(),
+            }),
+        };
+
+        htpasswd.reload_if_changed()?;
+        Ok(htpasswd)
+    }
+
+    fn reload_if_changed(&self) -> Result<(), String> {
+        let meta = fs::metadata(&self.path).map_err(|err| format!("{}: {}", self.path, err))?;
+        let stamp = (meta.modified().map_err(|err| err.to_string())?, meta.len());
+
+        let mut state = self.state.lock().unwrap();
+        if state.stamp == Some(stamp) {
+            return Ok(());
+        }
+
+        let raw = fs::read_to_string(&self.path).map_err(|err| format!("{}: {}", self.path, err))?;
+        state.users = parse_htpasswd(&raw);
+        state.stamp = Some(stamp);
+        println!("Loaded {} users from {}", state.users.len(), self.path);
+        let mut weak: Vec<&String> = state.users.iter().filter(|(_, hash)| !is_supported(hash)).map(|(user, _)| user).collect();
+        weak.sort();
+        for user in weak {
+            println!("User {} in {} has an unsupported password hash and cannot log in", user, self.path);
+        }
+        Ok(())
+    }
+
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        // A broken file keeps the previously loaded users.
+        if let Err(err) = self.reload_if_changed() {
+            println!("Error while reload htpasswd: {}", err);
+        }
+
+        let hash = self.state.lock().unwrap().users.get(user).cloned();
+        match hash {
+            Some(hash) if is_supported(&hash) => pwhash::unix::verify(password, &hash),
+            _ => {
+                pwhash::unix::verify(password, DUMMY_HASH);
+                false
+            }
+        }
+    }
+}
+
+// Only bcrypt and SHA-crypt hashes are accepted, older crypt formats are too
+// weak to protect anything.
+fn is_supported(hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2y$", "$5$", "$6$"].iter().any(|prefix| hash.starts_with(prefix))
+}
+
+fn parse_htpasswd(raw: &str) -> HashMap<String, String> {
+    raw.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut pair = line.splitn(2, ':');
+            Some((pair.next()?.to_owned(), pair.next()?.to_owned()))
+        })
+        .collect()
+}
+
+// `auth_basic` settings of a location.
+#[derive(Debug)]
+pub struct BasicAuth {
+    pub realm: String,
+    pub users: Htpasswd,
+}
+
+impl BasicAuth {
+    // Checks the `Authorization: Basic` credentials of the request.
+    pub fn authorize(&self, req: &HTTPRequest) -> bool {
+        self.user(req).is_some()
+    }
+
+    // The user name of valid credentials.
+    pub fn user(&self, req: &HTTPRequest) -> Option<String> {
+        let (user, password) = req.header("Authorization").and_then(parse_basic)?;
+
+        match self.users.verify(&user, &password) {
+            true => Some(user),
+            false => None,
+        }
+    }
+
+    pub fn challenge(&self) -> String {
+        format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm.replace('"', "'"))
+    }
+}
+
+fn parse_basic(value: &str) -> Option<(String, String)> {
+    let mut parts = value.trim().splitn(2, ' ');
+    if !parts.next()?.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+
+    let decoded = STANDARD.decode(parts.next()?.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let mut pair = decoded.splitn(2, ':');
+    Some((pair.next()?.to_owned(), pair.next()?.to_owned()))
+}
+
+// Collects `auth_basic` and `auth_basic_user_file` while a location is parsed.
+#[derive(Debug, Default)]
+pub struct BasicAuthBuilder {
+    realm: Option<String>,
+    user_file: Option<String>,
+}
+
+impl BasicAuthBuilder {
+    // Returns false for directives that are not about basic auth.
+    pub fn parse_directive(&mut self, tokens: &[String]) -> Result<bool, String> {
+        let slot = match tokens[0].as_str() {
+            "auth_basic" => &mut self.realm,
+            "auth_basic_user_file" => &mut self.user_file,
+            _ => return Ok(false),
+        };
+
+        if tokens.len() != 2 {
+            return Err(String::from(AUTH_INVALID_FORMAT));
+        }
+
+        *slot = Some(tokens[1].clone());
+        Ok(true)
+    }
+
+    // `auth_basic off` disables authentication for the location.
+    pub fn build(self) -> Result<Option<BasicAuth>, String> {
+        let realm = match self.realm {
+            Some(ref realm) if realm == "off" => return Ok(None),
+            Some(realm) => realm,
+            None => return Ok(None),
+        };
+
+        let user_file = match self.user_file {
+            Some(file) => file,
+            None => return Err(String::from(AUTH_NO_USER_FILE)),
+        };
+
+        Ok(Some(BasicAuth {
+            realm,
+            users: Htpasswd::open(&user_file)?,
+        }))
+    }
+}
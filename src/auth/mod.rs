@@ -0,0 +1,3 @@
+pub mod auth;
+#[cfg(test)]
+mod auth_test;
@@ -0,0 +1,495 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::io::{Chain, Cursor};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::http::reader::read_body;
+use crate::http::request::HTTPRequest;
+use crate::http::response::HTTPResponse;
+use crate::http::status::StatusCode;
+use crate::server::stream::Client;
+
+pub const CGI_INVALID_FORMAT: &str = "Invalid cgi format";
+
+const DEFAULT_TIMEOUT: u64 = 30;
+const DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+const MAX_HEADER_SIZE: usize = 16 * 1024;
+const READ_CHUNK: usize = 4096;
+
+// Response headers of a script that describe its own framing, the server
+// frames the body itself.
+const DROPPED_HEADERS: [&str; 4] = ["Connection", "Keep-Alive", "Transfer-Encoding", "Content-Length"];
+
+// Settings of a location that runs CGI scripts from `root`.
+#[derive(Debug)]
+pub struct Cgi {
+    pub prefix: String,
+    pub root: PathBuf,
+    pub timeout: Duration,
+}
+
+// Collects `cgi_root` and `cgi_timeout` while a location is parsed.
+#[derive(Default)]
+pub struct CgiBuilder {
+    root: Option<String>,
+    timeout: Option<String>,
+}
+
+impl CgiBuilder {
+    // Returns false for directives that are not about CGI.
+    pub fn parse_directive(&mut self, tokens: &[String]) -> Result<bool, String> {
+        let slot = match tokens[0].as_str() {
+            "cgi_root" => &mut self.root,
+            "cgi_timeout" => &mut self.timeout,
+            _ => return Ok(false),
+        };
+
+        if tokens.len() != 2 {
+            return Err(String::from(CGI_INVALID_FORMAT));
+        }
+
+        *slot = Some(tokens[1].clone());
+        Ok(true)
+    }
+
+    pub fn build(self, prefix: &str) -> Result<Option<Cgi>, String> {
+        let root = match self.root {
+            Some(root) => PathBuf::from(root),
+            None => return Ok(None),
+        };
+
+        let timeout = match self.timeout {
+            Some(timeout) => match timeout.parse() {
+                Ok(secs) => Duration::from_secs(secs),
+                Err(_) => return Err(format!("{}: {}", CGI_INVALID_FORMAT, timeout)),
+            },
+            None => Duration::from_secs(DEFAULT_TIMEOUT),
+        };
+
+        Ok(Some(Cgi {
+            prefix: prefix.to_owned(),
+            root,
+            timeout,
+        }))
+    }
+}
+
+// A script found for a request path.
+struct Script {
+    file: PathBuf,
+    // The request path up to and including the script.
+    name: String,
+    // Whatever follows the script in the request path.
+    path_info: String,
+}
+
+impl Cgi {
+    // Runs the script the request path points to and streams its output.
+    pub fn execute(&self, req: &HTTPRequest, client: &Client, document_root: &str, body: &mut dyn Read) -> HTTPResponse {
+        let script = match self.resolve(&req.path) {
+            Ok(script) => script,
+            Err(status) => return error_response(status),
+        };
+
+        let input = match read_body(body) {
+            Ok(input) => input,
+            Err(status) => return error_response(status),
+        };
+
+        let meta = meta_variables(req, client, &script.name, &script.path_info, document_root, input.len());
+        let mut child = match self.spawn(&script.file, meta) {
+            Ok(child) => child,
+            Err(err) => {
+                println!("Error while run {}: {}", script.file.display(), err);
+                return error_response(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        };
+
+        // Written from another thread so that a script producing output
+        // before it read all of its input can not dead lock.
+        if let Some(mut stdin) = child.stdin.take() {
+            thread::spawn(move || {
+                let _ = stdin.write_all(&input);
+            });
+        }
+
+        let watchdog = Watchdog::start(child.id(), self.timeout);
+        let stdout = child.stdout.take().unwrap();
+        let mut output = CgiOutput {
+            stdout: None,
+            child,
+            watchdog,
+        };
+
+        let (head, rest, stdout) = match read_head(stdout) {
+            Ok(head) => head,
+            Err(err) => {
+                return match output.watchdog.expired() {
+                    true => {
+                        println!("CGI script {} timed out", script.file.display());
+                        error_response(StatusCode::GATEWAY_TIMEOUT)
+                    },
+                    false => {
+                        println!("Invalid output of CGI script {}: {}", script.file.display(), err);
+                        error_response(StatusCode::BAD_GATEWAY)
+                    },
+                };
+            }
+        };
+
+        let mut resp = match parse_response(&head) {
+            Ok(resp) => resp,
+            Err(err) => {
+                println!("Invalid output of CGI script {}: {}", script.file.display(), err);
+                return error_response(StatusCode::BAD_GATEWAY);
+            }
+        };
+
+        if req.method != "HEAD" {
+            output.stdout = Some(Cursor::new(rest).chain(stdout));
+            resp.setStream(Box::new(output));
+        }
+
+        resp
+    }
+
+    // Walks the path below the prefix until it reaches a file, the rest of
+    // the path becomes PATH_INFO.
+    fn resolve(&self, path: &str) -> Result<Script, StatusCode> {
+        let rest = match path.strip_prefix(self.prefix.as_str()) {
+            Some(rest) => rest,
+            None => return Err(StatusCode::NOT_FOUND),
+        };
+
+        let mut file = self.root.clone();
+        let mut offset = path.len() - rest.len();
+        for segment in rest.split('/') {
+            let start = offset;
+            offset += segment.len() + 1;
+            if segment.is_empty() {
+                continue;
+            }
+
+            file.push(segment);
+            let meta = match fs::metadata(&file) {
+                Ok(meta) => meta,
+                Err(_) => return Err(StatusCode::NOT_FOUND),
+            };
+            if meta.is_dir() {
+                continue;
+            }
+            if !meta.is_file() || meta.permissions().mode() & 0o111 == 0 {
+                return Err(StatusCode::FORBIDDEN);
+            }
+
+            let end = start + segment.len();
+            return Ok(Script {
+                file,
+                name: path[..end].to_owned(),
+                path_info: path[end..].to_owned(),
+            });
+        }
+
+        Err(StatusCode::NOT_FOUND)
+    }
+
+    fn spawn(&self, file: &Path, meta: Vec<(String, String)>) -> io::Result<Child> {
+        // Relative to our working directory, not to the script's.
+        let file = match file.is_relative() {
+            true => env::current_dir()?.join(file),
+            false => file.to_owned(),
+        };
+
+        let mut command = Command::new(&file);
+        command.env_clear()
+            .env("PATH", env::var("PATH").unwrap_or_else(|_| String::from(DEFAULT_PATH)))
+            .envs(meta)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            // Its own group, so that a timeout also stops what it started.
+            .process_group(0);
+        if let Some(dir) = file.parent() {
+            command.current_dir(dir);
+        }
+
+        command.spawn()
+    }
+}
+
+// Kills the process group of a script that runs longer than its timeout.
+struct Watchdog {
+    cancel: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+    expired: Arc<AtomicBool>,
+}
+
+impl Watchdog {
+    fn start(pid: u32, timeout: Duration) -> Watchdog {
+        let (cancel, canceled) = mpsc::channel::<()>();
+        let expired = Arc::new(AtomicBool::new(false));
+        let flag = expired.clone();
+
+        let thread = thread::spawn(move || {
+            if let Err(RecvTimeoutError::Timeout) = canceled.recv_timeout(timeout) {
+                flag.store(true, Ordering::SeqCst);
+                kill_group(pid);
+            }
+        });
+
+        Watchdog {
+            cancel: Some(cancel),
+            thread: Some(thread),
+            expired,
+        }
+    }
+
+    fn expired(&self) -> bool {
+        self.expired.load(Ordering::SeqCst)
+    }
+
+    fn stop(&mut self) {
+        drop(self.cancel.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// The body of a script's output. Dropping it stops the script if it is
+// still running and reaps it.
+struct CgiOutput {
+    stdout: Option<Chain<Cursor<Vec<u8>>, ChildStdout>>,
+    child: Child,
+    watchdog: Watchdog,
+}
+
+impl Read for CgiOutput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = match self.stdout {
+            Some(ref mut stdout) => stdout.read(buf)?,
+            None => return Ok(0),
+        };
+
+        // A killed script must not look like a complete response.
+        if n == 0 && !buf.is_empty() && self.watchdog.expired() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "cgi script timed out"));
+        }
+
+        Ok(n)
+    }
+}
+
+impl Drop for CgiOutput {
+    fn drop(&mut self) {
+        // The watchdog is stopped before the child is reaped, its pid can
+        // not be reused while the watchdog may still kill it.
+        self.watchdog.stop();
+        if let Ok(None) = self.child.try_wait() {
+            kill_group(self.child.id());
+        }
+
+        match self.child.wait() {
+            Ok(status) if !status.success() && !self.watchdog.expired() => {
+                println!("CGI script exited with {}", status);
+            },
+            Ok(_) => {},
+            Err(err) => println!("Error while wait for CGI script: {}", err),
+        }
+    }
+}
+
+fn kill_group(pid: u32) {
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+// Reads up to the empty line that ends the header block. Returns the block,
+// the body bytes read past it and the rest of the output.
+fn read_head<R: Read>(mut output: R) -> io::Result<(String, Vec<u8>, R)> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0; READ_CHUNK];
+
+    loop {
+        if let Some((end, body)) = find_head_end(&buffer) {
+            let rest = buffer.split_off(body);
+            buffer.truncate(end);
+            let head = String::from_utf8(buffer)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "header block is not utf-8"))?;
+            return Ok((head, rest, output));
+        }
+
+        if buffer.len() > MAX_HEADER_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "header block too large"));
+        }
+
+        match output.read(&mut chunk) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "output ended before the header block")),
+            Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+// End of the header block and start of the body, scripts may end lines with
+// either LF or CRLF.
+pub fn find_head_end(buffer: &[u8]) -> Option<(usize, usize)> {
+    let lf = buffer.windows(2).position(|w| w == b"\n\n").map(|i| (i, i + 2));
+    let crlf = buffer.windows(4).position(|w| w == b"\r\n\r\n").map(|i| (i, i + 4));
+
+    match (lf, crlf) {
+        (Some(lf), Some(crlf)) => Some(if lf.0 < crlf.0 { lf } else { crlf }),
+        (lf, crlf) => lf.or(crlf),
+    }
+}
+
+// Turns the header block of a CGI response into a response (RFC 3875
+// section 6). `Status` sets the status line, a `Location` without one is a
+// redirect. Local redirects are passed on to the client.
+pub fn parse_response(head: &str) -> Result<HTTPResponse, String> {
+    let mut resp = HTTPResponse::new();
+    let mut status = None;
+    let mut has_type = false;
+    let mut has_location = false;
+
+    for line in head.lines() {
+        let (name, value) = match line.split_once(':') {
+            Some(pair) => pair,
+            None => return Err(format!("invalid header line: {}", line)),
+        };
+        if name.is_empty() || name.contains(|c: char| c.is_whitespace()) {
+            return Err(format!("invalid header name: {}", name));
+        }
+        let value = value.trim();
+
+        if name.eq_ignore_ascii_case("Status") {
+            status = Some(parse_status(value)?);
+            continue;
+        }
+        if DROPPED_HEADERS.iter().any(|h| h.eq_ignore_ascii_case(name)) {
+            continue;
+        }
+
+        has_type |= name.eq_ignore_ascii_case("Content-Type");
+        has_location |= name.eq_ignore_ascii_case("Location");
+        resp.add_header(name.to_owned(), value.to_owned());
+    }
+
+    match status {
+        Some((status, reason)) => {
+            resp.setStatus(status);
+            // The script's own phrase is passed on, a bare code gets the
+            // registered one.
+            if !reason.is_empty() {
+                resp.setReason(reason);
+            }
+        },
+        None if has_location => resp.setStatus(StatusCode::FOUND),
+        None if has_type => resp.setStatus(StatusCode::OK),
+        None => return Err(String::from("no Status, Content-Type or Location")),
+    }
+
+    Ok(resp)
+}
+
+fn parse_status(value: &str) -> Result<(StatusCode, String), String> {
+    let (code, reason) = value.split_once(' ').unwrap_or((value, ""));
+    match code.parse::<u16>().ok().and_then(StatusCode::from_u16) {
+        Some(status) if code.len() == 3 && status.as_u16() < 600 => Ok((status, reason.trim().to_owned())),
+        _ => Err(format!("invalid status: {}", value)),
+    }
+}
+
+// The meta-variables of RFC 3875 section 4.1 plus the common extensions.
+pub fn meta_variables(req: &HTTPRequest, client: &Client, script_name: &str, path_info: &str, document_root: &str, content_length: usize) -> Vec<(String, String)> {
+    let (server_name, host_port) = split_host(req.header("Host").unwrap_or("localhost"));
+    let server_port = client.port
+        .or(host_port)
+        .unwrap_or(if client.secure { 443 } else { 80 });
+    let query = req.uri.split_once('?').map(|(_, q)| q).unwrap_or("");
+
+    let mut vars = vec![
+        (String::from("GATEWAY_INTERFACE"), String::from("CGI/1.1")),
+        (String::from("SERVER_SOFTWARE"), String::from("dz1")),
+        (String::from("SERVER_PROTOCOL"), req.version.to_string()),
+        (String::from("SERVER_NAME"), server_name.to_owned()),
+        (String::from("SERVER_PORT"), server_port.to_string()),
+        (String::from("REQUEST_METHOD"), req.method.clone()),
+        (String::from("REQUEST_URI"), req.uri.clone()),
+        (String::from("SCRIPT_NAME"), script_name.to_owned()),
+        (String::from("QUERY_STRING"), query.to_owned()),
+        (String::from("REMOTE_ADDR"), client.addr.to_string()),
+        (String::from("REMOTE_HOST"), client.addr.to_string()),
+        (String::from("DOCUMENT_ROOT"), document_root.to_owned()),
+    ];
+
+    if !path_info.is_empty() {
+        vars.push((String::from("PATH_INFO"), path_info.to_owned()));
+        vars.push((String::from("PATH_TRANSLATED"), format!("{}{}", document_root, path_info)));
+    }
+    if let Some(ref user) = client.user {
+        vars.push((String::from("AUTH_TYPE"), String::from("Basic")));
+        vars.push((String::from("REMOTE_USER"), user.clone()));
+    }
+    if client.secure {
+        vars.push((String::from("HTTPS"), String::from("on")));
+    }
+    // The length of the body as read, a chunked request has no header.
+    if content_length > 0 {
+        vars.push((String::from("CONTENT_LENGTH"), content_length.to_string()));
+    }
+    if let Some(content_type) = req.header("Content-Type") {
+        vars.push((String::from("CONTENT_TYPE"), content_type.to_owned()));
+    }
+
+    for (name, value) in &req.headers {
+        // Credentials stay with the server. `Proxy` would become
+        // HTTP_PROXY, which many HTTP libraries take as their proxy. The
+        // script gets the body already decoded.
+        let skip = ["Content-Length", "Content-Type", "Transfer-Encoding", "Authorization", "Proxy"];
+        if skip.iter().any(|s| s.eq_ignore_ascii_case(name)) {
+            continue;
+        }
+        if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            continue;
+        }
+
+        let var = format!("HTTP_{}", name.to_ascii_uppercase().replace('-', "_"));
+        match vars.iter_mut().find(|(n, _)| *n == var) {
+            Some((_, joined)) => {
+                joined.push_str(", ");
+                joined.push_str(value);
+            },
+            None => vars.push((var, value.clone())),
+        }
+    }
+
+    vars
+}
+
+// Splits a Host header into name and port.
+fn split_host(host: &str) -> (&str, Option<u16>) {
+    match host.rsplit_once(':') {
+        Some((name, port)) if !host.ends_with(']') => match port.parse() {
+            Ok(port) => (name, Some(port)),
+            Err(_) => (host, None),
+        },
+        _ => (host, None),
+    }
+}
+
+pub fn error_response(status: StatusCode) -> HTTPResponse {
+    HTTPResponse::builder().status(status).build()
+}
@@ -0,0 +1,3 @@
+pub mod cgi;
+#[cfg(test)]
+mod cgi_test;
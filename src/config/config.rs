@@ -1,9 +1,43 @@
 use std::fs;
-use std::error::Error;
-use std::fmt::Display;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::access::access::{AccessList, Cidr, RealIp};
+use crate::config::listen::Listen;
+use crate::config::location::Location;
+use crate::error_page::error_page::ErrorPages;
+use crate::headers::headers::ResponseHeaders;
+use crate::mime::mime::{builtin_type, extension, with_charset, MimeTypes, DEFAULT_TYPE};
+use crate::mime::sniff::SniffCache;
+use crate::proxy::upstream::UpstreamGroup;
+use crate::rewrite::rewrite::Rewrites;
+use crate::rewrite::try_files::TryFiles;
+use crate::security::security::SecurityHeaders;
+use crate::tls::tls::TlsSettings;
 
 const DOCUMENT_ROOT_NAME: &str = "document_root";
 const THREAD_LIMIT_NAME: &str = "thread_limit";
+const CLIENT_HEADER_TIMEOUT_NAME: &str = "client_header_timeout";
+const KEEPALIVE_TIMEOUT_NAME: &str = "keepalive_timeout";
+const SEND_TIMEOUT_NAME: &str = "send_timeout";
+const CLIENT_BODY_TIMEOUT_NAME: &str = "client_body_timeout";
+const CLIENT_BODY_TOTAL_TIMEOUT_NAME: &str = "client_body_total_timeout";
+const SEND_MIN_RATE_NAME: &str = "send_min_rate";
+const CLIENT_MAX_BODY_SIZE_NAME: &str = "client_max_body_size";
+const LOCATION_NAME: &str = "location";
+const UPSTREAM_NAME: &str = "upstream";
+const LISTEN_NAME: &str = "listen";
+const SET_REAL_IP_FROM_NAME: &str = "set_real_ip_from";
+const REAL_IP_HEADER_NAME: &str = "real_ip_header";
+
+const DEFAULT_CLIENT_HEADER_TIMEOUT: u64 = 10;
+const DEFAULT_KEEPALIVE_TIMEOUT: u64 = 5;
+const DEFAULT_SEND_TIMEOUT: u64 = 10;
+const DEFAULT_CLIENT_BODY_TIMEOUT: u64 = 10;
+const DEFAULT_CLIENT_BODY_TOTAL_TIMEOUT: u64 = 300;
+const DEFAULT_SEND_MIN_RATE: u64 = 1024;
+const DEFAULT_CLIENT_MAX_BODY_SIZE: u64 = 1024 * 1024;
 
 pub const DOCUMENT_ROOT_ERROR: &str = "Can't find document_root";
 pub const DOCUMENT_ROOT_INVALID_FORMAT: &str = "Invalid document root format";
@@ -11,10 +45,59 @@ pub const DOCUMENT_ROOT_INVALID_FORMAT: &str = "Invalid document root format";
 pub const THREAD_LIMIT_ERROR: &str = "Can't find thread_limit";
 pub const THREAD_LIMIT_INVALID_FORMAT: &str = "Invalid thread limit format";
 
-#[derive(Debug)]
+pub const TIMEOUT_INVALID_FORMAT: &str = "Invalid timeout format";
+pub const REAL_IP_INVALID_FORMAT: &str = "Invalid real ip format";
+pub const SIZE_INVALID_FORMAT: &str = "Invalid size format";
+
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    // Time allowed to receive the whole request head.
+    pub header: Duration,
+    // Time an idle keep-alive connection waits for the next request.
+    pub keepalive: Duration,
+    // Time a single write of the response may block.
+    pub send: Duration,
+    // Minimum response transfer rate in bytes per second, 0 disables the check.
+    pub send_min_rate: u64,
+    // Time a single read of the request body may wait for data.
+    pub body: Duration,
+    // Time allowed to receive the whole request body, 0 is no limit.
+    pub body_total: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Timeouts {
+        Timeouts {
+            header: Duration::from_secs(DEFAULT_CLIENT_HEADER_TIMEOUT),
+            keepalive: Duration::from_secs(DEFAULT_KEEPALIVE_TIMEOUT),
+            send: Duration::from_secs(DEFAULT_SEND_TIMEOUT),
+            send_min_rate: DEFAULT_SEND_MIN_RATE,
+            body: Duration::from_secs(DEFAULT_CLIENT_BODY_TIMEOUT),
+            body_total: Duration::from_secs(DEFAULT_CLIENT_BODY_TOTAL_TIMEOUT),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
 pub struct Config {
     pub thread_count: u16,
     pub dir_root: String,
+    pub timeouts: Timeouts,
+    // Largest request body accepted, 0 accepts any size.
+    pub max_body_size: u64,
+    pub access: AccessList,
+    pub real_ip: RealIp,
+    pub locations: Vec<Location>,
+    pub upstreams: Vec<Arc<UpstreamGroup>>,
+    pub listen: Vec<Listen>,
+    pub tls: TlsSettings,
+    pub error_pages: ErrorPages,
+    pub mime: MimeTypes,
+    pub sniff_cache: SniffCache,
+    pub headers: ResponseHeaders,
+    pub security: SecurityHeaders,
+    pub rewrites: Rewrites,
+    pub try_files: TryFiles,
 }
 
 impl Config {
@@ -31,8 +114,152 @@ impl Config {
         };
     }
 
+    // The longest matching location prefix wins.
+    pub fn location(&self, path: &str) -> Option<&Location> {
+        self.locations.iter()
+            .filter(|l| l.matches(path))
+            .max_by_key(|l| l.prefix.len())
+    }
+
+    // Location rules replace the server-wide ones rather than adding to them.
+    pub fn access_for(&self, path: &str) -> &AccessList {
+        self.access_in(self.location(path))
+    }
+
+    pub fn access_in<'a>(&'a self, location: Option<&'a Location>) -> &'a AccessList {
+        match location {
+            Some(location) if !location.access.is_empty() => &location.access,
+            _ => &self.access,
+        }
+    }
+
+    // Content-Type of the file served for `path`. The location's mappings
+    // come first, then the server's and the built-in table.
+    pub fn content_type(&self, path: &str) -> String {
+        let mime = self.labeled_type(path).unwrap_or_else(|| self.default_type(path));
+        with_charset(&mime)
+    }
+
+    // Like content_type, with `sniff_types on` the first bytes of `file`
+    // decide for files without a known extension and correct the type of
+    // mislabeled binary files.
+    pub fn file_content_type(&self, path: &str, file: &Path) -> String {
+        let sniffed = match self.sniffs(path) {
+            true => self.sniff_cache.sniff_file(file),
+            false => None,
+        };
+        let mime = match (self.labeled_type(path), sniffed) {
+            (Some(labeled), Some(sniffed)) => sniffed.signature.map(String::from).unwrap_or(labeled),
+            (Some(labeled), None) => labeled,
+            (None, Some(sniffed)) => String::from(sniffed.unlabeled),
+            (None, None) => self.default_type(path),
+        };
+        with_charset(&mime)
+    }
+
+    // The caching and added header rules for `path`, each kind comes from
+    // the location if it has rules of that kind.
+    pub fn headers_for(&self, path: &str) -> (&ResponseHeaders, &ResponseHeaders) {
+        let location = self.location(path).map(|l| &l.headers);
+        let cache = location.filter(|h| h.has_cache_rules()).unwrap_or(&self.headers);
+        let added = location.filter(|h| h.has_added_headers()).unwrap_or(&self.headers);
+        (cache, added)
+    }
+
+    pub fn sniffs(&self, path: &str) -> bool {
+        self.location(path).and_then(|l| l.mime.sniff()).or_else(|| self.mime.sniff()).unwrap_or(false)
+    }
+
+    fn labeled_type(&self, path: &str) -> Option<String> {
+        let location = self.location(path).map(|l| &l.mime);
+        let ext = extension(path)?;
+        location.and_then(|m| m.get(&ext))
+            .or_else(|| self.mime.get(&ext))
+            .or_else(|| builtin_type(&ext))
+            .map(String::from)
+    }
+
+    fn default_type(&self, path: &str) -> String {
+        let location = self.location(path).map(|l| &l.mime);
+        let mime = location.and_then(|m| m.default_type()).or_else(|| self.mime.default_type());
+        String::from(mime.unwrap_or(DEFAULT_TYPE))
+    }
+
+    // A location with error_page directives replaces the server ones.
+    pub fn error_pages_for(&self, path: &str) -> &ErrorPages {
+        match self.location(path) {
+            Some(location) if !location.error_pages.is_empty() => &location.error_pages,
+            _ => &self.error_pages,
+        }
+    }
+
+    // A location with security header directives replaces the server ones.
+    pub fn security_for(&self, path: &str) -> &SecurityHeaders {
+        match self.location(path) {
+            Some(location) if !location.security.is_empty() => &location.security,
+            _ => &self.security,
+        }
+    }
+
+    // A location's try_files or spa replaces the server one. Locations
+    // with a handler do not inherit it, their paths are not files.
+    pub fn try_files_for(&self, path: &str) -> &TryFiles {
+        self.try_files_in(self.location(path))
+    }
+
+    pub fn try_files_in<'a>(&'a self, location: Option<&'a Location>) -> &'a TryFiles {
+        match location {
+            Some(location) if !location.try_files.is_empty() || location.has_handler() => &location.try_files,
+            _ => &self.try_files,
+        }
+    }
+
     fn parse(raw: String) -> Result<Config, String> {
-        let params: Vec<_> = raw.split("\n").collect();
+        let mut params: Vec<&str> = Vec::new();
+        let mut locations: Vec<Location> = Vec::new();
+        let mut upstreams = Vec::new();
+        let mut access = AccessList::default();
+        let mut real_ip = RealIp::default();
+        let mut listen = Vec::new();
+        let mut tls = TlsSettings::default();
+        let mut error_pages = ErrorPages::default();
+        let mut mime = MimeTypes::default();
+        let mut headers = ResponseHeaders::default();
+        let mut security = SecurityHeaders::default();
+        let mut rewrites = Rewrites::default();
+        let mut try_files = TryFiles::default();
+
+        let mut lines = raw.split("\n");
+        while let Some(line) = lines.next() {
+            let tokens = tokenize(line);
+            if tokens.is_empty() {
+                continue;
+            }
+
+            match tokens[0].as_str() {
+                LOCATION_NAME => locations.push(Location::parse(&tokens, &mut lines)?),
+                UPSTREAM_NAME => upstreams.push(Arc::new(UpstreamGroup::parse(&tokens, &mut lines)?)),
+                LISTEN_NAME => listen.push(Listen::parse(&tokens)?),
+                SET_REAL_IP_FROM_NAME | REAL_IP_HEADER_NAME if tokens.len() != 2 => {
+                    return Err(String::from(REAL_IP_INVALID_FORMAT));
+                },
+                SET_REAL_IP_FROM_NAME => real_ip.trusted.push(Cidr::parse(&tokens[1])?),
+                REAL_IP_HEADER_NAME => real_ip.header = tokens[1].clone(),
+                _ => {
+                    if !access.parse_directive(&tokens)? && !tls.parse_directive(&tokens)? && !error_pages.parse_directive(&tokens)?
+                        && !mime.parse_directive(&tokens)? && !headers.parse_directive(&tokens)?
+                        && !security.parse_directive(&tokens)? && !rewrites.parse_directive(&tokens)?
+                        && !try_files.parse_directive(&tokens)? {
+                        params.push(line);
+                    }
+                },
+            }
+        }
+
+        // Upstream blocks may follow the locations that use them.
+        for proxy in locations.iter_mut().filter_map(|l| l.proxy.as_mut()) {
+            proxy.upstream.resolve(&upstreams);
+        }
 
         let document_root_pair: Vec<_> = match params.iter().find(|&x| x.to_string().contains(DOCUMENT_ROOT_NAME)) {
             Some(pair) => pair.trim().split(" ").collect(),
@@ -52,9 +279,105 @@ impl Config {
             return Err(String::from(THREAD_LIMIT_INVALID_FORMAT));
         }
 
+        let timeouts = Timeouts {
+            header: Duration::from_secs(parse_number(&params, CLIENT_HEADER_TIMEOUT_NAME, DEFAULT_CLIENT_HEADER_TIMEOUT)?),
+            keepalive: Duration::from_secs(parse_number(&params, KEEPALIVE_TIMEOUT_NAME, DEFAULT_KEEPALIVE_TIMEOUT)?),
+            send: Duration::from_secs(parse_number(&params, SEND_TIMEOUT_NAME, DEFAULT_SEND_TIMEOUT)?),
+            send_min_rate: parse_number(&params, SEND_MIN_RATE_NAME, DEFAULT_SEND_MIN_RATE)?,
+            body: Duration::from_secs(parse_number(&params, CLIENT_BODY_TIMEOUT_NAME, DEFAULT_CLIENT_BODY_TIMEOUT)?),
+            body_total: Duration::from_secs(parse_number(&params, CLIENT_BODY_TOTAL_TIMEOUT_NAME, DEFAULT_CLIENT_BODY_TOTAL_TIMEOUT)?),
+        };
+
+        let max_body_size = match params.iter().find(|x| x.split_whitespace().next() == Some(CLIENT_MAX_BODY_SIZE_NAME)) {
+            Some(line) => match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [_, size] => parse_size(size).ok_or_else(|| String::from(SIZE_INVALID_FORMAT))?,
+                _ => return Err(String::from(SIZE_INVALID_FORMAT)),
+            },
+            None => DEFAULT_CLIENT_MAX_BODY_SIZE,
+        };
+
         Ok(Config{
             thread_count: thread_limit_pair[1].parse().unwrap(),
             dir_root: String::from(document_root_pair[1]),
+            timeouts,
+            max_body_size,
+            access,
+            real_ip,
+            locations,
+            upstreams,
+            listen,
+            tls,
+            error_pages,
+            mime,
+            headers,
+            security,
+            rewrites,
+            try_files,
+            ..Default::default()
         })
     }
-}
\ No newline at end of file
+}
+
+// Splits a config line into whitespace separated tokens, double quotes group
+// a token that contains spaces.
+pub fn tokenize(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut has_token = false;
+
+    for c in line.trim().chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                has_token = true;
+            },
+            c if c.is_whitespace() && !quoted => {
+                if has_token {
+                    tokens.push(std::mem::take(&mut current));
+                    has_token = false;
+                }
+            },
+            c => {
+                current.push(c);
+                has_token = true;
+            },
+        }
+    }
+
+    if has_token {
+        tokens.push(current);
+    }
+
+    tokens
+}
+
+// Looks up an optional `name value` line, falling back to `default` when absent.
+fn parse_number(params: &[&str], name: &str, default: u64) -> Result<u64, String> {
+    let line = params.iter().find(|x| x.split_whitespace().next() == Some(name));
+    let pair: Vec<_> = match line {
+        Some(line) => line.split_whitespace().collect(),
+        None => return Ok(default),
+    };
+
+    if pair.len() != 2 {
+        return Err(String::from(TIMEOUT_INVALID_FORMAT));
+    }
+
+    pair[1].parse().map_err(|_| String::from(TIMEOUT_INVALID_FORMAT))
+}
+
+// A byte count with an optional `k`, `m` or `g` suffix.
+pub fn parse_size(value: &str) -> Option<u64> {
+    let (number, unit) = match value.char_indices().last()? {
+        (i, 'k') | (i, 'K') => (&value[..i], 1024),
+        (i, 'm') | (i, 'M') => (&value[..i], 1024 * 1024),
+        (i, 'g') | (i, 'G') => (&value[..i], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+
+    if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    number.parse::<u64>().ok()?.checked_mul(unit)
+}
@@ -1,4 +1,28 @@
 use super::config;
+use super::listen;
+use super::location;
+use crate::access::access::ClientAddr;
+use crate::http::response::HTTPResponse;
+use crate::http::status::StatusCode;
+use std::time::Duration;
+
+// Parses a directive into `T`, as the `parse_directive` methods do.
+pub type Parse<T> = fn(&mut T, &[String]) -> Result<bool, String>;
+
+// Feeds `lines`, tokenized as in a config file, to `parse`. Each must be
+// accepted.
+pub fn directives<T: Default>(lines: &[&str], parse: Parse<T>) -> T {
+    let mut target = T::default();
+    for line in lines {
+        assert_eq!(parse(&mut target, &config::tokenize(line)), Ok(true), "{}", line);
+    }
+    target
+}
+
+// The error `parse` gives for `line`, tokenized as in a config file.
+pub fn parse_err<T: Default>(line: &str, parse: Parse<T>) -> String {
+    parse(&mut T::default(), &config::tokenize(line)).unwrap_err()
+}
 
 struct TestCase {
     path: String,
@@ -25,6 +49,7 @@ fn test_valid_config() {
         expected: Some(config::Config {
             dir_root: String::from("test/test.txt"),
             thread_count: 0,
+            ..Default::default()
         }),
         err: None,
     };
@@ -81,6 +106,190 @@ fn test_not_contains_thread_limit() {
     }   
 }
 
+#[test]
+fn test_timeouts() {
+    let test = TestCase{
+        path: String::from("test/test_timeouts.txt"),
+        expected: None,
+        err: None,
+    };
+
+    match config::Config::read(&&test.path[..]) {
+        Ok(cfg) => {
+            assert_eq!(cfg.timeouts.header.as_secs(), 3);
+            assert_eq!(cfg.timeouts.keepalive.as_secs(), 15);
+            assert_eq!(cfg.timeouts.send.as_secs(), 20);
+            assert_eq!(cfg.timeouts.send_min_rate, 0);
+            assert_eq!(cfg.timeouts.body.as_secs(), 4);
+            assert_eq!(cfg.timeouts.body_total.as_secs(), 60);
+        },
+        Err(err) => panic!("Unexcpected error {}", err),
+    }
+}
+
+#[test]
+fn test_locations() {
+    let cfg = match config::Config::read("test/test_locations.txt") {
+        Ok(cfg) => cfg,
+        Err(err) => panic!("Unexcpected error {}", err),
+    };
+
+    assert_eq!(cfg.locations.len(), 2);
+    assert_eq!(cfg.access.rules.len(), 1);
+    assert_eq!(cfg.real_ip.trusted.len(), 1);
+    assert_eq!(cfg.location("/private/a.html").unwrap().prefix, "/private");
+    assert_eq!(cfg.location("/private/public/a.html").unwrap().prefix, "/private/public");
+    assert!(cfg.location("/index.html").is_none());
+
+    let office = ClientAddr::Ip("192.168.1.5".parse().unwrap());
+    let outside = ClientAddr::Ip("8.8.8.8".parse().unwrap());
+    assert!(cfg.access_for("/private/a.html").is_allowed(office));
+    assert!(!cfg.access_for("/private/a.html").is_allowed(outside));
+    assert!(cfg.access_for("/private/public/a.html").is_allowed(outside));
+    assert!(!cfg.access_for("/index.html").is_allowed(ClientAddr::Ip("10.1.1.1".parse().unwrap())));
+}
+
+#[test]
+fn test_location_unknown_directive() {
+    match config::Config::read("test/test_location_unknown.txt") {
+        Ok(_) => panic!("Unexcpected OK"),
+        Err(err) => assert!(err.starts_with(location::LOCATION_UNKNOWN_DIRECTIVE)),
+    }
+}
+
+#[test]
+fn test_proxy() {
+    let cfg = match config::Config::read("test/test_proxy.txt") {
+        Ok(cfg) => cfg,
+        Err(err) => panic!("Unexcpected error {}", err),
+    };
+
+    let proxy = cfg.location("/api/users").unwrap().proxy.as_ref().unwrap();
+    assert_eq!(proxy.upstream.authority(), "127.0.0.1:8081");
+    assert_eq!(proxy.upstream.path.as_deref(), Some("/v1/"));
+    assert_eq!(proxy.connect_timeout, Duration::from_secs(2));
+    assert_eq!(proxy.read_timeout, Duration::from_secs(30));
+}
+
+#[test]
+fn test_upstream() {
+    let cfg = match config::Config::read("test/test_upstream.txt") {
+        Ok(cfg) => cfg,
+        Err(err) => panic!("Unexcpected error {}", err),
+    };
+
+    assert_eq!(cfg.upstreams.len(), 1);
+    let group = &cfg.location("/api/").unwrap().proxy.as_ref().unwrap().upstream.group;
+    assert_eq!(group.name, "backend");
+    assert_eq!(group.backends.len(), 2);
+    assert_eq!(group.backends[0].weight, 2);
+    assert_eq!(group.backends[1].max_fails, 3);
+
+    let single = &cfg.location("/single/").unwrap().proxy.as_ref().unwrap().upstream.group;
+    assert_eq!(single.backends.len(), 1);
+    assert_eq!(single.backends[0].address(), "127.0.0.1:8081");
+}
+
+#[test]
+fn test_cgi() {
+    let cfg = match config::Config::read("test/test_cgi.txt") {
+        Ok(cfg) => cfg,
+        Err(err) => panic!("Unexcpected error {}", err),
+    };
+
+    let cgi = cfg.location("/cgi-bin/env.cgi").unwrap().cgi.as_ref().unwrap();
+    assert_eq!(cgi.root.to_str(), Some("test/cgi-bin"));
+    assert_eq!(cgi.timeout, Duration::from_secs(5));
+}
+
+#[test]
+fn test_fastcgi() {
+    let cfg = match config::Config::read("test/test_fastcgi.txt") {
+        Ok(cfg) => cfg,
+        Err(err) => panic!("Unexcpected error {}", err),
+    };
+
+    let php = cfg.location("/php/index.php").unwrap().fastcgi.as_ref().unwrap();
+    assert_eq!(php.address(), "unix:/run/php-fpm.sock");
+    assert_eq!(php.params, vec![(String::from("APP_ENV"), String::from("production"))]);
+    assert_eq!(php.read_timeout, Duration::from_secs(30));
+
+    let app = cfg.location("/app/").unwrap().fastcgi.as_ref().unwrap();
+    assert_eq!(app.address(), "127.0.0.1:9000");
+    assert_eq!(app.connect_timeout, Duration::from_secs(60));
+}
+
+#[test]
+fn test_max_body_size() {
+    let cfg = match config::Config::read("test/test_body_size.txt") {
+        Ok(cfg) => cfg,
+        Err(err) => panic!("Unexcpected error {}", err),
+    };
+    assert_eq!(cfg.max_body_size, 8 * 1024 * 1024);
+
+    let cfg = config::Config::read("test/test_cgi.txt").unwrap();
+    assert_eq!(cfg.max_body_size, 1024 * 1024);
+
+    assert_eq!(config::parse_size("0"), Some(0));
+    assert_eq!(config::parse_size("512K"), Some(512 * 1024));
+    assert_eq!(config::parse_size("2g"), Some(2 * 1024 * 1024 * 1024));
+    assert_eq!(config::parse_size("m"), None);
+    assert_eq!(config::parse_size("1.5m"), None);
+    assert_eq!(config::parse_size("99999999999999g"), None);
+}
+
+#[test]
+fn test_dav() {
+    let cfg = match config::Config::read("test/test_dav.txt") {
+        Ok(cfg) => cfg,
+        Err(err) => panic!("Unexcpected error {}", err),
+    };
+
+    let dav = cfg.location("/artifacts/app.tar").unwrap().dav.as_ref().unwrap();
+    assert!(dav.allows("PUT") && dav.allows("DELETE"));
+    assert!(dav.create_full_path);
+    assert_eq!(dav.max_file_size, 512 * 1024 * 1024);
+    assert!(!dav.allows("PROPFIND"));
+
+    let share = cfg.location("/share/docs/").unwrap().dav.as_ref().unwrap();
+    assert!(share.allows("PROPFIND") && share.allows("LOCK") && share.allows("OPTIONS"));
+
+    match config::Config::read("test/test_dav_no_auth.txt") {
+        Ok(_) => panic!("Unexpected Ok"),
+        Err(err) => assert!(err.starts_with("dav_methods requires auth_basic")),
+    };
+}
+
+#[test]
+fn test_listen() {
+    let cfg = match config::Config::read("test/test_listen.txt") {
+        Ok(cfg) => cfg,
+        Err(err) => panic!("Unexcpected error {}", err),
+    };
+
+    assert_eq!(cfg.listen, vec![
+        listen::Listen::tcp(String::from("0.0.0.0:8080")),
+        listen::Listen { ssl: true, ..listen::Listen::tcp(String::from("127.0.0.1:8443")) },
+        listen::Listen { ipv6only: false, ..listen::Listen::tcp(String::from("[::]:8080")) },
+        listen::Listen {
+            address: listen::ListenAddress::Unix(String::from("/run/dz1.sock")),
+            mode: Some(0o660),
+            ..listen::Listen::tcp(String::new())
+        },
+    ]);
+    assert_eq!(cfg.tls.certificate, Some(String::from("test/tls/localhost.pem")));
+}
+
+#[test]
+fn test_listen_invalid() {
+    let tokens = |line: &str| line.split(' ').map(String::from).collect::<Vec<_>>();
+
+    assert!(listen::Listen::parse(&tokens("listen localhost")).is_err());
+    assert!(listen::Listen::parse(&tokens("listen 8080 mode=0660")).is_err());
+    assert!(listen::Listen::parse(&tokens("listen [::]:80 ipv6only=maybe")).is_err());
+    assert!(listen::Listen::parse(&tokens("listen unix:/run/dz1.sock mode=999")).is_err());
+}
+
 fn test_invalid_format_thread_limit() {
     let test = TestCase{
         path: String::from("test/test_thread_limit_invalid.txt"),
@@ -93,3 +302,124 @@ fn test_invalid_format_thread_limit() {
         Err(err) => assert_eq!(err, test.err.unwrap()),
     }    
 }
+
+#[test]
+fn test_error_pages() {
+    let cfg = match config::Config::read("test/test_error_pages.txt") {
+        Ok(cfg) => cfg,
+        Err(err) => panic!("Unexcpected error {}", err),
+    };
+
+    let page = cfg.error_pages_for("/missing.html").find(StatusCode::NOT_FOUND).unwrap();
+    assert_eq!(page.uri, "/errors/404.html");
+    assert!(cfg.error_pages_for("/").find(StatusCode::FORBIDDEN).is_none());
+
+    let page = cfg.error_pages_for("/").find(StatusCode::BAD_GATEWAY).unwrap();
+    assert_eq!(page.status, Some(StatusCode::SERVICE_UNAVAILABLE));
+
+    // The location's own directives replace the server ones.
+    let page = cfg.error_pages_for("/legacy/a.html").find(StatusCode::NOT_FOUND).unwrap();
+    assert!(page.is_redirect());
+    assert!(cfg.error_pages_for("/legacy/a.html").find(StatusCode::BAD_GATEWAY).is_none());
+    assert!(cfg.error_pages_for("/errors/x").find(StatusCode::BAD_GATEWAY).is_some());
+}
+
+#[test]
+fn test_mime() {
+    let cfg = match config::Config::read("test/test_mime.txt") {
+        Ok(cfg) => cfg,
+        Err(err) => panic!("Unexcpected error {}", err),
+    };
+
+    assert_eq!(cfg.content_type("/index.html"), "text/html; charset=utf-8");
+    assert_eq!(cfg.content_type("/notes.rst"), "text/x-rst; charset=utf-8");
+    assert_eq!(cfg.content_type("/blob.dat"), "application/x-custom");
+    assert_eq!(cfg.content_type("/LICENSE"), "text/plain; charset=utf-8");
+    // The location's mappings and default come first.
+    assert_eq!(cfg.content_type("/raw/index.html"), "application/octet-stream");
+    assert_eq!(cfg.content_type("/raw/notes.rst"), "text/x-rst; charset=utf-8");
+    assert_eq!(cfg.content_type("/raw/LICENSE"), "application/x-raw");
+
+    let cfg = config::Config::read("test/test.txt").unwrap();
+    assert_eq!(cfg.content_type("/LICENSE"), "application/octet-stream");
+}
+
+#[test]
+fn test_sniff() {
+    let cfg = match config::Config::read("test/test_sniff.txt") {
+        Ok(cfg) => cfg,
+        Err(err) => panic!("Unexcpected error {}", err),
+    };
+
+    let file = |path: &str| format!("test{}", path);
+    let content_type = |path: &str| cfg.file_content_type(path, std::path::Path::new(&file(path)));
+    assert!(cfg.sniffs("/sniff/pixel") && !cfg.sniffs("/pixel"));
+    assert_eq!(content_type("/sniff/pixel"), "image/gif");
+    assert_eq!(content_type("/sniff/report"), "text/html; charset=utf-8");
+    // A binary signature corrects the extension, text does not.
+    assert_eq!(content_type("/sniff/logo.jpg"), "image/png");
+    assert_eq!(content_type("/sniff/cars.html"), "text/html; charset=utf-8");
+    assert_eq!(content_type("/sniff/missing"), "application/octet-stream");
+    assert_eq!(cfg.sniff_cache.len(), 4);
+}
+
+#[test]
+fn test_response_headers() {
+    let cfg = match config::Config::read("test/test_headers.txt") {
+        Ok(cfg) => cfg,
+        Err(err) => panic!("Unexcpected error {}", err),
+    };
+
+    let apply = |path: &str| {
+        let mut resp = HTTPResponse::new();
+        let (cache, added) = cfg.headers_for(path);
+        cache.apply_cache(&mut resp, path, 0);
+        added.apply_added(&mut resp);
+        resp
+    };
+
+    let resp = apply("/index.html");
+    assert_eq!(resp.header("Cache-Control"), Some("max-age=3600"));
+    assert_eq!(resp.header("X-Served-By"), Some("dz1"));
+
+    let resp = apply("/static/app.3f9a2b1c.js");
+    assert_eq!(resp.header("Cache-Control"), Some("public, max-age=31536000, immutable"));
+    assert_eq!(resp.header("Expires"), Some("Sat, 31 Jan 1970 00:00:00 GMT"));
+    assert_eq!(resp.header("X-Served-By"), Some("dz1"));
+
+    // Each kind is replaced separately.
+    let resp = apply("/api/users");
+    assert_eq!(resp.header("Cache-Control"), Some("max-age=3600"));
+    assert_eq!(resp.header("X-Api"), Some("v1"));
+    assert!(!resp.has_header("X-Served-By"));
+}
+
+#[test]
+fn test_security() {
+    let cfg = match config::Config::read("test/test_security.txt") {
+        Ok(cfg) => cfg,
+        Err(err) => panic!("Unexcpected error {}", err),
+    };
+
+    let apply = |path: &str| {
+        let mut resp = HTTPResponse::new();
+        cfg.security_for(path).apply(&mut resp, true);
+        resp
+    };
+
+    let resp = apply("/index.html");
+    assert_eq!(resp.header("Strict-Transport-Security"), Some("max-age=31536000; includeSubDomains"));
+    assert_eq!(resp.header("Content-Security-Policy"), Some("default-src 'self'"));
+    assert_eq!(resp.header("X-Frame-Options"), Some("DENY"));
+    assert_eq!(resp.header("Referrer-Policy"), Some("strict-origin-when-cross-origin"));
+
+    // The location's own directives replace the server ones.
+    let resp = apply("/api/users");
+    assert_eq!(resp.header("X-Frame-Options"), Some("SAMEORIGIN"));
+    assert!(!resp.has_header("Content-Security-Policy"));
+    assert!(!apply("/embed/widget.html").has_header("X-Frame-Options"));
+
+    let cors = cfg.location("/api/users").and_then(|l| l.cors.as_ref()).unwrap();
+    assert!(cors.allow_origin("https://app.example.com").is_some());
+    assert!(cfg.location("/embed/").unwrap().cors.is_none());
+}
@@ -0,0 +1,109 @@
+pub const LISTEN_INVALID_FORMAT: &str = "Invalid listen format";
+
+pub const DEFAULT_BACKLOG: i32 = 1024;
+
+const UNIX_PREFIX: &str = "unix:";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListenAddress {
+    // `host:port`, IPv6 hosts in brackets.
+    Tcp(String),
+    // Filesystem path of a Unix domain socket.
+    Unix(String),
+}
+
+// A `listen` directive:
+//
+//   listen 8080
+//   listen 127.0.0.1:8443 ssl
+//   listen [::]:8080 ipv6only=off
+//   listen unix:/run/dz1.sock mode=0660
+//   listen 80 reuseport=4 backlog=4096 nodelay deferred
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listen {
+    pub address: ListenAddress,
+    pub ssl: bool,
+    // IPV6_V6ONLY for IPv6 addresses, on unless disabled so that `[::]:port`
+    // can be bound next to `0.0.0.0:port`.
+    pub ipv6only: bool,
+    // Permissions of the socket file.
+    pub mode: Option<u32>,
+    // Number of SO_REUSEPORT sockets bound to the address, each with its own
+    // accept loop. 0 binds a single socket without the option.
+    pub reuseport: usize,
+    pub backlog: i32,
+    // TCP_NODELAY on accepted connections.
+    pub nodelay: bool,
+    // TCP_DEFER_ACCEPT, connections are only accepted once data arrived.
+    pub deferred: bool,
+}
+
+impl Listen {
+    pub fn tcp(address: String) -> Listen {
+        Listen {
+            address: ListenAddress::Tcp(address),
+            ssl: false,
+            ipv6only: true,
+            mode: None,
+            reuseport: 0,
+            backlog: DEFAULT_BACKLOG,
+            nodelay: false,
+            deferred: false,
+        }
+    }
+
+    pub fn parse(tokens: &[String]) -> Result<Listen, String> {
+        if tokens.len() < 2 {
+            return Err(String::from(LISTEN_INVALID_FORMAT));
+        }
+
+        let mut listen = match tokens[1].parse::<u16>() {
+            Ok(port) => Listen::tcp(format!("0.0.0.0:{}", port)),
+            Err(_) if tokens[1].starts_with(UNIX_PREFIX) && tokens[1].len() > UNIX_PREFIX.len() => Listen {
+                address: ListenAddress::Unix(tokens[1][UNIX_PREFIX.len()..].to_owned()),
+                ..Listen::tcp(String::new())
+            },
+            Err(_) if tokens[1].contains(':') => Listen::tcp(tokens[1].clone()),
+            Err(_) => return Err(String::from(LISTEN_INVALID_FORMAT)),
+        };
+
+        for option in &tokens[2..] {
+            let mut pair = option.splitn(2, '=');
+            match (pair.next().unwrap(), pair.next()) {
+                ("ssl", None) => listen.ssl = true,
+                ("ipv6only", Some("on")) => listen.ipv6only = true,
+                ("ipv6only", Some("off")) => listen.ipv6only = false,
+                ("mode", Some(mode)) if listen.is_unix() => match u32::from_str_radix(mode, 8) {
+                    Ok(mode) if mode <= 0o7777 => listen.mode = Some(mode),
+                    _ => return Err(String::from(LISTEN_INVALID_FORMAT)),
+                },
+                ("reuseport", None) if !listen.is_unix() => listen.reuseport = default_acceptors(),
+                ("reuseport", Some(count)) if !listen.is_unix() => match count.parse() {
+                    Ok(count) if count > 0 => listen.reuseport = count,
+                    _ => return Err(String::from(LISTEN_INVALID_FORMAT)),
+                },
+                ("backlog", Some(backlog)) => match backlog.parse() {
+                    Ok(backlog) if backlog > 0 => listen.backlog = backlog,
+                    _ => return Err(String::from(LISTEN_INVALID_FORMAT)),
+                },
+                ("nodelay", None) if !listen.is_unix() => listen.nodelay = true,
+                ("deferred", None) if !listen.is_unix() => listen.deferred = true,
+                _ => return Err(String::from(LISTEN_INVALID_FORMAT)),
+            }
+        }
+
+        Ok(listen)
+    }
+
+    pub fn is_unix(&self) -> bool {
+        match self.address {
+            ListenAddress::Unix(_) => true,
+            ListenAddress::Tcp(_) => false,
+        }
+    }
+}
+
+// A bare `reuseport` binds one socket per CPU.
+fn default_acceptors() -> usize {
+    std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
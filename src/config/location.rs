@@ -0,0 +1,109 @@
+use crate::access::access::AccessList;
+use crate::auth::auth::{BasicAuth, BasicAuthBuilder};
+use crate::cgi::cgi::{Cgi, CgiBuilder};
+use crate::config::config::tokenize;
+use crate::dav::dav::{Dav, DavBuilder, DAV_REQUIRES_AUTH};
+use crate::error_page::error_page::ErrorPages;
+use crate::fastcgi::fastcgi::{FastCgi, FastCgiBuilder};
+use crate::headers::headers::ResponseHeaders;
+use crate::mime::mime::MimeTypes;
+use crate::proxy::proxy::{Proxy, ProxyBuilder};
+use crate::rewrite::rewrite::Rewrites;
+use crate::rewrite::try_files::TryFiles;
+use crate::security::cors::{Cors, CorsBuilder};
+use crate::security::security::SecurityHeaders;
+
+pub const LOCATION_INVALID_FORMAT: &str = "Invalid location format";
+pub const LOCATION_NOT_CLOSED: &str = "Location block is not closed";
+pub const LOCATION_UNKNOWN_DIRECTIVE: &str = "Unknown location directive";
+
+// Settings applied to requests whose path starts with `prefix`.
+#[derive(Debug, Default)]
+pub struct Location {
+    pub prefix: String,
+    pub access: AccessList,
+    pub auth: Option<BasicAuth>,
+    pub proxy: Option<Proxy>,
+    pub cgi: Option<Cgi>,
+    pub fastcgi: Option<FastCgi>,
+    pub dav: Option<Dav>,
+    pub error_pages: ErrorPages,
+    pub mime: MimeTypes,
+    pub headers: ResponseHeaders,
+    pub security: SecurityHeaders,
+    pub cors: Option<Cors>,
+    pub rewrites: Rewrites,
+    pub try_files: TryFiles,
+}
+
+impl Location {
+    // Parses a `location <prefix> {` block, consuming lines up to the closing `}`.
+    pub fn parse<'a, I>(header: &[String], lines: &mut I) -> Result<Location, String>
+        where I: Iterator<Item = &'a str>
+    {
+        if header.len() != 3 || header[2] != "{" {
+            return Err(String::from(LOCATION_INVALID_FORMAT));
+        }
+
+        let mut location = Location {
+            prefix: header[1].clone(),
+            ..Default::default()
+        };
+
+        let mut auth = BasicAuthBuilder::default();
+        let mut proxy = ProxyBuilder::default();
+        let mut cgi = CgiBuilder::default();
+        let mut fastcgi = FastCgiBuilder::default();
+        let mut dav = DavBuilder::default();
+        let mut cors = CorsBuilder::default();
+
+        for line in lines {
+            let tokens = tokenize(line);
+            if tokens.is_empty() {
+                continue;
+            }
+            if tokens[0] == "}" {
+                location.auth = auth.build()?;
+                location.proxy = proxy.build(&location.prefix)?;
+                location.cgi = cgi.build(&location.prefix)?;
+                location.fastcgi = fastcgi.build(&location.prefix)?;
+                location.dav = dav.build(&location.prefix)?;
+                location.cors = cors.build(&location.prefix)?;
+                // Writes into the document root are never anonymous.
+                if location.dav.is_some() && location.auth.is_none() {
+                    return Err(format!("{}: {}", DAV_REQUIRES_AUTH, location.prefix));
+                }
+                return Ok(location);
+            }
+            if auth.parse_directive(&tokens)? || proxy.parse_directive(&tokens)?
+                || cgi.parse_directive(&tokens)? || fastcgi.parse_directive(&tokens)?
+                || dav.parse_directive(&tokens)? || cors.parse_directive(&tokens)? {
+                continue;
+            }
+            location.parse_directive(&tokens)?;
+        }
+
+        Err(String::from(LOCATION_NOT_CLOSED))
+    }
+
+    fn parse_directive(&mut self, tokens: &[String]) -> Result<(), String> {
+        if self.access.parse_directive(tokens)? || self.error_pages.parse_directive(tokens)?
+            || self.mime.parse_directive(tokens)? || self.headers.parse_directive(tokens)?
+            || self.security.parse_directive(tokens)? || self.rewrites.parse_directive(tokens)?
+            || self.try_files.parse_directive(tokens)? {
+            return Ok(());
+        }
+
+        Err(format!("{}: {}", LOCATION_UNKNOWN_DIRECTIVE, tokens[0]))
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        path.starts_with(&self.prefix)
+    }
+
+    // Whether requests are passed to a proxy, a script or DAV rather than
+    // served as static files.
+    pub fn has_handler(&self) -> bool {
+        self.proxy.is_some() || self.cgi.is_some() || self.fastcgi.is_some() || self.dav.is_some()
+    }
+}
@@ -1,2 +1,5 @@
 pub mod config;
-pub mod config_test;
\ No newline at end of file
+#[cfg(test)]
+pub mod config_test;
+pub mod listen;
+pub mod location;
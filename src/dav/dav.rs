@@ -0,0 +1,917 @@
+use std::ffi::CString;
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use percent_encoding::percent_decode;
+
+use crate::config::config::parse_size;
+use crate::dav::lock::{parse_lockinfo, parse_timeout, submitted_tokens, LockTable};
+use crate::dav::props::{self, parse_propertyupdate, parse_propfind, PropertyStore, Resource, Update, DAV_NS};
+use crate::http::reader::body_error_status;
+use crate::http::request::{normalize_path, BodyFraming, HTTPRequest};
+use crate::http::response::HTTPResponse;
+use crate::http::status::StatusCode;
+
+pub const DAV_INVALID_FORMAT: &str = "Invalid dav format";
+pub const DAV_REQUIRES_AUTH: &str = "dav_methods requires auth_basic";
+
+const DEFAULT_MIN_FREE_SPACE: u64 = 64 * 1024 * 1024;
+// PROPFIND lists a collection but does not walk the tree below it.
+const DEFAULT_MAX_DEPTH: usize = 1;
+const COPY_CHUNK: usize = 64 * 1024;
+// Free space is checked again after this many bytes were written.
+const SPACE_CHECK_INTERVAL: u64 = 1024 * 1024;
+// Largest XML body of PROPFIND, PROPPATCH and LOCK.
+const MAX_XML_BODY: u64 = 64 * 1024;
+
+const TEMP_SUFFIX: &str = ".tmp";
+
+// Uploads in flight share a directory, each gets its own temp file.
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DavMethod {
+    Put,
+    Delete,
+    Mkcol,
+    Copy,
+    Move,
+    Propfind,
+    Proppatch,
+    Lock,
+    Unlock,
+}
+
+// Everything `webdav on` enables.
+const ALL_METHODS: [DavMethod; 9] = [
+    DavMethod::Put,
+    DavMethod::Delete,
+    DavMethod::Mkcol,
+    DavMethod::Copy,
+    DavMethod::Move,
+    DavMethod::Propfind,
+    DavMethod::Proppatch,
+    DavMethod::Lock,
+    DavMethod::Unlock,
+];
+
+impl DavMethod {
+    fn parse(name: &str) -> Option<DavMethod> {
+        ALL_METHODS.iter().find(|m| m.name() == name).copied()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DavMethod::Put => "PUT",
+            DavMethod::Delete => "DELETE",
+            DavMethod::Mkcol => "MKCOL",
+            DavMethod::Copy => "COPY",
+            DavMethod::Move => "MOVE",
+            DavMethod::Propfind => "PROPFIND",
+            DavMethod::Proppatch => "PROPPATCH",
+            DavMethod::Lock => "LOCK",
+            DavMethod::Unlock => "UNLOCK",
+        }
+    }
+}
+
+// Write methods enabled for a location. Files are stored below the
+// document root like the static files they become.
+#[derive(Debug)]
+pub struct Dav {
+    pub prefix: String,
+    pub methods: Vec<DavMethod>,
+    // Whether PUT creates missing parent directories.
+    pub create_full_path: bool,
+    // Largest file PUT may store, 0 leaves it to client_max_body_size.
+    pub max_file_size: u64,
+    // Space that must stay free on the file system after a PUT.
+    pub min_free_space: u64,
+    // Deepest PROPFIND answered, usize::MAX for `infinity`.
+    pub max_depth: usize,
+    pub locks: LockTable,
+    pub properties: PropertyStore,
+}
+
+// Collects `webdav`, `dav_methods`, `create_full_put_path`,
+// `dav_max_file_size`, `dav_min_free_space` and `dav_max_depth` while a
+// location is parsed.
+#[derive(Default)]
+pub struct DavBuilder {
+    webdav: Option<String>,
+    methods: Option<Vec<String>>,
+    create_full_path: Option<String>,
+    max_file_size: Option<String>,
+    min_free_space: Option<String>,
+    max_depth: Option<String>,
+}
+
+impl DavBuilder {
+    // Returns false for directives that are not about DAV.
+    pub fn parse_directive(&mut self, tokens: &[String]) -> Result<bool, String> {
+        if tokens[0] == "dav_methods" {
+            if tokens.len() < 2 {
+                return Err(String::from(DAV_INVALID_FORMAT));
+            }
+            self.methods = Some(tokens[1..].to_vec());
+            return Ok(true);
+        }
+
+        let slot = match tokens[0].as_str() {
+            "webdav" => &mut self.webdav,
+            "create_full_put_path" => &mut self.create_full_path,
+            "dav_max_file_size" => &mut self.max_file_size,
+            "dav_min_free_space" => &mut self.min_free_space,
+            "dav_max_depth" => &mut self.max_depth,
+            _ => return Ok(false),
+        };
+
+        if tokens.len() != 2 {
+            return Err(String::from(DAV_INVALID_FORMAT));
+        }
+
+        *slot = Some(tokens[1].clone());
+        Ok(true)
+    }
+
+    // `webdav on` enables every method, `dav_methods` a list of them and
+    // `dav_methods off` none.
+    pub fn build(self, prefix: &str) -> Result<Option<Dav>, String> {
+        let webdav = match self.webdav.as_deref() {
+            Some("on") => true,
+            Some("off") | None => false,
+            Some(value) => return Err(format!("{}: {}", DAV_INVALID_FORMAT, value)),
+        };
+
+        let mut methods = Vec::new();
+        match self.methods {
+            _ if webdav => methods.extend_from_slice(&ALL_METHODS),
+            Some(ref names) if names.len() == 1 && names[0] == "off" => {},
+            Some(names) => {
+                for name in &names {
+                    match DavMethod::parse(name) {
+                        Some(method) => methods.push(method),
+                        None => return Err(format!("{}: {}", DAV_INVALID_FORMAT, name)),
+                    }
+                }
+            },
+            None => {},
+        }
+        if methods.is_empty() {
+            return Ok(None);
+        }
+
+        let create_full_path = match self.create_full_path.as_deref() {
+            Some("on") => true,
+            Some("off") | None => false,
+            Some(value) => return Err(format!("{}: {}", DAV_INVALID_FORMAT, value)),
+        };
+
+        let max_depth = match self.max_depth.as_deref() {
+            Some("infinity") => usize::MAX,
+            Some(value) => value.parse().map_err(|_| format!("{}: {}", DAV_INVALID_FORMAT, value))?,
+            None => DEFAULT_MAX_DEPTH,
+        };
+
+        Ok(Some(Dav {
+            prefix: prefix.to_owned(),
+            methods,
+            create_full_path,
+            max_file_size: size(self.max_file_size, 0)?,
+            min_free_space: size(self.min_free_space, DEFAULT_MIN_FREE_SPACE)?,
+            max_depth,
+            locks: LockTable::default(),
+            properties: PropertyStore::default(),
+        }))
+    }
+}
+
+fn size(value: Option<String>, default: u64) -> Result<u64, String> {
+    match value {
+        Some(value) => parse_size(&value).ok_or_else(|| format!("{}: {}", DAV_INVALID_FORMAT, value)),
+        None => Ok(default),
+    }
+}
+
+// Why a PUT body could not be stored.
+enum Failure {
+    Status(StatusCode),
+    Io(io::Error),
+}
+
+// The resource a request is about.
+struct Target<'a> {
+    root: &'a Path,
+    // Request path, with a trailing `/` for a collection.
+    path: String,
+    file: PathBuf,
+    meta: Option<Metadata>,
+    // Lock tokens submitted in the `If` header.
+    tokens: Vec<String>,
+}
+
+impl Dav {
+    // OPTIONS is answered once PROPFIND is enabled, clients look for the
+    // `DAV` header before they mount a share.
+    pub fn allows(&self, method: &str) -> bool {
+        (method == "OPTIONS" && self.methods.contains(&DavMethod::Propfind))
+            || self.methods.iter().any(|m| m.name() == method)
+    }
+
+    // The enabled methods next to those of static files.
+    pub fn allowed_methods(&self) -> Vec<&'static str> {
+        let mut allowed = vec!["GET", "HEAD", "OPTIONS"];
+        allowed.extend(self.methods.iter().map(|m| m.name()));
+        allowed
+    }
+
+    pub fn handle(&self, req: &HTTPRequest, document_root: &str, body: &mut dyn Read) -> HTTPResponse {
+        if req.method == "OPTIONS" {
+            return self.options();
+        }
+
+        let root = Path::new(document_root);
+        // The parser appended the index file to a path with a trailing slash.
+        let path = match req.isAutoIndex {
+            true => req.path.strip_suffix("index.html").unwrap_or(&req.path),
+            false => &req.path,
+        };
+        let file = root.join(path.trim_start_matches('/'));
+        let meta = match metadata(&file) {
+            Ok(meta) => meta,
+            Err(err) => return io_error(&file, err),
+        };
+
+        let mut path = path.to_owned();
+        if meta.as_ref().is_some_and(|m| m.is_dir()) && !path.ends_with('/') {
+            path.push('/');
+        }
+        let target = Target {
+            root,
+            path,
+            file,
+            meta,
+            tokens: submitted_tokens(req.header("If")),
+        };
+
+        match req.method.as_str() {
+            "PUT" => self.put(req, &target, body),
+            "DELETE" => self.delete(req, &target),
+            "MKCOL" => self.mkcol(req, &target),
+            "COPY" => self.transfer(req, &target, true),
+            "MOVE" => self.transfer(req, &target, false),
+            "PROPFIND" => self.propfind(req, &target, body),
+            "PROPPATCH" => self.proppatch(&target, body),
+            "LOCK" => self.lock(req, &target, body),
+            "UNLOCK" => self.unlock(req, &target),
+            _ => response(StatusCode::METHOD_NOT_ALLOWED),
+        }
+    }
+
+    fn options(&self) -> HTTPResponse {
+        let class = match self.methods.contains(&DavMethod::Lock) {
+            true => "1, 2",
+            false => "1",
+        };
+
+        let mut resp = response(StatusCode::OK);
+        resp.push_header("DAV".to_owned(), class.to_owned());
+        resp.push_header("Allow".to_owned(), self.allowed_methods().join(", "));
+        resp.push_header("MS-Author-Via".to_owned(), "DAV".to_owned());
+        resp
+    }
+
+    fn put(&self, req: &HTTPRequest, target: &Target, body: &mut dyn Read) -> HTTPResponse {
+        // A path with a trailing slash names a directory.
+        if req.isAutoIndex {
+            return response(StatusCode::CONFLICT);
+        }
+
+        let (root, file) = (target.root, target.file.as_path());
+        let exists = match fs::symlink_metadata(file) {
+            Ok(meta) if meta.is_dir() => return response(StatusCode::CONFLICT),
+            Ok(_) => true,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => false,
+            Err(err) => return io_error(file, err),
+        };
+        if !preconditions_hold(req, exists) {
+            return response(StatusCode::PRECONDITION_FAILED);
+        }
+        if !self.unlocked(target, &target.path, false) || (!exists && !self.members_unlocked(target, &target.path)) {
+            return response(StatusCode::LOCKED);
+        }
+
+        let parent = file.parent().unwrap_or(root);
+        if !parent.is_dir() {
+            if !self.create_full_path {
+                return response(StatusCode::CONFLICT);
+            }
+            if !inside_root(root, parent) {
+                return response(StatusCode::FORBIDDEN);
+            }
+            if let Err(err) = fs::create_dir_all(parent) {
+                return io_error(parent, err);
+            }
+        }
+        if !inside_root(root, parent) {
+            return response(StatusCode::FORBIDDEN);
+        }
+
+        // A declared length is checked before anything is written.
+        if let Ok(BodyFraming::Length(length)) = req.body_framing() {
+            if self.max_file_size > 0 && length > self.max_file_size {
+                return response(StatusCode::CONTENT_TOO_LARGE);
+            }
+            match free_space(parent) {
+                Ok(free) if free < length.saturating_add(self.min_free_space) => return response(StatusCode::INSUFFICIENT_STORAGE),
+                Ok(_) => {},
+                Err(err) => return io_error(parent, err),
+            }
+        }
+
+        let mut temp = match TempFile::create(file) {
+            Ok(temp) => temp,
+            Err(err) => return io_error(parent, err),
+        };
+        match self.store(body, &mut temp.file, parent) {
+            Ok(()) => {},
+            Err(Failure::Status(status)) => return response(status),
+            Err(Failure::Io(err)) => return io_error(&temp.path, err),
+        }
+
+        // Without `If-None-Match: *` an existing file is replaced, with it
+        // the link fails if another upload created the file meanwhile.
+        let placed = match req.header_list("If-None-Match").contains(&"*") {
+            true => fs::hard_link(&temp.path, file),
+            false => fs::rename(&temp.path, file).map(|_| temp.keep = true),
+        };
+        match placed {
+            Ok(()) if exists => response(StatusCode::NO_CONTENT),
+            Ok(()) => response(StatusCode::CREATED),
+            Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => response(StatusCode::PRECONDITION_FAILED),
+            Err(err) => io_error(file, err),
+        }
+    }
+
+    // Copies the body into `file`, enforcing the file size limit and the
+    // free space margin as the data arrives.
+    fn store(&self, body: &mut dyn Read, file: &mut File, dir: &Path) -> Result<(), Failure> {
+        let mut chunk = vec![0; COPY_CHUNK];
+        let mut written: u64 = 0;
+        let mut next_check = SPACE_CHECK_INTERVAL;
+
+        loop {
+            let n = match body.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(Failure::Status(body_error_status(&err))),
+            };
+
+            written += n as u64;
+            if self.max_file_size > 0 && written > self.max_file_size {
+                return Err(Failure::Status(StatusCode::CONTENT_TOO_LARGE));
+            }
+            match file.write_all(&chunk[..n]) {
+                Ok(()) => {},
+                Err(ref err) if err.kind() == io::ErrorKind::StorageFull => return Err(Failure::Status(StatusCode::INSUFFICIENT_STORAGE)),
+                Err(err) => return Err(Failure::Io(err)),
+            }
+
+            if written >= next_check {
+                next_check += SPACE_CHECK_INTERVAL;
+                if free_space(dir).map_err(Failure::Io)? < self.min_free_space {
+                    return Err(Failure::Status(StatusCode::INSUFFICIENT_STORAGE));
+                }
+            }
+        }
+
+        file.sync_all().map_err(Failure::Io)
+    }
+
+    fn delete(&self, req: &HTTPRequest, target: &Target) -> HTTPResponse {
+        let (root, file) = (target.root, target.file.as_path());
+        let is_dir = match fs::symlink_metadata(file) {
+            // A directory is only removed through its collection path.
+            Ok(meta) if meta.is_dir() && !req.isAutoIndex => return response(StatusCode::CONFLICT),
+            Ok(meta) => meta.is_dir(),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound || err.kind() == io::ErrorKind::NotADirectory => {
+                return response(StatusCode::NOT_FOUND)
+            },
+            Err(err) => return io_error(file, err),
+        };
+        if !preconditions_hold(req, true) {
+            return response(StatusCode::PRECONDITION_FAILED);
+        }
+        if !self.unlocked(target, &target.path, true) || !self.members_unlocked(target, &target.path) {
+            return response(StatusCode::LOCKED);
+        }
+        // The whole tree goes, so it must not lead out of the document root.
+        let inside = match is_dir {
+            true => target.path != "/" && inside_root(root, file),
+            false => inside_root(root, file.parent().unwrap_or(root)),
+        };
+        if !inside {
+            return response(StatusCode::FORBIDDEN);
+        }
+
+        let removed = match is_dir {
+            true => fs::remove_dir_all(file),
+            false => fs::remove_file(file),
+        };
+        match removed {
+            Ok(()) => {
+                self.forget(&target.path);
+                response(StatusCode::NO_CONTENT)
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => response(StatusCode::NOT_FOUND),
+            Err(err) => io_error(file, err),
+        }
+    }
+
+    fn mkcol(&self, req: &HTTPRequest, target: &Target) -> HTTPResponse {
+        // No request body format is defined for MKCOL.
+        if req.body_framing() != Ok(BodyFraming::None) {
+            return response(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        }
+        if target.meta.is_some() {
+            return response(StatusCode::METHOD_NOT_ALLOWED);
+        }
+
+        let parent = target.file.parent().unwrap_or(target.root);
+        if !parent.is_dir() {
+            return response(StatusCode::CONFLICT);
+        }
+        if !inside_root(target.root, parent) {
+            return response(StatusCode::FORBIDDEN);
+        }
+        if !self.members_unlocked(target, &target.path) {
+            return response(StatusCode::LOCKED);
+        }
+
+        match fs::create_dir(&target.file) {
+            Ok(()) => response(StatusCode::CREATED),
+            Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => response(StatusCode::METHOD_NOT_ALLOWED),
+            Err(err) => io_error(&target.file, err),
+        }
+    }
+
+    // COPY with `keep_source`, MOVE without. Dead properties go along, locks
+    // stay behind.
+    fn transfer(&self, req: &HTTPRequest, target: &Target, keep_source: bool) -> HTTPResponse {
+        let root = target.root;
+        let is_dir = match target.meta {
+            Some(ref meta) => meta.is_dir(),
+            None => return response(StatusCode::NOT_FOUND),
+        };
+        let infinite = match req.header("Depth") {
+            Some("infinity") | None => true,
+            Some("0") if keep_source => false,
+            Some(_) => return response(StatusCode::BAD_REQUEST),
+        };
+        let overwrite = match req.header("Overwrite") {
+            Some("T") | None => true,
+            Some("F") => false,
+            Some(_) => return response(StatusCode::BAD_REQUEST),
+        };
+
+        let destination = match self.destination(req) {
+            Ok(destination) => destination,
+            Err(status) => return response(status),
+        };
+        let name = destination.trim_end_matches('/');
+        let destination = match is_dir {
+            true => format!("{}/", name),
+            false => name.to_owned(),
+        };
+        if name.is_empty() || props::is_within(&destination, &target.path) || props::is_within(&target.path, &destination) {
+            return response(StatusCode::FORBIDDEN);
+        }
+
+        let file = root.join(name.trim_start_matches('/'));
+        let existing = match metadata(&file) {
+            Ok(existing) => existing,
+            Err(err) => return io_error(&file, err),
+        };
+        if existing.is_some() && !overwrite {
+            return response(StatusCode::PRECONDITION_FAILED);
+        }
+        let parent = file.parent().unwrap_or(root);
+        if !parent.is_dir() {
+            return response(StatusCode::CONFLICT);
+        }
+        if !inside_root(root, parent) || !inside_root(root, target.file.parent().unwrap_or(root)) {
+            return response(StatusCode::FORBIDDEN);
+        }
+
+        // What is replaced at the destination, by its own path.
+        let replaced = match existing {
+            Some(ref meta) if meta.is_dir() => format!("{}/", name),
+            _ => name.to_owned(),
+        };
+        let source_locked = !keep_source && (!self.unlocked(target, &target.path, true) || !self.members_unlocked(target, &target.path));
+        if source_locked || !self.unlocked(target, &replaced, true) || !self.members_unlocked(target, &replaced) {
+            return response(StatusCode::LOCKED);
+        }
+
+        if let Some(ref meta) = existing {
+            let removed = match meta.is_dir() {
+                true => fs::remove_dir_all(&file),
+                false => fs::remove_file(&file),
+            };
+            if let Err(err) = removed {
+                return io_error(&file, err);
+            }
+            self.forget(&replaced);
+        }
+
+        let done = match keep_source {
+            true => copy_tree(&target.file, &file, infinite),
+            false => fs::rename(&target.file, &file),
+        };
+        if let Err(err) = done {
+            return io_error(&file, err);
+        }
+
+        match (keep_source, infinite) {
+            (false, _) => {
+                self.locks.remove(&target.path);
+                self.properties.transfer(&target.path, &destination, false);
+            },
+            (true, true) => self.properties.transfer(&target.path, &destination, true),
+            (true, false) => {
+                let copied = self.properties.get(&target.path).into_iter().map(Update::Set).collect();
+                self.properties.apply(&destination, copied);
+            },
+        }
+
+        match existing {
+            Some(_) => response(StatusCode::NO_CONTENT),
+            None => response(StatusCode::CREATED),
+        }
+    }
+
+    // The path of the `Destination` header, which holds an absolute URI or
+    // path. It must lie in this location, the authority is not compared.
+    fn destination(&self, req: &HTTPRequest) -> Result<String, StatusCode> {
+        let value = req.header("Destination").ok_or(StatusCode::BAD_REQUEST)?;
+        let path = match value.find("://") {
+            Some(scheme) => {
+                let rest = &value[scheme + 3..];
+                rest.find('/').map(|start| &rest[start..]).unwrap_or("/")
+            },
+            None => value,
+        };
+        let path = path.split(['?', '#']).next().unwrap_or("");
+
+        let decoded = percent_decode(path.as_bytes()).decode_utf8().map_err(|_| StatusCode::BAD_REQUEST)?;
+        let path = normalize_path(&decoded).map_err(|_| StatusCode::BAD_REQUEST)?;
+        // `/dav` covers `/dav/a` but not `/davx/a`.
+        let inside = match path.strip_prefix(self.prefix.as_str()) {
+            Some(rest) => self.prefix.ends_with('/') || rest.is_empty() || rest.starts_with('/'),
+            None => false,
+        };
+        match inside {
+            true => Ok(path),
+            false => Err(StatusCode::FORBIDDEN),
+        }
+    }
+
+    fn propfind(&self, req: &HTTPRequest, target: &Target, body: &mut dyn Read) -> HTTPResponse {
+        let meta = match target.meta {
+            Some(ref meta) => meta.clone(),
+            None => return response(StatusCode::NOT_FOUND),
+        };
+        let depth = match req.header("Depth") {
+            Some("0") => 0,
+            Some("1") => 1,
+            Some("infinity") | None => usize::MAX,
+            Some(_) => return response(StatusCode::BAD_REQUEST),
+        };
+        // A walk of the whole share is refused rather than cut short.
+        if depth > self.max_depth {
+            return xml_response(StatusCode::FORBIDDEN, props::render_error("propfind-finite-depth"));
+        }
+        let find = match read_xml(body).and_then(|xml| parse_propfind(&xml).ok_or(StatusCode::BAD_REQUEST)) {
+            Ok(find) => find,
+            Err(status) => return response(status),
+        };
+
+        let mut resources = Vec::new();
+        let resource = Resource {
+            path: target.path.clone(),
+            meta,
+        };
+        collect(&target.file, resource, depth, &mut resources);
+        xml_response(StatusCode::MULTI_STATUS, props::render_propfind(&find, &resources, &self.properties, &self.locks))
+    }
+
+    fn proppatch(&self, target: &Target, body: &mut dyn Read) -> HTTPResponse {
+        if target.meta.is_none() {
+            return response(StatusCode::NOT_FOUND);
+        }
+        if !self.unlocked(target, &target.path, false) {
+            return response(StatusCode::LOCKED);
+        }
+        let updates = match read_xml(body).and_then(|xml| parse_propertyupdate(&xml).ok_or(StatusCode::BAD_REQUEST)) {
+            Ok(updates) => updates,
+            Err(status) => return response(status),
+        };
+
+        // DAV: properties are computed, not stored. A PROPPATCH applies in
+        // full or not at all, so one of them fails the rest too.
+        let protected = updates.iter().any(|u| u.name().namespace == DAV_NS);
+        let results: Vec<_> = updates.iter()
+            .map(|u| {
+                let status = match (protected, u.name().namespace == DAV_NS) {
+                    (false, _) => StatusCode::OK,
+                    (true, true) => StatusCode::FORBIDDEN,
+                    (true, false) => StatusCode::FAILED_DEPENDENCY,
+                };
+                (u.name().clone(), status)
+            })
+            .collect();
+        if !protected {
+            self.properties.apply(&target.path, updates);
+        }
+
+        xml_response(StatusCode::MULTI_STATUS, props::render_proppatch(&target.path, &results))
+    }
+
+    fn lock(&self, req: &HTTPRequest, target: &Target, body: &mut dyn Read) -> HTTPResponse {
+        let xml = match read_xml(body) {
+            Ok(xml) => xml,
+            Err(status) => return response(status),
+        };
+        let timeout = parse_timeout(req.header("Timeout"));
+
+        // Without a body the request refreshes a lock named in `If`.
+        if xml.trim().is_empty() {
+            return match self.locks.refresh(&target.path, &target.tokens, timeout) {
+                Some(lock) => xml_response(StatusCode::OK, props::render_lock(&lock)),
+                None => response(StatusCode::PRECONDITION_FAILED),
+            };
+        }
+
+        let (scope, owner) = match parse_lockinfo(&xml) {
+            Some(info) => info,
+            None => return response(StatusCode::BAD_REQUEST),
+        };
+        let infinite = match req.header("Depth") {
+            Some("infinity") | None => true,
+            Some("0") => false,
+            Some(_) => return response(StatusCode::BAD_REQUEST),
+        };
+
+        // Locking a missing resource creates an empty file, which reserves
+        // the name for the client that is about to write it.
+        let created = target.meta.is_none();
+        if created {
+            let parent = target.file.parent().unwrap_or(target.root);
+            if target.path.ends_with('/') || !parent.is_dir() {
+                return response(StatusCode::CONFLICT);
+            }
+            if !inside_root(target.root, parent) {
+                return response(StatusCode::FORBIDDEN);
+            }
+            if !self.members_unlocked(target, &target.path) {
+                return response(StatusCode::LOCKED);
+            }
+        }
+
+        let lock = match self.locks.lock(&target.path, scope, infinite, owner, timeout) {
+            Some(lock) => lock,
+            None => return response(StatusCode::LOCKED),
+        };
+        if created {
+            if let Err(err) = OpenOptions::new().write(true).create_new(true).open(&target.file) {
+                self.locks.unlock(&target.path, &lock.token);
+                return io_error(&target.file, err);
+            }
+        }
+
+        let status = match created {
+            true => StatusCode::CREATED,
+            false => StatusCode::OK,
+        };
+        let mut resp = xml_response(status, props::render_lock(&lock));
+        resp.push_header("Lock-Token".to_owned(), format!("<{}>", lock.token));
+        resp
+    }
+
+    fn unlock(&self, req: &HTTPRequest, target: &Target) -> HTTPResponse {
+        let token = match req.header("Lock-Token") {
+            Some(token) => token.trim().trim_start_matches('<').trim_end_matches('>'),
+            None => return response(StatusCode::BAD_REQUEST),
+        };
+
+        match self.locks.unlock(&target.path, token) {
+            true => response(StatusCode::NO_CONTENT),
+            false => response(StatusCode::CONFLICT),
+        }
+    }
+
+    // Whether the submitted tokens allow changing `path`, with
+    // `descendants` also everything below a collection.
+    fn unlocked(&self, target: &Target, path: &str, descendants: bool) -> bool {
+        self.locks.permits(path, &target.tokens, descendants)
+    }
+
+    // Adding or removing `path` changes the members of its parent, which a
+    // lock on the parent protects.
+    fn members_unlocked(&self, target: &Target, path: &str) -> bool {
+        let name = path.trim_end_matches('/');
+        let parent = match name.rfind('/') {
+            Some(slash) => &name[..=slash],
+            None => "/",
+        };
+        parent == path || self.locks.permits(parent, &target.tokens, false)
+    }
+
+    // The resource at `path` and below is gone.
+    fn forget(&self, path: &str) {
+        self.locks.remove(path);
+        self.properties.remove(path);
+    }
+}
+
+// `If-Match` and `If-None-Match` as far as they can be evaluated without
+// entity tags: `*` tests whether the file exists and a tag never matches.
+fn preconditions_hold(req: &HTTPRequest, exists: bool) -> bool {
+    let if_match = req.header_list("If-Match");
+    let matched = exists && if_match.contains(&"*");
+    if !if_match.is_empty() && !matched {
+        return false;
+    }
+
+    !(exists && req.header_list("If-None-Match").contains(&"*"))
+}
+
+// Whether `dir`, or the part of it that exists, resolves to a directory
+// below `root`. A symlink must not lead a write out of the document root.
+fn inside_root(root: &Path, dir: &Path) -> bool {
+    let root = match root.canonicalize() {
+        Ok(root) => root,
+        Err(_) => return false,
+    };
+
+    let mut existing = dir;
+    loop {
+        if let Ok(resolved) = existing.canonicalize() {
+            return resolved.starts_with(&root);
+        }
+        existing = match existing.parent() {
+            Some(parent) => parent,
+            None => return false,
+        };
+    }
+}
+
+// Metadata of `path`, None when nothing is there.
+fn metadata(path: &Path) -> io::Result<Option<Metadata>> {
+    match fs::metadata(path) {
+        Ok(meta) => Ok(Some(meta)),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound || err.kind() == io::ErrorKind::NotADirectory => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+// `resource` and its members down to `depth`. Symlinked directories are
+// listed but not entered and uploads in flight are left out.
+fn collect(file: &Path, resource: Resource, depth: usize, resources: &mut Vec<Resource>) {
+    let enter = resource.meta.is_dir() && depth > 0;
+    let path = resource.path.clone();
+    resources.push(resource);
+    if !enter {
+        return;
+    }
+
+    let mut entries: Vec<fs::DirEntry> = match fs::read_dir(file) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).collect(),
+        Err(_) => return,
+    };
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let name = match entry.file_name().into_string() {
+            Ok(name) if !is_temp(&name) => name,
+            _ => continue,
+        };
+        let meta = match fs::metadata(entry.path()) {
+            Ok(meta) => meta,
+            Err(_) => continue,
+        };
+        let linked = entry.file_type().map(|t| t.is_symlink()).unwrap_or(true);
+        let child = Resource {
+            path: format!("{}{}{}", path, name, if meta.is_dir() { "/" } else { "" }),
+            meta,
+        };
+        collect(&entry.path(), child, if linked { 0 } else { depth - 1 }, resources);
+    }
+}
+
+// Copies `from` to `to`, the members of a directory only with `infinite`.
+// Symlinks are copied as links.
+fn copy_tree(from: &Path, to: &Path, infinite: bool) -> io::Result<()> {
+    let meta = fs::symlink_metadata(from)?;
+    if meta.file_type().is_symlink() {
+        return std::os::unix::fs::symlink(fs::read_link(from)?, to);
+    }
+    if !meta.is_dir() {
+        return fs::copy(from, to).map(|_| ());
+    }
+
+    fs::create_dir(to)?;
+    if infinite {
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            if !is_temp(&entry.file_name().to_string_lossy()) {
+                copy_tree(&entry.path(), &to.join(entry.file_name()), true)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// The XML body of PROPFIND, PROPPATCH or LOCK, empty when there is none.
+fn read_xml(body: &mut dyn Read) -> Result<String, StatusCode> {
+    let mut xml = Vec::new();
+    match body.take(MAX_XML_BODY + 1).read_to_end(&mut xml) {
+        Ok(_) if xml.len() as u64 > MAX_XML_BODY => Err(StatusCode::CONTENT_TOO_LARGE),
+        Ok(_) => String::from_utf8(xml).map_err(|_| StatusCode::BAD_REQUEST),
+        Err(err) => Err(body_error_status(&err)),
+    }
+}
+
+// Bytes available to unprivileged users on the file system of `path`.
+fn free_space(path: &Path) -> io::Result<u64> {
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a nul byte"))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+// A hidden file next to the target, removed unless it was renamed into
+// place.
+struct TempFile {
+    path: PathBuf,
+    file: File,
+    keep: bool,
+}
+
+impl TempFile {
+    fn create(target: &Path) -> io::Result<TempFile> {
+        let name = target.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let path = target.with_file_name(format!(".{}.{}.{}{}", name, process::id(), TEMP_COUNTER.fetch_add(1, Ordering::SeqCst), TEMP_SUFFIX));
+        let file = OpenOptions::new().write(true).create_new(true).open(&path)?;
+
+        Ok(TempFile {
+            path,
+            file,
+            keep: false,
+        })
+    }
+}
+
+// Whether `name` is a TempFile, which listings and copies skip.
+fn is_temp(name: &str) -> bool {
+    name.starts_with('.') && name.ends_with(TEMP_SUFFIX)
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+fn io_error(path: &Path, err: io::Error) -> HTTPResponse {
+    match err.kind() {
+        io::ErrorKind::PermissionDenied => response(StatusCode::FORBIDDEN),
+        io::ErrorKind::StorageFull => response(StatusCode::INSUFFICIENT_STORAGE),
+        _ => {
+            println!("Error while write {}: {}", path.display(), err);
+            response(StatusCode::INTERNAL_SERVER_ERROR)
+        },
+    }
+}
+
+fn response(status: StatusCode) -> HTTPResponse {
+    HTTPResponse::builder().status(status).build()
+}
+
+fn xml_response(status: StatusCode, xml: String) -> HTTPResponse {
+    HTTPResponse::builder()
+        .status(status)
+        .header("Content-Type", "application/xml; charset=utf-8")
+        .body(xml)
+}
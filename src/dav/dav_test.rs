@@ -0,0 +1,376 @@
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::dav::{Dav, DavBuilder, DavMethod};
+use crate::http::request::HTTPRequest;
+use crate::http::status::StatusCode;
+
+static ROOTS: AtomicUsize = AtomicUsize::new(0);
+
+// An empty document root of its own for every test.
+fn root() -> PathBuf {
+    let root = std::env::temp_dir().join(format!("dz1-dav-{}-{}", std::process::id(), ROOTS.fetch_add(1, Ordering::SeqCst)));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    root
+}
+
+fn dav(directives: &[&[&str]]) -> Dav {
+    let mut builder = DavBuilder::default();
+    builder.parse_directive(&[String::from("dav_methods"), String::from("PUT"), String::from("DELETE")]).unwrap();
+    builder.parse_directive(&[String::from("dav_min_free_space"), String::from("0")]).unwrap();
+    for tokens in directives {
+        let tokens: Vec<String> = tokens.iter().map(|t| t.to_string()).collect();
+        assert!(builder.parse_directive(&tokens).unwrap());
+    }
+    builder.build("/").unwrap().unwrap()
+}
+
+// Every method, as `webdav on` enables them.
+fn webdav() -> Dav {
+    let mut builder = DavBuilder::default();
+    builder.parse_directive(&[String::from("webdav"), String::from("on")]).unwrap();
+    builder.parse_directive(&[String::from("dav_min_free_space"), String::from("0")]).unwrap();
+    builder.build("/").unwrap().unwrap()
+}
+
+fn request(method: &str, path: &str, headers: &str, body: &str) -> HTTPRequest {
+    let raw = format!("{} {} HTTP/1.1\r\nContent-Length: {}\r\n{}\r\n", method, path, body.len(), headers);
+    HTTPRequest::parse(raw.as_bytes()).unwrap()
+}
+
+fn run(dav: &Dav, root: &Path, method: &str, path: &str, headers: &str, body: &str) -> String {
+    let req = request(method, path, headers, body);
+    let resp = dav.handle(&req, root.to_str().unwrap(), &mut body.as_bytes());
+    resp.status().to_string()
+}
+
+// The status and body of a response.
+fn exchange(dav: &Dav, root: &Path, method: &str, path: &str, headers: &str, body: &str) -> (String, String) {
+    let req = request(method, path, headers, body);
+    let resp = dav.handle(&req, root.to_str().unwrap(), &mut body.as_bytes());
+    let status = resp.status().to_string();
+    let mut body = String::new();
+    if let Some(mut stream) = resp.stream {
+        stream.read_to_string(&mut body).unwrap();
+    }
+    (status, body)
+}
+
+// Names in `dir`, temp files included.
+fn entries(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().into_owned()).collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn put_creates_then_replaces() {
+    let root = root();
+    let dav = dav(&[]);
+
+    assert_eq!(run(&dav, &root, "PUT", "/a.txt", "", "first"), "201 Created");
+    assert_eq!(fs::read_to_string(root.join("a.txt")).unwrap(), "first");
+    assert_eq!(run(&dav, &root, "PUT", "/a.txt", "", "second"), "204 No Content");
+    assert_eq!(fs::read_to_string(root.join("a.txt")).unwrap(), "second");
+    assert_eq!(entries(&root), vec!["a.txt"]);
+}
+
+#[test]
+fn put_parent_directories() {
+    let root = root();
+    assert_eq!(run(&dav(&[]), &root, "PUT", "/builds/1/app.tar", "", "x"), "409 Conflict");
+
+    let dav = dav(&[&["create_full_put_path", "on"]]);
+    assert_eq!(run(&dav, &root, "PUT", "/builds/1/app.tar", "", "x"), "201 Created");
+    assert!(root.join("builds/1/app.tar").is_file());
+    // Neither a directory nor a collection path can be overwritten.
+    assert_eq!(run(&dav, &root, "PUT", "/builds/1", "", "x"), "409 Conflict");
+    assert_eq!(run(&dav, &root, "PUT", "/builds/", "", "x"), "409 Conflict");
+}
+
+#[test]
+fn put_preconditions() {
+    let root = root();
+    let dav = dav(&[]);
+
+    assert_eq!(run(&dav, &root, "PUT", "/a.txt", "If-Match: *\r\n", "x"), "412 Precondition Failed");
+    assert!(!root.join("a.txt").exists());
+    assert_eq!(run(&dav, &root, "PUT", "/a.txt", "If-None-Match: *\r\n", "first"), "201 Created");
+    assert_eq!(run(&dav, &root, "PUT", "/a.txt", "If-None-Match: *\r\n", "second"), "412 Precondition Failed");
+    assert_eq!(run(&dav, &root, "PUT", "/a.txt", "If-Match: \"abc\"\r\n", "second"), "412 Precondition Failed");
+    assert_eq!(fs::read_to_string(root.join("a.txt")).unwrap(), "first");
+
+    assert_eq!(run(&dav, &root, "PUT", "/a.txt", "If-Match: *\r\n", "second"), "204 No Content");
+    assert_eq!(fs::read_to_string(root.join("a.txt")).unwrap(), "second");
+    assert_eq!(entries(&root), vec!["a.txt"]);
+}
+
+#[test]
+fn put_size_limits() {
+    let root = root();
+    let dav = dav(&[&["dav_max_file_size", "4"]]);
+    assert_eq!(run(&dav, &root, "PUT", "/a.txt", "", "12345"), "413 Content Too Large");
+
+    // Without a declared length the limit applies while writing.
+    let raw = "PUT /b.txt HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+    let req = HTTPRequest::parse(raw.as_bytes()).unwrap();
+    let resp = dav.handle(&req, root.to_str().unwrap(), &mut "12345".as_bytes());
+    assert_eq!(resp.status(), StatusCode::CONTENT_TOO_LARGE);
+    assert!(entries(&root).is_empty());
+
+    let full = self::dav(&[&["dav_min_free_space", "1000000g"]]);
+    assert_eq!(run(&full, &root, "PUT", "/a.txt", "", "1"), "507 Insufficient Storage");
+    assert!(entries(&root).is_empty());
+}
+
+#[test]
+fn put_outside_root_through_symlink() {
+    let root = root();
+    let outside = self::root();
+    std::os::unix::fs::symlink(&outside, root.join("escape")).unwrap();
+
+    assert_eq!(run(&dav(&[]), &root, "PUT", "/escape/a.txt", "", "x"), "403 Forbidden");
+    assert!(entries(&outside).is_empty());
+}
+
+#[test]
+fn delete_files() {
+    let root = root();
+    let dav = dav(&[]);
+    fs::write(root.join("a.txt"), "x").unwrap();
+    fs::create_dir(root.join("dir")).unwrap();
+
+    assert_eq!(run(&dav, &root, "DELETE", "/a.txt", "If-None-Match: *\r\n", ""), "412 Precondition Failed");
+    assert_eq!(run(&dav, &root, "DELETE", "/a.txt", "", ""), "204 No Content");
+    assert!(!root.join("a.txt").exists());
+    assert_eq!(run(&dav, &root, "DELETE", "/a.txt", "", ""), "404 Not Found");
+    assert_eq!(run(&dav, &root, "DELETE", "/dir", "", ""), "409 Conflict");
+}
+
+#[test]
+fn parse_directives() {
+    let parse = |lines: &[&[&str]]| {
+        let mut builder = DavBuilder::default();
+        for tokens in lines {
+            let tokens: Vec<String> = tokens.iter().map(|t| t.to_string()).collect();
+            builder.parse_directive(&tokens)?;
+        }
+        builder.build("/")
+    };
+
+    let dav = parse(&[&["dav_methods", "PUT"], &["dav_max_file_size", "10m"]]).unwrap().unwrap();
+    assert_eq!(dav.methods, vec![DavMethod::Put]);
+    assert!(dav.allows("PUT"));
+    assert!(!dav.allows("DELETE"));
+    assert_eq!(dav.max_file_size, 10 * 1024 * 1024);
+    assert_eq!(dav.min_free_space, 64 * 1024 * 1024);
+    assert_eq!(dav.max_depth, 1);
+
+    let dav = parse(&[&["webdav", "on"]]).unwrap().unwrap();
+    assert_eq!(dav.methods.len(), 9);
+    assert!(dav.allows("PROPFIND") && dav.allows("OPTIONS"));
+    assert!(parse(&[&["webdav", "off"]]).unwrap().is_none());
+    assert!(parse(&[&["webdav", "yes"]]).is_err());
+
+    assert!(parse(&[&["dav_methods", "off"]]).unwrap().is_none());
+    assert!(parse(&[&["create_full_put_path", "on"]]).unwrap().is_none());
+    assert!(parse(&[&["dav_methods", "PATCH"]]).is_err());
+    assert!(parse(&[&["dav_methods"]]).is_err());
+    assert!(parse(&[&["dav_methods", "PUT"], &["create_full_put_path", "yes"]]).is_err());
+    assert!(parse(&[&["dav_methods", "PUT"], &["dav_min_free_space", "lots"]]).is_err());
+    assert_eq!(parse(&[&["webdav", "on"], &["dav_max_depth", "0"]]).unwrap().unwrap().max_depth, 0);
+    assert_eq!(parse(&[&["webdav", "on"], &["dav_max_depth", "infinity"]]).unwrap().unwrap().max_depth, usize::MAX);
+    assert!(parse(&[&["webdav", "on"], &["dav_max_depth", "deep"]]).is_err());
+}
+
+#[test]
+fn options_advertise_dav() {
+    let req = request("OPTIONS", "/", "", "");
+    let resp = webdav().handle(&req, "/nonexistent", &mut "".as_bytes());
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.header("DAV"), Some("1, 2"));
+    assert!(resp.header("Allow").unwrap().contains("PROPFIND"));
+
+    assert!(webdav().allows("OPTIONS"));
+    assert!(!dav(&[]).allows("OPTIONS"));
+}
+
+#[test]
+fn mkcol_and_delete_collections() {
+    let root = root();
+    let dav = webdav();
+
+    assert_eq!(run(&dav, &root, "MKCOL", "/a/b/", "", ""), "409 Conflict");
+    assert_eq!(run(&dav, &root, "MKCOL", "/a/", "", ""), "201 Created");
+    assert_eq!(run(&dav, &root, "MKCOL", "/a/", "", ""), "405 Method Not Allowed");
+    assert_eq!(run(&dav, &root, "MKCOL", "/a/b", "", "<x/>"), "415 Unsupported Media Type");
+    assert_eq!(run(&dav, &root, "MKCOL", "/a/b", "", ""), "201 Created");
+    assert_eq!(run(&dav, &root, "PUT", "/a/b/c.txt", "", "x"), "201 Created");
+
+    // A directory is only deleted through its collection path.
+    assert_eq!(run(&dav, &root, "DELETE", "/a", "", ""), "409 Conflict");
+    assert_eq!(run(&dav, &root, "DELETE", "/a/", "", ""), "204 No Content");
+    assert!(entries(&root).is_empty());
+    assert_eq!(run(&dav, &root, "DELETE", "/", "", ""), "403 Forbidden");
+}
+
+#[test]
+fn propfind_depths() {
+    let root = root();
+    let dav = webdav();
+    fs::create_dir_all(root.join("docs/sub")).unwrap();
+    fs::write(root.join("docs/a.txt"), "hello").unwrap();
+    fs::write(root.join("docs/sub/b.txt"), "x").unwrap();
+    fs::write(root.join("docs/.a.txt.1.2.tmp"), "partial").unwrap();
+
+    let (status, body) = exchange(&dav, &root, "PROPFIND", "/docs", "Depth: 0\r\n", "");
+    assert_eq!(status, "207 Multi-Status");
+    assert!(body.contains("<D:href>/docs/</D:href>"));
+    assert!(!body.contains("a.txt"));
+
+    let (_, body) = exchange(&dav, &root, "PROPFIND", "/docs/", "Depth: 1\r\n", "");
+    assert!(body.contains("<D:href>/docs/a.txt</D:href>"));
+    assert!(body.contains("<D:href>/docs/sub/</D:href>"));
+    assert!(!body.contains("b.txt"));
+    assert!(!body.contains(".tmp"));
+
+    // Without a Depth header the client asks for the whole tree.
+    let (status, body) = exchange(&dav, &root, "PROPFIND", "/docs/", "", "");
+    assert_eq!(status, "403 Forbidden");
+    assert!(body.contains("<D:error xmlns:D=\"DAV:\"><D:propfind-finite-depth/></D:error>"));
+    assert_eq!(run(&dav, &root, "PROPFIND", "/docs/", "Depth: infinity\r\n", ""), "403 Forbidden");
+
+    let mut builder = DavBuilder::default();
+    builder.parse_directive(&[String::from("webdav"), String::from("on")]).unwrap();
+    builder.parse_directive(&[String::from("dav_max_depth"), String::from("infinity")]).unwrap();
+    let dav = builder.build("/").unwrap().unwrap();
+    let find = "<?xml version=\"1.0\"?><D:propfind xmlns:D=\"DAV:\"><D:prop><D:getcontentlength/></D:prop></D:propfind>";
+    let (_, body) = exchange(&dav, &root, "PROPFIND", "/docs/", "", find);
+    assert!(body.contains("<D:href>/docs/sub/b.txt</D:href>"));
+    assert!(body.contains("<D:getcontentlength>5</D:getcontentlength>"));
+
+    assert_eq!(run(&dav, &root, "PROPFIND", "/docs/", "Depth: 2\r\n", ""), "400 Bad Request");
+    assert_eq!(run(&dav, &root, "PROPFIND", "/docs/", "", "<propfind"), "400 Bad Request");
+    assert_eq!(run(&dav, &root, "PROPFIND", "/missing", "", ""), "404 Not Found");
+}
+
+#[test]
+fn proppatch_dead_properties() {
+    let root = root();
+    let dav = webdav();
+    fs::write(root.join("a.txt"), "x").unwrap();
+
+    let update = "<D:propertyupdate xmlns:D=\"DAV:\" xmlns:Z=\"urn:z\"><D:set><D:prop><Z:color>red</Z:color></D:prop></D:set></D:propertyupdate>";
+    let (status, body) = exchange(&dav, &root, "PROPPATCH", "/a.txt", "", update);
+    assert_eq!(status, "207 Multi-Status");
+    assert!(body.contains("<D:status>HTTP/1.1 200 OK</D:status>"));
+
+    // A protected property fails the whole update.
+    let update = "<D:propertyupdate xmlns:D=\"DAV:\" xmlns:Z=\"urn:z\"><D:set><D:prop><Z:color>blue</Z:color>\
+                  <D:getcontentlength>9</D:getcontentlength></D:prop></D:set></D:propertyupdate>";
+    let (_, body) = exchange(&dav, &root, "PROPPATCH", "/a.txt", "", update);
+    assert!(body.contains("403 Forbidden"));
+    assert!(body.contains("424 Failed Dependency"));
+
+    let (_, body) = exchange(&dav, &root, "PROPFIND", "/a.txt", "Depth: 0\r\n", "");
+    assert!(body.contains("<P:color xmlns:P=\"urn:z\">red</P:color>"));
+}
+
+#[test]
+fn copy_and_move() {
+    let root = root();
+    let dav = webdav();
+    fs::create_dir_all(root.join("src/sub")).unwrap();
+    fs::write(root.join("src/sub/a.txt"), "a").unwrap();
+    fs::write(root.join("b.txt"), "b").unwrap();
+
+    let to = |path: &str| format!("Destination: http://example.com{}\r\n", path);
+    assert_eq!(run(&dav, &root, "COPY", "/src/", &to("/copy/"), ""), "201 Created");
+    assert_eq!(fs::read_to_string(root.join("copy/sub/a.txt")).unwrap(), "a");
+    assert_eq!(run(&dav, &root, "COPY", "/src/", &format!("{}Depth: 0\r\n", to("/shallow/")), ""), "201 Created");
+    assert!(entries(&root.join("shallow")).is_empty());
+
+    assert_eq!(run(&dav, &root, "COPY", "/b.txt", &format!("{}Overwrite: F\r\n", to("/copy/sub/a.txt")), ""), "412 Precondition Failed");
+    assert_eq!(run(&dav, &root, "COPY", "/b.txt", &to("/copy/sub/a.txt"), ""), "204 No Content");
+    assert_eq!(fs::read_to_string(root.join("copy/sub/a.txt")).unwrap(), "b");
+
+    assert_eq!(run(&dav, &root, "MOVE", "/b.txt", &to("/moved%20b.txt"), ""), "201 Created");
+    assert!(!root.join("b.txt").exists());
+    assert!(root.join("moved b.txt").is_file());
+    assert_eq!(run(&dav, &root, "MOVE", "/src/", "Destination: /src/sub/inner/\r\n", ""), "403 Forbidden");
+    assert_eq!(run(&dav, &root, "MOVE", "/src/", &format!("{}Depth: 0\r\n", to("/x/")), ""), "400 Bad Request");
+    assert_eq!(run(&dav, &root, "MOVE", "/src/", &to("/missing/x/"), ""), "409 Conflict");
+    assert_eq!(run(&dav, &root, "MOVE", "/src/", "", ""), "400 Bad Request");
+    assert_eq!(run(&dav, &root, "MOVE", "/nothing", &to("/x"), ""), "404 Not Found");
+}
+
+#[test]
+fn destination_outside_location() {
+    let root = root();
+    let mut builder = DavBuilder::default();
+    builder.parse_directive(&[String::from("webdav"), String::from("on")]).unwrap();
+    let dav = builder.build("/files/").unwrap().unwrap();
+    fs::create_dir(root.join("files")).unwrap();
+    fs::write(root.join("files/a.txt"), "a").unwrap();
+
+    assert_eq!(run(&dav, &root, "COPY", "/files/a.txt", "Destination: /other/a.txt\r\n", ""), "403 Forbidden");
+    assert_eq!(run(&dav, &root, "COPY", "/files/a.txt", "Destination: /files/../a.txt\r\n", ""), "400 Bad Request");
+    assert_eq!(run(&dav, &root, "COPY", "/files/a.txt", "Destination: /files/b.txt\r\n", ""), "201 Created");
+
+    // A prefix without a trailing slash ends on a segment boundary.
+    let mut builder = DavBuilder::default();
+    builder.parse_directive(&[String::from("webdav"), String::from("on")]).unwrap();
+    let dav = builder.build("/files").unwrap().unwrap();
+    assert_eq!(run(&dav, &root, "COPY", "/files/a.txt", "Destination: /filesx/a.txt\r\n", ""), "403 Forbidden");
+    assert_eq!(run(&dav, &root, "COPY", "/files/a.txt", "Destination: /files/c.txt\r\n", ""), "201 Created");
+}
+
+#[test]
+fn locks_guard_writes() {
+    let root = root();
+    let dav = webdav();
+    let lockinfo = "<D:lockinfo xmlns:D=\"DAV:\"><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype>\
+                    <D:owner>ci</D:owner></D:lockinfo>";
+
+    // Locking a missing file creates it.
+    let req = request("LOCK", "/a.txt", "Timeout: Second-60\r\n", lockinfo);
+    let resp = dav.handle(&req, root.to_str().unwrap(), &mut lockinfo.as_bytes());
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let token = resp.header("Lock-Token").unwrap().trim_matches(|c| c == '<' || c == '>').to_owned();
+    assert!(root.join("a.txt").is_file());
+    assert_eq!(run(&dav, &root, "LOCK", "/a.txt", "", lockinfo), "423 Locked");
+
+    let with_token = format!("If: (<{}>)\r\n", token);
+    assert_eq!(run(&dav, &root, "PUT", "/a.txt", "", "x"), "423 Locked");
+    assert_eq!(run(&dav, &root, "PUT", "/a.txt", &with_token, "x"), "204 No Content");
+    assert_eq!(run(&dav, &root, "DELETE", "/a.txt", "", ""), "423 Locked");
+    assert_eq!(run(&dav, &root, "MOVE", "/a.txt", "Destination: /b.txt\r\n", ""), "423 Locked");
+    assert_eq!(run(&dav, &root, "COPY", "/a.txt", "Destination: /b.txt\r\n", ""), "201 Created");
+
+    let (status, body) = exchange(&dav, &root, "LOCK", "/a.txt", &with_token, "");
+    assert_eq!(status, "200 OK");
+    assert!(body.contains(&token));
+    assert_eq!(run(&dav, &root, "LOCK", "/a.txt", "", ""), "412 Precondition Failed");
+
+    assert_eq!(run(&dav, &root, "UNLOCK", "/a.txt", "Lock-Token: <opaquelocktoken:other>\r\n", ""), "409 Conflict");
+    assert_eq!(run(&dav, &root, "UNLOCK", "/a.txt", &format!("Lock-Token: <{}>\r\n", token), ""), "204 No Content");
+    assert_eq!(run(&dav, &root, "DELETE", "/a.txt", "", ""), "204 No Content");
+}
+
+#[test]
+fn collection_locks_cover_members() {
+    let root = root();
+    let dav = webdav();
+    fs::create_dir(root.join("docs")).unwrap();
+    let lockinfo = "<lockinfo xmlns=\"DAV:\"><lockscope><exclusive/></lockscope><locktype><write/></locktype></lockinfo>";
+
+    assert_eq!(run(&dav, &root, "LOCK", "/docs/", "Depth: 0\r\n", lockinfo), "200 OK");
+    // Depth 0 guards the membership, not the contents of members.
+    assert_eq!(run(&dav, &root, "PUT", "/docs/a.txt", "", "x"), "423 Locked");
+    assert_eq!(run(&dav, &root, "MKCOL", "/docs/sub/", "", ""), "423 Locked");
+    assert_eq!(run(&dav, &root, "DELETE", "/docs/", "", ""), "423 Locked");
+    assert_eq!(run(&dav, &root, "PUT", "/other.txt", "", "x"), "201 Created");
+}
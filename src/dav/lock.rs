@@ -0,0 +1,200 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use roxmltree::Document;
+
+use crate::dav::props::{dav_element, text};
+
+pub const DEFAULT_LOCK_TIMEOUT: u64 = 600;
+pub const MAX_LOCK_TIMEOUT: u64 = 3600;
+
+static TOKEN_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LockScope {
+    Exclusive,
+    Shared,
+}
+
+// A write lock of RFC 4918 section 6. Collection paths end with `/`.
+#[derive(Debug, Clone)]
+pub struct Lock {
+    pub token: String,
+    pub path: String,
+    pub scope: LockScope,
+    // Depth infinity, the lock also covers everything below a collection.
+    pub infinite: bool,
+    // Text of the `owner` element.
+    pub owner: Option<String>,
+    pub timeout: Duration,
+    expires: Instant,
+}
+
+impl Lock {
+    // Whether the lock applies to `path`.
+    pub fn covers(&self, path: &str) -> bool {
+        self.path == path || (self.infinite && self.path.ends_with('/') && path.starts_with(&self.path))
+    }
+}
+
+// Locks live in memory only, they end with the process or a reload.
+#[derive(Debug, Default)]
+pub struct LockTable {
+    locks: Mutex<Vec<Lock>>,
+}
+
+impl LockTable {
+    // Takes a new lock, None when it conflicts with a held one.
+    pub fn lock(&self, path: &str, scope: LockScope, infinite: bool, owner: Option<String>, timeout: Duration) -> Option<Lock> {
+        let mut locks = self.active();
+        let conflict = locks.iter().any(|held| {
+            let overlaps = held.covers(path) || (infinite && path.ends_with('/') && held.path.starts_with(path));
+            overlaps && (scope == LockScope::Exclusive || held.scope == LockScope::Exclusive)
+        });
+        if conflict {
+            return None;
+        }
+
+        let lock = Lock {
+            token: new_token(),
+            path: path.to_owned(),
+            scope,
+            infinite,
+            owner,
+            timeout,
+            expires: Instant::now() + timeout,
+        };
+        locks.push(lock.clone());
+        Some(lock)
+    }
+
+    // Extends the lock on `path` whose token was submitted.
+    pub fn refresh(&self, path: &str, tokens: &[String], timeout: Duration) -> Option<Lock> {
+        let mut locks = self.active();
+        let lock = locks.iter_mut().find(|l| l.covers(path) && tokens.contains(&l.token))?;
+        lock.timeout = timeout;
+        lock.expires = Instant::now() + timeout;
+        Some(lock.clone())
+    }
+
+    pub fn unlock(&self, path: &str, token: &str) -> bool {
+        let mut locks = self.active();
+        let before = locks.len();
+        locks.retain(|l| !(l.token == token && l.covers(path)));
+        locks.len() != before
+    }
+
+    // Locks that apply to `path`, for `lockdiscovery`.
+    pub fn discover(&self, path: &str) -> Vec<Lock> {
+        self.active().iter().filter(|l| l.covers(path)).cloned().collect()
+    }
+
+    // Whether `path` may be changed by a request that submitted `tokens`.
+    // With `descendants` locks below a collection count too, as they do
+    // for DELETE and MOVE. An exclusive lock needs its own token, a shared
+    // one the token of any shared lock on the resource.
+    pub fn permits(&self, path: &str, tokens: &[String], descendants: bool) -> bool {
+        let locks = self.active();
+        let affecting: Vec<&Lock> = locks.iter()
+            .filter(|l| l.covers(path) || (descendants && path.ends_with('/') && l.path.starts_with(path)))
+            .collect();
+
+        let shared_token = affecting.iter().any(|l| l.scope == LockScope::Shared && tokens.contains(&l.token));
+        affecting.iter().all(|l| tokens.contains(&l.token) || (l.scope == LockScope::Shared && shared_token))
+    }
+
+    // Drops the locks on `path` and below, the resources are gone.
+    pub fn remove(&self, path: &str) {
+        self.active().retain(|l| !(l.path == path || (path.ends_with('/') && l.path.starts_with(path))));
+    }
+
+    fn active(&self) -> std::sync::MutexGuard<'_, Vec<Lock>> {
+        let mut locks = self.locks.lock().unwrap();
+        let now = Instant::now();
+        locks.retain(|l| l.expires > now);
+        locks
+    }
+}
+
+// Lock tokens of an `If` header. The state lists are not evaluated, a
+// token anywhere in the header counts as submitted.
+pub fn submitted_tokens(header: Option<&str>) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut rest = header.unwrap_or("");
+    while let Some(start) = rest.find('<') {
+        let end = match rest[start..].find('>') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let token = &rest[start + 1..end];
+        if token.starts_with("opaquelocktoken:") {
+            tokens.push(token.to_owned());
+        }
+        rest = &rest[end + 1..];
+    }
+    tokens
+}
+
+// The scope and owner of a `lockinfo` body. Only write locks exist.
+pub fn parse_lockinfo(body: &str) -> Option<(LockScope, Option<String>)> {
+    let doc = Document::parse(body).ok()?;
+    let root = dav_element(doc.root_element(), "lockinfo")?;
+
+    let mut scope = None;
+    let mut write = false;
+    let mut owner = None;
+    for child in root.children().filter(|n| n.is_element()) {
+        let first = child.children().find(|n| n.is_element());
+        if dav_element(child, "lockscope").is_some() {
+            scope = match first.map(|n| (dav_element(n, "exclusive").is_some(), dav_element(n, "shared").is_some())) {
+                Some((true, _)) => Some(LockScope::Exclusive),
+                Some((_, true)) => Some(LockScope::Shared),
+                _ => return None,
+            };
+        } else if dav_element(child, "locktype").is_some() {
+            write = first.is_some_and(|n| dav_element(n, "write").is_some());
+        } else if dav_element(child, "owner").is_some() {
+            owner = Some(text(child).trim().to_owned()).filter(|o| !o.is_empty());
+        }
+    }
+
+    match scope {
+        Some(scope) if write => Some((scope, owner)),
+        _ => None,
+    }
+}
+
+// `Timeout: Second-N` or `Infinite`, the first usable value wins and is
+// capped at MAX_LOCK_TIMEOUT.
+pub fn parse_timeout(header: Option<&str>) -> Duration {
+    let secs = header.unwrap_or("")
+        .split(',')
+        .map(|t| t.trim())
+        .find_map(|t| match t {
+            "Infinite" => Some(MAX_LOCK_TIMEOUT),
+            t => t.strip_prefix("Second-").and_then(|s| s.parse().ok()),
+        })
+        .unwrap_or(DEFAULT_LOCK_TIMEOUT);
+
+    Duration::from_secs(secs.clamp(1, MAX_LOCK_TIMEOUT))
+}
+
+// `opaquelocktoken:` with a random UUID.
+fn new_token() -> String {
+    let mut bytes = [0; 16];
+    let random = File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut bytes));
+    if random.is_err() {
+        // Unique within the process, which is as long as locks live.
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+        let counter = TOKEN_COUNTER.fetch_add(1, Ordering::SeqCst) as u128;
+        bytes = (nanos ^ (counter << 64)).to_be_bytes();
+    }
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("opaquelocktoken:{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
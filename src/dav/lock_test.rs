@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+use super::lock::*;
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+#[test]
+fn exclusive_and_shared_conflicts() {
+    let table = LockTable::default();
+    let first = table.lock("/docs/", LockScope::Shared, true, None, MINUTE).unwrap();
+    assert!(first.token.starts_with("opaquelocktoken:"));
+    assert_eq!(first.token.len(), "opaquelocktoken:".len() + 36);
+
+    let second = table.lock("/docs/", LockScope::Shared, false, None, MINUTE).unwrap();
+    assert_ne!(first.token, second.token);
+    // Depth infinity covers the members.
+    assert!(table.lock("/docs/a.txt", LockScope::Exclusive, false, None, MINUTE).is_none());
+    // And a lock on a member blocks an infinite lock above it.
+    assert!(table.lock("/other/a.txt", LockScope::Exclusive, false, None, MINUTE).is_some());
+    assert!(table.lock("/other/", LockScope::Shared, true, None, MINUTE).is_none());
+    assert!(table.lock("/other/", LockScope::Shared, false, None, MINUTE).is_some());
+}
+
+#[test]
+fn permits_with_tokens() {
+    let table = LockTable::default();
+    let lock = table.lock("/docs/", LockScope::Exclusive, true, Some(String::from("ci")), MINUTE).unwrap();
+    let tokens = vec![lock.token.clone()];
+
+    assert!(!table.permits("/docs/a.txt", &[], false));
+    assert!(table.permits("/docs/a.txt", &tokens, false));
+    assert!(table.permits("/other.txt", &[], false));
+    // A lock below a collection only counts with descendants.
+    assert!(table.permits("/", &[], false));
+    assert!(!table.permits("/", &[], true));
+
+    assert_eq!(table.discover("/docs/a.txt").len(), 1);
+    assert_eq!(table.discover("/docs/a.txt")[0].owner.as_deref(), Some("ci"));
+    assert!(!table.unlock("/docs/", "opaquelocktoken:other"));
+    assert!(table.unlock("/docs/", &lock.token));
+    assert!(table.permits("/docs/a.txt", &[], false));
+}
+
+#[test]
+fn refresh_and_expiry() {
+    let table = LockTable::default();
+    let lock = table.lock("/a.txt", LockScope::Exclusive, false, None, Duration::from_millis(20)).unwrap();
+
+    assert!(table.refresh("/a.txt", &[String::from("opaquelocktoken:other")], MINUTE).is_none());
+    let refreshed = table.refresh("/a.txt", std::slice::from_ref(&lock.token), Duration::from_millis(20)).unwrap();
+    assert_eq!(refreshed.token, lock.token);
+
+    std::thread::sleep(Duration::from_millis(40));
+    assert!(table.discover("/a.txt").is_empty());
+    assert!(table.permits("/a.txt", &[], false));
+}
+
+#[test]
+fn remove_drops_locks_below() {
+    let table = LockTable::default();
+    table.lock("/docs/a.txt", LockScope::Exclusive, false, None, MINUTE).unwrap();
+    table.lock("/docs2.txt", LockScope::Exclusive, false, None, MINUTE).unwrap();
+
+    table.remove("/docs/");
+    assert!(table.discover("/docs/a.txt").is_empty());
+    assert_eq!(table.discover("/docs2.txt").len(), 1);
+}
+
+#[test]
+fn if_header_tokens() {
+    let header = "</docs/a.txt> (<opaquelocktoken:1234> [\"etag\"]) (Not <DAV:no-lock>)";
+    assert_eq!(submitted_tokens(Some(header)), vec!["opaquelocktoken:1234"]);
+    assert!(submitted_tokens(None).is_empty());
+    assert!(submitted_tokens(Some("(<opaquelocktoken:1234")).is_empty());
+}
+
+#[test]
+fn timeouts() {
+    assert_eq!(parse_timeout(None), Duration::from_secs(DEFAULT_LOCK_TIMEOUT));
+    assert_eq!(parse_timeout(Some("Second-30")), Duration::from_secs(30));
+    assert_eq!(parse_timeout(Some("Infinite, Second-30")), Duration::from_secs(MAX_LOCK_TIMEOUT));
+    assert_eq!(parse_timeout(Some("Second-999999")), Duration::from_secs(MAX_LOCK_TIMEOUT));
+    assert_eq!(parse_timeout(Some("Minute-5, Second-5")), Duration::from_secs(5));
+}
+
+#[test]
+fn lockinfo() {
+    let body = "<?xml version=\"1.0\"?><D:lockinfo xmlns:D=\"DAV:\"><D:lockscope><D:shared/></D:lockscope>\
+                <D:locktype><D:write/></D:locktype><D:owner><D:href>mailto:ci@example.com</D:href></D:owner></D:lockinfo>";
+    assert_eq!(parse_lockinfo(body), Some((LockScope::Shared, Some(String::from("mailto:ci@example.com")))));
+
+    let body = "<lockinfo xmlns=\"DAV:\"><lockscope><exclusive/></lockscope><locktype><write/></locktype></lockinfo>";
+    assert_eq!(parse_lockinfo(body), Some((LockScope::Exclusive, None)));
+
+    assert!(parse_lockinfo("<lockinfo xmlns=\"DAV:\"><lockscope><exclusive/></lockscope></lockinfo>").is_none());
+    assert!(parse_lockinfo("<lockinfo xmlns=\"DAV:\"><lockscope><other/></lockscope><locktype><write/></locktype></lockinfo>").is_none());
+    assert!(parse_lockinfo("<lockinfo><lockscope><exclusive/></lockscope><locktype><write/></locktype></lockinfo>").is_none());
+    assert!(parse_lockinfo("<lockinfo").is_none());
+}
@@ -0,0 +1,10 @@
+pub mod dav;
+pub mod lock;
+pub mod props;
+
+#[cfg(test)]
+mod dav_test;
+#[cfg(test)]
+mod lock_test;
+#[cfg(test)]
+mod props_test;
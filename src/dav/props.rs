@@ -0,0 +1,375 @@
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{TimeZone, UTC};
+use roxmltree::{Document, Node};
+
+use crate::dav::lock::{Lock, LockScope, LockTable};
+use crate::mime::mime;
+use crate::http::status::StatusCode;
+
+pub const DAV_NS: &str = "DAV:";
+
+// Live properties in the order `allprop` and `propname` list them.
+const LIVE: [&str; 8] = [
+    "resourcetype",
+    "displayname",
+    "getcontentlength",
+    "getcontenttype",
+    "getlastmodified",
+    "creationdate",
+    "supportedlock",
+    "lockdiscovery",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropName {
+    pub namespace: String,
+    pub name: String,
+}
+
+impl PropName {
+    pub fn dav(name: &str) -> PropName {
+        PropName {
+            namespace: String::from(DAV_NS),
+            name: name.to_owned(),
+        }
+    }
+
+    fn of(node: Node) -> PropName {
+        PropName {
+            namespace: node.tag_name().namespace().unwrap_or("").to_owned(),
+            name: node.tag_name().name().to_owned(),
+        }
+    }
+}
+
+// A dead property, one that is stored rather than computed. Values are
+// kept as text, markup inside them is not preserved.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Property {
+    pub name: PropName,
+    pub value: String,
+}
+
+// Dead properties by resource path, in memory like the locks.
+#[derive(Debug, Default)]
+pub struct PropertyStore {
+    props: Mutex<HashMap<String, Vec<Property>>>,
+}
+
+impl PropertyStore {
+    pub fn get(&self, path: &str) -> Vec<Property> {
+        self.props.lock().unwrap().get(path).cloned().unwrap_or_default()
+    }
+
+    pub fn apply(&self, path: &str, updates: Vec<Update>) {
+        let mut props = self.props.lock().unwrap();
+        let stored = props.entry(path.to_owned()).or_default();
+        for update in updates {
+            match update {
+                Update::Set(prop) => {
+                    stored.retain(|p| p.name != prop.name);
+                    stored.push(prop);
+                },
+                Update::Remove(name) => stored.retain(|p| p.name != name),
+            }
+        }
+        if stored.is_empty() {
+            props.remove(path);
+        }
+    }
+
+    // Forgets the properties of `path` and everything below it.
+    pub fn remove(&self, path: &str) {
+        self.props.lock().unwrap().retain(|p, _| !is_within(p, path));
+    }
+
+    // Properties follow a MOVE and are duplicated by a COPY.
+    pub fn transfer(&self, from: &str, to: &str, keep_source: bool) {
+        let mut props = self.props.lock().unwrap();
+        props.retain(|p, _| !is_within(p, to));
+
+        let moved: Vec<(String, Vec<Property>)> = props.iter()
+            .filter(|(p, _)| is_within(p, from))
+            .map(|(p, v)| (format!("{}{}", to, &p[from.len()..]), v.clone()))
+            .collect();
+        if !keep_source {
+            props.retain(|p, _| !is_within(p, from));
+        }
+        props.extend(moved);
+    }
+}
+
+// Whether `path` is `base` or, for a collection, below it.
+pub fn is_within(path: &str, base: &str) -> bool {
+    path == base || (base.ends_with('/') && path.starts_with(base))
+}
+
+// What a PROPFIND asks for.
+#[derive(Debug, PartialEq)]
+pub enum PropFind {
+    AllProp,
+    PropName,
+    Prop(Vec<PropName>),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Update {
+    Set(Property),
+    Remove(PropName),
+}
+
+impl Update {
+    pub fn name(&self) -> &PropName {
+        match self {
+            Update::Set(prop) => &prop.name,
+            Update::Remove(name) => name,
+        }
+    }
+}
+
+// A resource listed in a multistatus response.
+pub struct Resource {
+    pub path: String,
+    pub meta: Metadata,
+}
+
+// An empty body asks for all properties.
+pub fn parse_propfind(body: &str) -> Option<PropFind> {
+    if body.trim().is_empty() {
+        return Some(PropFind::AllProp);
+    }
+
+    let doc = Document::parse(body).ok()?;
+    let root = dav_element(doc.root_element(), "propfind")?;
+    for child in root.children().filter(|n| n.is_element()) {
+        if child.tag_name().namespace() != Some(DAV_NS) {
+            continue;
+        }
+        match child.tag_name().name() {
+            "allprop" => return Some(PropFind::AllProp),
+            "propname" => return Some(PropFind::PropName),
+            "prop" => return Some(PropFind::Prop(child.children().filter(|n| n.is_element()).map(PropName::of).collect())),
+            _ => {},
+        }
+    }
+
+    None
+}
+
+// The `set` and `remove` instructions of a PROPPATCH, in document order.
+pub fn parse_propertyupdate(body: &str) -> Option<Vec<Update>> {
+    let doc = Document::parse(body).ok()?;
+    let root = dav_element(doc.root_element(), "propertyupdate")?;
+
+    let mut updates = Vec::new();
+    for instruction in root.children().filter(|n| n.is_element()) {
+        let set = match (dav_element(instruction, "set"), dav_element(instruction, "remove")) {
+            (Some(_), _) => true,
+            (_, Some(_)) => false,
+            _ => continue,
+        };
+        let props = instruction.children().filter(|n| n.is_element()).filter(|n| dav_element(*n, "prop").is_some());
+        for prop in props.flat_map(|p| p.children().filter(|n| n.is_element())) {
+            updates.push(match set {
+                true => Update::Set(Property {
+                    name: PropName::of(prop),
+                    value: text(prop),
+                }),
+                false => Update::Remove(PropName::of(prop)),
+            });
+        }
+    }
+
+    Some(updates).filter(|u| !u.is_empty())
+}
+
+// `node` if it is the DAV: element `name`.
+pub fn dav_element<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    Some(node).filter(|n| n.tag_name().namespace() == Some(DAV_NS) && n.tag_name().name() == name)
+}
+
+// All text below `node`.
+pub fn text(node: Node) -> String {
+    node.descendants().filter(|n| n.is_text()).filter_map(|n| n.text()).collect()
+}
+
+// The multistatus body of a PROPFIND over `resources`.
+pub fn render_propfind(find: &PropFind, resources: &[Resource], store: &PropertyStore, locks: &LockTable) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n");
+    for resource in resources {
+        let dead = store.get(&resource.path);
+        let mut found = String::new();
+        let mut missing = String::new();
+
+        match find {
+            PropFind::PropName => {
+                for name in LIVE.iter().filter(|n| live(n, resource, locks).is_some()) {
+                    found.push_str(&element(&PropName::dav(name), None));
+                }
+                for prop in &dead {
+                    found.push_str(&element(&prop.name, None));
+                }
+            },
+            PropFind::AllProp => {
+                for name in LIVE.iter() {
+                    if let Some(value) = live(name, resource, locks) {
+                        found.push_str(&element(&PropName::dav(name), Some(&value)));
+                    }
+                }
+                for prop in &dead {
+                    found.push_str(&element(&prop.name, Some(&escape(&prop.value))));
+                }
+            },
+            PropFind::Prop(names) => {
+                for name in names {
+                    let value = match name.namespace == DAV_NS {
+                        true => live(&name.name, resource, locks),
+                        false => None,
+                    }.or_else(|| dead.iter().find(|p| p.name == *name).map(|p| escape(&p.value)));
+                    match value {
+                        Some(value) => found.push_str(&element(name, Some(&value))),
+                        None => missing.push_str(&element(name, None)),
+                    }
+                }
+            },
+        }
+
+        xml.push_str(&format!("<D:response><D:href>{}</D:href>", escape(&encode_href(&resource.path))));
+        if !found.is_empty() || missing.is_empty() {
+            xml.push_str(&propstat(&found, StatusCode::OK));
+        }
+        if !missing.is_empty() {
+            xml.push_str(&propstat(&missing, StatusCode::NOT_FOUND));
+        }
+        xml.push_str("</D:response>\n");
+    }
+
+    xml.push_str("</D:multistatus>\n");
+    xml
+}
+
+// The multistatus body of a PROPPATCH, every property gets `status`.
+pub fn render_proppatch(path: &str, results: &[(PropName, StatusCode)]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n");
+    xml.push_str(&format!("<D:response><D:href>{}</D:href>", escape(&encode_href(path))));
+    for (name, status) in results {
+        xml.push_str(&propstat(&element(name, None), *status));
+    }
+    xml.push_str("</D:response>\n</D:multistatus>\n");
+    xml
+}
+
+// The `lockdiscovery` content for `locks`.
+pub fn render_locks(locks: &[Lock]) -> String {
+    locks.iter()
+        .map(|lock| {
+            let scope = match lock.scope {
+                LockScope::Exclusive => "<D:exclusive/>",
+                LockScope::Shared => "<D:shared/>",
+            };
+            let owner = match lock.owner {
+                Some(ref owner) => format!("<D:owner>{}</D:owner>", escape(owner)),
+                None => String::new(),
+            };
+            format!(
+                "<D:activelock><D:locktype><D:write/></D:locktype><D:lockscope>{}</D:lockscope><D:depth>{}</D:depth>{}<D:timeout>Second-{}</D:timeout><D:locktoken><D:href>{}</D:href></D:locktoken><D:lockroot><D:href>{}</D:href></D:lockroot></D:activelock>",
+                scope,
+                if lock.infinite { "infinity" } else { "0" },
+                owner,
+                lock.timeout.as_secs(),
+                escape(&lock.token),
+                escape(&encode_href(&lock.path)),
+            )
+        })
+        .collect()
+}
+
+// The body of a LOCK response.
+pub fn render_lock(lock: &Lock) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:prop xmlns:D=\"DAV:\"><D:lockdiscovery>{}</D:lockdiscovery></D:prop>\n",
+        render_locks(std::slice::from_ref(lock)),
+    )
+}
+
+// The body of an error with the failed precondition `condition`.
+pub fn render_error(condition: &str) -> String {
+    format!("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:error xmlns:D=\"DAV:\"><D:{}/></D:error>\n", condition)
+}
+
+fn propstat(props: &str, status: StatusCode) -> String {
+    format!("<D:propstat><D:prop>{}</D:prop><D:status>HTTP/1.1 {}</D:status></D:propstat>", props, status)
+}
+
+// A property element, with its namespace declared on it unless it is DAV:.
+fn element(name: &PropName, value: Option<&str>) -> String {
+    let (tag, declaration) = match name.namespace.as_str() {
+        DAV_NS => (format!("D:{}", name.name), String::new()),
+        "" => (name.name.clone(), String::from(" xmlns=\"\"")),
+        ns => (format!("P:{}", name.name), format!(" xmlns:P=\"{}\"", escape(ns))),
+    };
+
+    match value {
+        Some(value) if !value.is_empty() => format!("<{}{}>{}</{}>", tag, declaration, value, tag),
+        _ => format!("<{}{}/>", tag, declaration),
+    }
+}
+
+// The value of a live property as XML content, None where it does not
+// apply to the resource.
+fn live(name: &str, resource: &Resource, locks: &LockTable) -> Option<String> {
+    let meta = &resource.meta;
+    match name {
+        "resourcetype" if meta.is_dir() => Some(String::from("<D:collection/>")),
+        "resourcetype" => Some(String::new()),
+        "displayname" => {
+            let name = resource.path.trim_end_matches('/').rsplit('/').next().unwrap_or("");
+            Some(escape(name))
+        },
+        "getcontentlength" if !meta.is_dir() => Some(meta.len().to_string()),
+        "getcontenttype" if !meta.is_dir() => Some(escape(&mime::guess(&resource.path))),
+        "getlastmodified" => meta.modified().ok().map(|t| format_time(t, "%a, %d %b %Y %H:%M:%S GMT")),
+        "creationdate" => meta.created().or_else(|_| meta.modified()).ok().map(|t| format_time(t, "%Y-%m-%dT%H:%M:%SZ")),
+        "supportedlock" => Some(String::from(
+            "<D:lockentry><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockentry>\
+             <D:lockentry><D:lockscope><D:shared/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockentry>",
+        )),
+        "lockdiscovery" => Some(render_locks(&locks.discover(&resource.path))),
+        _ => None,
+    }
+}
+
+fn format_time(time: SystemTime, format: &str) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    UTC.timestamp(secs as i64, 0).format(format).to_string()
+}
+
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Percent-encodes everything in a path but unreserved characters and `/`.
+pub fn encode_href(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
@@ -0,0 +1,121 @@
+use std::fs;
+
+use super::lock::{LockScope, LockTable};
+use super::props::*;
+
+fn name(namespace: &str, name: &str) -> PropName {
+    PropName {
+        namespace: namespace.to_owned(),
+        name: name.to_owned(),
+    }
+}
+
+fn set(namespace: &str, prop: &str, value: &str) -> Update {
+    Update::Set(Property {
+        name: name(namespace, prop),
+        value: value.to_owned(),
+    })
+}
+
+#[test]
+fn propfind_bodies() {
+    assert_eq!(parse_propfind(""), Some(PropFind::AllProp));
+    assert_eq!(parse_propfind("<D:propfind xmlns:D=\"DAV:\"><D:allprop/></D:propfind>"), Some(PropFind::AllProp));
+    assert_eq!(parse_propfind("<propfind xmlns=\"DAV:\"><propname/></propfind>"), Some(PropFind::PropName));
+
+    let body = "<D:propfind xmlns:D=\"DAV:\" xmlns:Z=\"urn:z\"><D:prop><D:getcontentlength/><Z:color/></D:prop></D:propfind>";
+    assert_eq!(parse_propfind(body), Some(PropFind::Prop(vec![PropName::dav("getcontentlength"), name("urn:z", "color")])));
+
+    assert!(parse_propfind("<propfind><prop/></propfind>").is_none());
+    assert!(parse_propfind("<D:propfind xmlns:D=\"DAV:\">").is_none());
+    // No DTDs, so no entity expansion.
+    assert!(parse_propfind("<!DOCTYPE x [<!ENTITY a \"aaaa\">]><propfind xmlns=\"DAV:\"><allprop/></propfind>").is_none());
+}
+
+#[test]
+fn propertyupdate_bodies() {
+    let body = "<D:propertyupdate xmlns:D=\"DAV:\" xmlns:Z=\"urn:z\">\
+                <D:set><D:prop><Z:color>red &amp; <b>blue</b></Z:color></D:prop></D:set>\
+                <D:remove><D:prop><Z:size/></D:prop></D:remove></D:propertyupdate>";
+    assert_eq!(parse_propertyupdate(body), Some(vec![set("urn:z", "color", "red & blue"), Update::Remove(name("urn:z", "size"))]));
+
+    assert!(parse_propertyupdate("<D:propertyupdate xmlns:D=\"DAV:\"/>").is_none());
+    assert!(parse_propertyupdate("").is_none());
+}
+
+#[test]
+fn store_moves_with_resources() {
+    let store = PropertyStore::default();
+    store.apply("/docs/", vec![set("urn:z", "color", "red")]);
+    store.apply("/docs/a.txt", vec![set("urn:z", "color", "blue"), set("urn:z", "size", "1")]);
+    store.apply("/docs/a.txt", vec![set("urn:z", "color", "green"), Update::Remove(name("urn:z", "size"))]);
+    assert_eq!(store.get("/docs/a.txt"), vec![Property {
+        name: name("urn:z", "color"),
+        value: String::from("green"),
+    }]);
+
+    store.transfer("/docs/", "/copy/", true);
+    assert_eq!(store.get("/copy/a.txt").len(), 1);
+    assert_eq!(store.get("/docs/a.txt").len(), 1);
+
+    store.transfer("/docs/", "/moved/", false);
+    assert!(store.get("/docs/").is_empty());
+    assert_eq!(store.get("/moved/").len(), 1);
+
+    store.remove("/moved/");
+    assert!(store.get("/moved/a.txt").is_empty());
+    assert_eq!(store.get("/copy/").len(), 1);
+}
+
+#[test]
+fn multistatus_rendering() {
+    let dir = std::env::temp_dir().join(format!("dz1-props-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a b.html"), "hello").unwrap();
+
+    let store = PropertyStore::default();
+    store.apply("/a b.html", vec![set("urn:z", "color", "<red>")]);
+    let locks = LockTable::default();
+    locks.lock("/a b.html", LockScope::Exclusive, false, Some(String::from("ci")), std::time::Duration::from_secs(60)).unwrap();
+
+    let resources = vec![
+        Resource {
+            path: String::from("/"),
+            meta: fs::metadata(&dir).unwrap(),
+        },
+        Resource {
+            path: String::from("/a b.html"),
+            meta: fs::metadata(dir.join("a b.html")).unwrap(),
+        },
+    ];
+
+    let xml = render_propfind(&PropFind::AllProp, &resources, &store, &locks);
+    assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">"));
+    assert!(xml.contains("<D:href>/</D:href>"));
+    assert!(xml.contains("<D:resourcetype><D:collection/></D:resourcetype>"));
+    assert!(xml.contains("<D:href>/a%20b.html</D:href>"));
+    assert!(xml.contains("<D:getcontentlength>5</D:getcontentlength>"));
+    assert!(xml.contains("<D:getcontenttype>text/html; charset=utf-8</D:getcontenttype>"));
+    assert!(xml.contains("<D:displayname>a b.html</D:displayname>"));
+    assert!(xml.contains("<P:color xmlns:P=\"urn:z\">&lt;red&gt;</P:color>"));
+    assert!(xml.contains("<D:owner>ci</D:owner>"));
+    assert!(xml.contains(" GMT</D:getlastmodified>"));
+    // The XML is well-formed.
+    assert!(roxmltree::Document::parse(&xml).is_ok());
+
+    let find = PropFind::Prop(vec![PropName::dav("getcontentlength"), name("urn:z", "color")]);
+    let xml = render_propfind(&find, &resources[..1], &store, &locks);
+    assert!(xml.contains("<D:prop><D:getcontentlength/><P:color xmlns:P=\"urn:z\"/></D:prop><D:status>HTTP/1.1 404 Not Found</D:status>"));
+    assert!(!xml.contains("200 OK"));
+
+    let xml = render_propfind(&PropFind::PropName, &resources[1..], &store, &locks);
+    assert!(xml.contains("<D:getcontentlength/>"));
+    assert!(xml.contains("<P:color xmlns:P=\"urn:z\"/>"));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn escaping() {
+    assert_eq!(escape("a<b>&\"c\""), "a&lt;b&gt;&amp;&quot;c&quot;");
+    assert_eq!(encode_href("/dir/ä b?.txt"), "/dir/%C3%A4%20b%3F.txt");
+}
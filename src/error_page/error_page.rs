@@ -0,0 +1,81 @@
+use crate::http::status::StatusCode;
+
+pub const ERROR_PAGE_INVALID_FORMAT: &str = "Invalid error_page format";
+
+// An `error_page <code>... [=<status>] <uri>` directive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorPage {
+    pub codes: Vec<StatusCode>,
+    // Sent instead of the original status.
+    pub status: Option<StatusCode>,
+    // A path served by an internal request, or an absolute URL the client
+    // is redirected to.
+    pub uri: String,
+}
+
+impl ErrorPage {
+    pub fn is_redirect(&self) -> bool {
+        self.uri.starts_with("http://") || self.uri.starts_with("https://")
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ErrorPages {
+    pages: Vec<ErrorPage>,
+}
+
+impl ErrorPages {
+    // Accepts an `error_page` directive, returns false for any other one.
+    pub fn parse_directive(&mut self, tokens: &[String]) -> Result<bool, String> {
+        if tokens[0] != "error_page" {
+            return Ok(false);
+        }
+
+        let invalid = || format!("{}: {}", ERROR_PAGE_INVALID_FORMAT, tokens[1..].join(" "));
+        let (uri, args) = match tokens[1..].split_last() {
+            Some((uri, args)) if !args.is_empty() => (uri, args),
+            _ => return Err(invalid()),
+        };
+        let (status, codes) = match args.split_last() {
+            Some((last, codes)) if last.starts_with('=') => (Some(parse_code(&last[1..], 200).ok_or_else(invalid)?), codes),
+            _ => (None, args),
+        };
+        let codes = codes.iter()
+            .map(|code| parse_code(code, 300).ok_or_else(invalid))
+            .collect::<Result<Vec<_>, _>>()?;
+        if codes.is_empty() || !(uri.starts_with('/') || uri.starts_with("http://") || uri.starts_with("https://")) {
+            return Err(invalid());
+        }
+
+        self.pages.push(ErrorPage {
+            codes,
+            status,
+            uri: uri.clone(),
+        });
+        Ok(true)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    // The first page configured for `status`.
+    pub fn find(&self, status: StatusCode) -> Option<&ErrorPage> {
+        self.pages.iter().find(|page| page.codes.contains(&status))
+    }
+}
+
+fn parse_code(raw: &str, min: u16) -> Option<StatusCode> {
+    match raw.parse::<u16>() {
+        Ok(code) if raw.len() == 3 && (min..600).contains(&code) => StatusCode::from_u16(code),
+        _ => None,
+    }
+}
+
+// The body of an error nothing else was configured for.
+pub fn default_page(status: StatusCode) -> String {
+    format!(
+        "<html>\r\n<head><title>{status}</title></head>\r\n<body>\r\n<center><h1>{status}</h1></center>\r\n<hr><center>dz1</center>\r\n</body>\r\n</html>\r\n",
+        status = status,
+    )
+}
@@ -0,0 +1,46 @@
+use super::error_page::*;
+use crate::config::config_test::{self, parse_err};
+use crate::http::status::StatusCode;
+
+fn parse(line: &str) -> ErrorPages {
+    config_test::directives(&[line], ErrorPages::parse_directive)
+}
+
+#[test]
+fn directives() {
+    let pages = parse("error_page 500 502 /50x.html");
+    assert_eq!(pages.find(StatusCode::BAD_GATEWAY), Some(&ErrorPage {
+        codes: vec![StatusCode::INTERNAL_SERVER_ERROR, StatusCode::BAD_GATEWAY],
+        status: None,
+        uri: String::from("/50x.html"),
+    }));
+    assert!(pages.find(StatusCode::NOT_FOUND).is_none());
+
+    let pages = parse("error_page 404 =200 /empty.gif");
+    assert_eq!(pages.find(StatusCode::NOT_FOUND).unwrap().status, Some(StatusCode::OK));
+
+    let pages = parse("error_page 403 http://example.com/forbidden");
+    assert!(pages.find(StatusCode::FORBIDDEN).unwrap().is_redirect());
+
+    let mut pages = ErrorPages::default();
+    assert_eq!(pages.parse_directive(&[String::from("allow"), String::from("all")]), Ok(false));
+    assert!(pages.is_empty());
+}
+
+#[test]
+fn invalid_directives() {
+    assert!(parse_err("error_page /404.html", ErrorPages::parse_directive).starts_with(ERROR_PAGE_INVALID_FORMAT));
+    assert!(parse_err("error_page 404", ErrorPages::parse_directive).starts_with(ERROR_PAGE_INVALID_FORMAT));
+    assert!(parse_err("error_page 200 /ok.html", ErrorPages::parse_directive).starts_with(ERROR_PAGE_INVALID_FORMAT));
+    assert!(parse_err("error_page 4O4 /404.html", ErrorPages::parse_directive).starts_with(ERROR_PAGE_INVALID_FORMAT));
+    assert!(parse_err("error_page 404 =2000 /404.html", ErrorPages::parse_directive).starts_with(ERROR_PAGE_INVALID_FORMAT));
+    assert!(parse_err("error_page =200 /404.html", ErrorPages::parse_directive).starts_with(ERROR_PAGE_INVALID_FORMAT));
+    assert!(parse_err("error_page 404 404.html", ErrorPages::parse_directive).starts_with(ERROR_PAGE_INVALID_FORMAT));
+}
+
+#[test]
+fn built_in_page() {
+    let page = default_page(StatusCode::NOT_FOUND);
+    assert!(page.contains("<title>404 Not Found</title>"));
+    assert!(page.contains("<h1>404 Not Found</h1>"));
+}
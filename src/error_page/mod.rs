@@ -0,0 +1,3 @@
+pub mod error_page;
+#[cfg(test)]
+mod error_page_test;
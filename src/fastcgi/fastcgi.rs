@@ -0,0 +1,488 @@
+use std::io;
+use std::io::prelude::*;
+use std::io::Cursor;
+use std::iter;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::cgi::cgi::{error_response, find_head_end, meta_variables, parse_response};
+use crate::config::listen::ListenAddress;
+use crate::http::reader::read_body;
+use crate::http::request::HTTPRequest;
+use crate::http::response::HTTPResponse;
+use crate::http::status::StatusCode;
+use crate::server::stream::{Client, Socket};
+
+pub const FASTCGI_INVALID_FORMAT: &str = "Invalid fastcgi format";
+
+const DEFAULT_CONNECT_TIMEOUT: u64 = 60;
+const DEFAULT_READ_TIMEOUT: u64 = 60;
+const UNIX_PREFIX: &str = "unix:";
+const MAX_HEADER_SIZE: usize = 16 * 1024;
+// Idle connections kept per location.
+const MAX_IDLE: usize = 32;
+
+// Record types of the FastCGI specification, section 8.
+pub const FCGI_BEGIN_REQUEST: u8 = 1;
+pub const FCGI_END_REQUEST: u8 = 3;
+pub const FCGI_PARAMS: u8 = 4;
+pub const FCGI_STDIN: u8 = 5;
+pub const FCGI_STDOUT: u8 = 6;
+pub const FCGI_STDERR: u8 = 7;
+
+pub const FCGI_RESPONDER: u16 = 1;
+pub const FCGI_KEEP_CONN: u8 = 1;
+pub const FCGI_REQUEST_COMPLETE: u8 = 0;
+
+const FCGI_VERSION: u8 = 1;
+const HEADER_LEN: usize = 8;
+const MAX_CONTENT: usize = 65535;
+// Requests are not multiplexed, every request on a connection has this id.
+const REQUEST_ID: u16 = 1;
+
+// Settings of a location whose requests are answered by a FastCGI
+// application server.
+#[derive(Debug)]
+pub struct FastCgi {
+    pub prefix: String,
+    pub address: ListenAddress,
+    // `fastcgi_param` values, they replace meta-variables of the same name.
+    pub params: Vec<(String, String)>,
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration,
+    idle: Arc<Mutex<Vec<Socket>>>,
+}
+
+// Collects `fastcgi_pass`, `fastcgi_param` and the timeouts while a
+// location is parsed.
+#[derive(Default)]
+pub struct FastCgiBuilder {
+    pass: Option<String>,
+    params: Vec<(String, String)>,
+    connect_timeout: Option<String>,
+    read_timeout: Option<String>,
+}
+
+impl FastCgiBuilder {
+    // Returns false for directives that are not about FastCGI.
+    pub fn parse_directive(&mut self, tokens: &[String]) -> Result<bool, String> {
+        if tokens[0] == "fastcgi_param" {
+            if tokens.len() != 3 {
+                return Err(String::from(FASTCGI_INVALID_FORMAT));
+            }
+            self.params.push((tokens[1].clone(), tokens[2].clone()));
+            return Ok(true);
+        }
+
+        let slot = match tokens[0].as_str() {
+            "fastcgi_pass" => &mut self.pass,
+            "fastcgi_connect_timeout" => &mut self.connect_timeout,
+            "fastcgi_read_timeout" => &mut self.read_timeout,
+            _ => return Ok(false),
+        };
+
+        if tokens.len() != 2 {
+            return Err(String::from(FASTCGI_INVALID_FORMAT));
+        }
+
+        *slot = Some(tokens[1].clone());
+        Ok(true)
+    }
+
+    pub fn build(self, prefix: &str) -> Result<Option<FastCgi>, String> {
+        let address = match self.pass {
+            Some(pass) => parse_address(&pass)?,
+            None if self.params.is_empty() => return Ok(None),
+            None => return Err(format!("{}: fastcgi_param without fastcgi_pass", FASTCGI_INVALID_FORMAT)),
+        };
+
+        Ok(Some(FastCgi {
+            prefix: prefix.to_owned(),
+            address,
+            params: self.params,
+            connect_timeout: parse_timeout(self.connect_timeout, DEFAULT_CONNECT_TIMEOUT)?,
+            read_timeout: parse_timeout(self.read_timeout, DEFAULT_READ_TIMEOUT)?,
+            idle: Arc::new(Mutex::new(Vec::new())),
+        }))
+    }
+}
+
+// `host:port` or `unix:/path/to/socket`.
+fn parse_address(pass: &str) -> Result<ListenAddress, String> {
+    match pass.strip_prefix(UNIX_PREFIX) {
+        Some(path) if !path.is_empty() => Ok(ListenAddress::Unix(path.to_owned())),
+        None if pass.contains(':') => Ok(ListenAddress::Tcp(pass.to_owned())),
+        _ => Err(format!("{}: {}", FASTCGI_INVALID_FORMAT, pass)),
+    }
+}
+
+fn parse_timeout(value: Option<String>, default: u64) -> Result<Duration, String> {
+    match value {
+        Some(value) => match value.parse() {
+            Ok(secs) => Ok(Duration::from_secs(secs)),
+            Err(_) => Err(format!("{}: {}", FASTCGI_INVALID_FORMAT, value)),
+        },
+        None => Ok(Duration::from_secs(default)),
+    }
+}
+
+// Why a request could not be answered.
+enum Failure {
+    // Nothing was read on a pooled connection, the application may have
+    // closed it while idle.
+    Stale,
+    Timeout,
+    Bad(String),
+}
+
+impl From<io::Error> for Failure {
+    fn from(err: io::Error) -> Failure {
+        match err.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Failure::Timeout,
+            _ => Failure::Bad(err.to_string()),
+        }
+    }
+}
+
+impl FastCgi {
+    // Passes the request to the application and streams its response.
+    pub fn execute(&self, req: &HTTPRequest, client: &Client, document_root: &str, body: &mut dyn Read) -> HTTPResponse {
+        // Kept in memory so that a request on a stale pooled connection can
+        // be sent again.
+        let input = match read_body(body) {
+            Ok(input) => input,
+            Err(status) => return error_response(status),
+        };
+
+        let params = self.params(req, client, document_root, input.len());
+        let mut failure = Failure::Stale;
+        // A pooled connection first, a new one if that turns out stale.
+        let conns = self.pooled().into_iter().map(|conn| Ok((conn, true)))
+            .chain(iter::once_with(|| self.connect().map(|conn| (conn, false))));
+
+        for conn in conns {
+            let (conn, pooled) = match conn {
+                Ok(conn) => conn,
+                Err(err) => {
+                    println!("Error while connect to FastCGI server {}: {}", self.address(), err);
+                    return error_response(StatusCode::BAD_GATEWAY);
+                }
+            };
+
+            match self.attempt(conn, &params, &input, pooled, req.method == "HEAD") {
+                Ok(resp) => return resp,
+                Err(Failure::Stale) => continue,
+                Err(err) => {
+                    failure = err;
+                    break;
+                }
+            }
+        }
+
+        match failure {
+            Failure::Timeout => {
+                println!("FastCGI server {} timed out", self.address());
+                error_response(StatusCode::GATEWAY_TIMEOUT)
+            },
+            Failure::Bad(err) => {
+                println!("Invalid response of FastCGI server {}: {}", self.address(), err);
+                error_response(StatusCode::BAD_GATEWAY)
+            },
+            Failure::Stale => error_response(StatusCode::BAD_GATEWAY),
+        }
+    }
+
+    // The CGI meta-variables with the ones FastCGI applications expect,
+    // overridden by `fastcgi_param`.
+    fn params(&self, req: &HTTPRequest, client: &Client, document_root: &str, content_length: usize) -> Vec<(String, String)> {
+        let mut params = meta_variables(req, client, &req.path, "", document_root, content_length);
+        params.push((String::from("SCRIPT_FILENAME"), format!("{}{}", document_root.trim_end_matches('/'), req.path)));
+        // PHP refuses to run without it when cgi.force_redirect is on.
+        params.push((String::from("REDIRECT_STATUS"), String::from("200")));
+
+        for (name, value) in &self.params {
+            params.retain(|(n, _)| n != name);
+            params.push((name.clone(), value.clone()));
+        }
+
+        params
+    }
+
+    fn attempt(&self, mut conn: Socket, params: &[(String, String)], input: &[u8], pooled: bool, is_head: bool) -> Result<HTTPResponse, Failure> {
+        // Only a connection the application closed while it sat in the pool
+        // is retried. A timeout may mean the request is being worked on and
+        // must not be sent twice.
+        let stale = |err: io::Error| match err.kind() {
+            io::ErrorKind::ConnectionReset | io::ErrorKind::BrokenPipe | io::ErrorKind::UnexpectedEof if pooled => Failure::Stale,
+            _ => Failure::from(err),
+        };
+
+        conn.set_read_timeout(Some(self.read_timeout))?;
+        conn.set_write_timeout(Some(self.read_timeout))?;
+        write_request(&mut conn, params, input).map_err(stale)?;
+
+        let mut output = FastCgiOutput {
+            conn: Some(conn),
+            idle: self.idle.clone(),
+            address: self.address(),
+            pending: Cursor::new(Vec::new()),
+            received: false,
+        };
+
+        let mut buffer = Vec::new();
+        let (end, start) = loop {
+            if let Some(end) = find_head_end(&buffer) {
+                break end;
+            }
+            if buffer.len() > MAX_HEADER_SIZE {
+                return Err(Failure::Bad(String::from("header block too large")));
+            }
+
+            match output.next_stdout() {
+                Ok(Some(data)) => buffer.extend_from_slice(&data),
+                Ok(None) => return Err(Failure::Bad(String::from("request ended before the header block"))),
+                Err(err) if !output.received => return Err(stale(err)),
+                Err(err) => return Err(Failure::from(err)),
+            }
+        };
+
+        let head = String::from_utf8(buffer[..end].to_vec())
+            .map_err(|_| Failure::Bad(String::from("header block is not utf-8")))?;
+        let mut resp = parse_response(&head).map_err(Failure::Bad)?;
+
+        if !is_head {
+            output.pending = Cursor::new(buffer.split_off(start));
+            resp.setStream(Box::new(output));
+        }
+
+        Ok(resp)
+    }
+
+    fn pooled(&self) -> Option<Socket> {
+        self.idle.lock().unwrap().pop()
+    }
+
+    fn connect(&self) -> io::Result<Socket> {
+        match self.address {
+            ListenAddress::Unix(ref path) => UnixStream::connect(path).map(Socket::Unix),
+            ListenAddress::Tcp(ref addr) => {
+                let mut last = io::Error::new(io::ErrorKind::NotFound, "fastcgi host did not resolve");
+                for addr in addr.to_socket_addrs()? {
+                    match TcpStream::connect_timeout(&addr, self.connect_timeout) {
+                        Ok(stream) => return Ok(Socket::Tcp(stream)),
+                        Err(err) => last = err,
+                    }
+                }
+                Err(last)
+            },
+        }
+    }
+
+    // `host:port` or `unix:/path` for logs.
+    pub fn address(&self) -> String {
+        match self.address {
+            ListenAddress::Tcp(ref addr) => addr.clone(),
+            ListenAddress::Unix(ref path) => format!("{}{}", UNIX_PREFIX, path),
+        }
+    }
+}
+
+fn write_request<W: Write>(conn: &mut W, params: &[(String, String)], input: &[u8]) -> io::Result<()> {
+    let mut begin = Vec::with_capacity(8);
+    begin.extend_from_slice(&FCGI_RESPONDER.to_be_bytes());
+    begin.push(FCGI_KEEP_CONN);
+    begin.extend_from_slice(&[0; 5]);
+
+    let mut buffer = Vec::new();
+    write_record(&mut buffer, FCGI_BEGIN_REQUEST, REQUEST_ID, &begin)?;
+    write_stream(&mut buffer, FCGI_PARAMS, REQUEST_ID, &encode_params(params))?;
+    write_stream(&mut buffer, FCGI_STDIN, REQUEST_ID, input)?;
+    conn.write_all(&buffer)?;
+    conn.flush()
+}
+
+// The output of a request. Its connection goes back to the pool once the
+// application ended the request.
+struct FastCgiOutput {
+    conn: Option<Socket>,
+    idle: Arc<Mutex<Vec<Socket>>>,
+    address: String,
+    // STDOUT data read but not yet returned.
+    pending: Cursor<Vec<u8>>,
+    // Whether any record arrived, a pooled connection that fails before
+    // that is stale.
+    received: bool,
+}
+
+impl FastCgiOutput {
+    // Content of the next STDOUT record, None once the request ended.
+    fn next_stdout(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            let conn = match self.conn {
+                Some(ref mut conn) => conn,
+                None => return Ok(None),
+            };
+
+            let record = read_record(conn)?;
+            self.received = true;
+            if record.id != REQUEST_ID {
+                return Err(invalid_data(format!("record for unknown request {}", record.id)));
+            }
+
+            match record.kind {
+                // An empty record only closes the stream.
+                FCGI_STDOUT if record.content.is_empty() => continue,
+                FCGI_STDOUT => return Ok(Some(record.content)),
+                FCGI_STDERR => {
+                    let message = String::from_utf8_lossy(&record.content);
+                    for line in message.lines().filter(|l| !l.is_empty()) {
+                        println!("FastCGI stderr from {}: {}", self.address, line);
+                    }
+                },
+                FCGI_END_REQUEST => return self.end(&record.content).map(|_| None),
+                kind => return Err(invalid_data(format!("unexpected record type {}", kind))),
+            }
+        }
+    }
+
+    fn end(&mut self, content: &[u8]) -> io::Result<()> {
+        if content.len() != 8 {
+            return Err(invalid_data(String::from("invalid end request record")));
+        }
+
+        let conn = self.conn.take();
+        match content[4] {
+            FCGI_REQUEST_COMPLETE => {
+                let mut idle = self.idle.lock().unwrap();
+                if idle.len() < MAX_IDLE {
+                    idle.extend(conn);
+                }
+                Ok(())
+            },
+            status => Err(invalid_data(format!("request rejected with protocol status {}", status))),
+        }
+    }
+}
+
+impl Read for FastCgiOutput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.pending.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+
+            match self.next_stdout()? {
+                Some(data) => self.pending = Cursor::new(data),
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// One record of the FastCGI protocol.
+#[derive(Debug, PartialEq)]
+pub struct Record {
+    pub kind: u8,
+    pub id: u16,
+    pub content: Vec<u8>,
+}
+
+// Writes a record padded to a multiple of 8 bytes, `content` must fit into
+// one record.
+pub fn write_record<W: Write>(w: &mut W, kind: u8, id: u16, content: &[u8]) -> io::Result<()> {
+    let padding = (8 - content.len() % 8) % 8;
+    let length = content.len() as u16;
+
+    let mut header = [FCGI_VERSION, kind, 0, 0, 0, 0, padding as u8, 0];
+    header[2..4].copy_from_slice(&id.to_be_bytes());
+    header[4..6].copy_from_slice(&length.to_be_bytes());
+    w.write_all(&header)?;
+    w.write_all(content)?;
+    w.write_all(&[0; 8][..padding])
+}
+
+// Writes `data` as a stream of records closed by an empty one.
+pub fn write_stream<W: Write>(w: &mut W, kind: u8, id: u16, data: &[u8]) -> io::Result<()> {
+    for chunk in data.chunks(MAX_CONTENT) {
+        write_record(w, kind, id, chunk)?;
+    }
+    write_record(w, kind, id, &[])
+}
+
+pub fn read_record<R: Read>(r: &mut R) -> io::Result<Record> {
+    let mut header = [0; HEADER_LEN];
+    r.read_exact(&mut header)?;
+    if header[0] != FCGI_VERSION {
+        return Err(invalid_data(format!("unsupported version {}", header[0])));
+    }
+
+    let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+    let mut content = vec![0; length + header[6] as usize];
+    r.read_exact(&mut content)?;
+    content.truncate(length);
+
+    Ok(Record {
+        kind: header[1],
+        id: u16::from_be_bytes([header[2], header[3]]),
+        content,
+    })
+}
+
+// Name-value pairs, lengths below 128 take one byte and four bytes with the
+// high bit set otherwise.
+pub fn encode_params(params: &[(String, String)]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for (name, value) in params {
+        encode_length(&mut bytes, name.len());
+        encode_length(&mut bytes, value.len());
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.extend_from_slice(value.as_bytes());
+    }
+    bytes
+}
+
+fn encode_length(bytes: &mut Vec<u8>, length: usize) {
+    match length {
+        0..=127 => bytes.push(length as u8),
+        _ => bytes.extend_from_slice(&(length as u32 | 0x8000_0000).to_be_bytes()),
+    }
+}
+
+pub fn decode_params(mut bytes: &[u8]) -> Option<Vec<(String, String)>> {
+    let mut params = Vec::new();
+    while !bytes.is_empty() {
+        let name_len = decode_length(&mut bytes)?;
+        let value_len = decode_length(&mut bytes)?;
+        if bytes.len() < name_len + value_len {
+            return None;
+        }
+
+        let name = String::from_utf8(bytes[..name_len].to_vec()).ok()?;
+        let value = String::from_utf8(bytes[name_len..name_len + value_len].to_vec()).ok()?;
+        params.push((name, value));
+        bytes = &bytes[name_len + value_len..];
+    }
+    Some(params)
+}
+
+fn decode_length(bytes: &mut &[u8]) -> Option<usize> {
+    match bytes.first()? {
+        byte if byte & 0x80 == 0 => {
+            *bytes = &bytes[1..];
+            Some(*byte as usize)
+        },
+        _ if bytes.len() >= 4 => {
+            let length = u32::from_be_bytes([bytes[0] & 0x7f, bytes[1], bytes[2], bytes[3]]);
+            *bytes = &bytes[4..];
+            Some(length as usize)
+        },
+        _ => None,
+    }
+}
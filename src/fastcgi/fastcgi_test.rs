@@ -0,0 +1,274 @@
+use std::io::prelude::*;
+use std::net::{IpAddr, Ipv4Addr, TcpListener};
+use std::os::unix::net::UnixListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use super::fastcgi::*;
+use crate::http::request::HTTPRequest;
+use crate::http::response::HTTPResponse;
+use crate::http::status::StatusCode;
+use crate::access::access::ClientAddr;
+use crate::server::stream::Client;
+
+const CLIENT: Client = Client {
+    addr: ClientAddr::Ip(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 7))),
+    secure: false,
+    port: Some(8080),
+    user: None,
+};
+
+// An in-process responder. Answers requests by SCRIPT_NAME until the
+// client closes the connection:
+//
+//   /app/echo   the params and stdin, with a line on stderr
+//   /app/bad    output without a header block
+//   /app/slow   nothing for two seconds
+//   /app/once   like echo, then closes the connection
+//
+// Every request read is counted in `requests`.
+fn serve<S: Read + Write>(mut conn: S, requests: &AtomicUsize) {
+    loop {
+        let begin = match read_record(&mut conn) {
+            Ok(record) => record,
+            Err(_) => return,
+        };
+        requests.fetch_add(1, Ordering::SeqCst);
+        assert_eq!(begin.kind, FCGI_BEGIN_REQUEST);
+        assert_eq!(u16::from_be_bytes([begin.content[0], begin.content[1]]), FCGI_RESPONDER);
+        let keep_conn = begin.content[2] & FCGI_KEEP_CONN != 0;
+
+        let params = decode_params(&read_stream(&mut conn, FCGI_PARAMS)).unwrap();
+        let stdin = read_stream(&mut conn, FCGI_STDIN);
+        let param = |name: &str| params.iter().find(|(n, _)| n == name).map(|(_, v)| v.clone()).unwrap_or_default();
+
+        let script = param("SCRIPT_NAME");
+        let mut out = Vec::new();
+        match script.as_str() {
+            "/app/bad" => write_stream(&mut out, FCGI_STDOUT, begin.id, b"just text").unwrap(),
+            "/app/slow" => thread::sleep(Duration::from_secs(2)),
+            _ => {
+                write_record(&mut out, FCGI_STDERR, begin.id, b"PHP Notice: echo\n").unwrap();
+                // The header block split over two records.
+                write_record(&mut out, FCGI_STDOUT, begin.id, b"Status: 201 Created\r\nContent-Ty").unwrap();
+                let mut body = b"pe: text/plain\r\n\r\n".to_vec();
+                for name in ["REQUEST_METHOD", "SCRIPT_FILENAME", "QUERY_STRING", "REDIRECT_STATUS", "APP_ENV"] {
+                    body.extend_from_slice(format!("{}={}\n", name, param(name)).as_bytes());
+                }
+                body.extend_from_slice(b"body=");
+                body.extend_from_slice(&stdin);
+                write_stream(&mut out, FCGI_STDOUT, begin.id, &body).unwrap();
+            },
+        }
+
+        let mut end = vec![0; 8];
+        end[4] = FCGI_REQUEST_COMPLETE;
+        write_record(&mut out, FCGI_END_REQUEST, begin.id, &end).unwrap();
+        if conn.write_all(&out).is_err() || !keep_conn || script == "/app/once" {
+            return;
+        }
+    }
+}
+
+fn read_stream<R: Read>(conn: &mut R, kind: u8) -> Vec<u8> {
+    let mut data = Vec::new();
+    loop {
+        let record = read_record(conn).unwrap();
+        assert_eq!(record.kind, kind);
+        if record.content.is_empty() {
+            return data;
+        }
+        data.extend_from_slice(&record.content);
+    }
+}
+
+// Starts a TCP responder, returns its address and the number of accepted
+// connections and of received requests.
+fn tcp_responder() -> (String, Arc<AtomicUsize>, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let requests = Arc::new(AtomicUsize::new(0));
+    let (counter, received) = (accepted.clone(), requests.clone());
+
+    thread::spawn(move || {
+        for conn in listener.incoming() {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let conn = conn.unwrap();
+            let received = received.clone();
+            thread::spawn(move || serve(conn, &received));
+        }
+    });
+
+    (address, accepted, requests)
+}
+
+fn fastcgi(pass: &str, read_timeout: &str) -> FastCgi {
+    let mut builder = FastCgiBuilder::default();
+    let directives = [
+        vec!["fastcgi_pass", pass],
+        vec!["fastcgi_param", "APP_ENV", "test"],
+        vec!["fastcgi_read_timeout", read_timeout],
+    ];
+    for tokens in &directives {
+        let tokens: Vec<String> = tokens.iter().map(|t| t.to_string()).collect();
+        assert!(builder.parse_directive(&tokens).unwrap());
+    }
+    builder.build("/app/").unwrap().unwrap()
+}
+
+fn run(fastcgi: &FastCgi, raw: &str, input: &str) -> HTTPResponse {
+    let req = HTTPRequest::parse(raw.as_bytes()).unwrap();
+    fastcgi.execute(&req, &CLIENT, "/srv/www/", &mut input.as_bytes())
+}
+
+fn body(resp: HTTPResponse) -> String {
+    let mut body = String::new();
+    resp.stream.unwrap().read_to_string(&mut body).unwrap();
+    body
+}
+
+#[test]
+fn params_round_trip() {
+    let params = vec![
+        (String::from("SHORT"), String::from("value")),
+        ("N".repeat(200), String::new()),
+        (String::from("LONG"), "v".repeat(70000)),
+    ];
+    let encoded = encode_params(&params);
+    assert_eq!(&encoded[..7], b"\x05\x05SHORT");
+    assert_eq!(&encoded[12..16], &[0x80, 0, 0, 200]);
+    assert_eq!(decode_params(&encoded), Some(params));
+
+    assert_eq!(decode_params(b"\x05\x05SHO"), None);
+    assert_eq!(decode_params(b"\x80\x00"), None);
+}
+
+#[test]
+fn records_are_padded() {
+    let mut out = Vec::new();
+    write_record(&mut out, FCGI_STDIN, 1, b"hello").unwrap();
+    assert_eq!(out.len(), 16);
+    assert_eq!(&out[..8], &[1, FCGI_STDIN, 0, 1, 0, 5, 3, 0]);
+
+    let mut out = Vec::new();
+    write_stream(&mut out, FCGI_STDIN, 1, &vec![b'x'; 70000]).unwrap();
+    let mut reader = out.as_slice();
+    assert_eq!(read_record(&mut reader).unwrap().content.len(), 65535);
+    assert_eq!(read_record(&mut reader).unwrap().content.len(), 70000 - 65535);
+    assert!(read_record(&mut reader).unwrap().content.is_empty());
+    assert!(reader.is_empty());
+}
+
+#[test]
+fn tcp_request() {
+    let (address, _, _) = tcp_responder();
+    let resp = run(&fastcgi(&address, "5"), "GET /app/echo?a=1 HTTP/1.1\r\nHost: example.com\r\nContent-Length: 5\r\n\r\n", "hello");
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    assert_eq!(resp.header("Content-Type"), Some("text/plain"));
+
+    let body = body(resp);
+    assert!(body.contains("REQUEST_METHOD=GET\n"));
+    assert!(body.contains("SCRIPT_FILENAME=/srv/www/app/echo\n"));
+    assert!(body.contains("QUERY_STRING=a=1\n"));
+    assert!(body.contains("REDIRECT_STATUS=200\n"));
+    assert!(body.contains("APP_ENV=test\n"));
+    assert!(body.ends_with("body=hello"));
+}
+
+#[test]
+fn unix_request() {
+    let path = std::env::temp_dir().join(format!("dz1-fastcgi-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    thread::spawn(move || {
+        for conn in listener.incoming() {
+            let conn = conn.unwrap();
+            thread::spawn(move || serve(conn, &AtomicUsize::new(0)));
+        }
+    });
+
+    let resp = run(&fastcgi(&format!("unix:{}", path.display()), "5"), "GET /app/echo HTTP/1.1\r\n\r\n", "");
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    assert!(body(resp).contains("APP_ENV=test\n"));
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn connections_are_reused() {
+    let (address, accepted, _) = tcp_responder();
+    let fastcgi = fastcgi(&address, "5");
+
+    for _ in 0..3 {
+        let resp = run(&fastcgi, "GET /app/echo HTTP/1.1\r\n\r\n", "");
+        assert!(body(resp).ends_with("body="));
+    }
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn stale_connection_is_retried() {
+    let (address, accepted, _) = tcp_responder();
+    let fastcgi = fastcgi(&address, "5");
+
+    // The responder closes the pooled connection after answering.
+    assert!(body(run(&fastcgi, "GET /app/once HTTP/1.1\r\n\r\n", "")).ends_with("body="));
+    thread::sleep(Duration::from_millis(50));
+
+    let resp = run(&fastcgi, "GET /app/echo HTTP/1.1\r\n\r\n", "");
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    assert_eq!(accepted.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn timeout_is_not_retried() {
+    let (address, accepted, requests) = tcp_responder();
+    let fastcgi = fastcgi(&address, "1");
+    assert!(body(run(&fastcgi, "GET /app/echo HTTP/1.1\r\n\r\n", "")).ends_with("body="));
+
+    // The pooled connection times out while the application works, the
+    // request must not be sent again.
+    let resp = run(&fastcgi, "POST /app/slow HTTP/1.1\r\nContent-Length: 1\r\n\r\n", "x");
+    assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn head_has_no_body() {
+    let (address, _, _) = tcp_responder();
+    let resp = run(&fastcgi(&address, "5"), "HEAD /app/echo HTTP/1.1\r\n\r\n", "");
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    assert!(resp.stream.is_none());
+}
+
+#[test]
+fn gateway_errors() {
+    let (address, _, _) = tcp_responder();
+    let fastcgi = fastcgi(&address, "1");
+    assert_eq!(run(&fastcgi, "GET /app/bad HTTP/1.1\r\n\r\n", "").status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(run(&fastcgi, "GET /app/slow HTTP/1.1\r\n\r\n", "").status(), StatusCode::GATEWAY_TIMEOUT);
+
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let refused = self::fastcgi(&format!("127.0.0.1:{}", port), "1");
+    assert_eq!(run(&refused, "GET /app/echo HTTP/1.1\r\n\r\n", "").status(), StatusCode::BAD_GATEWAY);
+}
+
+#[test]
+fn invalid_directives() {
+    let parse = |tokens: &[&str]| {
+        let mut builder = FastCgiBuilder::default();
+        let tokens: Vec<String> = tokens.iter().map(|t| t.to_string()).collect();
+        builder.parse_directive(&tokens).and_then(|_| builder.build("/app/"))
+    };
+
+    assert!(parse(&["fastcgi_pass", "127.0.0.1"]).is_err());
+    assert!(parse(&["fastcgi_pass", "unix:"]).is_err());
+    assert!(parse(&["fastcgi_pass"]).is_err());
+    assert!(parse(&["fastcgi_param", "APP_ENV"]).is_err());
+    // A param alone does not say where to send the request.
+    assert!(parse(&["fastcgi_param", "APP_ENV", "test"]).is_err());
+    assert!(parse(&["cgi_root", "/srv/cgi"]).unwrap().is_none());
+}
@@ -0,0 +1,3 @@
+pub mod fastcgi;
+#[cfg(test)]
+mod fastcgi_test;
@@ -0,0 +1,280 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{TimeZone, UTC};
+
+use crate::http::response::HTTPResponse;
+use crate::mime::mime::extension;
+
+pub const EXPIRES_INVALID_FORMAT: &str = "Invalid expires format";
+pub const CACHE_CONTROL_INVALID_FORMAT: &str = "Invalid cache_control format";
+pub const ADD_HEADER_INVALID_FORMAT: &str = "Invalid add_header format";
+
+const HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+// What `expires max` sends, as nginx does.
+const MAX_EXPIRES: &str = "Thu, 31 Dec 2037 23:55:55 GMT";
+const MAX_AGE: i64 = 10 * 365 * 24 * 60 * 60;
+const EPOCH_EXPIRES: &str = "Thu, 01 Jan 1970 00:00:01 GMT";
+
+// Responses that get caching and added headers, others only get headers
+// added with `always`.
+const CACHEABLE_STATUSES: [u16; 10] = [200, 201, 204, 206, 301, 302, 303, 304, 307, 308];
+
+// What a rule applies to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Matcher {
+    // `.css`, compared case-insensitively.
+    Extension(String),
+    // `text/html` or `image/*`.
+    Type(String),
+    // File names with a content hash, such as `app.3f9a2b1c.js`.
+    Fingerprinted,
+}
+
+impl Matcher {
+    fn parse(raw: &str) -> Option<Matcher> {
+        if raw == "fingerprinted" {
+            return Some(Matcher::Fingerprinted);
+        }
+        if let Some(ext) = raw.strip_prefix('.').filter(|e| !e.is_empty()) {
+            return Some(Matcher::Extension(ext.to_ascii_lowercase()));
+        }
+        match raw.split_once('/') {
+            Some((top, sub)) if !top.is_empty() && !sub.is_empty() => Some(Matcher::Type(raw.to_ascii_lowercase())),
+            _ => None,
+        }
+    }
+
+    fn matches(&self, path: &str, content_type: &str) -> bool {
+        match self {
+            Matcher::Extension(ext) => extension(path).as_deref() == Some(ext.as_str()),
+            Matcher::Type(mime) => match mime.strip_suffix("/*") {
+                Some(top) => content_type.split('/').next() == Some(top),
+                None => content_type == mime,
+            },
+            Matcher::Fingerprinted => is_fingerprinted(path),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Expiry {
+    // Turns off inherited rules.
+    Off,
+    Epoch,
+    Max,
+    // Seconds from now, negative means do not cache.
+    After(i64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Policy {
+    Expires(Expiry),
+    CacheControl(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct CacheRule {
+    policy: Policy,
+    // Empty matches everything.
+    matchers: Vec<Matcher>,
+}
+
+impl CacheRule {
+    fn matches(&self, path: &str, content_type: &str) -> bool {
+        self.matchers.is_empty() || self.matchers.iter().any(|m| m.matches(path, content_type))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct AddedHeader {
+    name: String,
+    value: String,
+    always: bool,
+}
+
+// The `expires`, `cache_control` and `add_header` directives of the server
+// or a location. A location with rules of a kind replaces the server's.
+#[derive(Debug, Default)]
+pub struct ResponseHeaders {
+    cache: Vec<CacheRule>,
+    added: Vec<AddedHeader>,
+}
+
+impl ResponseHeaders {
+    // Accepts `expires <time>|epoch|max|off [<matcher>...]`,
+    // `cache_control <value> [<matcher>...]` and
+    // `add_header <name> <value> [always]`, returns false for any other
+    // directive.
+    pub fn parse_directive(&mut self, tokens: &[String]) -> Result<bool, String> {
+        match tokens[0].as_str() {
+            "expires" => {
+                let invalid = || format!("{}: {}", EXPIRES_INVALID_FORMAT, tokens[1..].join(" "));
+                let expiry = tokens.get(1).and_then(|t| parse_expiry(t)).ok_or_else(invalid)?;
+                let matchers = parse_matchers(&tokens[2..]).ok_or_else(invalid)?;
+                self.cache.push(CacheRule {
+                    policy: Policy::Expires(expiry),
+                    matchers,
+                });
+            },
+            "cache_control" => {
+                let invalid = || format!("{}: {}", CACHE_CONTROL_INVALID_FORMAT, tokens[1..].join(" "));
+                let value = tokens.get(1).filter(|v| !v.is_empty() && !v.contains(['\r', '\n'])).ok_or_else(invalid)?;
+                let matchers = parse_matchers(&tokens[2..]).ok_or_else(invalid)?;
+                self.cache.push(CacheRule {
+                    policy: Policy::CacheControl(value.clone()),
+                    matchers,
+                });
+            },
+            "add_header" => {
+                let always = match tokens.len() {
+                    3 => false,
+                    4 if tokens[3] == "always" => true,
+                    _ => return Err(format!("{}: {}", ADD_HEADER_INVALID_FORMAT, tokens[1..].join(" "))),
+                };
+                let valid_name = !tokens[1].is_empty() && tokens[1].bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+                if !valid_name || tokens[2].contains(['\r', '\n']) {
+                    return Err(format!("{}: {}", ADD_HEADER_INVALID_FORMAT, tokens[1..].join(" ")));
+                }
+                self.added.push(AddedHeader {
+                    name: tokens[1].clone(),
+                    value: tokens[2].clone(),
+                    always,
+                });
+            },
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    pub fn has_cache_rules(&self) -> bool {
+        !self.cache.is_empty()
+    }
+
+    pub fn has_added_headers(&self) -> bool {
+        !self.added.is_empty()
+    }
+
+    // Sets Expires and Cache-Control from the first matching rule of each
+    // kind. `now` is in seconds since the epoch.
+    pub fn apply_cache(&self, resp: &mut HTTPResponse, path: &str, now: i64) {
+        if !CACHEABLE_STATUSES.contains(&resp.status().as_u16()) {
+            return;
+        }
+        let content_type = resp.header("Content-Type")
+            .map(|t| t.split(';').next().unwrap_or("").trim().to_ascii_lowercase())
+            .unwrap_or_default();
+
+        let matching = || self.cache.iter().filter(|rule| rule.matches(path, &content_type));
+        let expires = matching().find_map(|rule| match rule.policy {
+            Policy::Expires(expiry) => Some(expiry),
+            _ => None,
+        });
+        let cache_control = matching().find_map(|rule| match &rule.policy {
+            Policy::CacheControl(value) => Some(value.clone()),
+            _ => None,
+        });
+
+        let (expires, max_age) = match expires {
+            Some(Expiry::Epoch) => (Some(String::from(EPOCH_EXPIRES)), Some(String::from("no-cache"))),
+            Some(Expiry::Max) => (Some(String::from(MAX_EXPIRES)), Some(format!("max-age={}", MAX_AGE))),
+            Some(Expiry::After(secs)) => {
+                // Only a clock far off could leave the range of dates.
+                let date = match now.checked_add(secs).and_then(|t| UTC.timestamp_opt(t, 0).single()) {
+                    Some(date) => date.format(HTTP_DATE).to_string(),
+                    None if secs < 0 => String::from(EPOCH_EXPIRES),
+                    None => String::from(MAX_EXPIRES),
+                };
+                let control = if secs < 0 { String::from("no-cache") } else { format!("max-age={}", secs) };
+                (Some(date), Some(control))
+            },
+            Some(Expiry::Off) | None => (None, None),
+        };
+        if let Some(expires) = expires {
+            resp.push_header("Expires".to_owned(), expires);
+        }
+        if let Some(control) = cache_control.or(max_age) {
+            resp.push_header("Cache-Control".to_owned(), control);
+        }
+    }
+
+    pub fn apply_added(&self, resp: &mut HTTPResponse) {
+        let cacheable = CACHEABLE_STATUSES.contains(&resp.status().as_u16());
+        for header in self.added.iter().filter(|h| h.always || cacheable) {
+            resp.add_header(header.name.clone(), header.value.clone());
+        }
+    }
+}
+
+pub fn unix_now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+// `30d`, `1h30m`, `-1` or `max`. A plain number is seconds, up to what
+// `max` sends either way.
+fn parse_expiry(raw: &str) -> Option<Expiry> {
+    match raw {
+        "off" => return Some(Expiry::Off),
+        "epoch" => return Some(Expiry::Epoch),
+        "max" => return Some(Expiry::Max),
+        _ => {},
+    }
+
+    let (negative, rest) = match raw.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, raw),
+    };
+    let mut secs: i64 = 0;
+    let mut number = String::new();
+    for c in rest.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            'M' => 30 * 24 * 60 * 60,
+            'y' => 365 * 24 * 60 * 60,
+            _ => return None,
+        };
+        let value: i64 = number.parse().ok()?;
+        secs = secs.checked_add(value.checked_mul(unit)?)?;
+        number.clear();
+    }
+    if !number.is_empty() {
+        secs = secs.checked_add(number.parse().ok()?)?;
+    } else if rest.is_empty() {
+        return None;
+    }
+    if secs > MAX_AGE {
+        return None;
+    }
+
+    Some(Expiry::After(if negative { -secs } else { secs }))
+}
+
+fn parse_matchers(tokens: &[String]) -> Option<Vec<Matcher>> {
+    tokens.iter().map(|t| Matcher::parse(t)).collect()
+}
+
+// A name part between `.` or `-` that looks like a content hash: 8 or more
+// letters and digits with at least one of each.
+fn is_fingerprinted(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or("");
+    let stem = match name.rsplit_once('.') {
+        Some((stem, _)) => stem,
+        None => return false,
+    };
+    stem.split(['.', '-', '_'])
+        .skip(1)
+        .any(|part| {
+            part.len() >= 8
+                && part.bytes().all(|b| b.is_ascii_alphanumeric())
+                && part.bytes().any(|b| b.is_ascii_digit())
+                && part.bytes().any(|b| b.is_ascii_alphabetic())
+        })
+}
@@ -0,0 +1,111 @@
+use super::headers::*;
+use crate::config::config_test::{directives, parse_err};
+use crate::http::response::HTTPResponse;
+use crate::http::status::StatusCode;
+
+// Fri, 13 Feb 2009 23:31:30 GMT
+const NOW: i64 = 1_234_567_890;
+
+fn rules(lines: &[&str]) -> ResponseHeaders {
+    directives(lines, ResponseHeaders::parse_directive)
+}
+
+fn response(status: StatusCode, content_type: &str) -> HTTPResponse {
+    let mut resp = HTTPResponse::builder().status(status).build();
+    resp.push_header("Content-Type".to_owned(), content_type.to_owned());
+    resp
+}
+
+#[test]
+fn expires_dates() {
+    let headers = rules(&["expires 1h30m"]);
+    let mut resp = response(StatusCode::OK, "text/css");
+    headers.apply_cache(&mut resp, "/a.css", NOW);
+    assert_eq!(resp.header("Expires"), Some("Sat, 14 Feb 2009 01:01:30 GMT"));
+    assert_eq!(resp.header("Cache-Control"), Some("max-age=5400"));
+
+    let headers = rules(&["expires -1"]);
+    let mut resp = response(StatusCode::OK, "text/css");
+    headers.apply_cache(&mut resp, "/a.css", NOW);
+    assert_eq!(resp.header("Expires"), Some("Fri, 13 Feb 2009 23:31:29 GMT"));
+    assert_eq!(resp.header("Cache-Control"), Some("no-cache"));
+
+    let headers = rules(&["expires max"]);
+    let mut resp = response(StatusCode::NOT_MODIFIED, "text/css");
+    headers.apply_cache(&mut resp, "/a.css", NOW);
+    assert_eq!(resp.header("Expires"), Some("Thu, 31 Dec 2037 23:55:55 GMT"));
+    assert_eq!(resp.header("Cache-Control"), Some("max-age=315360000"));
+
+    // The longest expiry a config can have, with a clock at the end of
+    // time.
+    let headers = rules(&["expires -10y .txt", "expires 10y"]);
+    let mut resp = response(StatusCode::OK, "text/css");
+    headers.apply_cache(&mut resp, "/a.css", i64::MAX - 1);
+    assert_eq!(resp.header("Expires"), Some("Thu, 31 Dec 2037 23:55:55 GMT"));
+    let mut resp = response(StatusCode::OK, "text/plain");
+    headers.apply_cache(&mut resp, "/a.txt", i64::MIN + 1);
+    assert_eq!(resp.header("Expires"), Some("Thu, 01 Jan 1970 00:00:01 GMT"));
+
+    let headers = rules(&["expires epoch"]);
+    let mut resp = response(StatusCode::OK, "text/css");
+    headers.apply_cache(&mut resp, "/a.css", NOW);
+    assert_eq!(resp.header("Expires"), Some("Thu, 01 Jan 1970 00:00:01 GMT"));
+    assert_eq!(resp.header("Cache-Control"), Some("no-cache"));
+}
+
+#[test]
+fn matching_rules() {
+    let headers = rules(&[
+        "cache_control public,max-age=31536000,immutable fingerprinted",
+        "cache_control no-cache text/html",
+        "expires 7d .css .JS image/*",
+        "expires off",
+    ]);
+    let apply = |path: &str, content_type: &str| {
+        let mut resp = response(StatusCode::OK, content_type);
+        headers.apply_cache(&mut resp, path, NOW);
+        (resp.header("Cache-Control").map(String::from), resp.has_header("Expires"))
+    };
+
+    assert_eq!(apply("/app.3f9a2b1c.js", "application/javascript"), (Some(String::from("public,max-age=31536000,immutable")), true));
+    assert_eq!(apply("/index-BQJ3kL2x.css", "text/css"), (Some(String::from("public,max-age=31536000,immutable")), true));
+    assert_eq!(apply("/index.html", "text/html; charset=utf-8"), (Some(String::from("no-cache")), false));
+    assert_eq!(apply("/site.css", "text/css"), (Some(String::from("max-age=604800")), true));
+    assert_eq!(apply("/app.js", "application/javascript"), (Some(String::from("max-age=604800")), true));
+    assert_eq!(apply("/logo", "image/png"), (Some(String::from("max-age=604800")), true));
+    assert_eq!(apply("/data.json", "application/json"), (None, false));
+    assert_eq!(apply("/my-component.js", "application/javascript"), (Some(String::from("max-age=604800")), true));
+}
+
+#[test]
+fn errors_are_not_cached() {
+    let headers = rules(&["expires 1d", "add_header X-Frame-Options DENY", "add_header X-Trace abc always"]);
+    let mut resp = response(StatusCode::NOT_FOUND, "text/html");
+    headers.apply_cache(&mut resp, "/missing.html", NOW);
+    headers.apply_added(&mut resp);
+    assert!(!resp.has_header("Expires") && !resp.has_header("Cache-Control"));
+    assert!(!resp.has_header("X-Frame-Options"));
+    assert_eq!(resp.header("X-Trace"), Some("abc"));
+
+    let mut resp = response(StatusCode::OK, "text/html");
+    headers.apply_added(&mut resp);
+    assert_eq!(resp.header("X-Frame-Options"), Some("DENY"));
+}
+
+#[test]
+fn invalid_directives() {
+    assert!(parse_err("expires", ResponseHeaders::parse_directive).starts_with(EXPIRES_INVALID_FORMAT));
+    assert!(parse_err("expires 1x", ResponseHeaders::parse_directive).starts_with(EXPIRES_INVALID_FORMAT));
+    assert!(parse_err("expires - ", ResponseHeaders::parse_directive).starts_with(EXPIRES_INVALID_FORMAT));
+    assert!(parse_err("expires 1d html", ResponseHeaders::parse_directive).starts_with(EXPIRES_INVALID_FORMAT));
+    assert!(parse_err("expires 1000000y", ResponseHeaders::parse_directive).starts_with(EXPIRES_INVALID_FORMAT));
+    assert!(parse_err("expires -11y", ResponseHeaders::parse_directive).starts_with(EXPIRES_INVALID_FORMAT));
+    assert!(parse_err("expires 9223372036854775807", ResponseHeaders::parse_directive).starts_with(EXPIRES_INVALID_FORMAT));
+    assert!(parse_err("cache_control", ResponseHeaders::parse_directive).starts_with(CACHE_CONTROL_INVALID_FORMAT));
+    assert!(parse_err("add_header X-Only", ResponseHeaders::parse_directive).starts_with(ADD_HEADER_INVALID_FORMAT));
+    assert!(parse_err("add_header Bad:Name x", ResponseHeaders::parse_directive).starts_with(ADD_HEADER_INVALID_FORMAT));
+    assert!(parse_err("add_header X-A b sometimes", ResponseHeaders::parse_directive).starts_with(ADD_HEADER_INVALID_FORMAT));
+
+    let mut headers = ResponseHeaders::default();
+    assert_eq!(headers.parse_directive(&[String::from("allow"), String::from("all")]), Ok(false));
+}
@@ -0,0 +1,3 @@
+pub mod headers;
+#[cfg(test)]
+mod headers_test;
@@ -0,0 +1,141 @@
+use std::io;
+use std::io::prelude::*;
+
+const MAX_LINE: usize = 4096;
+const MAX_TRAILERS: usize = 64;
+
+#[derive(Debug, PartialEq)]
+enum State {
+    Size,
+    Data(u64),
+    DataEnd,
+    Trailers,
+    Done,
+}
+
+// Decodes a `Transfer-Encoding: chunked` body. Size lines are read byte by
+// byte so that nothing past the final chunk is consumed from `inner`.
+pub struct ChunkedReader<R> {
+    inner: R,
+    state: State,
+    trailers: Vec<(String, String)>,
+}
+
+impl<R: Read> ChunkedReader<R> {
+    pub fn new(inner: R) -> ChunkedReader<R> {
+        ChunkedReader {
+            inner,
+            state: State::Size,
+            trailers: Vec::new(),
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    // Trailer fields, complete once the body was read to the end.
+    pub fn trailers(&self) -> &[(String, String)] {
+        &self.trailers
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    // Reads a CRLF terminated line without the terminator.
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = Vec::new();
+        let mut byte = [0; 1];
+
+        loop {
+            if self.inner.read(&mut byte)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "chunked body ended early"));
+            }
+            if byte[0] == b'\n' {
+                break;
+            }
+            if line.len() >= MAX_LINE {
+                return Err(invalid("chunk line too long"));
+            }
+            line.push(byte[0]);
+        }
+
+        match line.pop() {
+            Some(b'\r') => String::from_utf8(line).map_err(|_| invalid("chunk line is not utf-8")),
+            _ => Err(invalid("chunk line without CRLF")),
+        }
+    }
+}
+
+impl<R: Read> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.state {
+                State::Done => return Ok(0),
+                State::Size => {
+                    let line = self.read_line()?;
+                    self.state = match parse_size(&line)? {
+                        0 => State::Trailers,
+                        size => State::Data(size),
+                    };
+                },
+                State::Data(remaining) => {
+                    if buf.is_empty() {
+                        return Ok(0);
+                    }
+                    let limit = (buf.len() as u64).min(remaining) as usize;
+                    let n = match self.inner.read(&mut buf[..limit])? {
+                        0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "chunked body ended early")),
+                        n => n,
+                    };
+                    self.state = match remaining - n as u64 {
+                        0 => State::DataEnd,
+                        left => State::Data(left),
+                    };
+                    return Ok(n);
+                },
+                State::DataEnd => {
+                    if !self.read_line()?.is_empty() {
+                        return Err(invalid("chunk data longer than its size"));
+                    }
+                    self.state = State::Size;
+                },
+                State::Trailers => {
+                    let line = self.read_line()?;
+                    if line.is_empty() {
+                        self.state = State::Done;
+                        continue;
+                    }
+                    if self.trailers.len() >= MAX_TRAILERS {
+                        return Err(invalid("too many trailer fields"));
+                    }
+                    match line.split_once(':') {
+                        Some((name, value)) if !name.is_empty() && !name.contains(' ') => {
+                            self.trailers.push((name.to_owned(), value.trim().to_owned()));
+                        },
+                        _ => return Err(invalid("invalid trailer field")),
+                    }
+                },
+            }
+        }
+    }
+}
+
+// Only hex digits are accepted, optionally followed by `;extensions`.
+fn parse_size(line: &str) -> io::Result<u64> {
+    let size = line.split(';').next().unwrap().trim_end_matches([' ', '\t']);
+    if size.is_empty() || size.len() > 16 || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(invalid("invalid chunk size"));
+    }
+
+    u64::from_str_radix(size, 16).map_err(|_| invalid("invalid chunk size"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
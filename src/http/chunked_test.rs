@@ -0,0 +1,50 @@
+use std::io::prelude::*;
+
+use super::chunked::ChunkedReader;
+
+// Body, trailers and the bytes left after the body.
+type Decoded = (String, Vec<(String, String)>, Vec<u8>);
+
+fn decode(raw: &[u8]) -> Result<Decoded, std::io::Error> {
+    let mut reader = ChunkedReader::new(raw);
+    let mut body = String::new();
+    reader.read_to_string(&mut body)?;
+    let trailers = reader.trailers().to_vec();
+    let rest = reader.into_inner().to_vec();
+    Ok((body, trailers, rest))
+}
+
+#[test]
+fn decode_chunks() {
+    let (body, trailers, rest) = decode(b"4\r\nWiki\r\n5;ext=1\r\npedia\r\n0\r\n\r\nGET /next").unwrap();
+    assert_eq!(body, "Wikipedia");
+    assert!(trailers.is_empty());
+    assert_eq!(rest, b"GET /next");
+}
+
+#[test]
+fn decode_trailers() {
+    let (body, trailers, _) = decode(b"3\r\nabc\r\n0\r\nExpires: never\r\n\r\n").unwrap();
+    assert_eq!(body, "abc");
+    assert_eq!(trailers, vec![(String::from("Expires"), String::from("never"))]);
+}
+
+#[test]
+fn reject_invalid_size() {
+    assert!(decode(b"zz\r\nabc\r\n0\r\n\r\n").is_err());
+    assert!(decode(b"-1\r\nabc\r\n0\r\n\r\n").is_err());
+    assert!(decode(b"0x3\r\nabc\r\n0\r\n\r\n").is_err());
+    assert!(decode(b"fffffffffffffffff\r\n").is_err());
+}
+
+#[test]
+fn reject_bare_lf_and_overlong_data() {
+    assert!(decode(b"3\nabc\n0\n\n").is_err());
+    assert!(decode(b"3\r\nabcd\r\n0\r\n\r\n").is_err());
+}
+
+#[test]
+fn reject_truncated() {
+    assert!(decode(b"5\r\nab").is_err());
+    assert!(decode(b"3\r\nabc\r\n").is_err());
+}
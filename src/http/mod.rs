@@ -1,3 +1,15 @@
+pub mod chunked;
+#[cfg(test)]
+mod chunked_test;
+pub mod reader;
+#[cfg(test)]
+mod reader_test;
 pub mod request;
-pub mod request_test;
-pub mod response;
\ No newline at end of file
+#[cfg(test)]
+mod request_test;
+pub mod response;
+#[cfg(test)]
+mod response_test;
+pub mod status;
+#[cfg(test)]
+mod status_test;
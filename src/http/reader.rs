@@ -0,0 +1,309 @@
+use std::io;
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use crate::http::chunked::ChunkedReader;
+use crate::http::request::BodyFraming;
+use crate::http::status::StatusCode;
+use crate::server::stream::Stream;
+
+const HEAD_TERMINATOR: &[u8] = b"\r\n\r\n";
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+const READ_CHUNK: usize = 4096;
+const CONTINUE: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
+
+#[derive(Debug)]
+pub enum ReadError {
+    // Peer closed the connection before sending anything.
+    Closed,
+    // Nothing arrived while the connection was idle.
+    Idle,
+    // The head started but was not complete in time.
+    Timeout,
+    TooLarge,
+    Io(io::Error),
+}
+
+// A connection whose reads can be bounded in time.
+pub trait ReadTimeout: Read {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl ReadTimeout for Stream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        Stream::set_read_timeout(self, timeout)
+    }
+}
+
+impl ReadTimeout for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+// Accumulates bytes from a connection and splits them into message heads.
+// Bytes past the end of a head stay buffered for the body or the next
+// message.
+#[derive(Default)]
+pub struct RequestReader {
+    buffer: Vec<u8>,
+}
+
+impl RequestReader {
+    pub fn new() -> RequestReader {
+        RequestReader {
+            buffer: Vec::new(),
+        }
+    }
+
+    // Reads one request head. With `idle` set the connection may stay silent
+    // that long before the first byte, after which the whole head must arrive
+    // within `header`. Without it `header` counts from now.
+    pub fn read_head<S: ReadTimeout>(&mut self, stream: &mut S, idle: Option<Duration>, header: Duration) -> Result<Vec<u8>, ReadError> {
+        let mut started = !self.buffer.is_empty() || idle.is_none();
+        let mut deadline = Instant::now() + match idle {
+            Some(idle) if self.buffer.is_empty() => idle,
+            _ => header,
+        };
+        let mut chunk = [0; READ_CHUNK];
+
+        loop {
+            if let Some(end) = find_terminator(&self.buffer) {
+                return Ok(self.buffer.drain(..end + HEAD_TERMINATOR.len()).collect());
+            }
+            // An HTTP/0.9 request is a single line, no headers follow it.
+            if let Some(end) = find_simple_request(&self.buffer) {
+                return Ok(self.buffer.drain(..end).collect());
+            }
+
+            if self.buffer.len() > MAX_HEAD_SIZE {
+                return Err(ReadError::TooLarge);
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(match self.buffer.is_empty() {
+                    true => ReadError::Idle,
+                    false => ReadError::Timeout,
+                });
+            }
+
+            stream.set_read_timeout(Some(deadline - now)).map_err(ReadError::Io)?;
+
+            match stream.read(&mut chunk) {
+                Ok(0) => return Err(ReadError::Closed),
+                Ok(n) => {
+                    self.buffer.extend_from_slice(&chunk[..n]);
+                    if !started {
+                        started = true;
+                        deadline = Instant::now() + header;
+                    }
+                },
+                Err(ref err) if is_timeout(err) => continue,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(ReadError::Io(err)),
+            }
+        }
+    }
+
+    // The next `length` bytes of the connection, starting with what is
+    // already buffered. Each read of the connection may wait `idle` at
+    // most, and the whole body must arrive within `total` of the first
+    // read, 0 is no limit. Reads past either fail with `TimedOut`.
+    pub fn body<'a, S: ReadTimeout>(&'a mut self, stream: &'a mut S, length: u64, idle: Duration, total: Duration) -> BodyReader<'a, S> {
+        BodyReader {
+            buffer: &mut self.buffer,
+            stream,
+            remaining: length,
+            idle,
+            total,
+            deadline: None,
+        }
+    }
+
+    // The body of a request as framed by its headers, with the time limits
+    // of `body`. Reading more than `limit` bytes fails with `FileTooLarge`,
+    // 0 is no limit. With `expect_continue` the interim 100 response is sent
+    // on the first read, so a client waiting for it only sends the body when
+    // a handler wants it.
+    pub fn request_body<'a, S: ReadTimeout + Write>(&'a mut self, stream: &'a mut S, framing: BodyFraming, limit: u64, expect_continue: bool, idle: Duration, total: Duration) -> RequestBody<'a, S> {
+        let framed = match framing {
+            BodyFraming::None => Framed::Length(self.body(stream, 0, idle, total)),
+            BodyFraming::Length(length) => Framed::Length(self.body(stream, length, idle, total)),
+            BodyFraming::Chunked => Framed::Chunked(ChunkedReader::new(self.body(stream, u64::MAX, idle, total))),
+        };
+
+        RequestBody {
+            framed,
+            limit,
+            read: 0,
+            expect_continue,
+        }
+    }
+
+    // Bytes read past the last head.
+    pub fn into_buffered(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+// A body of known length, see `RequestReader::body`.
+pub struct BodyReader<'a, S> {
+    buffer: &'a mut Vec<u8>,
+    stream: &'a mut S,
+    remaining: u64,
+    idle: Duration,
+    total: Duration,
+    // Set on the first read, when the handler starts to want the body.
+    deadline: Option<Instant>,
+}
+
+impl<'a, S> BodyReader<'a, S> {
+    pub fn remaining(&self) -> u64 {
+        self.remaining
+    }
+}
+
+impl<'a, S: ReadTimeout> BodyReader<'a, S> {
+    fn read_stream(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let now = Instant::now();
+        let total = self.total;
+        let deadline = *self.deadline.get_or_insert_with(|| now + total);
+        let mut wait = self.idle;
+        if total > Duration::ZERO {
+            if now >= deadline {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "request body took longer than client_body_total_timeout"));
+            }
+            wait = wait.min(deadline - now);
+        }
+        self.stream.set_read_timeout(Some(wait))?;
+
+        match self.stream.read(buf) {
+            Ok(0) => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "body is shorter than its length")),
+            Ok(n) => Ok(n),
+            Err(ref err) if is_timeout(err) => Err(io::Error::new(io::ErrorKind::TimedOut, "request body stalled")),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl<'a, S: ReadTimeout> Read for BodyReader<'a, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let limit = (buf.len() as u64).min(self.remaining) as usize;
+        if limit == 0 {
+            return Ok(0);
+        }
+
+        let n = match self.buffer.is_empty() {
+            false => {
+                let n = limit.min(self.buffer.len());
+                buf[..n].copy_from_slice(&self.buffer[..n]);
+                self.buffer.drain(..n);
+                n
+            },
+            true => self.read_stream(&mut buf[..limit])?,
+        };
+
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+enum Framed<'a, S> {
+    Length(BodyReader<'a, S>),
+    Chunked(ChunkedReader<BodyReader<'a, S>>),
+}
+
+// See `RequestReader::request_body`.
+pub struct RequestBody<'a, S> {
+    framed: Framed<'a, S>,
+    limit: u64,
+    read: u64,
+    expect_continue: bool,
+}
+
+impl<'a, S: ReadTimeout + Write> RequestBody<'a, S> {
+    // Whether the body was read to its end, the connection can only carry
+    // another request then.
+    pub fn is_complete(&self) -> bool {
+        match self.framed {
+            Framed::Length(ref body) => body.remaining() == 0,
+            Framed::Chunked(ref body) => body.is_done(),
+        }
+    }
+
+    fn send_continue(&mut self) -> io::Result<()> {
+        let body = match self.framed {
+            Framed::Length(ref mut body) => body,
+            Framed::Chunked(ref mut body) => body.get_mut(),
+        };
+        // A client that did not wait has nothing to be told.
+        if !body.buffer.is_empty() {
+            return Ok(());
+        }
+
+        body.stream.write_all(CONTINUE)?;
+        body.stream.flush()
+    }
+}
+
+impl<'a, S: ReadTimeout + Write> Read for RequestBody<'a, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.expect_continue && !self.is_complete() {
+            self.expect_continue = false;
+            self.send_continue()?;
+        }
+
+        let n = match self.framed {
+            Framed::Length(ref mut body) => body.read(buf)?,
+            Framed::Chunked(ref mut body) => body.read(buf)?,
+        };
+
+        self.read += n as u64;
+        if self.limit > 0 && self.read > self.limit {
+            return Err(io::Error::new(io::ErrorKind::FileTooLarge, "request body is larger than client_max_body_size"));
+        }
+
+        Ok(n)
+    }
+}
+
+// Reads a whole request body for handlers that need it in memory. The
+// error is the status to answer with.
+pub fn read_body(body: &mut dyn Read) -> Result<Vec<u8>, StatusCode> {
+    let mut input = Vec::new();
+    match body.read_to_end(&mut input) {
+        Ok(_) => Ok(input),
+        Err(err) => Err(body_error_status(&err)),
+    }
+}
+
+// The status to answer a request whose body could not be read with.
+pub fn body_error_status(err: &io::Error) -> StatusCode {
+    match err.kind() {
+        io::ErrorKind::FileTooLarge => StatusCode::CONTENT_TOO_LARGE,
+        io::ErrorKind::TimedOut => StatusCode::REQUEST_TIMEOUT,
+        _ => {
+            println!("Error while read request body: {}", err);
+            StatusCode::BAD_REQUEST
+        },
+    }
+}
+
+fn find_terminator(buffer: &[u8]) -> Option<usize> {
+    buffer.windows(HEAD_TERMINATOR.len()).position(|w| w == HEAD_TERMINATOR)
+}
+
+// The end of a first line that holds a method and a target but no version.
+fn find_simple_request(buffer: &[u8]) -> Option<usize> {
+    let line = buffer.windows(2).position(|w| w == b"\r\n")?;
+    match buffer[..line].split(|b| *b == b' ').count() {
+        2 => Some(line + 2),
+        _ => None,
+    }
+}
+
+fn is_timeout(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut
+}
@@ -0,0 +1,197 @@
+use std::io;
+use std::io::prelude::*;
+use std::io::Cursor;
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::reader::{read_body, ReadTimeout, RequestReader};
+use super::request::BodyFraming;
+use super::status::StatusCode;
+
+const BODY_TIMEOUT: Duration = Duration::from_secs(1);
+
+// A connection with canned input that records what is written to it.
+struct Conn {
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+impl Conn {
+    fn new(input: &[u8]) -> Conn {
+        Conn {
+            input: Cursor::new(input.to_vec()),
+            output: Vec::new(),
+        }
+    }
+}
+
+impl Read for Conn {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for Conn {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ReadTimeout for Conn {
+    fn set_read_timeout(&self, _: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Reads the head and the body as framed, returns the body, whether it was
+// complete and the bytes left for the next request.
+fn read(raw: &[u8], framing: BodyFraming, limit: u64) -> (io::Result<Vec<u8>>, bool, Vec<u8>) {
+    let mut conn = Conn::new(raw);
+    let mut reader = RequestReader::new();
+    reader.read_head(&mut conn, None, Duration::from_secs(1)).unwrap();
+
+    let mut body = reader.request_body(&mut conn, framing, limit, false, BODY_TIMEOUT, Duration::ZERO);
+    let mut data = Vec::new();
+    let result = body.read_to_end(&mut data).map(|_| data);
+    let complete = body.is_complete();
+
+    let mut rest = reader.into_buffered();
+    conn.input.read_to_end(&mut rest).unwrap();
+    (result, complete, rest)
+}
+
+#[test]
+fn length_body() {
+    let (body, complete, rest) = read(b"POST / HTTP/1.1\r\n\r\nhelloGET /", BodyFraming::Length(5), 0);
+    assert_eq!(body.unwrap(), b"hello");
+    assert!(complete);
+    assert_eq!(rest, b"GET /");
+}
+
+#[test]
+fn chunked_body() {
+    let (body, complete, rest) = read(b"POST / HTTP/1.1\r\n\r\n5\r\nhello\r\n1\r\n!\r\n0\r\n\r\nGET /", BodyFraming::Chunked, 0);
+    assert_eq!(body.unwrap(), b"hello!");
+    assert!(complete);
+    assert_eq!(rest, b"GET /");
+}
+
+#[test]
+fn no_body() {
+    let (body, complete, rest) = read(b"GET / HTTP/1.1\r\n\r\nGET /", BodyFraming::None, 0);
+    assert!(body.unwrap().is_empty());
+    assert!(complete);
+    assert_eq!(rest, b"GET /");
+}
+
+#[test]
+fn chunked_body_over_limit() {
+    let (body, complete, _) = read(b"POST / HTTP/1.1\r\n\r\n5\r\nhello\r\n5\r\nworld\r\n0\r\n\r\n", BodyFraming::Chunked, 8);
+    assert_eq!(body.unwrap_err().kind(), io::ErrorKind::FileTooLarge);
+    assert!(!complete);
+
+    let mut conn = Conn::new(b"5\r\nhello\r\n5\r\nworld\r\n0\r\n\r\n");
+    let mut reader = RequestReader::new();
+    let mut body = reader.request_body(&mut conn, BodyFraming::Chunked, 8, false, BODY_TIMEOUT, Duration::ZERO);
+    assert_eq!(read_body(&mut body), Err(StatusCode::CONTENT_TOO_LARGE));
+}
+
+#[test]
+fn truncated_body() {
+    let (body, complete, _) = read(b"POST / HTTP/1.1\r\n\r\nhel", BodyFraming::Length(5), 0);
+    assert_eq!(body.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    assert!(!complete);
+}
+
+#[test]
+fn continue_sent_on_first_read() {
+    let mut conn = Conn::new(b"hello");
+    let mut reader = RequestReader::new();
+    let mut body = reader.request_body(&mut conn, BodyFraming::Length(5), 0, true, BODY_TIMEOUT, Duration::ZERO);
+    let mut data = String::new();
+    body.read_to_string(&mut data).unwrap();
+    assert_eq!(data, "hello");
+    assert_eq!(conn.output, b"HTTP/1.1 100 Continue\r\n\r\n");
+}
+
+#[test]
+fn continue_not_sent_when_unread_or_already_sent() {
+    let mut conn = Conn::new(b"hello");
+    let mut reader = RequestReader::new();
+    drop(reader.request_body(&mut conn, BodyFraming::Length(5), 0, true, BODY_TIMEOUT, Duration::ZERO));
+    assert!(conn.output.is_empty());
+
+    // The client sent the body along with the head without waiting.
+    let mut conn = Conn::new(b"POST / HTTP/1.1\r\nExpect: 100-continue\r\n\r\nhello");
+    let mut reader = RequestReader::new();
+    reader.read_head(&mut conn, None, Duration::from_secs(1)).unwrap();
+    let mut body = reader.request_body(&mut conn, BodyFraming::Length(5), 0, true, BODY_TIMEOUT, Duration::ZERO);
+    body.read_to_end(&mut Vec::new()).unwrap();
+    assert!(conn.output.is_empty());
+}
+
+#[test]
+fn simple_request_head() {
+    let mut conn = Conn::new(b"GET /index.html\r\n");
+    let mut reader = RequestReader::new();
+    assert_eq!(reader.read_head(&mut conn, None, Duration::from_secs(1)).unwrap(), b"GET /index.html\r\n");
+
+    // A full request line waits for the headers.
+    let mut conn = Conn::new(b"GET / HTTP/1.1\r\nHost: a\r\n\r\nGET /next\r\n");
+    let mut reader = RequestReader::new();
+    assert_eq!(reader.read_head(&mut conn, None, Duration::from_secs(1)).unwrap(), b"GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+}
+
+// A loopback connection whose peer sends `chunks` of a chunked body
+// `interval` apart and then keeps the connection open.
+fn trickle(chunks: usize, interval: Duration) -> TcpStream {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (conn, _) = listener.accept().unwrap();
+
+    thread::spawn(move || {
+        for _ in 0..chunks {
+            if peer.write_all(b"1\r\na\r\n").is_err() {
+                return;
+            }
+            thread::sleep(interval);
+        }
+        thread::sleep(Duration::from_secs(5));
+    });
+    conn
+}
+
+#[test]
+fn stalled_body() {
+    let mut conn = trickle(1, Duration::ZERO);
+    let mut reader = RequestReader::new();
+    let started = Instant::now();
+    let mut body = reader.request_body(&mut conn, BodyFraming::Chunked, 0, false, Duration::from_millis(300), Duration::ZERO);
+    assert_eq!(read_body(&mut body), Err(StatusCode::REQUEST_TIMEOUT));
+    assert!(!body.is_complete());
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn trickled_body() {
+    // Every chunk arrives within the idle limit, the whole body does not.
+    let mut conn = trickle(20, Duration::from_millis(100));
+    let mut reader = RequestReader::new();
+    let started = Instant::now();
+    let mut body = reader.request_body(&mut conn, BodyFraming::Chunked, 0, false, Duration::from_millis(500), Duration::from_millis(600));
+    let mut data = vec![0; 16];
+    let err = loop {
+        match body.read(&mut data) {
+            Ok(n) => assert!(n > 0),
+            Err(err) => break err,
+        }
+    };
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(600) && elapsed < Duration::from_millis(1500), "{:?}", elapsed);
+}
@@ -1,11 +1,62 @@
 extern crate percent_encoding;
 use self::percent_encoding::percent_decode;
+use std::fmt;
+
+use crate::http::status::StatusCode;
+
+// How the body of a request is delimited, RFC 9112 section 6.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BodyFraming {
+    None,
+    Length(u64),
+    Chunked,
+}
+
+// Protocol version of a request line, RFC 9112 section 2.3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl Version {
+    // A simple request, a request line without a version.
+    pub const HTTP_09: Version = Version { major: 0, minor: 9 };
+    pub const HTTP_10: Version = Version { major: 1, minor: 0 };
+    pub const HTTP_11: Version = Version { major: 1, minor: 1 };
+
+    // `HTTP/x.y` with single digits.
+    fn parse(token: &str) -> Option<Version> {
+        match token.strip_prefix("HTTP/")?.as_bytes() {
+            [major, b'.', minor] if major.is_ascii_digit() && minor.is_ascii_digit() => Some(Version {
+                major: major - b'0',
+                minor: minor - b'0',
+            }),
+            _ => None,
+        }
+    }
+
+    // Any HTTP/1.x is served, a minor version above 1 as HTTP/1.1.
+    pub fn is_supported(&self) -> bool {
+        self.major == 1
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HTTP/{}.{}", self.major, self.minor)
+    }
+}
 
 #[derive(Debug)]
 pub struct HTTPRequest {
     pub method: String,    
     pub path: String,
+    // Request target as sent, still encoded and with the query.
+    pub uri: String,
     pub isAutoIndex: bool,
+    pub version: Version,
+    pub headers: Vec<(String, String)>,
 }
 
 impl HTTPRequest {
@@ -13,12 +64,18 @@ impl HTTPRequest {
         HTTPRequest {
             method: String::new(),
             path: String::new(),
+            uri: String::new(),
             isAutoIndex: false,
+            version: Version::HTTP_11,
+            headers: Vec::new(),
         }
     }
 
     pub fn parse(buffer: &[u8]) -> Result<(HTTPRequest), ()> {
-        let stringRaw = std::str::from_utf8(buffer).unwrap();
+        let stringRaw = match std::str::from_utf8(buffer) {
+            Ok(raw) => raw,
+            Err(_) => return Err(()),
+        };
         let firstLine = stringRaw.split("\r\n").nth(0).unwrap();
 
         if firstLine.len() == 0 {
@@ -27,26 +84,146 @@ impl HTTPRequest {
 
         let requestVec: Vec<&str> = firstLine.split(" ").collect();
 
-        if requestVec.len() < 3 {
+        let version = match requestVec.len() {
+            2 => Version::HTTP_09,
+            3 => Version::parse(requestVec[2]).ok_or(())?,
+            _ => return Err(()),
+        };
+
+        let method = requestVec[0];
+        if !is_token(method) {
             return Err(());
         }
+        // Nothing else is read from a request that is answered with 505.
+        if !version.is_supported() {
+            return Ok(HTTPRequest{
+                method: method.to_owned(),
+                version,
+                ..HTTPRequest::new()
+            });
+        }
 
-        let (parsedPath, isAutoIndex) = match parsePath(requestVec[1]) {
-            Ok((p, i)) => (p, i),
-            Err(()) => return Err(()),
+        // `*` addresses the server rather than a resource, only OPTIONS
+        // can ask about it.
+        let (parsedPath, isAutoIndex) = match requestVec[1] {
+            "*" if method == "OPTIONS" => (String::from("*"), false),
+            target => parsePath(target)?,
         };
 
         Ok(HTTPRequest{
-            method: match requestVec[0] {
-                "GET" => String::from("GET"),
-                "HEAD" => String::from("HEAD"),
-                _ => return Err(()),
-            },
+            method: method.to_owned(),
             path: parsedPath,
+            uri: requestVec[1].to_owned(),
             isAutoIndex: isAutoIndex,
+            version,
+            headers: parseHeaders(stringRaw)?,
+        })
+    }
+
+    // Header names are case-insensitive, the first occurrence wins.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    // Every value of a list header, repeated headers included.
+    pub fn header_list(&self, name: &str) -> Vec<&str> {
+        self.headers.iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+            .flat_map(|(_, v)| v.split(','))
+            .map(|v| v.trim())
+            .filter(|v| !v.is_empty())
+            .collect()
+    }
+
+    // Transfer-Encoding would override Content-Length, but a request that
+    // sends both, or lengths that disagree, is how requests get smuggled
+    // past a proxy that reads it differently, so it is refused. The error
+    // is the status to answer with.
+    pub fn body_framing(&self) -> Result<BodyFraming, StatusCode> {
+        let has_encoding = self.header("Transfer-Encoding").is_some();
+        let has_length = self.header("Content-Length").is_some();
+        // HTTP/1.0 has no transfer codings, RFC 9112 section 6.1.
+        if has_encoding && (has_length || self.version < Version::HTTP_11) {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+
+        if has_encoding {
+            let encodings = self.header_list("Transfer-Encoding");
+            return match encodings.last() {
+                Some(last) if !last.eq_ignore_ascii_case("chunked") => Err(StatusCode::BAD_REQUEST),
+                Some(_) if encodings.len() == 1 => Ok(BodyFraming::Chunked),
+                Some(_) => Err(StatusCode::NOT_IMPLEMENTED),
+                None => Err(StatusCode::BAD_REQUEST),
+            };
+        }
+
+        let lengths = self.header_list("Content-Length");
+        let length = match lengths.first() {
+            Some(length) => *length,
+            None if has_length => return Err(StatusCode::BAD_REQUEST),
+            None => return Ok(BodyFraming::None),
+        };
+        // `+1` and the like parse as numbers but are not lengths.
+        if !length.bytes().all(|b| b.is_ascii_digit()) || lengths.iter().any(|l| *l != length) {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+
+        match length.parse() {
+            Ok(0) => Ok(BodyFraming::None),
+            Ok(length) => Ok(BodyFraming::Length(length)),
+            Err(_) => Err(StatusCode::BAD_REQUEST),
+        }
+    }
+
+    // A copy of the request for another target, encoded and with an
+    // optional query, as after a rewrite. None if the target is invalid.
+    pub fn with_target(&self, target: &str) -> Option<HTTPRequest> {
+        let (path, auto_index) = parsePath(target).ok()?;
+        Some(HTTPRequest{
+            method: self.method.clone(),
+            path,
+            uri: target.to_owned(),
+            isAutoIndex: auto_index,
+            version: self.version,
+            headers: self.headers.clone(),
         })
     }
 
+    // HTTP/1.1 connections persist unless the client sends `close`,
+    // HTTP/1.0 ones only when it asks for `keep-alive`.
+    pub fn wants_close(&self) -> bool {
+        let has = |option: &str| self.header_list("Connection").iter().any(|t| t.eq_ignore_ascii_case(option));
+        match self.version >= Version::HTTP_11 {
+            true => has("close"),
+            false => !has("keep-alive"),
+        }
+    }
+
+}
+
+// A field without a colon, whitespace in or around a name and folded
+// lines are refused, RFC 9112 section 5. Proxies in front may read such
+// fields differently and let a second request through.
+fn parseHeaders(raw: &str) -> Result<Vec<(String, String)>, ()> {
+    raw.split("\r\n")
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .map(|line| {
+            let (name, value) = line.split_once(':').ok_or(())?;
+            if !is_token(name) || value.contains(['\r', '\n']) {
+                return Err(());
+            }
+            Ok((name.to_owned(), value.trim_matches([' ', '\t']).to_owned()))
+        })
+        .collect()
+}
+
+// A method is a token, RFC 9110 section 5.6.2. Which methods are
+// implemented is up to the server.
+pub(crate) fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
 }
 
 fn parsePath(path: &str) -> Result<(String, bool), ()> {
@@ -57,10 +234,37 @@ fn parsePath(path: &str) -> Result<(String, bool), ()> {
         false => {},
     };
 
-    let decoded = percent_decode(rawPath.as_bytes()).decode_utf8().unwrap();
+    let decoded = match percent_decode(rawPath.as_bytes()).decode_utf8() {
+        Ok(decoded) => normalize_path(&decoded)?,
+        Err(_) => return Err(()),
+    };
     Ok(match decoded.split("/").last().unwrap() {
         "" => (format!("{}{}", decoded, "index.html"), true)
         ,
         _ => (String::from(decoded), false),
     })
 }
+
+// Collapses `//` and `/./` so that every spelling of a path maps to the same
+// string, rejects `..` segments that only show up after decoding.
+pub fn normalize_path(path: &str) -> Result<String, ()> {
+    if !path.starts_with('/') {
+        return Err(());
+    }
+
+    let mut segments = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {},
+            ".." => return Err(()),
+            s => segments.push(s),
+        }
+    }
+
+    let mut normalized = format!("/{}", segments.join("/"));
+    if !segments.is_empty() && (path.ends_with('/') || path.ends_with("/.")) {
+        normalized.push('/');
+    }
+
+    Ok(normalized)
+}
@@ -1,5 +1,6 @@
 // use dz1::::request;
 use super::request;
+use super::status::StatusCode;
 
 struct TestCase {
     raw_http: &'static [u8],
@@ -10,7 +11,7 @@ struct TestCase {
 fn get_method_parse() {
     let testCase = TestCase{
         raw_http: "GET /foo/bar/ HTTP/1.1".as_bytes(),
-        expected: request::HTTPRequest{method: String::from("GET"), path: String::from(""), isAutoIndex: true},
+        expected: request::HTTPRequest{method: String::from("GET"), path: String::from(""), isAutoIndex: true, ..request::HTTPRequest::new()},
     };
 
     let result = match request::HTTPRequest::parse(testCase.raw_http) {
@@ -23,7 +24,7 @@ fn get_method_parse() {
 fn test_auto_index_true() {
     let testCase = TestCase{
         raw_http: "GET /foo/bar/ HTTP/1.1".as_bytes(),
-        expected: request::HTTPRequest{method: String::from("GET"), path: String::from(""), isAutoIndex: true},
+        expected: request::HTTPRequest{method: String::from("GET"), path: String::from(""), isAutoIndex: true, ..request::HTTPRequest::new()},
     };
 
     let result = match request::HTTPRequest::parse(testCase.raw_http) {
@@ -36,7 +37,7 @@ fn test_auto_index_true() {
 fn test_auto_index_false() {
     let testCase = TestCase{
         raw_http: "GET /foo/bar/kek.html HTTP/1.1".as_bytes(),
-        expected: request::HTTPRequest{method: String::from("GET"), path: String::from(""), isAutoIndex: false},
+        expected: request::HTTPRequest{method: String::from("GET"), path: String::from(""), isAutoIndex: false, ..request::HTTPRequest::new()},
     };
 
     let result = match request::HTTPRequest::parse(testCase.raw_http) {
@@ -49,7 +50,7 @@ fn test_auto_index_false() {
 fn head_method_parse() {
     let testCase = TestCase{
         raw_http: "HEAD /foo/bar/ HTTP/1.1".as_bytes(),
-        expected: request::HTTPRequest{method: String::from("HEAD"), path: String::from(""), isAutoIndex: true},
+        expected: request::HTTPRequest{method: String::from("HEAD"), path: String::from(""), isAutoIndex: true, ..request::HTTPRequest::new()},
     };
 
     let result = match request::HTTPRequest::parse(testCase.raw_http) {
@@ -61,7 +62,7 @@ fn head_method_parse() {
 #[test]
 fn not_valid_method_parse() {
     let testCase = TestCase{
-        raw_http: "POST /foo/bar/ HTTP/1.1".as_bytes(),
+        raw_http: "BR(EW /foo/bar/ HTTP/1.1".as_bytes(),
         expected: request::HTTPRequest::new(),
     };
 
@@ -75,7 +76,7 @@ fn not_valid_method_parse() {
 fn valid_path_parse() {
     let testCase = TestCase{
         raw_http: "HEAD /foo/bar HTTP/1.1".as_bytes(),
-        expected: request::HTTPRequest{method: String::from("HEAD"), path: String::from("/foo/bar"), isAutoIndex: true},
+        expected: request::HTTPRequest{method: String::from("HEAD"), path: String::from("/foo/bar"), isAutoIndex: true, ..request::HTTPRequest::new()},
     };
 
     let result = match request::HTTPRequest::parse(testCase.raw_http) {
@@ -88,7 +89,7 @@ fn valid_path_parse() {
 fn index_valid_path_parse() {
     let testCase = TestCase{
         raw_http: "HEAD /foo/bar/ HTTP/1.1".as_bytes(),
-        expected: request::HTTPRequest{method: String::from("HEAD"), path: String::from("/foo/bar/index.html"), isAutoIndex: true},
+        expected: request::HTTPRequest{method: String::from("HEAD"), path: String::from("/foo/bar/index.html"), isAutoIndex: true, ..request::HTTPRequest::new()},
     };
 
     let result = match request::HTTPRequest::parse(testCase.raw_http) {
@@ -101,7 +102,7 @@ fn index_valid_path_parse() {
 fn with_query_valid_path_parse() {
     let testCase = TestCase{
         raw_http: "HEAD /foo/bar/kek.html?asdsa HTTP/1.1".as_bytes(),
-        expected: request::HTTPRequest{method: String::from("HEAD"), path: String::from("/foo/bar/kek.html"), isAutoIndex: false},
+        expected: request::HTTPRequest{method: String::from("HEAD"), path: String::from("/foo/bar/kek.html"), isAutoIndex: false, ..request::HTTPRequest::new()},
     };
 
     let result = match request::HTTPRequest::parse(testCase.raw_http) {
@@ -115,7 +116,7 @@ fn with_query_valid_path_parse() {
 fn with_space_valid_path_parse() {
     let testCase = TestCase{
         raw_http: "HEAD /foo/bar/space%20in%20name.html HTTP/1.1".as_bytes(),
-        expected: request::HTTPRequest{method: String::from("HEAD"), path: String::from("/foo/bar/space in name.html"), isAutoIndex: false},
+        expected: request::HTTPRequest{method: String::from("HEAD"), path: String::from("/foo/bar/space in name.html"), isAutoIndex: false, ..request::HTTPRequest::new()},
     };
 
     let result = match request::HTTPRequest::parse(testCase.raw_http) {
@@ -128,7 +129,7 @@ fn with_space_valid_path_parse() {
 fn with_space_query_valid_path_parse() {
     let testCase = TestCase{
         raw_http: "HEAD /foo/bar/space%20in%20name.html?l&=1 HTTP/1.1".as_bytes(),
-        expected: request::HTTPRequest{method: String::from("HEAD"), path: String::from("/foo/bar/space in name.html"), isAutoIndex: false},
+        expected: request::HTTPRequest{method: String::from("HEAD"), path: String::from("/foo/bar/space in name.html"), isAutoIndex: false, ..request::HTTPRequest::new()},
     };
 
     let result = match request::HTTPRequest::parse(testCase.raw_http) {
@@ -142,7 +143,7 @@ fn with_space_query_valid_path_parse() {
 fn url_encode_valid_path_parse() {
     let testCase = TestCase{
         raw_http: "HEAD /foo/bar/%70%61%67%65%2e%68%74%6d%6c HTTP/1.1".as_bytes(),
-        expected: request::HTTPRequest{method: String::from("HEAD"), path: String::from("/foo/bar/page.html"), isAutoIndex: false},
+        expected: request::HTTPRequest{method: String::from("HEAD"), path: String::from("/foo/bar/page.html"), isAutoIndex: false, ..request::HTTPRequest::new()},
     };
 
     let result = match request::HTTPRequest::parse(testCase.raw_http) {
@@ -155,7 +156,7 @@ fn url_encode_valid_path_parse() {
 fn url_encode_query_valid_path_parse() {
     let testCase = TestCase{
         raw_http: "HEAD /foo/bar/%70%61%67%65%2e%68%74%6d%6c?asd=1&asd HTTP/1.1".as_bytes(),
-        expected: request::HTTPRequest{method: String::from("HEAD"), path: String::from("/foo/bar/page.html"), isAutoIndex: false},
+        expected: request::HTTPRequest{method: String::from("HEAD"), path: String::from("/foo/bar/page.html"), isAutoIndex: false, ..request::HTTPRequest::new()},
     };
 
     let result = match request::HTTPRequest::parse(testCase.raw_http) {
@@ -181,11 +182,156 @@ fn dot_escape_not_valid_path_parse() {
 fn file_with_dot_valid_path_parse() {
     let testCase = TestCase{
         raw_http: "HEAD /foo/bar/index..html HTTP/1.1".as_bytes(),
-        expected: request::HTTPRequest{method: String::from("HEAD"), path: String::from("/foo/bar/index..html"), isAutoIndex: false},
+        expected: request::HTTPRequest{method: String::from("HEAD"), path: String::from("/foo/bar/index..html"), isAutoIndex: false, ..request::HTTPRequest::new()},
     };
 
     let result = match request::HTTPRequest::parse(testCase.raw_http) {
         Ok(req) => assert_eq!(req.path, testCase.expected.path),
         Err(()) => panic!("Unexpected Err"),
     };
-}
\ No newline at end of file
+}
+
+#[test]
+fn headers_parse() {
+    let raw = "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: Keep-Alive, close\r\n\r\n".as_bytes();
+
+    match request::HTTPRequest::parse(raw) {
+        Ok(req) => {
+            assert_eq!(req.header("host"), Some("localhost"));
+            assert_eq!(req.wants_close(), true);
+        },
+        Err(()) => panic!("Unexpected Err"),
+    };
+}
+
+#[test]
+fn encoded_dot_escape_not_valid_path_parse() {
+    match request::HTTPRequest::parse("GET /%2e%2e/etc/passwd HTTP/1.1".as_bytes()) {
+        Ok(_) => panic!("Unexpected OK"),
+        Err(()) => {},
+    };
+}
+
+#[test]
+fn duplicate_slash_path_parse() {
+    match request::HTTPRequest::parse("GET //private/./a.html HTTP/1.1".as_bytes()) {
+        Ok(req) => assert_eq!(req.path, "/private/a.html"),
+        Err(()) => panic!("Unexpected Err"),
+    };
+}
+
+#[test]
+fn raw_uri_parse() {
+    match request::HTTPRequest::parse("GET /a%20b/?x=1 HTTP/1.1".as_bytes()) {
+        Ok(req) => assert_eq!(req.uri, "/a%20b/?x=1"),
+        Err(()) => panic!("Unexpected Err"),
+    };
+}
+
+#[test]
+fn post_method_parse() {
+    match request::HTTPRequest::parse("POST /form HTTP/1.1\r\n\r\n".as_bytes()) {
+        Ok(req) => assert_eq!(req.method, "POST"),
+        Err(()) => panic!("Unexpected Err"),
+    };
+}
+
+#[test]
+fn extension_method_parse() {
+    match request::HTTPRequest::parse("BREW /pot HTTP/1.1\r\n\r\n".as_bytes()) {
+        Ok(req) => assert_eq!(req.method, "BREW"),
+        Err(()) => panic!("Unexpected Err"),
+    };
+    assert!(request::HTTPRequest::parse("G@T /pot HTTP/1.1\r\n\r\n".as_bytes()).is_err());
+    assert!(request::HTTPRequest::parse(" /pot HTTP/1.1\r\n\r\n".as_bytes()).is_err());
+}
+
+#[test]
+fn options_asterisk_parse() {
+    match request::HTTPRequest::parse("OPTIONS * HTTP/1.1\r\n\r\n".as_bytes()) {
+        Ok(req) => assert_eq!(req.path, "*"),
+        Err(()) => panic!("Unexpected Err"),
+    };
+    assert!(request::HTTPRequest::parse("GET * HTTP/1.1\r\n\r\n".as_bytes()).is_err());
+}
+
+#[test]
+fn version_parse() {
+    let version = |line: &str| request::HTTPRequest::parse(format!("{}\r\n\r\n", line).as_bytes()).map(|r| r.version);
+
+    assert_eq!(version("GET / HTTP/1.1"), Ok(request::Version::HTTP_11));
+    assert_eq!(version("GET / HTTP/1.0"), Ok(request::Version::HTTP_10));
+    assert_eq!(version("GET /"), Ok(request::Version::HTTP_09));
+    assert_eq!(version("GET / HTTP/1.2").map(|v| v.is_supported()), Ok(true));
+    assert_eq!(version("PRI * HTTP/2.0").map(|v| v.is_supported()), Ok(false));
+    assert_eq!(version("GET / HTTP/3.0").map(|v| v.to_string()), Ok(String::from("HTTP/3.0")));
+
+    assert!(version("GET / HTTP/1").is_err());
+    assert!(version("GET / HTTP/1.10").is_err());
+    assert!(version("GET / http/1.1").is_err());
+    assert!(version("GET /a b HTTP/1.1").is_err());
+}
+
+#[test]
+fn http10_connections() {
+    let close = |raw: &str| request::HTTPRequest::parse(raw.as_bytes()).unwrap().wants_close();
+
+    assert!(close("GET / HTTP/1.0\r\n\r\n"));
+    assert!(!close("GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n"));
+    assert!(!close("GET / HTTP/1.1\r\n\r\n"));
+    assert!(close("GET / HTTP/1.1\r\nConnection: close\r\n\r\n"));
+
+    let framing = request::HTTPRequest::parse("POST / HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n".as_bytes()).unwrap().body_framing();
+    assert_eq!(framing, Err(StatusCode::BAD_REQUEST));
+}
+
+#[test]
+fn body_framing_parse() {
+    let framing = |headers: &str| {
+        let raw = format!("POST / HTTP/1.1\r\n{}\r\n", headers);
+        request::HTTPRequest::parse(raw.as_bytes()).unwrap().body_framing()
+    };
+
+    assert_eq!(framing(""), Ok(request::BodyFraming::None));
+    assert_eq!(framing("Content-Length: 0\r\n"), Ok(request::BodyFraming::None));
+    assert_eq!(framing("Content-Length: 42\r\n"), Ok(request::BodyFraming::Length(42)));
+    assert_eq!(framing("Content-Length: 42, 42\r\nContent-Length: 42\r\n"), Ok(request::BodyFraming::Length(42)));
+    assert_eq!(framing("Transfer-Encoding: Chunked\r\n"), Ok(request::BodyFraming::Chunked));
+}
+
+#[test]
+fn body_framing_smuggling_rejected() {
+    let framing = |headers: &str| {
+        let raw = format!("POST / HTTP/1.1\r\n{}\r\n", headers);
+        request::HTTPRequest::parse(raw.as_bytes()).unwrap().body_framing()
+    };
+
+    assert_eq!(framing("Content-Length: 5\r\nTransfer-Encoding: chunked\r\n"), Err(StatusCode::BAD_REQUEST));
+    assert_eq!(framing("Content-Length: 5\r\nContent-Length: 6\r\n"), Err(StatusCode::BAD_REQUEST));
+    assert_eq!(framing("Content-Length: +5\r\n"), Err(StatusCode::BAD_REQUEST));
+    assert_eq!(framing("Content-Length: \r\n"), Err(StatusCode::BAD_REQUEST));
+    assert_eq!(framing("Content-Length: 99999999999999999999\r\n"), Err(StatusCode::BAD_REQUEST));
+    assert_eq!(framing("Transfer-Encoding: chunked, identity\r\n"), Err(StatusCode::BAD_REQUEST));
+    assert_eq!(framing("Transfer-Encoding: \r\n"), Err(StatusCode::BAD_REQUEST));
+    assert_eq!(framing("Transfer-Encoding: gzip, chunked\r\n"), Err(StatusCode::NOT_IMPLEMENTED));
+    assert_eq!(framing("Transfer-Encoding: chunked\r\nTransfer-Encoding: chunked\r\n"), Err(StatusCode::NOT_IMPLEMENTED));
+}
+
+#[test]
+fn malformed_fields_rejected() {
+    let parse = |headers: &str| request::HTTPRequest::parse(format!("POST / HTTP/1.1\r\n{}\r\n", headers).as_bytes());
+
+    assert!(parse("Transfer-Encoding : chunked\r\n").is_err());
+    assert!(parse(" Transfer-Encoding: chunked\r\n").is_err());
+    assert!(parse("Transfer Encoding: chunked\r\n").is_err());
+    assert!(parse("Host: a\r\nTransfer-Encoding chunked\r\n").is_err());
+    assert!(parse(": chunked\r\n").is_err());
+    // obs-fold
+    assert!(parse("X-A: 1\r\n chunked\r\n").is_err());
+    assert!(parse("X-A: 1\r\n\tchunked\r\n").is_err());
+    assert!(parse("X-A: 1\rTransfer-Encoding: chunked\r\n").is_err());
+
+    let req = parse("X-A:\t1 2 \r\nX-B:\r\n").unwrap();
+    assert_eq!(req.header("X-A"), Some("1 2"));
+    assert_eq!(req.header("X-B"), Some(""));
+}
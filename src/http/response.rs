@@ -1,87 +1,88 @@
 extern crate chrono;
 
-use std::collections::{HashMap};
-use std::net::{TcpStream};
 use std::path::Path;
 use std::fs::File;
+use std::io;
 use std::io::prelude::*;
-use std::io::SeekFrom;
+use std::time::{Duration, Instant};
 use std::string::String;
 use chrono::{DateTime, TimeZone, NaiveDateTime, UTC};
 
+use crate::http::status::StatusCode;
+
 const HTTP_VERSION: &str = "HTTP/1.1";
 const HTTP_TERMINATOR: &str = "\r\n";
+const SEND_CHUNK: usize = 64 * 1024;
+// Slow starts are tolerated for this long before the rate is enforced.
+const RATE_GRACE: Duration = Duration::from_secs(1);
 
 pub struct HTTPResponse {
-    pub headers: HashMap<String, String>,
-    status: Option<String>,
+    // Sent in insertion order, a name may repeat (Set-Cookie).
+    pub headers: Vec<(String, String)>,
+    status: StatusCode,
+    // Overrides the registered reason phrase, for gateways passing on the
+    // one of their backend.
+    reason: Option<String>,
     pub file: Option<File>,
+    // Body of unknown length, sent with chunked encoding unless a
+    // Content-Length header is set.
+    pub stream: Option<Box<dyn Read + Send>>,
+    // For HTTP/1.0 clients, which do not know chunked encoding. A stream
+    // without Content-Length is sent as is and ends with the connection.
+    pub close_delimited: bool,
 }
 
 impl HTTPResponse {
+    pub fn builder() -> ResponseBuilder {
+        ResponseBuilder {
+            resp: HTTPResponse::new(),
+        }
+    }
+
     pub fn new() -> HTTPResponse{
         HTTPResponse{
-            headers: HashMap::new(),
-            status: None,
+            headers: Vec::new(),
+            status: StatusCode::OK,
+            reason: None,
             file: None,
+            stream: None,
+            close_delimited: false,
         }
     }
-    pub fn send(self, mut stream: &TcpStream) {
+    // Writes the response. Every write is bounded by the stream's write
+    // timeout; `min_rate` (bytes per second, 0 disables) additionally aborts
+    // transfers to clients that read too slowly.
+    pub fn send<W: Write>(self, stream: &mut W, min_rate: u64) -> io::Result<()> {
+        let chunked = self.stream.is_some() && !self.has_header("Content-Length") && !self.close_delimited;
         let mut response = String::new();
         response.push_str(HTTP_VERSION);
         response.push_str(" ");
-        response.push_str(&self.status.unwrap()[..]);
+        response.push_str(&format!("{} {}", self.status.as_u16(), self.reason()));
         response.push_str(HTTP_TERMINATOR);
 
         for (header, value) in &self.headers {
             response.push_str(format!("{}: {}", header, value).as_str());
             response.push_str(HTTP_TERMINATOR);
         }
+        if chunked {
+            response.push_str("Transfer-Encoding: chunked");
+            response.push_str(HTTP_TERMINATOR);
+        }
         response.push_str(HTTP_TERMINATOR);
 
-        stream.write(response.as_bytes()).unwrap();
-        match self.file {
-            Some(mut f) => {
-                let mut buf = [0; 1024 * 1024];
-                let mut n: u64 = 0;
-                loop {
-                    match f.read(&mut buf).unwrap() {
-                        0 => { 
-                            break; 
-                        },
-                        i => {
-                            n += i as u64;
-                            stream.write(&buf[..i]).unwrap();
-                            f.seek(SeekFrom::Start(n as u64));
-                        }
-                    }
-                }
-            }
-            None => {}
+        let started = Instant::now();
+        stream.write_all(response.as_bytes())?;
+        let sent = response.len() as u64;
+        match (self.file, self.stream) {
+            (Some(mut f), _) => copy_body(&mut f, stream, false, sent, started, min_rate)?,
+            (None, Some(mut body)) => copy_body(&mut body, stream, chunked, sent, started, min_rate)?,
+            (None, None) => {},
         }
-        stream.flush().unwrap();
+        stream.flush()
     }
 
-    pub fn setContentType(&mut self, path: &Path) {
-        let ext = match path.extension() {
-            Some(ext) => ext,
-            None => panic!("no ext")
-        };
-        let content_type = HTTPResponse::get_content_type_by_ext(ext.to_str().unwrap());
-        self.push_header("Content-Type".to_owned(), content_type);
-    }
-
-    fn get_content_type_by_ext(ext: &str) -> String{
-        match ext {
-            "html" => String::from("text/html"),
-            "css" => String::from("text/css"),
-            "js" => String::from("application/javascript"),
-            "jpeg" | "jpg" => String::from("image/jpeg"),
-            "png" => String::from("image/png"),
-            "swf" => String::from("application/x-shockwave-flash"),
-            "gif" => String::from("image/gif"),
-            _ => String::from("application/chiki_briki"),
-        }
+    pub fn setContentType(&mut self, content_type: &str) {
+        self.push_header("Content-Type".to_owned(), content_type.to_owned());
     }
 
     pub fn setContentLength(&mut self, path: &Path) {
@@ -94,37 +95,52 @@ impl HTTPResponse {
         self.push_header("Content-Length".to_owned(), format!("{}",content_len));
     }
 
+    // Replaces any header with the same name.
     pub fn push_header(&mut self, header: String, value: String) {
-        self.headers.insert(header, value);
+        self.headers.retain(|(name, _)| !name.eq_ignore_ascii_case(&header));
+        self.headers.push((header, value));
     }
 
-    pub fn setOk(&mut self, file: Option<File>) {
-        match file {
-            Some(file) => self.file = Some(file),
-            None => self.file = None,
-        }
+    // Adds a header even if one with the same name is already set.
+    pub fn add_header(&mut self, header: String, value: String) {
+        self.headers.push((header, value));
+    }
 
-        self.status = Some("200 OK".to_string());
+    pub fn header(&self, header: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(header))
+            .map(|(_, value)| value.as_str())
     }
-    
-    pub fn setNotFound(&mut self) {
-        self.file = None;
-        self.status = Some("404 Not Found".to_string());
+
+    pub fn has_header(&self, header: &str) -> bool {
+        self.header(header).is_some()
     }
 
-    pub fn setBadRequest(&mut self) {
-        self.file = None;
-        self.status = Some("400 Bad Request".to_string());
+    pub fn status(&self) -> StatusCode {
+        self.status
     }
 
-    pub fn setNotAllowed(&mut self) {
-        self.file = None;
-        self.status = Some("405 Not Allowed".to_string());
+    pub fn reason(&self) -> &str {
+        self.reason.as_deref().unwrap_or_else(|| self.status.reason())
+    }
+
+    pub fn setStatus(&mut self, status: StatusCode) {
+        self.status = status;
+        self.reason = None;
+    }
+
+    pub fn setReason(&mut self, reason: String) {
+        self.reason = Some(reason);
     }
 
-    pub fn set403(&mut self) {
+    pub fn setStream(&mut self, body: Box<dyn Read + Send>) {
         self.file = None;
-        self.status = Some("403 Forbidden".to_string());
+        self.stream = Some(body);
+    }
+
+    pub fn setFile(&mut self, file: File) {
+        self.stream = None;
+        self.file = Some(file);
     }
 
     pub fn setDate(&mut self) {
@@ -139,4 +155,136 @@ impl HTTPResponse {
     pub fn setConnection(&mut self, conn: &str) {
         self.push_header("Connection".to_owned(), conn.to_owned());
     }
-}
\ No newline at end of file
+}
+
+// What a response carries after its head.
+pub enum Body {
+    Empty,
+    Bytes(Vec<u8>),
+    // Sent with the Content-Length of its metadata.
+    File(File),
+    // Of unknown length, see HTTPResponse::stream.
+    Stream(Box<dyn Read + Send>),
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Body {
+        Body::Bytes(bytes)
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Body {
+        Body::Bytes(text.into_bytes())
+    }
+}
+
+impl From<&str> for Body {
+    fn from(text: &str) -> Body {
+        Body::Bytes(text.as_bytes().to_vec())
+    }
+}
+
+impl From<File> for Body {
+    fn from(file: File) -> Body {
+        Body::File(file)
+    }
+}
+
+impl From<Box<dyn Read + Send>> for Body {
+    fn from(stream: Box<dyn Read + Send>) -> Body {
+        Body::Stream(stream)
+    }
+}
+
+// Assembles a response, headers are sent in the order they were added.
+pub struct ResponseBuilder {
+    resp: HTTPResponse,
+}
+
+impl ResponseBuilder {
+    pub fn status(mut self, status: StatusCode) -> ResponseBuilder {
+        self.resp.setStatus(status);
+        self
+    }
+
+    pub fn reason(mut self, reason: String) -> ResponseBuilder {
+        self.resp.setReason(reason);
+        self
+    }
+
+    pub fn header(mut self, header: &str, value: &str) -> ResponseBuilder {
+        self.resp.add_header(header.to_owned(), value.to_owned());
+        self
+    }
+
+    // Bytes and files get a Content-Length unless one was set.
+    pub fn body<B: Into<Body>>(mut self, body: B) -> HTTPResponse {
+        let length = match body.into() {
+            Body::Empty => None,
+            Body::Bytes(bytes) => {
+                let length = bytes.len() as u64;
+                self.resp.setStream(Box::new(io::Cursor::new(bytes)));
+                Some(length)
+            },
+            Body::File(file) => {
+                let length = file.metadata().map(|meta| meta.len()).ok();
+                self.resp.setFile(file);
+                length
+            },
+            Body::Stream(stream) => {
+                self.resp.setStream(stream);
+                None
+            },
+        };
+        if let Some(length) = length.filter(|_| !self.resp.has_header("Content-Length")) {
+            self.resp.push_header("Content-Length".to_owned(), length.to_string());
+        }
+        self.resp
+    }
+
+    pub fn build(self) -> HTTPResponse {
+        self.body(Body::Empty)
+    }
+}
+
+fn copy_body<R: Read, W: Write>(body: &mut R, stream: &mut W, chunked: bool, mut sent: u64, started: Instant, min_rate: u64) -> io::Result<()> {
+    let mut buf = vec![0; SEND_CHUNK];
+    loop {
+        let i = match body.read(&mut buf) {
+            Ok(0) => break,
+            Ok(i) => i,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+
+        if chunked {
+            write!(stream, "{:x}\r\n", i)?;
+            stream.write_all(&buf[..i])?;
+            stream.write_all(b"\r\n")?;
+        } else {
+            stream.write_all(&buf[..i])?;
+        }
+        sent += i as u64;
+        check_rate(sent, started, min_rate)?;
+    }
+
+    if chunked {
+        stream.write_all(b"0\r\n\r\n")?;
+    }
+
+    Ok(())
+}
+
+fn check_rate(sent: u64, started: Instant, min_rate: u64) -> io::Result<()> {
+    let elapsed = started.elapsed();
+    if min_rate == 0 || elapsed < RATE_GRACE {
+        return Ok(());
+    }
+
+    if (sent as u128) * 1000 < (min_rate as u128) * elapsed.as_millis() {
+        return Err(io::Error::new(io::ErrorKind::TimedOut, "transfer rate below send_min_rate"));
+    }
+
+    Ok(())
+}
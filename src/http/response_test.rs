@@ -0,0 +1,78 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::Cursor;
+
+use super::response::HTTPResponse;
+use super::status::StatusCode;
+
+fn sent(resp: HTTPResponse) -> String {
+    let mut out = Vec::new();
+    resp.send(&mut out, 0).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+fn streamed(body: &str) -> HTTPResponse {
+    let mut resp = HTTPResponse::new();
+    resp.setStatus(StatusCode::OK);
+    resp.setStream(Box::new(Cursor::new(body.as_bytes().to_vec())));
+    resp
+}
+
+#[test]
+fn stream_is_chunked() {
+    assert_eq!(sent(streamed("hello")), "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n");
+}
+
+#[test]
+fn stream_with_length_is_not_chunked() {
+    let mut resp = streamed("hello");
+    resp.push_header("Content-Length".to_owned(), "5".to_owned());
+    assert_eq!(sent(resp), "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello");
+}
+
+#[test]
+fn close_delimited_stream() {
+    let mut resp = streamed("hello");
+    resp.close_delimited = true;
+    assert_eq!(sent(resp), "HTTP/1.1 200 OK\r\n\r\nhello");
+}
+
+#[test]
+fn unset_status_is_ok() {
+    assert_eq!(sent(HTTPResponse::new()), "HTTP/1.1 200 OK\r\n\r\n");
+}
+
+#[test]
+fn reason_override() {
+    let mut resp = HTTPResponse::new();
+    resp.setStatus(StatusCode::NOT_FOUND);
+    resp.setReason("Missing".to_owned());
+    assert_eq!(sent(resp), "HTTP/1.1 404 Missing\r\n\r\n");
+}
+
+#[test]
+fn builder_keeps_header_order() {
+    let resp = HTTPResponse::builder()
+        .status(StatusCode::CREATED)
+        .header("X-B", "1")
+        .header("Set-Cookie", "a=1")
+        .header("X-A", "2")
+        .header("Set-Cookie", "b=2")
+        .body("hello");
+    assert_eq!(sent(resp), "HTTP/1.1 201 Created\r\nX-B: 1\r\nSet-Cookie: a=1\r\nX-A: 2\r\nSet-Cookie: b=2\r\nContent-Length: 5\r\n\r\nhello");
+}
+
+#[test]
+fn builder_bodies() {
+    let resp = HTTPResponse::builder().status(StatusCode::NO_CONTENT).build();
+    assert_eq!(sent(resp), "HTTP/1.1 204 No Content\r\n\r\n");
+
+    let stream: Box<dyn Read + Send> = Box::new(Cursor::new(b"abc".to_vec()));
+    assert_eq!(sent(HTTPResponse::builder().body(stream)), "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n");
+
+    let path = std::env::temp_dir().join(format!("dz1-response-{}", std::process::id()));
+    std::fs::write(&path, "file body").unwrap();
+    let resp = HTTPResponse::builder().body(File::open(&path).unwrap());
+    assert_eq!(sent(resp), "HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\nfile body");
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,33 @@
+use super::status::StatusCode;
+
+#[test]
+fn registered_codes() {
+    assert_eq!(StatusCode::OK.to_string(), "200 OK");
+    assert_eq!(StatusCode::METHOD_NOT_ALLOWED.to_string(), "405 Method Not Allowed");
+    assert_eq!(StatusCode::CONTENT_TOO_LARGE.reason(), "Content Too Large");
+    assert_eq!(StatusCode::from_u16(207), Some(StatusCode::MULTI_STATUS));
+    assert_eq!(StatusCode::NETWORK_AUTHENTICATION_REQUIRED.as_u16(), 511);
+    assert_eq!(StatusCode::default(), StatusCode::OK);
+}
+
+#[test]
+fn unregistered_codes() {
+    let status = StatusCode::from_u16(299).unwrap();
+    assert_eq!(status.canonical_reason(), None);
+    assert_eq!(status.to_string(), "299 ");
+    // 306 is reserved, 418 unused.
+    assert_eq!(StatusCode::from_u16(306).unwrap().canonical_reason(), None);
+    assert_eq!(StatusCode::from_u16(418).unwrap().canonical_reason(), None);
+
+    assert!(StatusCode::from_u16(99).is_none());
+    assert!(StatusCode::from_u16(1000).is_none());
+}
+
+#[test]
+fn classes() {
+    assert!(StatusCode::CONTINUE.is_informational());
+    assert!(StatusCode::NO_CONTENT.is_success());
+    assert!(StatusCode::PERMANENT_REDIRECT.is_redirection());
+    assert!(StatusCode::LOCKED.is_error());
+    assert!(!StatusCode::FOUND.is_error());
+}
@@ -1,4 +1,17 @@
+pub mod access;
+pub mod auth;
+pub mod cgi;
+pub mod dav;
+pub mod error_page;
+pub mod fastcgi;
+pub mod headers;
 pub mod http;
+pub mod mime;
+pub mod proxy;
+pub mod rewrite;
 pub mod config;
+pub mod security;
 pub mod server;
-pub mod thread_pool;
\ No newline at end of file
+pub mod systemd;
+pub mod thread_pool;
+pub mod tls;
\ No newline at end of file
@@ -39,6 +39,6 @@ fn main() {
         Err(err) => panic!(err),
     };
 
-    let server = Server::new(config.dir_root, config.thread_count, address, port);
+    let server = Server::new(config, address, port);
     server.start();
 }
\ No newline at end of file
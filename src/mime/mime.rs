@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::fs;
+
+pub const MIME_INVALID_FORMAT: &str = "Invalid mime types format";
+pub const DEFAULT_TYPE: &str = "application/octet-stream";
+
+// The common part of the mime.types nginx ships.
+const BUILTIN_TYPES: &[(&str, &[&str])] = &[
+    ("text/html", &["html", "htm", "shtml"]),
+    ("text/css", &["css"]),
+    ("text/xml", &["xml"]),
+    ("text/plain", &["txt", "text", "log", "conf", "ini"]),
+    ("text/csv", &["csv"]),
+    ("text/markdown", &["md", "markdown"]),
+    ("text/calendar", &["ics"]),
+    ("text/vcard", &["vcf"]),
+    ("text/mathml", &["mml"]),
+    ("text/vnd.sun.j2me.app-descriptor", &["jad"]),
+    ("text/vnd.wap.wml", &["wml"]),
+    ("text/x-component", &["htc"]),
+    ("image/gif", &["gif"]),
+    ("image/jpeg", &["jpeg", "jpg"]),
+    ("image/png", &["png"]),
+    ("image/apng", &["apng"]),
+    ("image/avif", &["avif"]),
+    ("image/svg+xml", &["svg", "svgz"]),
+    ("image/tiff", &["tif", "tiff"]),
+    ("image/vnd.wap.wbmp", &["wbmp"]),
+    ("image/webp", &["webp"]),
+    ("image/x-icon", &["ico"]),
+    ("image/x-jng", &["jng"]),
+    ("image/bmp", &["bmp"]),
+    ("font/woff", &["woff"]),
+    ("font/woff2", &["woff2"]),
+    ("font/ttf", &["ttf"]),
+    ("font/otf", &["otf"]),
+    ("application/javascript", &["js", "mjs"]),
+    ("application/json", &["json", "map"]),
+    ("application/ld+json", &["jsonld"]),
+    ("application/manifest+json", &["webmanifest"]),
+    ("application/wasm", &["wasm"]),
+    ("application/atom+xml", &["atom"]),
+    ("application/rss+xml", &["rss"]),
+    ("application/xhtml+xml", &["xhtml"]),
+    ("application/java-archive", &["jar", "war", "ear"]),
+    ("application/mac-binhex40", &["hqx"]),
+    ("application/msword", &["doc"]),
+    ("application/pdf", &["pdf"]),
+    ("application/postscript", &["ps", "eps", "ai"]),
+    ("application/rtf", &["rtf"]),
+    ("application/vnd.apple.mpegurl", &["m3u8"]),
+    ("application/vnd.google-earth.kml+xml", &["kml"]),
+    ("application/vnd.google-earth.kmz", &["kmz"]),
+    ("application/vnd.ms-excel", &["xls"]),
+    ("application/vnd.ms-fontobject", &["eot"]),
+    ("application/vnd.ms-powerpoint", &["ppt"]),
+    ("application/vnd.oasis.opendocument.graphics", &["odg"]),
+    ("application/vnd.oasis.opendocument.presentation", &["odp"]),
+    ("application/vnd.oasis.opendocument.spreadsheet", &["ods"]),
+    ("application/vnd.oasis.opendocument.text", &["odt"]),
+    ("application/vnd.openxmlformats-officedocument.presentationml.presentation", &["pptx"]),
+    ("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet", &["xlsx"]),
+    ("application/vnd.openxmlformats-officedocument.wordprocessingml.document", &["docx"]),
+    ("application/vnd.wap.wmlc", &["wmlc"]),
+    ("application/x-7z-compressed", &["7z"]),
+    ("application/x-bzip2", &["bz2"]),
+    ("application/x-cocoa", &["cco"]),
+    ("application/x-java-archive-diff", &["jardiff"]),
+    ("application/x-java-jnlp-file", &["jnlp"]),
+    ("application/x-makeself", &["run"]),
+    ("application/x-perl", &["pl", "pm"]),
+    ("application/x-pilot", &["prc", "pdb"]),
+    ("application/x-rar-compressed", &["rar"]),
+    ("application/x-redhat-package-manager", &["rpm"]),
+    ("application/x-sea", &["sea"]),
+    ("application/x-shockwave-flash", &["swf"]),
+    ("application/x-stuffit", &["sit"]),
+    ("application/x-tar", &["tar"]),
+    ("application/x-tcl", &["tcl", "tk"]),
+    ("application/x-x509-ca-cert", &["der", "pem", "crt"]),
+    ("application/x-xpinstall", &["xpi"]),
+    ("application/xspf+xml", &["xspf"]),
+    ("application/gzip", &["gz", "tgz"]),
+    ("application/x-xz", &["xz"]),
+    ("application/zstd", &["zst"]),
+    ("application/zip", &["zip"]),
+    ("application/octet-stream", &["bin", "exe", "dll", "deb", "dmg", "iso", "img", "msi", "msp", "msm"]),
+    ("audio/midi", &["mid", "midi", "kar"]),
+    ("audio/mpeg", &["mp3"]),
+    ("audio/ogg", &["ogg", "oga", "opus"]),
+    ("audio/flac", &["flac"]),
+    ("audio/wav", &["wav"]),
+    ("audio/aac", &["aac"]),
+    ("audio/webm", &["weba"]),
+    ("audio/x-m4a", &["m4a"]),
+    ("audio/x-realaudio", &["ra"]),
+    ("video/3gpp", &["3gpp", "3gp"]),
+    ("video/mp2t", &["ts"]),
+    ("video/mp4", &["mp4", "m4v"]),
+    ("video/mpeg", &["mpeg", "mpg"]),
+    ("video/ogg", &["ogv"]),
+    ("video/quicktime", &["mov"]),
+    ("video/webm", &["webm"]),
+    ("video/x-flv", &["flv"]),
+    ("video/x-matroska", &["mkv"]),
+    ("video/x-mng", &["mng"]),
+    ("video/x-ms-asf", &["asx", "asf"]),
+    ("video/x-ms-wmv", &["wmv"]),
+    ("video/x-msvideo", &["avi"]),
+];
+
+// Types sent with `charset=utf-8` besides text/*.
+const TEXT_TYPES: [&str; 4] = ["application/javascript", "application/json", "application/xml", "image/svg+xml"];
+
+// Extension to type mappings and a default type. The server and every
+// location have one, the built-in table sits below them.
+#[derive(Debug, Default)]
+pub struct MimeTypes {
+    types: HashMap<String, String>,
+    default: Option<String>,
+    // Whether files are sniffed, see sniff.rs.
+    sniff: Option<bool>,
+}
+
+impl MimeTypes {
+    // Accepts `types_file <path>`, `add_type <type> <ext>...`,
+    // `default_type <type>` and `sniff_types on|off`, returns false for any
+    // other directive.
+    pub fn parse_directive(&mut self, tokens: &[String]) -> Result<bool, String> {
+        let invalid = || format!("{}: {}", MIME_INVALID_FORMAT, tokens.join(" "));
+        match tokens[0].as_str() {
+            "types_file" if tokens.len() == 2 => {
+                let raw = fs::read_to_string(&tokens[1]).map_err(|err| format!("Error: {}: {}", tokens[1], err))?;
+                self.types.extend(parse_types(&raw)?);
+            },
+            "add_type" if tokens.len() >= 3 && is_type(&tokens[1]) => {
+                for ext in &tokens[2..] {
+                    self.types.insert(ext.trim_start_matches('.').to_ascii_lowercase(), tokens[1].clone());
+                }
+            },
+            "default_type" if tokens.len() == 2 && is_type(&tokens[1]) => self.default = Some(tokens[1].clone()),
+            "sniff_types" if tokens.len() == 2 && (tokens[1] == "on" || tokens[1] == "off") => self.sniff = Some(tokens[1] == "on"),
+            "types_file" | "add_type" | "default_type" | "sniff_types" => return Err(invalid()),
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    // The type configured for `ext`, which is lowercase.
+    pub fn get(&self, ext: &str) -> Option<&str> {
+        self.types.get(ext).map(|t| t.as_str())
+    }
+
+    pub fn default_type(&self) -> Option<&str> {
+        self.default.as_deref()
+    }
+
+    pub fn sniff(&self) -> Option<bool> {
+        self.sniff
+    }
+}
+
+// Reads a mime.types file in nginx (`types { text/html html; }`) or Apache
+// (`text/html html`) format into extension to type mappings.
+pub fn parse_types(raw: &str) -> Result<HashMap<String, String>, String> {
+    let text: String = raw.lines()
+        .map(|line| line.split('#').next().unwrap_or(""))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let entries: Vec<&str> = match (text.find('{'), text.rfind('}')) {
+        (Some(start), Some(end)) if start < end && text[..start].trim() == "types" => text[start + 1..end].split(';').collect(),
+        (None, None) => text.lines().collect(),
+        _ => return Err(String::from(MIME_INVALID_FORMAT)),
+    };
+
+    let mut types = HashMap::new();
+    for entry in entries {
+        let mut tokens = entry.split_whitespace();
+        let mime = match tokens.next() {
+            Some(mime) if is_type(mime) => mime,
+            Some(other) => return Err(format!("{}: {}", MIME_INVALID_FORMAT, other)),
+            None => continue,
+        };
+        for ext in tokens {
+            types.insert(ext.to_ascii_lowercase(), mime.to_owned());
+        }
+    }
+
+    Ok(types)
+}
+
+// The type in the built-in table.
+pub fn builtin_type(ext: &str) -> Option<&'static str> {
+    BUILTIN_TYPES.iter()
+        .find(|(_, exts)| exts.contains(&ext))
+        .map(|(mime, _)| *mime)
+}
+
+// The lowercase extension of the last segment of `path`, if it has one.
+// Dot files such as `.htaccess` have none.
+pub fn extension(path: &str) -> Option<String> {
+    let name = path.rsplit('/').next().unwrap_or("");
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() && !ext.is_empty() => Some(ext.to_ascii_lowercase()),
+        _ => None,
+    }
+}
+
+// The Content-Type value of `mime`, text gets a charset.
+pub fn with_charset(mime: &str) -> String {
+    let text = mime.starts_with("text/") || TEXT_TYPES.contains(&mime);
+    match text && !mime.contains("charset=") {
+        true => format!("{}; charset=utf-8", mime),
+        false => mime.to_owned(),
+    }
+}
+
+// The Content-Type of `path` from the built-in table alone.
+pub fn guess(path: &str) -> String {
+    let mime = extension(path).and_then(|ext| builtin_type(&ext)).unwrap_or(DEFAULT_TYPE);
+    with_charset(mime)
+}
+
+fn is_type(mime: &str) -> bool {
+    match mime.split_once('/') {
+        Some((top, sub)) => !top.is_empty() && !sub.is_empty() && !sub.contains('/'),
+        None => false,
+    }
+}
@@ -0,0 +1,69 @@
+use super::mime::*;
+
+fn tokens(line: &str) -> Vec<String> {
+    line.split_whitespace().map(String::from).collect()
+}
+
+#[test]
+fn builtin_table() {
+    assert_eq!(builtin_type("html"), Some("text/html"));
+    assert_eq!(builtin_type("woff2"), Some("font/woff2"));
+    assert_eq!(builtin_type("wasm"), Some("application/wasm"));
+    assert_eq!(builtin_type("nope"), None);
+
+    assert_eq!(guess("/docs/Index.HTML"), "text/html; charset=utf-8");
+    assert_eq!(guess("/app.js"), "application/javascript; charset=utf-8");
+    assert_eq!(guess("/photo.jpg"), "image/jpeg");
+    assert_eq!(guess("/README"), DEFAULT_TYPE);
+    assert_eq!(guess("/.htaccess"), DEFAULT_TYPE);
+    assert_eq!(guess("/dir.d/file"), DEFAULT_TYPE);
+}
+
+#[test]
+fn extensions() {
+    assert_eq!(extension("/a/b.tar.GZ"), Some(String::from("gz")));
+    assert_eq!(extension("/a/b."), None);
+    assert_eq!(extension("/a.b/"), None);
+    assert_eq!(extension("/.profile"), None);
+}
+
+#[test]
+fn charsets() {
+    assert_eq!(with_charset("text/plain"), "text/plain; charset=utf-8");
+    assert_eq!(with_charset("application/json"), "application/json; charset=utf-8");
+    assert_eq!(with_charset("text/html; charset=koi8-r"), "text/html; charset=koi8-r");
+    assert_eq!(with_charset("image/png"), "image/png");
+}
+
+#[test]
+fn nginx_format() {
+    let raw = "\n# shipped with nginx\ntypes {\n    text/html  html htm;\n    application/vnd.custom\n        cst CSX;\n}\n";
+    let types = parse_types(raw).unwrap();
+    assert_eq!(types.get("htm").map(String::as_str), Some("text/html"));
+    assert_eq!(types.get("csx").map(String::as_str), Some("application/vnd.custom"));
+
+    assert!(parse_types("types {\n text/html html;\n").is_err());
+    assert!(parse_types("types {\n html text/html;\n}").is_err());
+}
+
+#[test]
+fn apache_format() {
+    let raw = "# MIME type\t\tExtensions\napplication/activemessage\ntext/x-rst\t\trst\nimage/x-custom   cus cu2 # trailing\n";
+    let types = parse_types(raw).unwrap();
+    assert_eq!(types.len(), 3);
+    assert_eq!(types.get("cu2").map(String::as_str), Some("image/x-custom"));
+}
+
+#[test]
+fn directives() {
+    let mut mime = MimeTypes::default();
+    assert_eq!(mime.parse_directive(&tokens("add_type text/x-rst rst .RST2")), Ok(true));
+    assert_eq!(mime.parse_directive(&tokens("default_type text/plain")), Ok(true));
+    assert_eq!(mime.parse_directive(&tokens("allow all")), Ok(false));
+    assert_eq!(mime.get("rst2"), Some("text/x-rst"));
+    assert_eq!(mime.default_type(), Some("text/plain"));
+
+    assert!(mime.parse_directive(&tokens("add_type rst")).unwrap_err().starts_with(MIME_INVALID_FORMAT));
+    assert!(mime.parse_directive(&tokens("default_type plain")).unwrap_err().starts_with(MIME_INVALID_FORMAT));
+    assert!(mime.parse_directive(&tokens("types_file test/missing.types")).is_err());
+}
@@ -0,0 +1,6 @@
+pub mod mime;
+#[cfg(test)]
+mod mime_test;
+pub mod sniff;
+#[cfg(test)]
+mod sniff_test;
@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::mime::mime::DEFAULT_TYPE;
+
+// Bytes the WHATWG MIME Sniffing standard looks at, the resource header.
+pub const SNIFF_LENGTH: usize = 1445;
+// The cache is dropped as a whole once it holds this many files.
+const MAX_CACHED: usize = 10_000;
+
+// Signatures with a mask, from the image, audio and video, font and
+// archive type pattern tables of the standard.
+const SIGNATURES: &[(&[u8], &[u8], &str)] = &[
+    (b"\x00\x00\x01\x00", b"\xff\xff\xff\xff", "image/x-icon"),
+    (b"\x00\x00\x02\x00", b"\xff\xff\xff\xff", "image/x-icon"),
+    (b"BM", b"\xff\xff", "image/bmp"),
+    (b"GIF87a", b"\xff\xff\xff\xff\xff\xff", "image/gif"),
+    (b"GIF89a", b"\xff\xff\xff\xff\xff\xff", "image/gif"),
+    (b"RIFF\x00\x00\x00\x00WEBPVP", b"\xff\xff\xff\xff\x00\x00\x00\x00\xff\xff\xff\xff\xff\xff", "image/webp"),
+    (b"\x89PNG\r\n\x1a\n", b"\xff\xff\xff\xff\xff\xff\xff\xff", "image/png"),
+    (b"\xff\xd8\xff", b"\xff\xff\xff", "image/jpeg"),
+    (b"FORM\x00\x00\x00\x00AIFF", b"\xff\xff\xff\xff\x00\x00\x00\x00\xff\xff\xff\xff", "audio/aiff"),
+    (b"ID3", b"\xff\xff\xff", "audio/mpeg"),
+    (b"OggS\x00", b"\xff\xff\xff\xff\xff", "application/ogg"),
+    (b"MThd\x00\x00\x00\x06", b"\xff\xff\xff\xff\xff\xff\xff\xff", "audio/midi"),
+    (b"RIFF\x00\x00\x00\x00AVI ", b"\xff\xff\xff\xff\x00\x00\x00\x00\xff\xff\xff\xff", "video/avi"),
+    (b"RIFF\x00\x00\x00\x00WAVE", b"\xff\xff\xff\xff\x00\x00\x00\x00\xff\xff\xff\xff", "audio/wave"),
+    (b"\x1a\x45\xdf\xa3", b"\xff\xff\xff\xff", "video/webm"),
+    (b"\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00LP",
+     b"\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\xff\xff",
+     "application/vnd.ms-fontobject"),
+    (b"\x00\x01\x00\x00", b"\xff\xff\xff\xff", "font/ttf"),
+    (b"OTTO", b"\xff\xff\xff\xff", "font/otf"),
+    (b"ttcf", b"\xff\xff\xff\xff", "font/collection"),
+    (b"wOFF", b"\xff\xff\xff\xff", "font/woff"),
+    (b"wOF2", b"\xff\xff\xff\xff", "font/woff2"),
+    (b"\x1f\x8b\x08", b"\xff\xff\xff", "application/x-gzip"),
+    (b"PK\x03\x04", b"\xff\xff\xff\xff", "application/zip"),
+    (b"Rar!\x1a\x07\x00", b"\xff\xff\xff\xff\xff\xff\xff", "application/x-rar-compressed"),
+    (b"Rar!\x1a\x07\x01\x00", b"\xff\xff\xff\xff\xff\xff\xff\xff", "application/x-rar-compressed"),
+    (b"%PDF-", b"\xff\xff\xff\xff\xff", "application/pdf"),
+];
+
+// Tags that make a resource HTML, matched case-insensitively after leading
+// whitespace and followed by a space or `>`.
+const HTML_TAGS: [&[u8]; 17] = [
+    b"<!DOCTYPE HTML", b"<HTML", b"<HEAD", b"<SCRIPT", b"<IFRAME", b"<H1", b"<DIV", b"<FONT",
+    b"<TABLE", b"<A", b"<STYLE", b"<TITLE", b"<B", b"<BODY", b"<BR", b"<P", b"<!--",
+];
+
+// What the first bytes of a file tell about it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sniffed {
+    // The type of a signature in a file that is not text, strong enough to
+    // correct the type of a mislabeled file.
+    pub signature: Option<&'static str>,
+    // The type of a file without a usable extension, falls back to
+    // text/plain or application/octet-stream.
+    pub unlabeled: &'static str,
+}
+
+// The type of an unlabeled resource, WHATWG MIME Sniffing section 7.1.
+pub fn sniff(bytes: &[u8]) -> Sniffed {
+    let bytes = &bytes[..bytes.len().min(SNIFF_LENGTH)];
+    let binary = bytes.iter().any(|&b| is_binary(b));
+    let signature = signature(bytes);
+    let unlabeled = scriptable(bytes)
+        .or(signature)
+        .unwrap_or(if binary { DEFAULT_TYPE } else { "text/plain" });
+
+    Sniffed {
+        // Short signatures such as `BM` also start plain text.
+        signature: signature.filter(|_| binary),
+        unlabeled,
+    }
+}
+
+// Types a browser would run script in: HTML, XML and PostScript, and text
+// marked by a byte order mark. PDF is among the signatures.
+fn scriptable(bytes: &[u8]) -> Option<&'static str> {
+    let start = bytes.iter().position(|&b| !is_whitespace(b)).unwrap_or(bytes.len());
+    let rest = &bytes[start..];
+    for tag in HTML_TAGS.iter() {
+        if rest.len() > tag.len() && rest[..tag.len()].eq_ignore_ascii_case(tag) && matches!(rest[tag.len()], b' ' | b'>') {
+            return Some("text/html");
+        }
+    }
+    if rest.starts_with(b"<?xml") {
+        return Some("text/xml");
+    }
+    if bytes.starts_with(b"%!PS-Adobe-") {
+        return Some("application/postscript");
+    }
+    if bytes.starts_with(b"\xfe\xff") || bytes.starts_with(b"\xff\xfe") || bytes.starts_with(b"\xef\xbb\xbf") {
+        return Some("text/plain");
+    }
+    None
+}
+
+fn signature(bytes: &[u8]) -> Option<&'static str> {
+    SIGNATURES.iter()
+        .find(|(pattern, mask, _)| {
+            bytes.len() >= pattern.len() && pattern.iter().zip(mask.iter()).zip(bytes).all(|((p, m), b)| b & m == *p)
+        })
+        .map(|(_, _, mime)| *mime)
+        .or_else(|| if is_mp4(bytes) { Some("video/mp4") } else { None })
+}
+
+// An `ftyp` box whose major or a compatible brand starts with `mp4`.
+fn is_mp4(bytes: &[u8]) -> bool {
+    if bytes.len() < 12 {
+        return false;
+    }
+    let size = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+    if size > bytes.len() || !size.is_multiple_of(4) || size < 12 || &bytes[4..8] != b"ftyp" {
+        return false;
+    }
+    bytes[8..size].chunks(4)
+        .enumerate()
+        .filter(|(i, _)| *i != 1)
+        .any(|(_, brand)| brand.starts_with(b"mp4"))
+}
+
+fn is_whitespace(b: u8) -> bool {
+    matches!(b, b'\t' | b'\n' | b'\x0c' | b'\r' | b' ')
+}
+
+// Control characters that do not occur in text.
+fn is_binary(b: u8) -> bool {
+    matches!(b, 0x00..=0x08 | 0x0b | 0x0e..=0x1a | 0x1c..=0x1f)
+}
+
+// A file as it is on disk, a changed file is sniffed again.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct FileId {
+    dev: u64,
+    ino: u64,
+    len: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+}
+
+// Results of sniffing files, shared by all connections.
+#[derive(Debug, Default)]
+pub struct SniffCache {
+    entries: Mutex<HashMap<FileId, Sniffed>>,
+}
+
+impl SniffCache {
+    // None if the file cannot be read.
+    pub fn sniff_file(&self, path: &Path) -> Option<Sniffed> {
+        let file = File::open(path).ok()?;
+        let meta = file.metadata().ok()?;
+        if !meta.is_file() {
+            return None;
+        }
+        let id = FileId {
+            dev: meta.dev(),
+            ino: meta.ino(),
+            len: meta.len(),
+            mtime: meta.mtime(),
+            mtime_nsec: meta.mtime_nsec(),
+        };
+        if let Some(sniffed) = self.entries.lock().unwrap().get(&id) {
+            return Some(*sniffed);
+        }
+
+        let mut head = Vec::with_capacity(SNIFF_LENGTH);
+        file.take(SNIFF_LENGTH as u64).read_to_end(&mut head).ok()?;
+        let sniffed = sniff(&head);
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_CACHED {
+            entries.clear();
+        }
+        entries.insert(id, sniffed);
+        Some(sniffed)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,75 @@
+use std::fs;
+
+use super::sniff::*;
+
+fn unlabeled(bytes: &[u8]) -> &'static str {
+    sniff(bytes).unlabeled
+}
+
+#[test]
+fn html_and_text() {
+    assert_eq!(unlabeled(b"  \n<!doctype html><html>"), "text/html");
+    assert_eq!(unlabeled(b"<HTML>"), "text/html");
+    assert_eq!(unlabeled(b"<p class=\"a\">"), "text/html");
+    assert_eq!(unlabeled(b"<!-- comment -->"), "text/html");
+    // The tag must end, `<pre>` is not `<p`.
+    assert_eq!(unlabeled(b"<pre>"), "text/plain");
+    assert_eq!(unlabeled(b"<?xml version=\"1.0\"?>"), "text/xml");
+    assert_eq!(unlabeled(b"%!PS-Adobe-3.0"), "application/postscript");
+    assert_eq!(unlabeled(b"\xef\xbb\xbfhello"), "text/plain");
+    assert_eq!(unlabeled(b"just some words\n"), "text/plain");
+    assert_eq!(unlabeled(b""), "text/plain");
+    assert_eq!(unlabeled(b"\x00\x01\x02\x03"), "application/octet-stream");
+
+    // Script-capable types never come from a signature.
+    assert_eq!(sniff(b"<html>").signature, None);
+}
+
+#[test]
+fn signatures() {
+    assert_eq!(unlabeled(b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR"), "image/png");
+    assert_eq!(unlabeled(b"\xff\xd8\xff\xe0\x00\x10JFIF"), "image/jpeg");
+    assert_eq!(unlabeled(b"GIF89a\x01\x00"), "image/gif");
+    assert_eq!(unlabeled(b"RIFF\x24\x00\x00\x00WEBPVP8 "), "image/webp");
+    assert_eq!(unlabeled(b"RIFF\x24\x00\x00\x00WAVEfmt "), "audio/wave");
+    assert_eq!(unlabeled(b"%PDF-1.7\n"), "application/pdf");
+    assert_eq!(unlabeled(b"PK\x03\x04\x14\x00"), "application/zip");
+    assert_eq!(unlabeled(b"\x1f\x8b\x08\x00"), "application/x-gzip");
+    assert_eq!(unlabeled(b"wOF2\x00\x01"), "font/woff2");
+    assert_eq!(unlabeled(b"\x1a\x45\xdf\xa3\x9f"), "video/webm");
+    assert_eq!(sniff(b"ID3\x04\x00").signature, Some("audio/mpeg"));
+
+    let mp4 = b"\x00\x00\x00\x18ftypisom\x00\x00\x02\x00isommp41";
+    assert_eq!(unlabeled(mp4), "video/mp4");
+    let mov = b"\x00\x00\x00\x14ftypqt  \x00\x00\x02\x00qt  ";
+    assert_eq!(sniff(mov).signature, None);
+}
+
+#[test]
+fn cache_by_file_identity() {
+    let dir = std::env::temp_dir().join(format!("dz1-sniff-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("generated");
+    fs::write(&path, b"GIF87a....").unwrap();
+
+    let cache = SniffCache::default();
+    assert_eq!(cache.sniff_file(&path).unwrap().unlabeled, "image/gif");
+    assert_eq!(cache.sniff_file(&path).unwrap().unlabeled, "image/gif");
+    assert_eq!(cache.len(), 1);
+
+    // A rewritten file is a different file.
+    fs::write(&path, b"<html><body>").unwrap();
+    assert_eq!(cache.sniff_file(&path).unwrap().unlabeled, "text/html");
+    assert_eq!(cache.len(), 2);
+
+    assert!(cache.sniff_file(&dir).is_none());
+    assert!(cache.sniff_file(&dir.join("missing")).is_none());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn text_keeps_its_label() {
+    let sniffed = sniff(b"BMW is a brand\n");
+    assert_eq!(sniffed.unlabeled, "image/bmp");
+    assert_eq!(sniffed.signature, None);
+}
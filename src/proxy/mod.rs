@@ -0,0 +1,6 @@
+pub mod proxy;
+#[cfg(test)]
+mod proxy_test;
+pub mod upstream;
+#[cfg(test)]
+mod upstream_test;
@@ -0,0 +1,574 @@
+use std::io;
+use std::io::prelude::*;
+use std::io::{Chain, Cursor, Take};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::http::chunked::ChunkedReader;
+use crate::http::reader::{body_error_status, read_body, ReadError, RequestReader};
+use crate::http::request::{normalize_path, BodyFraming, HTTPRequest};
+use crate::http::response::HTTPResponse;
+use crate::http::status::StatusCode;
+use crate::proxy::upstream::{parse_authority, Backend, Balance, Lease, UpstreamGroup};
+use crate::server::stream::Client;
+
+pub const PROXY_INVALID_FORMAT: &str = "Invalid proxy format";
+pub const PROXY_UNSUPPORTED_SCHEME: &str = "Only http:// upstreams are supported";
+
+const DEFAULT_CONNECT_TIMEOUT: u64 = 60;
+const DEFAULT_READ_TIMEOUT: u64 = 60;
+
+// Headers that only describe a single connection and are never forwarded.
+const HOP_BY_HOP: [&str; 9] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Connection",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+// Upstream bytes read past the response head, followed by the socket.
+type Connection = Chain<Cursor<Vec<u8>>, TcpStream>;
+
+// The `proxy_pass` URL. Its host names either an `upstream` group or a
+// single server.
+#[derive(Debug)]
+pub struct Upstream {
+    pub host: String,
+    pub port: u16,
+    // Replaces the location prefix when set, as in `http://app:8080/api/`.
+    pub path: Option<String>,
+    pub group: Arc<UpstreamGroup>,
+}
+
+impl Upstream {
+    pub fn parse(url: &str) -> Result<Upstream, String> {
+        let rest = match url.strip_prefix("http://") {
+            Some(rest) => rest,
+            None => return Err(format!("{}: {}", PROXY_UNSUPPORTED_SCHEME, url)),
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], Some(rest[i..].to_owned())),
+            None => (rest, None),
+        };
+
+        let (host, port) = match parse_authority(authority) {
+            Some(pair) => pair,
+            None => return Err(format!("{}: {}", PROXY_INVALID_FORMAT, url)),
+        };
+
+        Ok(Upstream {
+            host: host.to_owned(),
+            port,
+            path,
+            group: Arc::new(UpstreamGroup::new(authority, Balance::RoundRobin, vec![Backend::new(host, port)])),
+        })
+    }
+
+    // Uses the `upstream` group named by the host, if there is one.
+    pub fn resolve(&mut self, groups: &[Arc<UpstreamGroup>]) {
+        if let Some(group) = groups.iter().find(|g| g.name == self.host) {
+            self.group = group.clone();
+        }
+    }
+
+    // `host:port` as used in the Host header and for redirects.
+    pub fn authority(&self) -> String {
+        match self.port {
+            80 => self.host.clone(),
+            port => format!("{}:{}", self.host, port),
+        }
+    }
+}
+
+// Settings of a `proxy_pass` location.
+#[derive(Debug)]
+pub struct Proxy {
+    pub prefix: String,
+    pub upstream: Upstream,
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration,
+}
+
+#[derive(Default)]
+pub struct ProxyBuilder {
+    pass: Option<String>,
+    connect_timeout: Option<String>,
+    read_timeout: Option<String>,
+}
+
+impl ProxyBuilder {
+    // Returns false for directives that are not about proxying.
+    pub fn parse_directive(&mut self, tokens: &[String]) -> Result<bool, String> {
+        let slot = match tokens[0].as_str() {
+            "proxy_pass" => &mut self.pass,
+            "proxy_connect_timeout" => &mut self.connect_timeout,
+            "proxy_read_timeout" => &mut self.read_timeout,
+            _ => return Ok(false),
+        };
+
+        if tokens.len() != 2 {
+            return Err(String::from(PROXY_INVALID_FORMAT));
+        }
+
+        *slot = Some(tokens[1].clone());
+        Ok(true)
+    }
+
+    pub fn build(self, prefix: &str) -> Result<Option<Proxy>, String> {
+        let upstream = match self.pass {
+            Some(url) => Upstream::parse(&url)?,
+            None => return Ok(None),
+        };
+
+        Ok(Some(Proxy {
+            prefix: prefix.to_owned(),
+            upstream,
+            connect_timeout: parse_timeout(self.connect_timeout, DEFAULT_CONNECT_TIMEOUT)?,
+            read_timeout: parse_timeout(self.read_timeout, DEFAULT_READ_TIMEOUT)?,
+        }))
+    }
+}
+
+fn parse_timeout(value: Option<String>, default: u64) -> Result<Duration, String> {
+    match value {
+        Some(value) => match value.parse() {
+            Ok(secs) => Ok(Duration::from_secs(secs)),
+            Err(_) => Err(format!("{}: {}", PROXY_INVALID_FORMAT, value)),
+        },
+        None => Ok(Duration::from_secs(default)),
+    }
+}
+
+// Why a request could not be forwarded.
+enum Failure {
+    // Nothing was read on a pooled connection, it may have been closed by
+    // the upstream while idle.
+    Stale,
+    Timeout,
+    Bad(String),
+    // The request body could not be read from the client, the status to
+    // answer with.
+    Client(StatusCode),
+}
+
+impl From<io::Error> for Failure {
+    fn from(err: io::Error) -> Failure {
+        match err.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Failure::Timeout,
+            _ => Failure::Bad(err.to_string()),
+        }
+    }
+}
+
+// A parsed upstream response head.
+struct Head {
+    version: String,
+    status: StatusCode,
+    reason: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Head {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+impl Proxy {
+    // Forwards the request and returns the upstream response with its body
+    // streamed. Idempotent requests without a body move on to the next
+    // backend when one fails. Failures become 502, or 504 when the upstream
+    // is too slow.
+    pub fn forward(&self, req: &HTTPRequest, client: &Client, body: &mut dyn Read) -> HTTPResponse {
+        // A chunked body is collected first and sent with its length, not
+        // every upstream accepts chunked requests.
+        let mut collected;
+        let (body, length): (&mut dyn Read, u64) = match req.body_framing() {
+            Ok(BodyFraming::None) => (body, 0),
+            Ok(BodyFraming::Length(length)) => (body, length),
+            Ok(BodyFraming::Chunked) => match read_body(body) {
+                Ok(input) => {
+                    let length = input.len() as u64;
+                    collected = Cursor::new(input);
+                    (&mut collected, length)
+                },
+                Err(status) => return error_response(status),
+            },
+            Err(status) => return error_response(status),
+        };
+        let retry = length == 0 && is_idempotent(&req.method);
+
+        let group = &self.upstream.group;
+        let mut tried = Vec::new();
+        let mut failure = None;
+        while let Some(lease) = group.select(client.addr, &tried) {
+            let address = lease.backend().address();
+            tried.push(lease.index());
+
+            let err = match self.attempt(lease, req, client, body, length) {
+                Ok(resp) => return resp,
+                Err(err) => err,
+            };
+            match err {
+                Failure::Timeout => println!("Upstream {} timed out", address),
+                Failure::Stale => println!("Upstream {} closed the connection", address),
+                Failure::Bad(ref err) => println!("Error while proxy to {}: {}", address, err),
+                Failure::Client(status) => return error_response(status),
+            }
+            failure = Some(err);
+            if !retry {
+                break;
+            }
+        }
+
+        match failure {
+            Some(Failure::Timeout) => error_response(StatusCode::GATEWAY_TIMEOUT),
+            Some(_) => error_response(StatusCode::BAD_GATEWAY),
+            None => {
+                println!("No live upstreams in {}", group.name);
+                error_response(StatusCode::BAD_GATEWAY)
+            },
+        }
+    }
+
+    // One try on the leased backend. A request without a body first goes
+    // over a pooled connection and is repeated on a fresh one if that
+    // turns out to be closed, a body can only be sent once.
+    fn attempt(&self, lease: Lease, req: &HTTPRequest, client: &Client, body: &mut dyn Read, length: u64) -> Result<HTTPResponse, Failure> {
+        let backend = lease.backend();
+        let pooled = match length {
+            0 => backend.pooled(),
+            _ => None,
+        };
+        let result = match pooled {
+            Some(conn) => match self.exchange(conn, req, client, body, 0, true) {
+                Err(Failure::Stale) => self.connect_and_exchange(backend, req, client, body, length),
+                result => result,
+            },
+            None => self.connect_and_exchange(backend, req, client, body, length),
+        };
+
+        // The backend is not to blame for a client that stalls.
+        if !matches!(result, Err(Failure::Client(_))) {
+            backend.report(result.is_ok());
+        }
+        let (head, conn, buffered) = result?;
+        Ok(self.response(req, client, head, conn, buffered, lease))
+    }
+
+    fn connect_and_exchange(&self, backend: &Backend, req: &HTTPRequest, client: &Client, body: &mut dyn Read, length: u64) -> Result<(Head, TcpStream, Vec<u8>), Failure> {
+        let conn = backend.connect(self.connect_timeout)?;
+        match self.exchange(conn, req, client, body, length, false) {
+            Err(Failure::Stale) => Err(Failure::Bad(String::from("upstream closed the connection"))),
+            result => result,
+        }
+    }
+
+    fn exchange(&self, mut conn: TcpStream, req: &HTTPRequest, client: &Client, body: &mut dyn Read, length: u64, pooled: bool) -> Result<(Head, TcpStream, Vec<u8>), Failure> {
+        conn.set_read_timeout(Some(self.read_timeout))?;
+        conn.set_write_timeout(Some(self.read_timeout))?;
+        conn.set_nodelay(true)?;
+
+        let head = self.request_head(req, client, length);
+        if let Err(err) = conn.write_all(head.as_bytes()) {
+            return Err(match pooled {
+                true => Failure::Stale,
+                false => err.into(),
+            });
+        }
+        if length > 0 {
+            send_body(&mut body.take(length), &mut conn)?;
+        }
+
+        let mut reader = RequestReader::new();
+        let mut first = true;
+        let head = loop {
+            let raw = match reader.read_head(&mut conn, None, self.read_timeout) {
+                Ok(raw) => raw,
+                Err(ReadError::Closed) if pooled && first => return Err(Failure::Stale),
+                Err(ReadError::Io(ref err)) if pooled && first && err.kind() == io::ErrorKind::ConnectionReset => return Err(Failure::Stale),
+                Err(ReadError::Idle) | Err(ReadError::Timeout) => return Err(Failure::Timeout),
+                Err(ReadError::Io(err)) => return Err(err.into()),
+                Err(err) => return Err(Failure::Bad(format!("invalid response: {:?}", err))),
+            };
+            first = false;
+
+            let head = parse_head(&raw).ok_or_else(|| Failure::Bad(String::from("invalid response head")))?;
+            // Interim responses are not passed on, the final one follows.
+            match head.status.as_u16() {
+                101 => return Err(Failure::Bad(String::from("protocol upgrades are not supported"))),
+                100..=199 => continue,
+                _ => break head,
+            }
+        };
+
+        Ok((head, conn, reader.into_buffered()))
+    }
+
+    fn request_head(&self, req: &HTTPRequest, client: &Client, length: u64) -> String {
+        let mut head = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", req.method, self.target(req), self.upstream.authority());
+
+        let connection = req.header("Connection").unwrap_or("");
+        for (name, value) in &req.headers {
+            if is_hop_by_hop(name, connection) || is_replaced(name) {
+                continue;
+            }
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+
+        let forwarded_for = match req.header("X-Forwarded-For") {
+            Some(chain) => format!("{}, {}", chain, client.addr),
+            None => client.addr.to_string(),
+        };
+        head.push_str(&format!("X-Forwarded-For: {}\r\n", forwarded_for));
+        head.push_str(&format!("X-Forwarded-Proto: {}\r\n", scheme(client)));
+        if let Some(host) = req.header("Host") {
+            head.push_str(&format!("X-Forwarded-Host: {}\r\n", host));
+        }
+        if length > 0 {
+            head.push_str(&format!("Content-Length: {}\r\n", length));
+        }
+        head.push_str("\r\n");
+
+        head
+    }
+
+    // The raw request target, with the location prefix replaced by the
+    // upstream path when there is one.
+    fn target(&self, req: &HTTPRequest) -> String {
+        let path = match self.upstream.path {
+            Some(ref path) => path,
+            None => return req.uri.clone(),
+        };
+
+        let (raw, query) = match req.uri.find('?') {
+            Some(i) => req.uri.split_at(i),
+            None => (req.uri.as_str(), ""),
+        };
+        let raw = normalize_path(raw).unwrap_or_else(|()| raw.to_owned());
+
+        match raw.strip_prefix(self.prefix.as_str()) {
+            Some(rest) => format!("{}{}{}", path, rest, query),
+            None => format!("{}{}", raw, query),
+        }
+    }
+
+    fn response(&self, req: &HTTPRequest, client: &Client, head: Head, conn: TcpStream, buffered: Vec<u8>, lease: Lease) -> HTTPResponse {
+        let mut resp = HTTPResponse::new();
+        resp.setStatus(head.status);
+        if !head.reason.is_empty() {
+            resp.setReason(head.reason.clone());
+        }
+
+        let connection = head.header("Connection").unwrap_or("");
+        let reusable = head.version == "HTTP/1.1" && !connection.split(',').any(|t| t.trim().eq_ignore_ascii_case("close"));
+        for (name, value) in &head.headers {
+            if is_hop_by_hop(name, connection) {
+                continue;
+            }
+            let value = match name.eq_ignore_ascii_case("Location") {
+                true => self.rewrite_location(req, client, value),
+                false => value.clone(),
+            };
+            resp.add_header(name.clone(), value);
+        }
+
+        let conn = Cursor::new(buffered).chain(conn);
+        let chunked = head.header("Transfer-Encoding")
+            .map(|te| te.rsplit(',').next().unwrap().trim().eq_ignore_ascii_case("chunked"))
+            .unwrap_or(false);
+
+        let length = head.header("Content-Length").map(|l| l.parse::<u64>());
+        let no_body = req.method == "HEAD" || head.status == StatusCode::NO_CONTENT || head.status == StatusCode::NOT_MODIFIED;
+        if no_body || (!chunked && length == Some(Ok(0))) {
+            if reusable {
+                release(lease.backend(), conn);
+            }
+            return resp;
+        }
+
+        let body = match length {
+            _ if chunked => {
+                resp.headers.retain(|(name, _)| !name.eq_ignore_ascii_case("Content-Length"));
+                Body::Chunked(ChunkedReader::new(conn))
+            },
+            Some(Ok(length)) => Body::Length(conn.take(length)),
+            Some(Err(_)) => return error_response(StatusCode::BAD_GATEWAY),
+            None => Body::Close(conn),
+        };
+
+        resp.setStream(Box::new(UpstreamBody {
+            body: Some(body),
+            lease,
+            reusable,
+        }));
+        resp
+    }
+
+    // Redirects to the upstream itself point back at this server instead.
+    fn rewrite_location(&self, req: &HTTPRequest, client: &Client, location: &str) -> String {
+        let (from, to) = match self.upstream.path {
+            Some(ref path) => (path.as_str(), self.prefix.as_str()),
+            None => ("/", "/"),
+        };
+
+        // Path-only redirects keep being relative.
+        if location.starts_with('/') {
+            return match location.strip_prefix(from) {
+                Some(rest) => format!("{}{}", to, rest),
+                None => location.to_owned(),
+            };
+        }
+
+        let upstream = format!("http://{}{}", self.upstream.authority(), from);
+        let rest = match location.strip_prefix(upstream.as_str()) {
+            Some(rest) => rest,
+            None => return location.to_owned(),
+        };
+
+        match req.header("Host") {
+            Some(host) => format!("{}://{}{}{}", scheme(client), host, to, rest),
+            None => format!("{}{}", to, rest),
+        }
+    }
+}
+
+enum Body {
+    Length(Take<Connection>),
+    Chunked(ChunkedReader<Connection>),
+    // Neither length nor chunked, the body ends when the upstream closes.
+    Close(Connection),
+}
+
+// Streams a response body and hands the connection back to the pool once
+// the body was read to its end.
+struct UpstreamBody {
+    body: Option<Body>,
+    lease: Lease,
+    reusable: bool,
+}
+
+impl Read for UpstreamBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = match self.body {
+            Some(Body::Length(ref mut body)) => {
+                let n = body.read(buf)?;
+                if n == 0 && body.limit() > 0 {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "upstream body is shorter than its length"));
+                }
+                n
+            },
+            Some(Body::Chunked(ref mut body)) => body.read(buf)?,
+            Some(Body::Close(ref mut body)) => return body.read(buf),
+            None => return Ok(0),
+        };
+
+        if n == 0 && !buf.is_empty() {
+            let conn = match self.body.take() {
+                Some(Body::Length(body)) => body.into_inner(),
+                Some(Body::Chunked(body)) => body.into_inner(),
+                _ => return Ok(0),
+            };
+            if self.reusable {
+                release(self.lease.backend(), conn);
+            }
+        }
+
+        Ok(n)
+    }
+}
+
+// Copies the request body to the upstream. Unlike io::copy it tells a
+// client that stalls or breaks off apart from an upstream that fails.
+fn send_body(body: &mut dyn Read, conn: &mut TcpStream) -> Result<(), Failure> {
+    let mut chunk = [0; 8192];
+    loop {
+        let n = match body.read(&mut chunk) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(Failure::Client(body_error_status(&err))),
+        };
+        conn.write_all(&chunk[..n])?;
+    }
+}
+
+// Keeps the connection for later requests unless the upstream sent more
+// than the response.
+fn release(backend: &Backend, conn: Connection) {
+    let (rest, conn) = conn.into_inner();
+    if rest.position() == rest.get_ref().len() as u64 {
+        backend.release(conn);
+    }
+}
+
+fn parse_head(raw: &[u8]) -> Option<Head> {
+    let raw = std::str::from_utf8(raw).ok()?;
+    let mut lines = raw.split("\r\n");
+
+    let mut status_line = lines.next()?.splitn(3, ' ');
+    let version = status_line.next()?;
+    if version != "HTTP/1.1" && version != "HTTP/1.0" {
+        return None;
+    }
+    let status = status_line.next()?;
+    if status.len() != 3 {
+        return None;
+    }
+    let status = status.parse().ok().and_then(StatusCode::from_u16)?;
+    let reason = status_line.next().unwrap_or("").to_owned();
+
+    let mut headers = Vec::new();
+    for line in lines.take_while(|line| !line.is_empty()) {
+        let (name, value) = line.split_once(':')?;
+        if name.is_empty() || name.contains(' ') {
+            return None;
+        }
+        headers.push((name.to_owned(), value.trim().to_owned()));
+    }
+
+    Some(Head {
+        version: version.to_owned(),
+        status,
+        reason,
+        headers,
+    })
+}
+
+// Hop-by-hop headers, including the ones listed in `Connection`.
+fn is_hop_by_hop(name: &str, connection: &str) -> bool {
+    HOP_BY_HOP.iter().any(|h| h.eq_ignore_ascii_case(name))
+        || connection.split(',').any(|t| t.trim().eq_ignore_ascii_case(name))
+}
+
+// Methods that may be sent again after a failed try.
+fn is_idempotent(method: &str) -> bool {
+    ["GET", "HEAD", "PUT", "DELETE", "OPTIONS", "TRACE"].contains(&method)
+}
+
+// Request headers that the proxy sets itself.
+fn is_replaced(name: &str) -> bool {
+    ["Host", "Content-Length", "X-Forwarded-For", "X-Forwarded-Proto", "X-Forwarded-Host"]
+        .iter()
+        .any(|h| h.eq_ignore_ascii_case(name))
+}
+
+fn scheme(client: &Client) -> &'static str {
+    match client.secure {
+        true => "https",
+        false => "http",
+    }
+}
+
+fn error_response(status: StatusCode) -> HTTPResponse {
+    HTTPResponse::builder().status(status).build()
+}
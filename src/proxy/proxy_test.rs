@@ -0,0 +1,262 @@
+use std::io::prelude::*;
+use std::net::{IpAddr, Ipv4Addr, TcpListener};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use super::proxy::{Proxy, ProxyBuilder, Upstream};
+use super::upstream::{Backend, Balance, UpstreamGroup};
+use crate::http::reader::RequestReader;
+use crate::http::request::HTTPRequest;
+use crate::http::response::HTTPResponse;
+use crate::http::status::StatusCode;
+use crate::access::access::ClientAddr;
+use crate::server::stream::Client;
+
+const CLIENT: Client = Client {
+    addr: ClientAddr::Ip(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 7))),
+    secure: false,
+    port: Some(80),
+    user: None,
+};
+
+// Starts an upstream on a free port. It accepts one connection per entry
+// of `conns`, answers requests on it with the entry's responses in turn and
+// closes it after the last one. `{port}` in a response is replaced by the
+// upstream port. The request heads it received are sent back to the test.
+fn upstream(conns: Vec<Vec<&'static str>>) -> (u16, Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        for responses in conns {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = RequestReader::new();
+            for resp in responses {
+                let head = reader.read_head(&mut stream, None, Duration::from_secs(5)).unwrap();
+                tx.send(String::from_utf8(head).unwrap()).unwrap();
+                stream.write_all(resp.replace("{port}", &port.to_string()).as_bytes()).unwrap();
+            }
+        }
+    });
+
+    (port, rx)
+}
+
+fn proxy(url: &str, prefix: &str) -> Proxy {
+    let mut builder = ProxyBuilder::default();
+    builder.parse_directive(&[String::from("proxy_pass"), url.to_owned()]).unwrap();
+    builder.build(prefix).unwrap().unwrap()
+}
+
+fn request(raw: &str) -> HTTPRequest {
+    HTTPRequest::parse(raw.as_bytes()).unwrap()
+}
+
+fn body(resp: HTTPResponse) -> String {
+    let mut body = String::new();
+    resp.stream.unwrap().read_to_string(&mut body).unwrap();
+    body
+}
+
+#[test]
+fn parse_upstream() {
+    let upstream = Upstream::parse("http://app:8080/api/").unwrap();
+    assert_eq!(upstream.host, "app");
+    assert_eq!(upstream.port, 8080);
+    assert_eq!(upstream.path.as_deref(), Some("/api/"));
+
+    let upstream = Upstream::parse("http://[::1]").unwrap();
+    assert_eq!(upstream.host, "[::1]");
+    assert_eq!(upstream.port, 80);
+    assert_eq!(upstream.path, None);
+
+    assert!(Upstream::parse("https://app").is_err());
+    assert!(Upstream::parse("http://app:port").is_err());
+    assert!(Upstream::parse("http://:80").is_err());
+}
+
+#[test]
+fn forward_request_headers() {
+    let (port, heads) = upstream(vec![vec!["HTTP/1.1 200 OK\r\nContent-Length: 2\r\nX-App: 1\r\n\r\nok"]]);
+    let proxy = proxy(&format!("http://127.0.0.1:{}/app/", port), "/api/");
+    let req = request("GET /api/a%20b?q=1 HTTP/1.1\r\nHost: example.com\r\nX-Forwarded-For: 10.0.0.1\r\nConnection: close, X-Secret\r\nX-Secret: 1\r\nAccept: */*\r\n\r\n");
+
+    let resp = proxy.forward(&req, &CLIENT, &mut std::io::empty());
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.header("X-App"), Some("1"));
+    assert_eq!(resp.header("Content-Length"), Some("2"));
+    assert_eq!(body(resp), "ok");
+
+    let head = heads.recv().unwrap();
+    assert!(head.starts_with("GET /app/a%20b?q=1 HTTP/1.1\r\n"));
+    assert!(head.contains(&format!("Host: 127.0.0.1:{}\r\n", port)));
+    assert!(head.contains("X-Forwarded-For: 10.0.0.1, 192.0.2.7\r\n"));
+    assert!(head.contains("X-Forwarded-Proto: http\r\n"));
+    assert!(head.contains("X-Forwarded-Host: example.com\r\n"));
+    assert!(head.contains("Accept: */*\r\n"));
+    assert!(!head.contains("Connection"));
+    assert!(!head.contains("X-Secret"));
+}
+
+#[test]
+fn forward_request_body() {
+    let (port, heads) = upstream(vec![vec!["HTTP/1.1 204 No Content\r\n\r\n"]]);
+    let proxy = proxy(&format!("http://127.0.0.1:{}", port), "/");
+    let req = request("GET /upload HTTP/1.1\r\nContent-Length: 5\r\n\r\n");
+
+    let resp = proxy.forward(&req, &CLIENT, &mut "hello".as_bytes());
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    assert!(resp.stream.is_none());
+
+    let head = heads.recv().unwrap();
+    assert!(head.starts_with("GET /upload HTTP/1.1\r\n"));
+    assert!(head.contains("Content-Length: 5\r\n"));
+}
+
+#[test]
+fn stream_chunked_response() {
+    let (port, _heads) = upstream(vec![vec!["HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n"]]);
+    let proxy = proxy(&format!("http://127.0.0.1:{}", port), "/");
+
+    let resp = proxy.forward(&request("GET / HTTP/1.1\r\n\r\n"), &CLIENT, &mut std::io::empty());
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(!resp.has_header("Transfer-Encoding"));
+    assert!(!resp.has_header("Content-Length"));
+    assert_eq!(body(resp), "abcde");
+}
+
+#[test]
+fn reuse_pooled_connection() {
+    // The upstream accepts a single connection, later requests only
+    // succeed if they go over the same one.
+    let (port, heads) = upstream(vec![vec![
+        "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nfirst",
+        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n6\r\nsecond\r\n0\r\n\r\n",
+        "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nthird",
+    ]]);
+    let proxy = proxy(&format!("http://127.0.0.1:{}", port), "/");
+
+    for expected in &["first", "second", "third"] {
+        let resp = proxy.forward(&request("GET / HTTP/1.1\r\n\r\n"), &CLIENT, &mut std::io::empty());
+        assert_eq!(body(resp), *expected);
+    }
+    assert_eq!(heads.iter().count(), 3);
+}
+
+#[test]
+fn retry_stale_connection() {
+    // The upstream closes the first connection after its response, the
+    // pooled connection is stale and the request goes to a new one.
+    let (port, _heads) = upstream(vec![
+        vec!["HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nfirst"],
+        vec!["HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nsecond"],
+    ]);
+    let proxy = proxy(&format!("http://127.0.0.1:{}", port), "/");
+
+    let resp = proxy.forward(&request("GET / HTTP/1.1\r\n\r\n"), &CLIENT, &mut std::io::empty());
+    assert_eq!(body(resp), "first");
+    thread::sleep(Duration::from_millis(50));
+
+    let resp = proxy.forward(&request("GET / HTTP/1.1\r\n\r\n"), &CLIENT, &mut std::io::empty());
+    assert_eq!(body(resp), "second");
+}
+
+#[test]
+fn rewrite_location() {
+    let (port, _heads) = upstream(vec![vec![
+        "HTTP/1.1 302 Found\r\nLocation: http://127.0.0.1:{port}/app/login?next=1\r\nContent-Length: 0\r\n\r\n",
+        "HTTP/1.1 302 Found\r\nLocation: /app/next\r\nContent-Length: 0\r\n\r\n",
+        "HTTP/1.1 302 Found\r\nLocation: http://elsewhere/app/\r\nContent-Length: 0\r\n\r\n",
+    ]]);
+    let proxy = proxy(&format!("http://127.0.0.1:{}/app/", port), "/api/");
+    let client = Client { secure: true, ..CLIENT };
+
+    let resp = proxy.forward(&request("GET /api/ HTTP/1.1\r\nHost: example.com\r\n\r\n"), &client, &mut std::io::empty());
+    assert_eq!(resp.header("Location"), Some("https://example.com/api/login?next=1"));
+
+    let resp = proxy.forward(&request("GET /api/ HTTP/1.1\r\nHost: example.com\r\n\r\n"), &client, &mut std::io::empty());
+    assert_eq!(resp.header("Location"), Some("/api/next"));
+
+    let resp = proxy.forward(&request("GET /api/ HTTP/1.1\r\nHost: example.com\r\n\r\n"), &client, &mut std::io::empty());
+    assert_eq!(resp.header("Location"), Some("http://elsewhere/app/"));
+}
+
+#[test]
+fn bad_gateway_on_refused() {
+    let port = {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().port()
+    };
+    let proxy = proxy(&format!("http://127.0.0.1:{}", port), "/");
+
+    let resp = proxy.forward(&request("GET / HTTP/1.1\r\n\r\n"), &CLIENT, &mut std::io::empty());
+    assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+}
+
+#[test]
+fn bad_gateway_on_invalid_response() {
+    let (port, _heads) = upstream(vec![vec!["SSH-2.0-OpenSSH\r\n\r\n"]]);
+    let proxy = proxy(&format!("http://127.0.0.1:{}", port), "/");
+
+    let resp = proxy.forward(&request("GET / HTTP/1.1\r\n\r\n"), &CLIENT, &mut std::io::empty());
+    assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+}
+
+#[test]
+fn gateway_timeout() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let mut proxy = proxy(&format!("http://127.0.0.1:{}", port), "/");
+    proxy.read_timeout = Duration::from_millis(200);
+
+    let resp = proxy.forward(&request("GET / HTTP/1.1\r\n\r\n"), &CLIENT, &mut std::io::empty());
+    assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT);
+    drop(listener);
+}
+
+#[test]
+fn invalid_directives() {
+    let mut builder = ProxyBuilder::default();
+    assert!(builder.parse_directive(&[String::from("proxy_pass")]).is_err());
+    assert_eq!(builder.parse_directive(&[String::from("root"), String::from("/")]), Ok(false));
+
+    let mut builder = ProxyBuilder::default();
+    builder.parse_directive(&[String::from("proxy_pass"), String::from("http://app")]).unwrap();
+    builder.parse_directive(&[String::from("proxy_read_timeout"), String::from("soon")]).unwrap();
+    assert!(builder.build("/").is_err());
+}
+
+// A group of a backend that refuses connections followed by `live`.
+fn failing_group(live: u16) -> Arc<UpstreamGroup> {
+    let dead = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let backends = vec![Backend::new("127.0.0.1", dead), Backend::new("127.0.0.1", live)];
+    Arc::new(UpstreamGroup::new("app", Balance::RoundRobin, backends))
+}
+
+#[test]
+fn retry_next_backend() {
+    let (port, heads) = upstream(vec![vec!["HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nlive"]]);
+    let group = failing_group(port);
+    let mut proxy = proxy("http://app/", "/");
+    proxy.upstream.resolve(std::slice::from_ref(&group));
+
+    let resp = proxy.forward(&request("GET / HTTP/1.1\r\n\r\n"), &CLIENT, &mut std::io::empty());
+    assert_eq!(body(resp), "live");
+    assert!(heads.recv().unwrap().contains("Host: app\r\n"));
+    assert!(group.backends[0].is_down());
+    assert!(!group.backends[1].is_down());
+}
+
+#[test]
+fn no_retry_with_body() {
+    let (port, _heads) = upstream(vec![vec!["HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nlive"]]);
+    let mut proxy = proxy("http://app/", "/");
+    proxy.upstream.resolve(&[failing_group(port)]);
+
+    let req = request("GET / HTTP/1.1\r\nContent-Length: 5\r\n\r\n");
+    let resp = proxy.forward(&req, &CLIENT, &mut "hello".as_bytes());
+    assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+}
@@ -0,0 +1,341 @@
+use std::io;
+use std::net::{IpAddr, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::access::access::ClientAddr;
+use crate::config::config::tokenize;
+
+pub const UPSTREAM_INVALID_FORMAT: &str = "Invalid upstream format";
+pub const UPSTREAM_NOT_CLOSED: &str = "Upstream block is not closed";
+pub const UPSTREAM_NO_SERVERS: &str = "Upstream has no servers";
+pub const UPSTREAM_UNKNOWN_DIRECTIVE: &str = "Unknown upstream directive";
+
+const DEFAULT_WEIGHT: usize = 1;
+const DEFAULT_MAX_FAILS: usize = 1;
+const DEFAULT_FAIL_TIMEOUT: u64 = 10;
+// Idle connections kept per backend.
+const MAX_IDLE: usize = 32;
+// Points on the hash ring per unit of weight.
+const RING_POINTS: usize = 160;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Balance {
+    // Smooth weighted round robin.
+    RoundRobin,
+    // Fewest requests in flight relative to the weight.
+    LeastConn,
+    // Consistent hashing of the client address, a backend going down only
+    // moves the clients that were on it.
+    IpHash,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    fails: usize,
+    down_until: Option<Instant>,
+}
+
+// One server of an upstream group.
+#[derive(Debug)]
+pub struct Backend {
+    pub host: String,
+    pub port: u16,
+    pub weight: usize,
+    // Consecutive failures after which the backend is taken out for
+    // `fail_timeout`, 0 never takes it out.
+    pub max_fails: usize,
+    pub fail_timeout: Duration,
+    health: Mutex<Health>,
+    active: AtomicUsize,
+    idle: Mutex<Vec<TcpStream>>,
+}
+
+impl Backend {
+    pub fn new(host: &str, port: u16) -> Backend {
+        Backend {
+            host: host.to_owned(),
+            port,
+            weight: DEFAULT_WEIGHT,
+            max_fails: DEFAULT_MAX_FAILS,
+            fail_timeout: Duration::from_secs(DEFAULT_FAIL_TIMEOUT),
+            health: Mutex::new(Health::default()),
+            active: AtomicUsize::new(0),
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    // Parses `server host:port [weight=N] [max_fails=N] [fail_timeout=S]`.
+    pub fn parse(tokens: &[String]) -> Result<Backend, String> {
+        if tokens.len() < 2 {
+            return Err(String::from(UPSTREAM_INVALID_FORMAT));
+        }
+
+        let (host, port) = parse_authority(&tokens[1])
+            .ok_or_else(|| format!("{}: {}", UPSTREAM_INVALID_FORMAT, tokens[1]))?;
+        let mut backend = Backend::new(host, port);
+
+        for option in &tokens[2..] {
+            let invalid = || format!("{}: {}", UPSTREAM_INVALID_FORMAT, option);
+            let (name, value) = option.split_once('=').ok_or_else(invalid)?;
+            let value: u64 = value.parse().map_err(|_| invalid())?;
+            match name {
+                "weight" if value > 0 => backend.weight = value as usize,
+                "max_fails" => backend.max_fails = value as usize,
+                "fail_timeout" => backend.fail_timeout = Duration::from_secs(value),
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(backend)
+    }
+
+    // `host:port` for logs.
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    pub fn is_down(&self) -> bool {
+        match self.health.lock().unwrap().down_until {
+            Some(until) => Instant::now() < until,
+            None => false,
+        }
+    }
+
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    // Passive health checking, every finished attempt is reported here.
+    pub fn report(&self, ok: bool) {
+        let mut health = self.health.lock().unwrap();
+        if ok {
+            health.fails = 0;
+            health.down_until = None;
+            return;
+        }
+
+        health.fails += 1;
+        if self.max_fails > 0 && health.fails >= self.max_fails {
+            println!("Upstream server {} is down for {}s after {} failures", self.address(), self.fail_timeout.as_secs(), health.fails);
+            health.fails = 0;
+            health.down_until = Some(Instant::now() + self.fail_timeout);
+            self.idle.lock().unwrap().clear();
+        }
+    }
+
+    pub fn connect(&self, timeout: Duration) -> io::Result<TcpStream> {
+        let host = self.host.trim_start_matches('[').trim_end_matches(']');
+        let mut last = io::Error::new(io::ErrorKind::NotFound, "upstream host did not resolve");
+        for addr in (host, self.port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last = err,
+            }
+        }
+
+        Err(last)
+    }
+
+    pub fn pooled(&self) -> Option<TcpStream> {
+        self.idle.lock().unwrap().pop()
+    }
+
+    pub fn release(&self, conn: TcpStream) {
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < MAX_IDLE {
+            idle.push(conn);
+        }
+    }
+}
+
+// Servers that share the requests of a `proxy_pass`.
+#[derive(Debug)]
+pub struct UpstreamGroup {
+    pub name: String,
+    pub balance: Balance,
+    pub backends: Vec<Backend>,
+    // Current weights of the smooth round robin.
+    weights: Mutex<Vec<i64>>,
+    // Sorted (hash, backend) points for `ip_hash`.
+    ring: Vec<(u64, usize)>,
+}
+
+impl UpstreamGroup {
+    pub fn new(name: &str, balance: Balance, backends: Vec<Backend>) -> UpstreamGroup {
+        let mut ring = Vec::new();
+        for (i, backend) in backends.iter().enumerate() {
+            for point in 0..backend.weight * RING_POINTS {
+                ring.push((hash(format!("{}#{}", backend.address(), point).as_bytes()), i));
+            }
+        }
+        ring.sort_unstable();
+
+        UpstreamGroup {
+            name: name.to_owned(),
+            balance,
+            weights: Mutex::new(vec![0; backends.len()]),
+            backends,
+            ring,
+        }
+    }
+
+    // Parses an `upstream <name> {` block, consuming lines up to the
+    // closing `}`.
+    pub fn parse<'a, I>(header: &[String], lines: &mut I) -> Result<UpstreamGroup, String>
+        where I: Iterator<Item = &'a str>
+    {
+        if header.len() != 3 || header[2] != "{" {
+            return Err(String::from(UPSTREAM_INVALID_FORMAT));
+        }
+
+        let mut balance = Balance::RoundRobin;
+        let mut backends = Vec::new();
+
+        for line in lines {
+            let tokens = tokenize(line);
+            if tokens.is_empty() {
+                continue;
+            }
+
+            match tokens[0].as_str() {
+                "}" if backends.is_empty() => return Err(format!("{}: {}", UPSTREAM_NO_SERVERS, header[1])),
+                "}" => return Ok(UpstreamGroup::new(&header[1], balance, backends)),
+                "server" => backends.push(Backend::parse(&tokens)?),
+                "least_conn" if tokens.len() == 1 => balance = Balance::LeastConn,
+                "ip_hash" if tokens.len() == 1 => balance = Balance::IpHash,
+                "least_conn" | "ip_hash" => return Err(String::from(UPSTREAM_INVALID_FORMAT)),
+                name => return Err(format!("{}: {}", UPSTREAM_UNKNOWN_DIRECTIVE, name)),
+            }
+        }
+
+        Err(String::from(UPSTREAM_NOT_CLOSED))
+    }
+
+    // Picks a backend that is up and not in `tried`, the returned lease
+    // counts the request as active until it is dropped.
+    pub fn select(self: &Arc<Self>, client: ClientAddr, tried: &[usize]) -> Option<Lease> {
+        // Like a lone server, a single backend is never taken out.
+        let single = self.backends.len() == 1;
+        let eligible: Vec<bool> = self.backends.iter()
+            .enumerate()
+            .map(|(i, b)| !tried.contains(&i) && (single || !b.is_down()))
+            .collect();
+
+        let index = match self.balance {
+            Balance::RoundRobin => self.round_robin(&eligible),
+            Balance::LeastConn => self.least_conn(&eligible),
+            Balance::IpHash => self.ip_hash(client, &eligible),
+        }?;
+
+        self.backends[index].active.fetch_add(1, Ordering::SeqCst);
+        Some(Lease {
+            group: self.clone(),
+            index,
+        })
+    }
+
+    fn round_robin(&self, eligible: &[bool]) -> Option<usize> {
+        let mut weights = self.weights.lock().unwrap();
+        let mut total = 0;
+        let mut best: Option<usize> = None;
+
+        for (i, backend) in self.backends.iter().enumerate() {
+            if !eligible[i] {
+                continue;
+            }
+            weights[i] += backend.weight as i64;
+            total += backend.weight as i64;
+            if best.is_none_or(|b| weights[i] > weights[b]) {
+                best = Some(i);
+            }
+        }
+
+        let best = best?;
+        weights[best] -= total;
+        Some(best)
+    }
+
+    fn least_conn(&self, eligible: &[bool]) -> Option<usize> {
+        let mut best: Option<usize> = None;
+        for (i, backend) in self.backends.iter().enumerate() {
+            if !eligible[i] {
+                continue;
+            }
+            // active / weight compared without division.
+            let better = best.is_none_or(|b| {
+                backend.active() * self.backends[b].weight < self.backends[b].active() * backend.weight
+            });
+            if better {
+                best = Some(i);
+            }
+        }
+
+        best
+    }
+
+    fn ip_hash(&self, client: ClientAddr, eligible: &[bool]) -> Option<usize> {
+        let key = match client {
+            ClientAddr::Ip(IpAddr::V4(ip)) => hash(&ip.octets()),
+            ClientAddr::Ip(IpAddr::V6(ip)) => hash(&ip.octets()),
+            ClientAddr::Unix => hash(b"unix:"),
+        };
+
+        let start = self.ring.partition_point(|&(point, _)| point < key);
+        (0..self.ring.len())
+            .map(|i| self.ring[(start + i) % self.ring.len()].1)
+            .find(|&i| eligible[i])
+    }
+}
+
+// A backend chosen for one request.
+pub struct Lease {
+    group: Arc<UpstreamGroup>,
+    index: usize,
+}
+
+impl Lease {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn backend(&self) -> &Backend {
+        &self.group.backends[self.index]
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.backend().active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Splits `host[:port]`, the port defaults to 80.
+pub fn parse_authority(authority: &str) -> Option<(&str, u16)> {
+    let (host, port) = match authority.rfind(':') {
+        Some(i) if !authority.ends_with(']') => (&authority[..i], authority[i + 1..].parse().ok()?),
+        _ => (authority, 80),
+    };
+
+    match host.is_empty() {
+        true => None,
+        false => Some((host, port)),
+    }
+}
+
+// FNV-1a with a final avalanche step so that similar keys spread over the
+// ring. Stable across runs, clients keep their backend after a restart.
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
@@ -0,0 +1,171 @@
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::upstream::{Backend, Balance, UpstreamGroup};
+use crate::access::access::ClientAddr;
+
+const CLIENT: &str = "192.0.2.7";
+
+fn group(balance: Balance, weights: &[usize]) -> Arc<UpstreamGroup> {
+    let backends = weights.iter()
+        .enumerate()
+        .map(|(i, &weight)| {
+            let mut backend = Backend::new("127.0.0.1", 8000 + i as u16);
+            backend.weight = weight;
+            backend
+        })
+        .collect();
+
+    Arc::new(UpstreamGroup::new("app", balance, backends))
+}
+
+fn client(ip: &str) -> ClientAddr {
+    ClientAddr::Ip(ip.parse().unwrap())
+}
+
+// Backend indexes of `n` selections, leases are released right away.
+fn picks(group: &Arc<UpstreamGroup>, n: usize) -> Vec<usize> {
+    (0..n).map(|_| group.select(client(CLIENT), &[]).unwrap().index()).collect()
+}
+
+fn lines(raw: &str) -> Vec<String> {
+    raw.lines().map(|l| l.to_owned()).collect()
+}
+
+fn parse(raw: &str) -> Result<UpstreamGroup, String> {
+    let header: Vec<String> = vec![String::from("upstream"), String::from("app"), String::from("{")];
+    let body = lines(raw);
+    UpstreamGroup::parse(&header, &mut body.iter().map(|l| l.as_str()))
+}
+
+#[test]
+fn parse_block() {
+    let group = parse("    least_conn\n    server 10.0.0.1:8080 weight=3 max_fails=2 fail_timeout=30\n    server backend\n}\n").unwrap();
+    assert_eq!(group.name, "app");
+    assert_eq!(group.balance, Balance::LeastConn);
+    assert_eq!(group.backends.len(), 2);
+    assert_eq!(group.backends[0].address(), "10.0.0.1:8080");
+    assert_eq!(group.backends[0].weight, 3);
+    assert_eq!(group.backends[0].max_fails, 2);
+    assert_eq!(group.backends[0].fail_timeout, Duration::from_secs(30));
+    assert_eq!(group.backends[1].address(), "backend:80");
+    assert_eq!(group.backends[1].weight, 1);
+}
+
+#[test]
+fn parse_invalid_block() {
+    assert!(parse("}\n").is_err());
+    assert!(parse("server 10.0.0.1\n").is_err());
+    assert!(parse("server 10.0.0.1 weight=0\n}\n").is_err());
+    assert!(parse("server 10.0.0.1 weight\n}\n").is_err());
+    assert!(parse("server 10.0.0.1 backup=1\n}\n").is_err());
+    assert!(parse("server 10.0.0.1:http\n}\n").is_err());
+    assert!(parse("server 10.0.0.1\nsticky\n}\n").is_err());
+}
+
+#[test]
+fn weighted_round_robin() {
+    let group = group(Balance::RoundRobin, &[5, 1, 1]);
+
+    // Smooth: the heavy backend is not picked five times in a row.
+    assert_eq!(picks(&group, 7), vec![0, 0, 1, 0, 2, 0, 0]);
+}
+
+#[test]
+fn least_conn() {
+    let group = group(Balance::LeastConn, &[1, 2]);
+
+    let first = group.select(client(CLIENT), &[]).unwrap();
+    assert_eq!(first.index(), 0);
+    let second = group.select(client(CLIENT), &[]).unwrap();
+    assert_eq!(second.index(), 1);
+    // 1 of weight 1 against 1 of weight 2.
+    let third = group.select(client(CLIENT), &[]).unwrap();
+    assert_eq!(third.index(), 1);
+    assert_eq!(group.backends[1].active(), 2);
+
+    drop(second);
+    drop(third);
+    assert_eq!(group.backends[1].active(), 0);
+    assert_eq!(group.select(client(CLIENT), &[]).unwrap().index(), 1);
+}
+
+#[test]
+fn ip_hash_is_sticky() {
+    let group = group(Balance::IpHash, &[1, 1, 1]);
+
+    let mut seen = [0; 3];
+    for i in 0..60 {
+        let ip = client(&format!("10.0.{}.{}", i / 7, i));
+        let index = group.select(ip, &[]).unwrap().index();
+        assert_eq!(group.select(ip, &[]).unwrap().index(), index);
+        seen[index] += 1;
+    }
+    assert!(seen.iter().all(|&n| n > 0));
+}
+
+#[test]
+fn ip_hash_only_moves_clients_of_failed_backend() {
+    let healthy = group(Balance::IpHash, &[1, 1, 1]);
+    let failed = group(Balance::IpHash, &[1, 1, 1]);
+    failed.backends[1].report(false);
+
+    for i in 0..60 {
+        let ip = client(&format!("10.1.0.{}", i));
+        let before = healthy.select(ip, &[]).unwrap().index();
+        let after = failed.select(ip, &[]).unwrap().index();
+        match before {
+            1 => assert_ne!(after, 1),
+            owner => assert_eq!(after, owner),
+        }
+    }
+}
+
+#[test]
+fn passive_health() {
+    let mut backends = vec![Backend::new("127.0.0.1", 8000), Backend::new("127.0.0.1", 8001)];
+    backends[0].max_fails = 2;
+    backends[0].fail_timeout = Duration::from_millis(100);
+    let group = Arc::new(UpstreamGroup::new("app", Balance::RoundRobin, backends));
+
+    group.backends[0].report(false);
+    assert!(!group.backends[0].is_down());
+    // A success in between resets the count.
+    group.backends[0].report(true);
+    group.backends[0].report(false);
+    assert!(!group.backends[0].is_down());
+    group.backends[0].report(false);
+    assert!(group.backends[0].is_down());
+
+    assert_eq!(picks(&group, 3), vec![1, 1, 1]);
+    std::thread::sleep(Duration::from_millis(150));
+    assert!(!group.backends[0].is_down());
+    assert!(picks(&group, 2).contains(&0));
+}
+
+#[test]
+fn no_live_backends() {
+    let down = group(Balance::RoundRobin, &[1, 1]);
+    down.backends[0].report(false);
+    down.backends[1].report(false);
+    assert!(down.select(client(CLIENT), &[]).is_none());
+
+    let tried = group(Balance::RoundRobin, &[1, 1]);
+    assert!(tried.select(client(CLIENT), &[0, 1]).is_none());
+}
+
+#[test]
+fn single_backend_never_down() {
+    let group = group(Balance::RoundRobin, &[1]);
+    group.backends[0].report(false);
+    assert!(group.backends[0].is_down());
+    assert!(group.select(client(CLIENT), &[]).is_some());
+}
+
+#[test]
+fn connect_refused() {
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let backend = Backend::new("127.0.0.1", port);
+    assert!(backend.connect(Duration::from_secs(1)).is_err());
+}
@@ -0,0 +1,6 @@
+pub mod rewrite;
+#[cfg(test)]
+mod rewrite_test;
+pub mod try_files;
+#[cfg(test)]
+mod try_files_test;
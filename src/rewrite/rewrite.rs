@@ -0,0 +1,401 @@
+use percent_encoding::percent_decode;
+use regex::{Captures, Regex};
+
+use crate::config::config::Config;
+use crate::config::location::Location;
+use crate::http::request::{normalize_path, HTTPRequest};
+use crate::http::response::HTTPResponse;
+use crate::http::status::StatusCode;
+
+pub const REWRITE_INVALID_FORMAT: &str = "Invalid rewrite format";
+pub const RETURN_INVALID_FORMAT: &str = "Invalid return format";
+// How often a request may start over with location matching, as in nginx.
+pub const MAX_REWRITE_CYCLES: usize = 10;
+
+// Besides the captures `$1` to `$9`.
+const VARIABLES: [&str; 5] = ["uri", "args", "request_uri", "scheme", "host"];
+const REDIRECT_STATUSES: [u16; 5] = [301, 302, 303, 307, 308];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flag {
+    // Later rules see the new URI, then locations are matched again.
+    None,
+    // Locations are matched again right away.
+    Last,
+    // The new URI is served by the current location.
+    Break,
+    Redirect,
+    Permanent,
+}
+
+#[derive(Debug)]
+enum Rule {
+    Rewrite {
+        regex: Regex,
+        path: String,
+        // What follows `?` in the replacement.
+        query: Option<String>,
+        // A replacement ending in `?` drops the original arguments.
+        keep_args: bool,
+        flag: Flag,
+    },
+    Return {
+        status: StatusCode,
+        // The Location of a redirect or the body of anything else.
+        text: Option<String>,
+    },
+}
+
+// The URI rules work on, the decoded path and the query as sent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub path: String,
+    pub query: Option<String>,
+}
+
+impl Target {
+    pub fn from_uri(uri: &str) -> Option<Target> {
+        let (path, query) = match uri.split_once('?') {
+            Some((path, query)) => (path, Some(query.to_owned())),
+            None => (uri, None),
+        };
+        let decoded = percent_decode(path.as_bytes()).decode_utf8().ok()?;
+        Some(Target {
+            path: normalize_path(&decoded).ok()?,
+            query,
+        })
+    }
+
+    // The encoded form for a request line or a Location header.
+    pub fn to_uri(&self) -> String {
+        match self.query {
+            Some(ref query) => format!("{}?{}", encode(&self.path, false), query),
+            None => encode(&self.path, false),
+        }
+    }
+}
+
+// Request values replacements refer to as `$scheme`, `$host` and
+// `$request_uri`. `$uri` and `$args` are those of the target.
+#[derive(Debug, Clone, Copy)]
+pub struct Vars<'a> {
+    pub scheme: &'a str,
+    pub host: &'a str,
+    pub request_uri: &'a str,
+}
+
+impl<'a> Vars<'a> {
+    pub fn new(req: &'a HTTPRequest, secure: bool) -> Vars<'a> {
+        let host = req.header("Host").unwrap_or("");
+        // The port is not part of $host, an IPv6 address keeps its colons.
+        let host = match host.rfind(':') {
+            Some(i) if !host[i..].contains(']') => &host[..i],
+            _ => host,
+        };
+        Vars {
+            scheme: if secure { "https" } else { "http" },
+            host,
+            request_uri: &req.uri,
+        }
+    }
+}
+
+// What the rules of a block did to the target.
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    // No rule stopped processing, true if one rewrote the target.
+    Next(bool),
+    Last,
+    Break,
+    // A `return` or a redirect, with the Location or the body.
+    Return(StatusCode, Option<String>),
+}
+
+// The `rewrite` and `return` directives of the server or a location, run
+// in order.
+#[derive(Debug, Default)]
+pub struct Rewrites {
+    rules: Vec<Rule>,
+}
+
+impl Rewrites {
+    // Accepts `rewrite <regex> <replacement> [last|break|redirect|permanent]`
+    // and `return <code> [<text>|<url>]` or `return <url>`, returns false for
+    // any other directive.
+    pub fn parse_directive(&mut self, tokens: &[String]) -> Result<bool, String> {
+        let rule = match tokens[0].as_str() {
+            "rewrite" => parse_rewrite(tokens).ok_or_else(|| format!("{}: {}", REWRITE_INVALID_FORMAT, tokens[1..].join(" ")))?,
+            "return" => parse_return(tokens).ok_or_else(|| format!("{}: {}", RETURN_INVALID_FORMAT, tokens[1..].join(" ")))?,
+            _ => return Ok(false),
+        };
+
+        self.rules.push(rule);
+        Ok(true)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn apply(&self, target: &mut Target, vars: &Vars) -> Outcome {
+        let mut changed = false;
+        for rule in &self.rules {
+            let (regex, path, query, keep_args, flag) = match rule {
+                Rule::Return { status, text } => {
+                    return Outcome::Return(*status, text.as_ref().map(|t| expand(t, None, target, vars)));
+                },
+                Rule::Rewrite { regex, path, query, keep_args, flag } => (regex, path, query, *keep_args, *flag),
+            };
+            let captures = match regex.captures(&target.path) {
+                Some(captures) => captures,
+                None => continue,
+            };
+
+            let new_path = expand(path, Some(&captures), target, vars);
+            let new_query = query.as_ref().map(|q| encode(&expand(q, Some(&captures), target, vars), true));
+            let query = match (new_query, target.query.take().filter(|_| keep_args)) {
+                (Some(new), Some(old)) if !new.is_empty() && !old.is_empty() => Some(format!("{}&{}", new, old)),
+                (Some(new), _) if !new.is_empty() => Some(new),
+                (_, old) => old,
+            };
+            let rewritten = Target {
+                path: new_path,
+                query,
+            };
+
+            let absolute = rewritten.path.starts_with("http://") || rewritten.path.starts_with("https://");
+            match flag {
+                Flag::Permanent => return Outcome::Return(StatusCode::MOVED_PERMANENTLY, Some(rewritten.to_uri())),
+                Flag::Redirect => return Outcome::Return(StatusCode::FOUND, Some(rewritten.to_uri())),
+                _ if absolute => return Outcome::Return(StatusCode::FOUND, Some(rewritten.to_uri())),
+                _ => {},
+            }
+
+            *target = rewritten;
+            changed = true;
+            match flag {
+                Flag::Last => return Outcome::Last,
+                Flag::Break => return Outcome::Break,
+                _ => {},
+            }
+        }
+
+        Outcome::Next(changed)
+    }
+}
+
+// A rewritten request.
+#[derive(Debug)]
+pub struct Rewritten<'a> {
+    pub req: HTTPRequest,
+    // Set after `break`, the location keeps serving the request whatever
+    // location its new path falls in.
+    pub location: Option<&'a Location>,
+}
+
+// Runs the server rules, then those of the location, matching locations
+// again after `last` or a rewrite without a flag. Ok(None) serves the
+// request as it is, the error is the response to a `return`, a redirect or
+// a rewrite that went wrong.
+pub fn rewrite<'a>(config: &'a Config, req: &HTTPRequest, secure: bool) -> Result<Option<Rewritten<'a>>, HTTPResponse> {
+    if config.rewrites.is_empty() && config.locations.iter().all(|l| l.rewrites.is_empty()) {
+        return Ok(None);
+    }
+    let original = match Target::from_uri(&req.uri) {
+        Some(target) => target,
+        None => return Ok(None),
+    };
+    let vars = Vars::new(req, secure);
+    let mut target = original.clone();
+
+    // `last` and `break` only end the server rules.
+    if let Outcome::Return(status, text) = config.rewrites.apply(&mut target, &vars) {
+        return Err(respond(req, status, text));
+    }
+    let mut cycles = 0;
+    let kept = loop {
+        let location = config.location(&target.path);
+        let outcome = match location {
+            Some(location) => location.rewrites.apply(&mut target, &vars),
+            None => Outcome::Next(false),
+        };
+        match outcome {
+            Outcome::Return(status, text) => return Err(respond(req, status, text)),
+            Outcome::Last | Outcome::Next(true) if cycles < MAX_REWRITE_CYCLES => cycles += 1,
+            Outcome::Last | Outcome::Next(true) => {
+                println!("Rewrite cycle for {}", req.uri);
+                return Err(HTTPResponse::builder().status(StatusCode::INTERNAL_SERVER_ERROR).build());
+            },
+            Outcome::Break => break location,
+            Outcome::Next(false) => break None,
+        }
+    };
+
+    if target == original {
+        return Ok(None);
+    }
+    match req.with_target(&target.to_uri()) {
+        Some(rewritten) => Ok(Some(Rewritten {
+            req: rewritten,
+            location: kept,
+        })),
+        None => {
+            println!("Rewrite of {} to an invalid uri {}", req.uri, target.to_uri());
+            Err(HTTPResponse::builder().status(StatusCode::INTERNAL_SERVER_ERROR).build())
+        },
+    }
+}
+
+// A redirect gets the Location, other statuses the text as body. A status
+// without text is left to the error pages.
+fn respond(req: &HTTPRequest, status: StatusCode, text: Option<String>) -> HTTPResponse {
+    let builder = HTTPResponse::builder().status(status);
+    match text {
+        Some(location) if REDIRECT_STATUSES.contains(&status.as_u16()) => builder.header("Location", &location).build(),
+        Some(text) if req.method == "HEAD" => builder
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .header("Content-Length", &text.len().to_string())
+            .build(),
+        Some(text) => builder.header("Content-Type", "text/plain; charset=utf-8").body(text.into_bytes()),
+        None => builder.build(),
+    }
+}
+
+fn parse_rewrite(tokens: &[String]) -> Option<Rule> {
+    let flag = match tokens.get(3).map(|f| f.as_str()) {
+        None => Flag::None,
+        Some("last") => Flag::Last,
+        Some("break") => Flag::Break,
+        Some("redirect") => Flag::Redirect,
+        Some("permanent") => Flag::Permanent,
+        Some(_) => return None,
+    };
+    if tokens.len() < 3 || tokens.len() > 4 {
+        return None;
+    }
+    let regex = Regex::new(&tokens[1]).ok()?;
+    let replacement = &tokens[2];
+    if !valid_template(replacement, regex.captures_len() - 1) {
+        return None;
+    }
+
+    let keep_args = !replacement.ends_with('?');
+    let replacement = replacement.strip_suffix('?').unwrap_or(replacement);
+    let (path, query) = match replacement.split_once('?') {
+        Some((path, query)) => (path, Some(query.to_owned())),
+        None => (replacement, None),
+    };
+    let absolute = ["http://", "https://", "$scheme://"].iter().any(|p| path.starts_with(p));
+    if path.is_empty() || !(path.starts_with('/') || path.starts_with('$') || absolute) {
+        return None;
+    }
+
+    Some(Rule::Rewrite {
+        regex,
+        path: path.to_owned(),
+        query,
+        keep_args,
+        flag,
+    })
+}
+
+fn parse_return(tokens: &[String]) -> Option<Rule> {
+    let (status, text) = match &tokens[1..] {
+        [url] if ["http://", "https://", "$scheme://"].iter().any(|p| url.starts_with(p)) => (StatusCode::FOUND, Some(url.clone())),
+        [code] => (parse_status(code)?, None),
+        [code, text] => (parse_status(code)?, Some(text.clone())),
+        _ => return None,
+    };
+    if REDIRECT_STATUSES.contains(&status.as_u16()) && text.is_none() {
+        return None;
+    }
+    if text.as_ref().is_some_and(|t| !valid_template(t, 0)) {
+        return None;
+    }
+
+    Some(Rule::Return { status, text })
+}
+
+// A status from 200 to 599.
+pub fn parse_status(raw: &str) -> Option<StatusCode> {
+    match raw.parse::<u16>() {
+        Ok(code) if raw.len() == 3 && (200..600).contains(&code) => StatusCode::from_u16(code),
+        _ => None,
+    }
+}
+
+// Every `$n` refers to a capture of the regex and every `$name` to a known
+// variable.
+pub fn valid_template(template: &str, groups: usize) -> bool {
+    let mut rest = template;
+    while let Some(i) = rest.find('$') {
+        let (name, len) = variable(&rest[i + 1..]);
+        let known = match name.parse::<usize>() {
+            Ok(n) => (1..=groups).contains(&n),
+            Err(_) => VARIABLES.contains(&name),
+        };
+        if !known {
+            return false;
+        }
+        rest = &rest[i + 1 + len..];
+    }
+    true
+}
+
+// The name after a `$`, `1`, `host` or `{host}`, and the bytes it takes.
+fn variable(rest: &str) -> (&str, usize) {
+    if let Some(braced) = rest.strip_prefix('{') {
+        return match braced.find('}') {
+            Some(end) => (&braced[..end], end + 2),
+            None => ("", 0),
+        };
+    }
+    match rest.bytes().next() {
+        Some(b) if b.is_ascii_digit() => (&rest[..1], 1),
+        _ => {
+            let len = rest.bytes().take_while(|b| b.is_ascii_alphanumeric() || *b == b'_').count();
+            (&rest[..len], len)
+        },
+    }
+}
+
+pub fn expand(template: &str, captures: Option<&Captures>, target: &Target, vars: &Vars) -> String {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(i) = rest.find('$') {
+        expanded.push_str(&rest[..i]);
+        let (name, len) = variable(&rest[i + 1..]);
+        let value = match name {
+            "uri" => target.path.as_str(),
+            "args" => target.query.as_deref().unwrap_or(""),
+            "request_uri" => vars.request_uri,
+            "scheme" => vars.scheme,
+            "host" => vars.host,
+            n => n.parse::<usize>().ok()
+                .and_then(|n| captures.and_then(|c| c.get(n)))
+                .map(|m| m.as_str())
+                .unwrap_or(""),
+        };
+        expanded.push_str(value);
+        rest = &rest[i + 1 + len..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+// Percent-encodes what cannot stand in a request target, and `?` in a path.
+// Already encoded arguments are left as they are.
+pub fn encode(raw: &str, query: bool) -> String {
+    let mut encoded = String::with_capacity(raw.len());
+    for byte in raw.bytes() {
+        match byte {
+            b'%' if query => encoded.push('%'),
+            b'?' if query => encoded.push('?'),
+            0..=0x20 | 0x7f..=0xff | b'"' | b'#' | b'<' | b'>' | b'`' | b'{' | b'}' | b'%' | b'?' => {
+                encoded.push_str(&format!("%{:02X}", byte));
+            },
+            _ => encoded.push(byte as char),
+        }
+    }
+    encoded
+}
@@ -0,0 +1,164 @@
+use super::rewrite::*;
+use crate::config::config_test::{directives, parse_err};
+use crate::config::config::Config;
+use crate::http::request::HTTPRequest;
+use crate::http::status::StatusCode;
+
+const VARS: Vars = Vars {
+    scheme: "https",
+    host: "example.com",
+    request_uri: "/blog/2019/hello%20world?ref=feed",
+};
+
+fn rules(lines: &[&str]) -> Rewrites {
+    directives(lines, Rewrites::parse_directive)
+}
+
+fn target(uri: &str) -> Target {
+    Target::from_uri(uri).unwrap()
+}
+
+#[test]
+fn captures_and_args() {
+    let rewrites = rules(&["rewrite ^/blog/(\\d{4})/(.+)$ /posts/$2?year=$1 last"]);
+    let mut t = target("/blog/2019/hello%20world?ref=feed");
+    assert_eq!(t.path, "/blog/2019/hello world");
+    assert_eq!(rewrites.apply(&mut t, &VARS), Outcome::Last);
+    assert_eq!(t.path, "/posts/hello world");
+    assert_eq!(t.to_uri(), "/posts/hello%20world?year=2019&ref=feed");
+
+    // A trailing `?` drops the original arguments.
+    let rewrites = rules(&["rewrite ^/search$ /find? break"]);
+    let mut t = target("/search?q=1");
+    assert_eq!(rewrites.apply(&mut t, &VARS), Outcome::Break);
+    assert_eq!(t.to_uri(), "/find");
+
+    let rewrites = rules(&["rewrite ^/a$ /b"]);
+    let mut t = target("/a?x=1");
+    assert_eq!(rewrites.apply(&mut t, &VARS), Outcome::Next(true));
+    assert_eq!(t.to_uri(), "/b?x=1");
+
+    let mut t = target("/c");
+    assert_eq!(rewrites.apply(&mut t, &VARS), Outcome::Next(false));
+    assert_eq!(t.to_uri(), "/c");
+}
+
+#[test]
+fn rules_run_in_order() {
+    let rewrites = rules(&[
+        "rewrite ^/old/(.*)$ /new/$1",
+        "rewrite ^/new/(.*)\\.htm$ /new/$1.html",
+        "return 404",
+    ]);
+    let mut t = target("/old/page.htm");
+    assert_eq!(rewrites.apply(&mut t, &VARS), Outcome::Return(StatusCode::NOT_FOUND, None));
+    assert_eq!(t.path, "/new/page.html");
+}
+
+#[test]
+fn redirects() {
+    let rewrites = rules(&["rewrite ^/docs/(.*)$ /manual/$1 permanent"]);
+    let mut t = target("/docs/a%3Fb?v=2");
+    assert_eq!(
+        rewrites.apply(&mut t, &VARS),
+        Outcome::Return(StatusCode::MOVED_PERMANENTLY, Some(String::from("/manual/a%3Fb?v=2"))),
+    );
+
+    let rewrites = rules(&["rewrite ^/(.*)$ $scheme://www.$host/$1"]);
+    let mut t = target("/x");
+    assert_eq!(rewrites.apply(&mut t, &VARS), Outcome::Return(StatusCode::FOUND, Some(String::from("https://www.example.com/x"))));
+
+    let rewrites = rules(&["rewrite ^ /moved redirect"]);
+    let mut t = target("/x");
+    assert_eq!(rewrites.apply(&mut t, &VARS), Outcome::Return(StatusCode::FOUND, Some(String::from("/moved"))));
+}
+
+#[test]
+fn returns() {
+    let rewrites = rules(&["return 301 https://$host$request_uri"]);
+    let mut t = target("/blog/2019/hello%20world?ref=feed");
+    assert_eq!(
+        rewrites.apply(&mut t, &VARS),
+        Outcome::Return(StatusCode::MOVED_PERMANENTLY, Some(String::from("https://example.com/blog/2019/hello%20world?ref=feed"))),
+    );
+
+    let rewrites = rules(&["return https://example.org${uri}"]);
+    let mut t = target("/a");
+    assert_eq!(rewrites.apply(&mut t, &VARS), Outcome::Return(StatusCode::FOUND, Some(String::from("https://example.org/a"))));
+
+    let rewrites = rules(&["return 200 ok:$args"]);
+    let mut t = target("/a?b=c");
+    assert_eq!(rewrites.apply(&mut t, &VARS), Outcome::Return(StatusCode::OK, Some(String::from("ok:b=c"))));
+}
+
+#[test]
+fn invalid() {
+    for line in [
+        "rewrite ^/a$",
+        "rewrite ^/(a$ /b",
+        "rewrite ^/a$ /b next",
+        "rewrite ^/a$ /$2",
+        "rewrite ^/a$ /$nope",
+        "rewrite ^/a$ b",
+        "rewrite ^/a$ /b last extra",
+    ] {
+        assert!(parse_err(line, Rewrites::parse_directive).starts_with(REWRITE_INVALID_FORMAT), "{}", line);
+    }
+    for line in ["return", "return 99", "return 301", "return 200 a b", "return 404 $1", "return /relative"] {
+        assert!(parse_err(line, Rewrites::parse_directive).starts_with(RETURN_INVALID_FORMAT), "{}", line);
+    }
+
+    let tokens = vec![String::from("expires"), String::from("1h")];
+    assert_eq!(Rewrites::default().parse_directive(&tokens), Ok(false));
+}
+
+fn request(uri: &str) -> HTTPRequest {
+    HTTPRequest::parse(format!("GET {} HTTP/1.1\r\nHost: example.com:8080\r\n\r\n", uri).as_bytes()).unwrap()
+}
+
+fn rewritten(cfg: &Config, uri: &str) -> Option<HTTPRequest> {
+    match rewrite(cfg, &request(uri), false) {
+        Ok(rewritten) => rewritten.map(|r| r.req),
+        Err(resp) => panic!("Unexpected response {} for {}", resp.status(), uri),
+    }
+}
+
+#[test]
+fn locations() {
+    let cfg = match Config::read("test/test_rewrite.txt") {
+        Ok(cfg) => cfg,
+        Err(err) => panic!("Unexcpected error {}", err),
+    };
+
+    assert!(rewritten(&cfg, "/index.html").is_none());
+
+    // Server rules, then `last` into the location of the new path.
+    let req = rewritten(&cfg, "/blog/2019/hello?ref=feed").unwrap();
+    assert_eq!(req.uri, "/articles/hello.html?year=2019&ref=feed");
+    assert_eq!(req.path, "/articles/hello.html");
+
+    let req = rewritten(&cfg, "/articles/").unwrap();
+    assert_eq!(req.path, "/articles/list.html");
+
+    // `break` keeps the request in the location of the rule.
+    let kept = rewrite(&cfg, &request("/static/app.js"), false).ok().flatten().unwrap();
+    assert_eq!(kept.req.path, "/assets/app.js");
+    assert_eq!(kept.location.map(|l| l.prefix.as_str()), Some("/static/"));
+    let last = rewrite(&cfg, &request("/blog/2019/hello"), false).ok().flatten().unwrap();
+    assert!(last.location.is_none());
+
+    let resp = rewrite(&cfg, &request("/shop/cart?id=1"), false).unwrap_err();
+    assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(resp.header("Location"), Some("https://store.example.com/cart?id=1"));
+
+    let resp = rewrite(&cfg, &request("/health"), false).unwrap_err();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.header("Content-Length"), Some("2"));
+
+    let resp = rewrite(&cfg, &request("/private/key"), false).unwrap_err();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    // Each cycle sends the request back to the same location.
+    let resp = rewrite(&cfg, &request("/loop/a"), false).unwrap_err();
+    assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
@@ -0,0 +1,134 @@
+use std::path::Path;
+
+use crate::http::request::{normalize_path, HTTPRequest};
+use crate::http::status::StatusCode;
+use crate::mime::mime::extension;
+use crate::rewrite::rewrite::{encode, expand, parse_status, valid_template, Target, Vars};
+
+pub const TRY_FILES_INVALID_FORMAT: &str = "Invalid try_files format";
+
+#[derive(Debug, Clone, PartialEq)]
+enum Fallback {
+    // Served in place of the request, with its own location.
+    Uri(String),
+    Status(StatusCode),
+}
+
+// A `try_files <file>... <uri>|=<code>` or `spa <uri>` directive of the
+// server or a location. A location with one replaces the server's.
+#[derive(Debug, Default)]
+pub struct TryFiles {
+    // Paths under the document root, one ending in `/` is a directory.
+    files: Vec<String>,
+    fallback: Option<Fallback>,
+    // `spa` answers missing files with an extension, such as scripts and
+    // images, with 404 rather than the app.
+    spa: bool,
+}
+
+impl TryFiles {
+    // Accepts `try_files` and `spa`, returns false for any other directive.
+    pub fn parse_directive(&mut self, tokens: &[String]) -> Result<bool, String> {
+        let invalid = || format!("{}: {}", TRY_FILES_INVALID_FORMAT, tokens[1..].join(" "));
+        match tokens[0].as_str() {
+            "try_files" => {
+                let (fallback, files) = match tokens[1..].split_last() {
+                    Some((fallback, files)) if !files.is_empty() => (fallback, files),
+                    _ => return Err(invalid()),
+                };
+                if !files.iter().all(|f| is_uri(f)) {
+                    return Err(invalid());
+                }
+                let fallback = match fallback.strip_prefix('=') {
+                    Some(code) => Fallback::Status(parse_status(code).ok_or_else(invalid)?),
+                    None if is_uri(fallback) => Fallback::Uri(fallback.clone()),
+                    None => return Err(invalid()),
+                };
+                *self = TryFiles {
+                    files: files.to_vec(),
+                    fallback: Some(fallback),
+                    spa: false,
+                };
+            },
+            "spa" => match &tokens[1..] {
+                [index] if is_uri(index) => {
+                    *self = TryFiles {
+                        files: vec![String::from("$uri"), String::from("$uri/")],
+                        fallback: Some(Fallback::Uri(index.clone())),
+                        spa: true,
+                    };
+                },
+                _ => return Err(invalid()),
+            },
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fallback.is_none()
+    }
+
+    // The request for the first file that exists under `root`, or for the
+    // fallback. Ok(None) serves the request as it is, the error is the
+    // status of an `=<code>` fallback. The fallback is served without
+    // trying files again. Only GET and HEAD requests are resolved, other
+    // methods are not about reading a file.
+    pub fn resolve(&self, req: &HTTPRequest, root: &str, vars: &Vars) -> Result<Option<HTTPRequest>, StatusCode> {
+        let fallback = match self.fallback {
+            Some(ref fallback) if req.method == "GET" || req.method == "HEAD" => fallback,
+            _ => return Ok(None),
+        };
+        let original = match Target::from_uri(&req.uri) {
+            Some(target) => target,
+            None => return Ok(None),
+        };
+
+        for file in &self.files {
+            let path = match normalize_path(&expand(file, None, &original, vars)) {
+                Ok(path) => path,
+                Err(()) => continue,
+            };
+            let full = Path::new(root).join(path.trim_start_matches('/'));
+            let found = match path.ends_with('/') {
+                true => full.is_dir(),
+                false => full.is_file(),
+            };
+            if !found {
+                continue;
+            }
+            if path == original.path {
+                return Ok(None);
+            }
+            let target = Target {
+                path,
+                query: original.query.clone(),
+            };
+            return Ok(req.with_target(&target.to_uri()));
+        }
+
+        if self.spa && extension(&original.path).is_some() {
+            return Err(StatusCode::NOT_FOUND);
+        }
+        let uri = match fallback {
+            Fallback::Status(status) => return Err(*status),
+            Fallback::Uri(uri) => expand(uri, None, &original, vars),
+        };
+        let (path, query) = match uri.split_once('?') {
+            Some((path, query)) => (path, Some(encode(query, true))),
+            None => (uri.as_str(), None),
+        };
+        let target = Target {
+            path: normalize_path(path).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+            // The arguments of the request are kept unless the fallback has
+            // its own.
+            query: query.or(original.query),
+        };
+        req.with_target(&target.to_uri()).map(Some).ok_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}
+
+fn is_uri(raw: &str) -> bool {
+    (raw.starts_with('/') || raw.starts_with('$')) && valid_template(raw, 0)
+}
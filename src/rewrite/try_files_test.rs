@@ -0,0 +1,80 @@
+use super::rewrite::Vars;
+use super::try_files::*;
+use crate::config::config_test::parse_err;
+use crate::config::config::Config;
+use crate::http::request::HTTPRequest;
+use crate::http::status::StatusCode;
+
+fn config() -> Config {
+    match Config::read("test/test_try_files.txt") {
+        Ok(cfg) => cfg,
+        Err(err) => panic!("Unexcpected error {}", err),
+    }
+}
+
+// The uri served for a GET of `uri`, None if it is served as it is.
+fn resolve(cfg: &Config, uri: &str) -> Result<Option<String>, StatusCode> {
+    resolve_method(cfg, "GET", uri)
+}
+
+fn resolve_method(cfg: &Config, method: &str, uri: &str) -> Result<Option<String>, StatusCode> {
+    let req = HTTPRequest::parse(format!("{} {} HTTP/1.1\r\nHost: example.com\r\n\r\n", method, uri).as_bytes()).unwrap();
+    let vars = Vars::new(&req, false);
+    cfg.try_files_for(&req.path).resolve(&req, &cfg.dir_root, &vars).map(|r| r.map(|r| r.uri))
+}
+
+#[test]
+fn spa() {
+    let cfg = config();
+    assert_eq!(resolve(&cfg, "/spa/app.js"), Ok(None));
+    assert_eq!(resolve(&cfg, "/spa/"), Ok(None));
+    assert_eq!(resolve(&cfg, "/spa/users/42?tab=posts"), Ok(Some(String::from("/spa/index.html?tab=posts"))));
+    // A missing asset is not answered with the app.
+    assert_eq!(resolve(&cfg, "/spa/missing.js"), Err(StatusCode::NOT_FOUND));
+}
+
+#[test]
+fn pretty_urls() {
+    let cfg = config();
+    assert_eq!(resolve(&cfg, "/site/about"), Ok(Some(String::from("/site/about.html"))));
+    assert_eq!(resolve(&cfg, "/site/docs?v=1"), Ok(Some(String::from("/site/docs/?v=1"))));
+    assert_eq!(resolve(&cfg, "/site/old%20page"), Ok(Some(String::from("/site/about.html?missing=/site/old%20page"))));
+}
+
+#[test]
+fn status_fallback() {
+    let cfg = config();
+    assert_eq!(resolve(&cfg, "/maintenance/"), Err(StatusCode::SERVICE_UNAVAILABLE));
+    // The server one applies outside of locations with their own.
+    assert_eq!(resolve(&cfg, "/test.txt"), Ok(None));
+    assert_eq!(resolve(&cfg, "/errors"), Ok(Some(String::from("/errors/"))));
+    assert_eq!(resolve(&cfg, "/nothing"), Err(StatusCode::NOT_FOUND));
+}
+
+#[test]
+fn only_get_and_head() {
+    let cfg = config();
+    assert_eq!(resolve_method(&cfg, "HEAD", "/nothing"), Err(StatusCode::NOT_FOUND));
+    assert_eq!(resolve_method(&cfg, "POST", "/nothing"), Ok(None));
+    assert_eq!(resolve_method(&cfg, "PUT", "/spa/users/42"), Ok(None));
+}
+
+#[test]
+fn handlers_do_not_inherit() {
+    let cfg = config();
+    // The server try_files would answer these with 404.
+    assert_eq!(resolve(&cfg, "/api/users"), Ok(None));
+    assert_eq!(resolve(&cfg, "/share/new.txt"), Ok(None));
+    assert_eq!(resolve_method(&cfg, "PROPFIND", "/share/"), Ok(None));
+}
+
+#[test]
+fn invalid() {
+    for line in ["try_files", "try_files $uri", "try_files $uri =99", "try_files $uri index.html", "try_files $nope =404", "spa", "spa index.html"] {
+        assert!(parse_err(line, TryFiles::parse_directive).starts_with(TRY_FILES_INVALID_FORMAT), "{}", line);
+    }
+
+    let tokens = vec![String::from("rewrite"), String::from("^"), String::from("/")];
+    assert_eq!(TryFiles::default().parse_directive(&tokens), Ok(false));
+    assert!(TryFiles::default().is_empty());
+}
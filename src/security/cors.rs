@@ -0,0 +1,255 @@
+use crate::http::request::{is_token, HTTPRequest};
+use crate::http::response::HTTPResponse;
+use crate::http::status::StatusCode;
+
+pub const CORS_INVALID_FORMAT: &str = "Invalid cors format";
+pub const CORS_REQUIRES_ORIGIN: &str = "cors directives require cors_allow_origin";
+
+// Methods allowed without `cors_allow_methods`, the CORS-safelisted ones.
+const DEFAULT_METHODS: [&str; 3] = ["GET", "HEAD", "POST"];
+
+#[derive(Debug, Clone, PartialEq)]
+enum AllowedOrigin {
+    Any,
+    // `https://app.example.com:8443`, lowercase.
+    Exact(String),
+    // `https://*.example.com` matches any subdomain, but not the domain.
+    Subdomains { prefix: String, suffix: String },
+}
+
+impl AllowedOrigin {
+    fn parse(raw: &str) -> Option<AllowedOrigin> {
+        if raw == "*" {
+            return Some(AllowedOrigin::Any);
+        }
+        let raw = raw.to_ascii_lowercase();
+        let (scheme, rest) = raw.split_once("://")?;
+        let (host, port) = match rest.rsplit_once(':').filter(|_| !rest.ends_with(']')) {
+            Some((host, port)) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => (host, Some(port)),
+            Some(_) => return None,
+            None => (rest, None),
+        };
+        let valid_scheme = !scheme.is_empty() && scheme.bytes().all(|b| b.is_ascii_alphanumeric() || b"+-.".contains(&b));
+        if !valid_scheme || port.is_some_and(|p| p.parse::<u16>().is_err()) {
+            return None;
+        }
+
+        match host.strip_prefix("*.") {
+            Some(domain) if is_host(domain) => Some(AllowedOrigin::Subdomains {
+                prefix: format!("{}://", scheme),
+                suffix: raw[scheme.len() + 4..].to_owned(),
+            }),
+            None if is_host(host) => Some(AllowedOrigin::Exact(raw.clone())),
+            _ => None,
+        }
+    }
+
+    fn matches(&self, origin: &str) -> bool {
+        match self {
+            AllowedOrigin::Any => true,
+            AllowedOrigin::Exact(allowed) => *allowed == origin,
+            AllowedOrigin::Subdomains { prefix, suffix } => {
+                origin.len() > prefix.len() + suffix.len()
+                    && origin.starts_with(prefix.as_str())
+                    && origin.ends_with(suffix.as_str())
+                    && is_host(&origin[prefix.len()..origin.len() - suffix.len()])
+            },
+        }
+    }
+}
+
+// The CORS policy of a location.
+#[derive(Debug)]
+pub struct Cors {
+    origins: Vec<AllowedOrigin>,
+    methods: Vec<String>,
+    // Lowercase, `*` allows any.
+    headers: Vec<String>,
+    expose: Vec<String>,
+    credentials: bool,
+    max_age: Option<u64>,
+}
+
+impl Cors {
+    // An OPTIONS request a browser sends before the actual one.
+    pub fn is_preflight(&self, req: &HTTPRequest) -> bool {
+        req.method == "OPTIONS" && req.header("Origin").is_some() && req.header("Access-Control-Request-Method").is_some()
+    }
+
+    // The Access-Control-Allow-Origin value for `origin`, None if it is not
+    // allowed. Credentials are never shared with `*`.
+    pub fn allow_origin(&self, origin: &str) -> Option<String> {
+        let lower = origin.to_ascii_lowercase();
+        let allowed = self.origins.iter().find(|o| o.matches(&lower))?;
+        match allowed {
+            AllowedOrigin::Any if !self.credentials => Some(String::from("*")),
+            _ => Some(origin.to_owned()),
+        }
+    }
+
+    // Answers a preflight with 204 if the origin, the method and all the
+    // headers are allowed and with 403 otherwise.
+    pub fn preflight(&self, req: &HTTPRequest) -> HTTPResponse {
+        let origin = req.header("Origin").unwrap_or("");
+        let method = req.header("Access-Control-Request-Method").unwrap_or("");
+        let requested = req.header_list("Access-Control-Request-Headers");
+
+        let any_header = self.headers.iter().any(|h| h == "*");
+        let headers_allowed = any_header || requested.iter().all(|h| self.headers.contains(&h.to_ascii_lowercase()));
+        let allow_origin = self.allow_origin(origin)
+            .filter(|_| self.methods.iter().any(|m| m == method) && headers_allowed);
+
+        let mut resp = HTTPResponse::builder().status(StatusCode::NO_CONTENT).build();
+        vary(&mut resp, "Origin");
+        vary(&mut resp, "Access-Control-Request-Method");
+        vary(&mut resp, "Access-Control-Request-Headers");
+        let allow_origin = match allow_origin {
+            Some(allow_origin) => allow_origin,
+            None => {
+                println!("CORS preflight denied for {} {} from {}", method, req.path, origin);
+                resp.setStatus(StatusCode::FORBIDDEN);
+                return resp;
+            },
+        };
+
+        resp.push_header("Access-Control-Allow-Origin".to_owned(), allow_origin);
+        if self.credentials {
+            resp.push_header("Access-Control-Allow-Credentials".to_owned(), "true".to_owned());
+        }
+        resp.push_header("Access-Control-Allow-Methods".to_owned(), self.methods.join(", "));
+        // `*` is taken literally for requests with credentials.
+        let allow_headers = match any_header && self.credentials {
+            true => requested.join(", "),
+            false => self.headers.join(", "),
+        };
+        if !allow_headers.is_empty() {
+            resp.push_header("Access-Control-Allow-Headers".to_owned(), allow_headers);
+        }
+        if let Some(max_age) = self.max_age {
+            resp.push_header("Access-Control-Max-Age".to_owned(), max_age.to_string());
+        }
+        resp
+    }
+
+    // Sets the Access-Control headers of an actual response. Vary is set
+    // whether or not the request had an Origin, caches must not give the
+    // response of one origin to another.
+    pub fn apply(&self, req: &HTTPRequest, resp: &mut HTTPResponse) {
+        if self.is_preflight(req) {
+            return;
+        }
+        vary(resp, "Origin");
+        let allow_origin = match req.header("Origin").and_then(|o| self.allow_origin(o)) {
+            Some(allow_origin) => allow_origin,
+            None => return,
+        };
+
+        resp.push_header("Access-Control-Allow-Origin".to_owned(), allow_origin);
+        if self.credentials {
+            resp.push_header("Access-Control-Allow-Credentials".to_owned(), "true".to_owned());
+        }
+        if !self.expose.is_empty() {
+            resp.push_header("Access-Control-Expose-Headers".to_owned(), self.expose.join(", "));
+        }
+    }
+}
+
+// Collects `cors_allow_origin`, `cors_allow_methods`, `cors_allow_headers`,
+// `cors_expose_headers`, `cors_allow_credentials` and `cors_max_age` while
+// a location is parsed.
+#[derive(Default)]
+pub struct CorsBuilder {
+    origins: Option<Vec<String>>,
+    methods: Option<Vec<String>>,
+    headers: Option<Vec<String>>,
+    expose: Option<Vec<String>>,
+    credentials: Option<String>,
+    max_age: Option<String>,
+}
+
+impl CorsBuilder {
+    // Returns false for directives that are not about CORS.
+    pub fn parse_directive(&mut self, tokens: &[String]) -> Result<bool, String> {
+        let slot = match tokens[0].as_str() {
+            "cors_allow_origin" => &mut self.origins,
+            "cors_allow_methods" => &mut self.methods,
+            "cors_allow_headers" => &mut self.headers,
+            "cors_expose_headers" => &mut self.expose,
+            "cors_allow_credentials" => return CorsBuilder::set(&mut self.credentials, tokens),
+            "cors_max_age" => return CorsBuilder::set(&mut self.max_age, tokens),
+            _ => return Ok(false),
+        };
+
+        if tokens.len() < 2 {
+            return Err(format!("{}: {}", CORS_INVALID_FORMAT, tokens.join(" ")));
+        }
+        slot.get_or_insert_with(Vec::new).extend_from_slice(&tokens[1..]);
+        Ok(true)
+    }
+
+    fn set(slot: &mut Option<String>, tokens: &[String]) -> Result<bool, String> {
+        if tokens.len() != 2 {
+            return Err(format!("{}: {}", CORS_INVALID_FORMAT, tokens.join(" ")));
+        }
+        *slot = Some(tokens[1].clone());
+        Ok(true)
+    }
+
+    pub fn build(self, prefix: &str) -> Result<Option<Cors>, String> {
+        let origins = match self.origins {
+            Some(origins) => origins,
+            None if self.methods.is_none() && self.headers.is_none() && self.expose.is_none()
+                && self.credentials.is_none() && self.max_age.is_none() => return Ok(None),
+            None => return Err(format!("{}: {}", CORS_REQUIRES_ORIGIN, prefix)),
+        };
+        let invalid = |value: &str| format!("{}: {}", CORS_INVALID_FORMAT, value);
+
+        let origins = origins.iter()
+            .map(|o| AllowedOrigin::parse(o).ok_or_else(|| invalid(o)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let credentials = match self.credentials.as_deref() {
+            Some("on") => true,
+            Some("off") | None => false,
+            Some(value) => return Err(invalid(value)),
+        };
+        // Reflecting any origin with credentials would let every site read
+        // the responses of logged in users.
+        if credentials && origins.contains(&AllowedOrigin::Any) {
+            return Err(invalid("cors_allow_origin * with cors_allow_credentials on"));
+        }
+
+        let methods = self.methods.unwrap_or_else(|| DEFAULT_METHODS.iter().map(|m| m.to_string()).collect());
+        let headers: Vec<String> = self.headers.unwrap_or_default().iter().map(|h| h.to_ascii_lowercase()).collect();
+        let expose = self.expose.unwrap_or_default();
+        if let Some(token) = methods.iter().chain(&headers).chain(&expose).find(|t| !is_token(t) && t.as_str() != "*") {
+            return Err(invalid(token));
+        }
+        let max_age = match self.max_age {
+            Some(raw) => Some(raw.parse::<u64>().map_err(|_| invalid(&raw))?),
+            None => None,
+        };
+
+        Ok(Some(Cors {
+            origins,
+            methods,
+            headers,
+            expose,
+            credentials,
+            max_age,
+        }))
+    }
+}
+
+// Adds `name` to the Vary header unless it is already listed.
+pub fn vary(resp: &mut HTTPResponse, name: &str) {
+    let value = match resp.header("Vary") {
+        Some(current) if current.split(',').any(|v| v.trim() == "*" || v.trim().eq_ignore_ascii_case(name)) => return,
+        Some(current) => format!("{}, {}", current, name),
+        None => name.to_owned(),
+    };
+    resp.push_header("Vary".to_owned(), value);
+}
+
+fn is_host(host: &str) -> bool {
+    !host.is_empty() && host.bytes().all(|b| b.is_ascii_alphanumeric() || b"-.[]:".contains(&b))
+}
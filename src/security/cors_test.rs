@@ -0,0 +1,146 @@
+use super::cors::*;
+use crate::config::config_test::directives;
+use crate::http::request::HTTPRequest;
+use crate::http::response::HTTPResponse;
+use crate::http::status::StatusCode;
+
+fn cors(lines: &[&str]) -> Result<Option<Cors>, String> {
+    directives(lines, CorsBuilder::parse_directive).build("/api/")
+}
+
+fn request(method: &str, headers: &[&str]) -> HTTPRequest {
+    let raw = format!("{} /api/users HTTP/1.1\r\n{}\r\n", method, headers.iter().map(|h| format!("{}\r\n", h)).collect::<String>());
+    HTTPRequest::parse(raw.as_bytes()).unwrap()
+}
+
+fn api() -> Cors {
+    cors(&[
+        "cors_allow_origin https://app.example.com https://*.example.org http://localhost:3000",
+        "cors_allow_methods GET POST DELETE",
+        "cors_allow_headers Content-Type X-Request-Id",
+        "cors_expose_headers X-Total-Count",
+        "cors_allow_credentials on",
+        "cors_max_age 600",
+    ]).unwrap().unwrap()
+}
+
+#[test]
+fn origins() {
+    let policy = api();
+    assert_eq!(policy.allow_origin("https://app.example.com"), Some(String::from("https://app.example.com")));
+    assert_eq!(policy.allow_origin("HTTPS://App.Example.com"), Some(String::from("HTTPS://App.Example.com")));
+    assert_eq!(policy.allow_origin("https://a.b.example.org"), Some(String::from("https://a.b.example.org")));
+    assert_eq!(policy.allow_origin("http://localhost:3000"), Some(String::from("http://localhost:3000")));
+    assert_eq!(policy.allow_origin("http://app.example.com"), None);
+    assert_eq!(policy.allow_origin("https://app.example.com.evil.net"), None);
+    assert_eq!(policy.allow_origin("https://example.org"), None);
+    assert_eq!(policy.allow_origin("https://evilexample.org"), None);
+    assert_eq!(policy.allow_origin("https://a/.example.org"), None);
+    assert_eq!(policy.allow_origin("http://localhost:30000"), None);
+    assert_eq!(policy.allow_origin("null"), None);
+
+    let any = cors(&["cors_allow_origin *"]).unwrap().unwrap();
+    assert_eq!(any.allow_origin("https://anyone.net"), Some(String::from("*")));
+}
+
+#[test]
+fn preflight() {
+    let cors = api();
+    let req = request("OPTIONS", &[
+        "Origin: https://app.example.com",
+        "Access-Control-Request-Method: DELETE",
+        "Access-Control-Request-Headers: content-type, x-request-id",
+    ]);
+    assert!(cors.is_preflight(&req));
+    let resp = cors.preflight(&req);
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    assert_eq!(resp.header("Access-Control-Allow-Origin"), Some("https://app.example.com"));
+    assert_eq!(resp.header("Access-Control-Allow-Credentials"), Some("true"));
+    assert_eq!(resp.header("Access-Control-Allow-Methods"), Some("GET, POST, DELETE"));
+    assert_eq!(resp.header("Access-Control-Allow-Headers"), Some("content-type, x-request-id"));
+    assert_eq!(resp.header("Access-Control-Max-Age"), Some("600"));
+    assert_eq!(resp.header("Vary"), Some("Origin, Access-Control-Request-Method, Access-Control-Request-Headers"));
+
+    assert!(!cors.is_preflight(&request("OPTIONS", &["Origin: https://app.example.com"])));
+    assert!(!cors.is_preflight(&request("GET", &["Origin: https://app.example.com", "Access-Control-Request-Method: GET"])));
+}
+
+#[test]
+fn preflight_denied() {
+    let cors = api();
+    for headers in [
+        ["Origin: https://evil.net", "Access-Control-Request-Method: GET", "X-A: b"],
+        ["Origin: https://app.example.com", "Access-Control-Request-Method: PUT", "X-A: b"],
+        ["Origin: https://app.example.com", "Access-Control-Request-Method: GET", "Access-Control-Request-Headers: authorization"],
+    ] {
+        let resp = cors.preflight(&request("OPTIONS", &headers));
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN, "{:?}", headers);
+        assert!(!resp.has_header("Access-Control-Allow-Origin"));
+        assert!(resp.has_header("Vary"));
+    }
+}
+
+#[test]
+fn preflight_any_header() {
+    let cors = cors(&["cors_allow_origin https://app.example.com", "cors_allow_headers *"]).unwrap().unwrap();
+    let req = request("OPTIONS", &["Origin: https://app.example.com", "Access-Control-Request-Method: POST", "Access-Control-Request-Headers: x-anything"]);
+    let resp = cors.preflight(&req);
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    assert_eq!(resp.header("Access-Control-Allow-Headers"), Some("*"));
+    assert_eq!(resp.header("Access-Control-Allow-Methods"), Some("GET, HEAD, POST"));
+    assert!(!resp.has_header("Access-Control-Allow-Credentials"));
+    assert!(!resp.has_header("Access-Control-Max-Age"));
+
+    // With credentials `*` is not special to browsers, the requested
+    // headers are listed instead.
+    let cors = cors_with_credentials();
+    let resp = cors.preflight(&req);
+    assert_eq!(resp.header("Access-Control-Allow-Headers"), Some("x-anything"));
+}
+
+fn cors_with_credentials() -> Cors {
+    cors(&["cors_allow_origin https://app.example.com", "cors_allow_headers *", "cors_allow_credentials on"]).unwrap().unwrap()
+}
+
+#[test]
+fn actual_response() {
+    let cors = api();
+    let mut resp = HTTPResponse::builder().header("Vary", "Accept-Encoding").build();
+    cors.apply(&request("GET", &["Origin: https://app.example.com"]), &mut resp);
+    assert_eq!(resp.header("Access-Control-Allow-Origin"), Some("https://app.example.com"));
+    assert_eq!(resp.header("Access-Control-Allow-Credentials"), Some("true"));
+    assert_eq!(resp.header("Access-Control-Expose-Headers"), Some("X-Total-Count"));
+    assert_eq!(resp.header("Vary"), Some("Accept-Encoding, Origin"));
+
+    let mut resp = HTTPResponse::new();
+    cors.apply(&request("GET", &["Origin: https://evil.net"]), &mut resp);
+    assert!(!resp.has_header("Access-Control-Allow-Origin"));
+    assert_eq!(resp.header("Vary"), Some("Origin"));
+
+    let mut resp = HTTPResponse::builder().header("Vary", "origin").build();
+    cors.apply(&request("GET", &[]), &mut resp);
+    assert_eq!(resp.header("Vary"), Some("origin"));
+}
+
+#[test]
+fn build() {
+    assert!(cors(&[]).unwrap().is_none());
+    assert_eq!(cors(&["cors_max_age 60"]).unwrap_err(), format!("{}: /api/", CORS_REQUIRES_ORIGIN));
+    for lines in [
+        &["cors_allow_origin *", "cors_allow_credentials on"][..],
+        &["cors_allow_origin app.example.com"],
+        &["cors_allow_origin https://app.example.com/path"],
+        &["cors_allow_origin https://app.example.com:99999"],
+        &["cors_allow_origin https://app.example.com", "cors_allow_credentials yes"],
+        &["cors_allow_origin https://app.example.com", "cors_max_age 1h"],
+        &["cors_allow_origin https://app.example.com", "cors_allow_methods GET,POST"],
+    ] {
+        assert!(cors(lines).unwrap_err().starts_with(CORS_INVALID_FORMAT), "{:?}", lines);
+    }
+
+    let mut builder = CorsBuilder::default();
+    let tokens = vec![String::from("cors_allow_origin")];
+    assert!(builder.parse_directive(&tokens).is_err());
+    let tokens = vec![String::from("expires"), String::from("1h")];
+    assert_eq!(builder.parse_directive(&tokens), Ok(false));
+}
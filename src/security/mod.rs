@@ -0,0 +1,7 @@
+pub mod cors;
+pub mod security;
+
+#[cfg(test)]
+mod cors_test;
+#[cfg(test)]
+mod security_test;
@@ -0,0 +1,123 @@
+use crate::http::response::HTTPResponse;
+
+pub const SECURITY_INVALID_FORMAT: &str = "Invalid security header format";
+
+const REFERRER_POLICIES: [&str; 8] = [
+    "no-referrer", "no-referrer-when-downgrade", "origin", "origin-when-cross-origin",
+    "same-origin", "strict-origin", "strict-origin-when-cross-origin", "unsafe-url",
+];
+// What browsers require of a domain on the HSTS preload list.
+const PRELOAD_MIN_AGE: u64 = 365 * 24 * 60 * 60;
+
+// The security headers of the server or a location. A location with any
+// of these directives replaces the server's, `security_headers off` drops
+// them without adding others.
+#[derive(Debug, Default)]
+pub struct SecurityHeaders {
+    headers: Vec<(&'static str, String)>,
+    // Only sent over TLS, RFC 6797 section 7.2.
+    hsts: Option<String>,
+    off: bool,
+}
+
+impl SecurityHeaders {
+    // Accepts `strict_transport_security <seconds> [includeSubDomains] [preload]`,
+    // `content_security_policy <policy> [report_only]`,
+    // `frame_options deny|sameorigin`, `referrer_policy <policy>...`,
+    // `content_type_options nosniff`, `permissions_policy <policy>` and
+    // `security_headers off`, returns false for any other directive.
+    pub fn parse_directive(&mut self, tokens: &[String]) -> Result<bool, String> {
+        let invalid = || format!("{}: {}", SECURITY_INVALID_FORMAT, tokens.join(" "));
+        let args = &tokens[1..];
+        match tokens[0].as_str() {
+            "strict_transport_security" => self.hsts = Some(parse_hsts(args).ok_or_else(invalid)?),
+            "content_security_policy" => {
+                let name = match args.get(1).map(|a| a.as_str()) {
+                    None => "Content-Security-Policy",
+                    Some("report_only") if args.len() == 2 => "Content-Security-Policy-Report-Only",
+                    Some(_) => return Err(invalid()),
+                };
+                let policy = args.first().filter(|p| is_value(p)).ok_or_else(invalid)?;
+                self.set(name, policy.clone());
+            },
+            "frame_options" => {
+                let value = match args {
+                    [value] if value == "deny" => "DENY",
+                    [value] if value == "sameorigin" => "SAMEORIGIN",
+                    _ => return Err(invalid()),
+                };
+                self.set("X-Frame-Options", value.to_owned());
+            },
+            "referrer_policy" => {
+                if args.is_empty() || !args.iter().all(|p| REFERRER_POLICIES.contains(&p.as_str())) {
+                    return Err(invalid());
+                }
+                self.set("Referrer-Policy", args.join(", "));
+            },
+            "content_type_options" => match args {
+                [value] if value == "nosniff" => self.set("X-Content-Type-Options", value.clone()),
+                _ => return Err(invalid()),
+            },
+            "permissions_policy" => match args {
+                [policy] if is_value(policy) => self.set("Permissions-Policy", policy.clone()),
+                _ => return Err(invalid()),
+            },
+            "security_headers" => match args {
+                [value] if value == "off" => self.off = true,
+                _ => return Err(invalid()),
+            },
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty() && self.hsts.is_none() && !self.off
+    }
+
+    // Sets the headers on responses of every status, a value from a
+    // handler or backend is replaced by the configured one.
+    pub fn apply(&self, resp: &mut HTTPResponse, secure: bool) {
+        if self.off {
+            return;
+        }
+        for (name, value) in &self.headers {
+            resp.push_header(name.to_string(), value.clone());
+        }
+        if let Some(hsts) = self.hsts.as_ref().filter(|_| secure) {
+            resp.push_header("Strict-Transport-Security".to_owned(), hsts.clone());
+        }
+    }
+
+    fn set(&mut self, name: &'static str, value: String) {
+        self.headers.retain(|(n, _)| *n != name);
+        self.headers.push((name, value));
+    }
+}
+
+fn parse_hsts(args: &[String]) -> Option<String> {
+    let (age, flags) = args.split_first()?;
+    let age: u64 = age.parse().ok()?;
+    let subdomains = flags.iter().any(|f| f == "includeSubDomains");
+    let preload = flags.iter().any(|f| f == "preload");
+    if flags.iter().any(|f| f != "includeSubDomains" && f != "preload") {
+        return None;
+    }
+    if preload && (!subdomains || age < PRELOAD_MIN_AGE) {
+        return None;
+    }
+
+    let mut value = format!("max-age={}", age);
+    if subdomains {
+        value.push_str("; includeSubDomains");
+    }
+    if preload {
+        value.push_str("; preload");
+    }
+    Some(value)
+}
+
+fn is_value(value: &str) -> bool {
+    !value.is_empty() && !value.contains(['\r', '\n'])
+}
@@ -0,0 +1,84 @@
+use super::security::*;
+use crate::config::config_test::{directives, parse_err};
+use crate::http::response::HTTPResponse;
+use crate::http::status::StatusCode;
+
+fn headers(lines: &[&str]) -> SecurityHeaders {
+    directives(lines, SecurityHeaders::parse_directive)
+}
+
+#[test]
+fn sets_headers() {
+    let security = headers(&[
+        "strict_transport_security 63072000 includeSubDomains preload",
+        "content_security_policy \"default-src 'self'; img-src *\"",
+        "frame_options deny",
+        "referrer_policy no-referrer strict-origin-when-cross-origin",
+        "content_type_options nosniff",
+        "permissions_policy geolocation=()",
+    ]);
+    let mut resp = HTTPResponse::builder().status(StatusCode::NOT_FOUND).build();
+    security.apply(&mut resp, true);
+    assert_eq!(resp.header("Strict-Transport-Security"), Some("max-age=63072000; includeSubDomains; preload"));
+    assert_eq!(resp.header("Content-Security-Policy"), Some("default-src 'self'; img-src *"));
+    assert_eq!(resp.header("X-Frame-Options"), Some("DENY"));
+    assert_eq!(resp.header("Referrer-Policy"), Some("no-referrer, strict-origin-when-cross-origin"));
+    assert_eq!(resp.header("X-Content-Type-Options"), Some("nosniff"));
+    assert_eq!(resp.header("Permissions-Policy"), Some("geolocation=()"));
+}
+
+#[test]
+fn hsts_only_over_tls() {
+    let security = headers(&["strict_transport_security 3600", "frame_options sameorigin"]);
+    let mut resp = HTTPResponse::new();
+    security.apply(&mut resp, false);
+    assert!(!resp.has_header("Strict-Transport-Security"));
+    assert_eq!(resp.header("X-Frame-Options"), Some("SAMEORIGIN"));
+
+    let mut resp = HTTPResponse::new();
+    security.apply(&mut resp, true);
+    assert_eq!(resp.header("Strict-Transport-Security"), Some("max-age=3600"));
+}
+
+#[test]
+fn replaces_values() {
+    let security = headers(&["content_security_policy \"default-src 'none'\" report_only", "frame_options deny", "frame_options sameorigin"]);
+    let mut resp = HTTPResponse::builder().header("X-Frame-Options", "ALLOWALL").build();
+    security.apply(&mut resp, false);
+    assert_eq!(resp.header("X-Frame-Options"), Some("SAMEORIGIN"));
+    assert_eq!(resp.header("Content-Security-Policy-Report-Only"), Some("default-src 'none'"));
+    assert!(!resp.has_header("Content-Security-Policy"));
+}
+
+#[test]
+fn off() {
+    let security = headers(&["security_headers off"]);
+    assert!(!security.is_empty());
+    let mut resp = HTTPResponse::new();
+    security.apply(&mut resp, true);
+    assert!(!resp.has_header("X-Frame-Options"));
+    assert!(SecurityHeaders::default().is_empty());
+}
+
+#[test]
+fn invalid() {
+    for line in [
+        "strict_transport_security",
+        "strict_transport_security 1y",
+        "strict_transport_security 3600 preload",
+        "strict_transport_security 3600 includeSubDomains preload",
+        "strict_transport_security 31536000 includeSubDomains always",
+        "content_security_policy",
+        "content_security_policy \"default-src 'self'\" enforce",
+        "frame_options allow-from",
+        "referrer_policy never",
+        "content_type_options sniff",
+        "permissions_policy",
+        "security_headers on",
+    ] {
+        assert!(parse_err(line, SecurityHeaders::parse_directive).starts_with(SECURITY_INVALID_FORMAT), "{}", line);
+    }
+
+    let tokens = vec![String::from("add_header"), String::from("X-A"), String::from("b")];
+    assert_eq!(SecurityHeaders::default().parse_directive(&tokens), Ok(false));
+}
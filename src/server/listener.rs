@@ -0,0 +1,209 @@
+use std::fs;
+use std::io;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::time::Duration;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixListener;
+
+use socket2::{Domain, Protocol, SockAddr, Socket as RawSocket, Type};
+
+use crate::config::listen::{Listen, ListenAddress};
+use crate::server::stream::Socket;
+
+// Seconds the kernel holds a deferred connection waiting for data.
+const DEFER_ACCEPT_SECS: libc::c_int = 1;
+
+enum Inner {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+// A bound listening socket together with its `listen` settings.
+pub struct Listener {
+    inner: Inner,
+    pub listen: Listen,
+}
+
+impl Listener {
+    pub fn bind(listen: &Listen) -> io::Result<Listener> {
+        let inner = match listen.address {
+            ListenAddress::Tcp(ref address) => Inner::Tcp(bind_tcp(address, listen)?),
+            ListenAddress::Unix(ref path) => Inner::Unix(bind_unix(path, listen)?),
+        };
+
+        Listener::new(inner, listen.clone())
+    }
+
+    // Listening sockets are non-blocking: they may be shared with another
+    // process during an upgrade, and a ready socket can be taken by it first.
+    fn new(inner: Inner, listen: Listen) -> io::Result<Listener> {
+        match inner {
+            Inner::Tcp(ref listener) => listener.set_nonblocking(true)?,
+            Inner::Unix(ref listener) => listener.set_nonblocking(true)?,
+        }
+
+        Ok(Listener {
+            inner,
+            listen,
+        })
+    }
+
+    // Adopts an already bound and listening socket, such as one passed by
+    // the service manager.
+    pub fn from_fd(fd: RawFd) -> io::Result<Listener> {
+        let socket = unsafe { RawSocket::from_raw_fd(fd) };
+        socket.set_cloexec(true)?;
+        let local = socket.local_addr()?;
+
+        if let Some(addr) = local.as_socket() {
+            return Listener::new(Inner::Tcp(socket.into()), Listen::tcp(addr.to_string()));
+        }
+
+        let path = match local.as_pathname() {
+            Some(path) if local.is_unix() => path.to_string_lossy().into_owned(),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("fd {} is not a tcp or unix socket", fd))),
+        };
+
+        Listener::new(Inner::Unix(UnixListener::from(OwnedFd::from(socket))), Listen {
+            address: ListenAddress::Unix(path),
+            ..Listen::tcp(String::new())
+        })
+    }
+
+    // Whether this socket is bound where `listen` asks for.
+    pub fn matches(&self, listen: &Listen) -> bool {
+        match (&self.inner, &listen.address) {
+            (Inner::Tcp(listener), ListenAddress::Tcp(address)) => match (listener.local_addr(), resolve(address)) {
+                (Ok(local), Ok(wanted)) => local == wanted,
+                _ => false,
+            },
+            (Inner::Unix(_), ListenAddress::Unix(path)) => self.listen.address == ListenAddress::Unix(path.clone()),
+            _ => false,
+        }
+    }
+
+    // Waits until a connection can be accepted, false on timeout.
+    pub fn wait(&self, timeout: Duration) -> io::Result<bool> {
+        let mut fds = [libc::pollfd {
+            fd: self.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        }];
+
+        match unsafe { libc::poll(fds.as_mut_ptr(), 1, timeout.as_millis() as libc::c_int) } {
+            n if n < 0 => {
+                let err = io::Error::last_os_error();
+                match err.kind() {
+                    io::ErrorKind::Interrupted => Ok(false),
+                    _ => Err(err),
+                }
+            },
+            0 => Ok(false),
+            _ => Ok(true),
+        }
+    }
+
+    // Accepted sockets are blocking regardless of the listener.
+    pub fn accept(&self) -> io::Result<Socket> {
+        match self.inner {
+            Inner::Tcp(ref listener) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nonblocking(false)?;
+                stream.set_nodelay(self.listen.nodelay)?;
+                Ok(Socket::Tcp(stream))
+            },
+            Inner::Unix(ref listener) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nonblocking(false)?;
+                Ok(Socket::Unix(stream))
+            },
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let address = match self.listen.address {
+            ListenAddress::Tcp(ref address) => address.clone(),
+            ListenAddress::Unix(ref path) => format!("unix:{}", path),
+        };
+
+        match self.listen.ssl {
+            true => format!("{} (ssl)", address),
+            false => address,
+        }
+    }
+}
+
+impl AsRawFd for Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        match self.inner {
+            Inner::Tcp(ref listener) => listener.as_raw_fd(),
+            Inner::Unix(ref listener) => listener.as_raw_fd(),
+        }
+    }
+}
+
+fn set_defer_accept(socket: &RawSocket, secs: libc::c_int) -> io::Result<()> {
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_DEFER_ACCEPT,
+            &secs as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+
+    match result {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+fn resolve(address: &str) -> io::Result<SocketAddr> {
+    match address.to_socket_addrs()?.next() {
+        Some(addr) => Ok(addr),
+        None => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("can't resolve {}", address))),
+    }
+}
+
+fn bind_tcp(address: &str, listen: &Listen) -> io::Result<TcpListener> {
+    let addr = resolve(address)?;
+    let socket = RawSocket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+
+    socket.set_reuse_address(true)?;
+    if listen.reuseport > 0 {
+        socket.set_reuse_port(true)?;
+    }
+    if addr.is_ipv6() {
+        socket.set_only_v6(listen.ipv6only)?;
+    }
+    if listen.deferred {
+        set_defer_accept(&socket, DEFER_ACCEPT_SECS)?;
+    }
+    socket.bind(&addr.into())?;
+    socket.listen(listen.backlog)?;
+
+    Ok(socket.into())
+}
+
+// A socket file left behind by a previous run is replaced, any other file at
+// the path is an error.
+fn bind_unix(path: &str, listen: &Listen) -> io::Result<UnixListener> {
+    if let Ok(meta) = fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} is not a socket", path)));
+        }
+        fs::remove_file(path)?;
+    }
+
+    let socket = RawSocket::new(Domain::UNIX, Type::STREAM, None)?;
+    socket.bind(&SockAddr::unix(path)?)?;
+    socket.listen(listen.backlog)?;
+    let listener = UnixListener::from(OwnedFd::from(socket));
+    if let Some(mode) = listen.mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+
+    Ok(listener)
+}
@@ -0,0 +1,74 @@
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::os::unix::io::AsRawFd;
+use std::time::Duration;
+
+use super::listener::Listener;
+use super::stream::Socket;
+use crate::config::listen::Listen;
+
+fn tokens(line: &str) -> Vec<String> {
+    line.split(' ').map(String::from).collect()
+}
+
+fn getsockopt(fd: i32, level: i32, name: i32) -> i32 {
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(fd, level, name, &mut value as *mut libc::c_int as *mut libc::c_void, &mut len)
+    };
+    assert_eq!(result, 0);
+    value
+}
+
+fn local_port(listener: &Listener) -> u16 {
+    let mut addr: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockname(listener.as_raw_fd(), &mut addr as *mut _ as *mut libc::sockaddr, &mut len)
+    };
+    assert_eq!(result, 0);
+    u16::from_be(addr.sin_port)
+}
+
+#[test]
+fn parse_socket_options() {
+    let listen = Listen::parse(&tokens("listen 8080 reuseport=4 backlog=511 nodelay deferred")).unwrap();
+    assert_eq!(listen.reuseport, 4);
+    assert_eq!(listen.backlog, 511);
+    assert!(listen.nodelay);
+    assert!(listen.deferred);
+
+    assert!(Listen::parse(&tokens("listen 8080 reuseport")).unwrap().reuseport >= 1);
+    assert!(Listen::parse(&tokens("listen 8080 reuseport=0")).is_err());
+    assert!(Listen::parse(&tokens("listen 8080 backlog=-1")).is_err());
+    assert!(Listen::parse(&tokens("listen unix:/run/dz1.sock reuseport=2")).is_err());
+}
+
+#[test]
+fn reuseport_shares_address() {
+    let first = Listener::bind(&Listen { reuseport: 2, ..Listen::tcp(String::from("127.0.0.1:0")) }).unwrap();
+    let address = format!("127.0.0.1:{}", local_port(&first));
+    let second = Listener::bind(&Listen { reuseport: 2, ..Listen::tcp(address.clone()) }).unwrap();
+    assert!(Listener::bind(&Listen::tcp(address.clone())).is_err());
+
+    assert_eq!(getsockopt(second.as_raw_fd(), libc::SOL_SOCKET, libc::SO_REUSEPORT), 1);
+    assert!(second.matches(&Listen::tcp(address)));
+}
+
+#[test]
+fn deferred_and_nodelay() {
+    let listen = Listen { deferred: true, nodelay: true, ..Listen::tcp(String::from("127.0.0.1:0")) };
+    let listener = Listener::bind(&listen).unwrap();
+    assert!(getsockopt(listener.as_raw_fd(), libc::IPPROTO_TCP, libc::TCP_DEFER_ACCEPT) > 0);
+
+    let mut client = TcpStream::connect(("127.0.0.1", local_port(&listener))).unwrap();
+    // A deferred connection is only ready for accept once data arrived.
+    client.write_all(b"GET").unwrap();
+
+    assert!(listener.wait(Duration::from_secs(5)).unwrap());
+    match listener.accept().unwrap() {
+        Socket::Tcp(stream) => assert!(stream.nodelay().unwrap()),
+        Socket::Unix(_) => panic!("Unexpected unix socket"),
+    }
+}
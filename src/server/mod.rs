@@ -1 +1,8 @@
-pub mod server;
\ No newline at end of file
+pub mod listener;
+#[cfg(test)]
+mod listener_test;
+pub mod server;
+#[cfg(test)]
+mod server_test;
+pub mod stream;
+pub mod upgrade;
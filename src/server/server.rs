@@ -1,105 +1,551 @@
 use crate::thread_pool::thread_pool::ThreadPool;
-use std::net::{TcpListener, TcpStream};
-use std::sync::{Arc, Mutex};
-use std::io::prelude::*;
-use crate::http::request::HTTPRequest;
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::config::config::Config;
+use crate::config::listen::Listen;
+use crate::config::location::Location;
+use crate::error_page::error_page::default_page;
+use crate::headers::headers::unix_now;
+use crate::http::reader::{ReadError, RequestReader};
+use crate::http::request::{BodyFraming, HTTPRequest, Version};
 use crate::http::response::HTTPResponse;
-use std::time::Duration;
-use std::collections::{HashMap};
+use crate::http::status::StatusCode;
+use crate::rewrite::rewrite::{rewrite, Vars};
+use crate::server::listener::Listener;
+use crate::server::stream::{Client, Stream};
+use crate::server::upgrade;
+use crate::tls::tls::Tls;
+use crate::systemd::systemd;
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR2};
+use signal_hook::iterator::Signals;
 use std::fs::File;
+use std::io;
+use std::io::{Cursor, Read};
 use std::path::Path;
 
+// Methods some handler implements. Others are answered with 501 instead
+// of 405.
+const KNOWN_METHODS: [&str; 13] = [
+    "GET", "HEAD", "POST", "OPTIONS", "PUT", "DELETE", "MKCOL", "COPY", "MOVE", "PROPFIND", "PROPPATCH", "LOCK", "UNLOCK",
+];
+// What static files answer to.
+const STATIC_METHODS: [&str; 3] = ["GET", "HEAD", "OPTIONS"];
+// Not passed on to the internal request of an error page, which has no
+// body and must not be answered partially.
+const INTERNAL_DROPPED_HEADERS: [&str; 4] = ["Content-Length", "Transfer-Encoding", "Expect", "Range"];
+
+// How often accept loops look at the draining flag.
+const ACCEPT_POLL: Duration = Duration::from_millis(500);
+// How long in-flight connections may take to finish after an upgrade.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+// How long, and how much, unread input is taken in before a connection is
+// closed after its last response.
+const LINGER_TIME: Duration = Duration::from_secs(5);
+const LINGER_SIZE: usize = 1024 * 1024;
+
+// Shared between accept loops, connection handlers and the signal thread.
+#[derive(Default)]
+pub(crate) struct State {
+    // Set once a new process took over, no more connections are accepted
+    // and keep-alive connections are closed after the current response.
+    draining: AtomicBool,
+    active: AtomicUsize,
+}
+
 pub struct Server {
     thread_pool: ThreadPool,
-    listener: TcpListener,
-    dir_root: Arc<Mutex<String>>,
+    listeners: Vec<Listener>,
+    config: Arc<Config>,
+    tls: Option<Arc<Tls>>,
+    state: Arc<State>,
 }
 
 impl Server {
-    pub fn new(root: String, thread_count: u16, adress: String, port: String) -> Server {
-        let full_address = format!("{}:{}", adress, port);
-        
-        let listener = match TcpListener::bind(&full_address) {
-            Ok(lst) => lst,
-            Err(err) => panic!(err),
+    // Binds the `listen` directives of the config, or `adress:port` when
+    // the config has none.
+    pub fn new(config: Config, adress: String, port: String) -> Server {
+        let listen = match config.listen.is_empty() {
+            true => vec![Listen::tcp(format!("{}:{}", adress, port))],
+            false => config.listen.clone(),
         };
 
-        println!("Start tcp listener: {}", full_address);
+        // Sockets inherited from the service manager or from the process
+        // being upgraded are used for the matching listen directives
+        // instead of binding again.
+        let fds = systemd::listen_fds().into_iter().map(|fd| fd.fd).chain(upgrade::inherited_fds());
+        let mut inherited = Vec::new();
+        for fd in fds {
+            match Listener::from_fd(fd) {
+                Ok(listener) => inherited.push(listener),
+                Err(err) => println!("Error while adopt fd {}: {}", fd, err),
+            }
+        }
+
+        let mut listeners = Vec::with_capacity(listen.len());
+        for l in listen {
+            // With reuseport every socket of the group gets its own accept loop.
+            for _ in 0..l.reuseport.max(1) {
+                let listener = match inherited.iter().position(|i| i.matches(&l)) {
+                    Some(i) => {
+                        let mut listener = inherited.remove(i);
+                        listener.listen = l.clone();
+                        listener
+                    },
+                    None => match Listener::bind(&l) {
+                        Ok(lst) => lst,
+                        Err(err) => panic!("{:?}: {}", l.address, err),
+                    },
+                };
+
+                println!("Start listener: {}", listener.describe());
+                listeners.push(listener);
+            }
+        }
+
+        for listener in inherited {
+            println!("Start inherited listener: {}", listener.describe());
+            listeners.push(listener);
+        }
+
+        let tls = match listeners.iter().any(|l| l.listen.ssl) {
+            true => match Tls::new(&config.tls) {
+                Ok(tls) => Some(Arc::new(tls)),
+                Err(err) => panic!("{}", err),
+            },
+            false => None,
+        };
 
         Server{
-            thread_pool: ThreadPool::new(thread_count as usize),
-            listener: listener,
-            dir_root: Arc::new(Mutex::new(root)),
+            thread_pool: ThreadPool::new(config.thread_count as usize),
+            listeners,
+            config: Arc::new(config),
+            tls,
+            state: Arc::new(State::default()),
         }
     }
 
     pub fn start(&self) {
         println!("Start server");
 
-        for stream in self.listener.incoming() {
-            let stream = stream.unwrap();
-            stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
-            let root = self.dir_root.clone();
-            self.thread_pool.execute(move|| {
-                let root_dir_guard = root.lock().unwrap();
+        let signals = match Signals::new([SIGHUP, SIGTERM, SIGINT, SIGUSR2]) {
+            Ok(signals) => Some(signals),
+            Err(err) => {
+                println!("Error while register signals: {}", err);
+                None
+            }
+        };
+
+        systemd::notify(systemd::STATE_READY);
+        upgrade::notify_ready();
 
-                let root_dir = (*root_dir_guard).clone();
+        thread::scope(|scope| {
+            for listener in &self.listeners {
+                scope.spawn(move || self.accept(listener));
+            }
+            if let Some(signals) = signals {
+                scope.spawn(move || self.handle_signals(signals));
+            }
+        });
+    }
 
-                std::mem::drop(root_dir_guard);
+    fn accept(&self, listener: &Listener) {
+        while !self.state.draining.load(Ordering::SeqCst) {
+            match listener.wait(ACCEPT_POLL) {
+                Ok(true) => {},
+                Ok(false) => continue,
+                Err(err) => {
+                    println!("Error while wait for connection: {}", err);
+                    continue;
+                }
+            }
 
-                Server::handle_connection(stream, &root_dir);
+            let stream = match listener.accept() {
+                Ok(stream) => stream,
+                Err(ref err) if err.kind() == std::io::ErrorKind::WouldBlock => continue,
+                Err(err) => {
+                    println!("Error while accept: {}", err);
+                    continue;
+                }
+            };
+            let config = self.config.clone();
+            let state = self.state.clone();
+            let tls = match listener.listen.ssl {
+                true => self.tls.clone(),
+                false => None,
+            };
+            state.active.fetch_add(1, Ordering::SeqCst);
+            self.thread_pool.execute(move|| {
+                let stream = match tls {
+                    Some(tls) => match tls.accept(stream) {
+                        Ok(stream) => Some(Stream::Tls(Box::new(stream))),
+                        Err(err) => {
+                            println!("Error while tls accept: {}", err);
+                            None
+                        }
+                    },
+                    None => Some(Stream::Plain(stream)),
+                };
+                if let Some(stream) = stream {
+                    Server::handle_connection(stream, &config, &state);
+                }
+                state.active.fetch_sub(1, Ordering::SeqCst);
             });
         }
     }
 
-    fn handle_connection(mut stream: TcpStream, root_dir: &String) {
-        let mut buffer = [0; 512];
+    // SIGHUP reloads certificates, SIGUSR2 hands the listeners to a freshly
+    // started binary, SIGTERM and SIGINT stop the server.
+    fn handle_signals(&self, mut signals: Signals) {
+        for signal in signals.forever() {
+            match signal {
+                SIGHUP => {
+                    systemd::notify(&systemd::reloading_state());
+                    if let Some(ref tls) = self.tls {
+                        tls.reload();
+                    }
+                    systemd::notify(systemd::STATE_READY);
+                },
+                SIGUSR2 => self.upgrade(),
+                _ => {
+                    println!("Stop server");
+                    systemd::notify(systemd::STATE_STOPPING);
+                    process::exit(0);
+                },
+            }
+        }
+    }
+
+    fn upgrade(&self) {
+        println!("Start upgrade");
 
-        match stream.read(&mut buffer) {
-            Ok(n) if n == 0 => return,
-            Ok(_) => {},
+        let listeners: Vec<&Listener> = self.listeners.iter().collect();
+        let child = match upgrade::spawn(&listeners) {
+            Ok(child) => child,
             Err(err) => {
-                println!("Error while read: {}", err);
+                println!("Error while upgrade: {}", err);
                 return;
-            } 
+            }
+        };
+
+        println!("New process {} is ready, draining", child.id());
+        systemd::notify(&format!("MAINPID={}", child.id()));
+        self.drain();
+    }
+
+    // Stops accepting and exits once in-flight connections are done.
+    fn drain(&self) -> ! {
+        self.state.draining.store(true, Ordering::SeqCst);
+
+        let deadline = Instant::now() + DRAIN_TIMEOUT;
+        while self.state.active.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        println!("Stop server after drain, {} connections left", self.state.active.load(Ordering::SeqCst));
+        process::exit(0);
+    }
+
+    pub(crate) fn handle_connection(mut stream: Stream, config: &Config, state: &State) {
+        let timeouts = config.timeouts;
+
+        if let Err(err) = stream.set_write_timeout(Some(timeouts.send)) {
+            println!("Error while set timeout: {}", err);
+            return;
         }
 
-        let request = match HTTPRequest::parse(&mut buffer) {
-            Ok(req) => req,
-            Err(()) => {
-                let mut resp = Server::handle_bad_request();
-                resp.setDate();
-                resp.setServer("Rust (Unix)");
-                resp.setConnection("close");
-                resp.send(&stream);
+        let peer = match stream.peer_addr() {
+            Ok(ip) => ip,
+            Err(err) => {
+                println!("Error while get peer address: {}", err);
                 return;
             }
         };
 
-        let mut resp = match Server::handle_request(request, &root_dir) {
-            Ok(resp) => resp,
-            Err(()) => {
-                println!("Error handle request");
+        let mut reader = RequestReader::new();
+        // The first request gets client_header_timeout from accept, later
+        // ones may idle for keepalive_timeout before they start.
+        let mut idle = None;
+
+        loop {
+            let head = match reader.read_head(&mut stream, idle, timeouts.header) {
+                Ok(head) => head,
+                Err(ReadError::Timeout) => {
+                    Server::finish(Server::handle_timeout(), &mut stream, config, None, false, Version::HTTP_11);
+                    return;
+                },
+                Err(ReadError::TooLarge) => {
+                    Server::finish(Server::handle_bad_request(), &mut stream, config, None, false, Version::HTTP_11);
+                    return;
+                },
+                Err(ReadError::Io(err)) => {
+                    println!("Error while read: {}", err);
+                    return;
+                },
+                Err(ReadError::Closed) | Err(ReadError::Idle) => return,
+            };
+
+            let req = match HTTPRequest::parse(&head) {
+                Ok(req) => req,
+                Err(()) => {
+                    Server::finish(Server::handle_bad_request(), &mut stream, config, None, false, Version::HTTP_11);
+                    return;
+                },
+            };
+            if !req.version.is_supported() {
+                Server::finish(Server::handle_status(StatusCode::HTTP_VERSION_NOT_SUPPORTED), &mut stream, config, None, false, Version::HTTP_11);
+                return;
+            }
+
+            let client = Client {
+                addr: peer,
+                secure: stream.is_secure(),
+                port: stream.local_port(),
+                user: None,
+            };
+            let framing = match req.body_framing() {
+                Ok(framing) => framing,
+                Err(status) => {
+                    Server::finish(Server::handle_status(status), &mut stream, config, Some((&req, &client)), false, req.version);
+                    return;
+                },
+            };
+            // Refused before anything is read, a client that waits for 100
+            // Continue never sends the body.
+            if let BodyFraming::Length(length) = framing {
+                if config.max_body_size > 0 && length > config.max_body_size {
+                    Server::finish(Server::handle_status(StatusCode::CONTENT_TOO_LARGE), &mut stream, config, Some((&req, &client)), false, req.version);
+                    return;
+                }
+            }
+            // HTTP/1.0 clients do not know interim responses, their
+            // expectations are ignored.
+            let expect_continue = match req.header("Expect").filter(|_| req.version >= Version::HTTP_11) {
+                Some(expect) if expect.eq_ignore_ascii_case("100-continue") => framing != BodyFraming::None,
+                Some(_) => {
+                    Server::finish(Server::handle_status(StatusCode::EXPECTATION_FAILED), &mut stream, config, Some((&req, &client)), false, req.version);
+                    return;
+                },
+                None => false,
+            };
+
+            let mut keep_alive = !req.wants_close() && !state.draining.load(Ordering::SeqCst);
+            let mut body = reader.request_body(&mut stream, framing, config.max_body_size, expect_continue, timeouts.body, timeouts.body_total);
+            let resp = Server::handle_request(&req, config, client.clone(), &mut body);
+            // A body the handler did not read would be taken for the next
+            // request.
+            if !body.is_complete() {
+                keep_alive = false;
+            }
+            let resp = match resp {
+                Ok(resp) => resp,
+                Err(()) => {
+                    println!("Error handle request");
+                    return;
+                }
+            };
+
+            if !Server::finish(resp, &mut stream, config, Some((&req, &client)), keep_alive, req.version) {
                 return;
             }
-        }; 
 
+            idle = Some(timeouts.keepalive);
+        }
+    }
+
+    // Adds the error page, the configured, CORS, security and common
+    // headers and sends the response, returns whether the connection can
+    // carry another request.
+    fn finish(resp: HTTPResponse, stream: &mut Stream, config: &Config, request: Option<(&HTTPRequest, &Client)>, mut keep_alive: bool, version: Version) -> bool {
+        let mut resp = Server::error_page(resp, request, config);
+        if let Some((req, _)) = request {
+            let (cache, added) = config.headers_for(&req.path);
+            cache.apply_cache(&mut resp, &req.path, unix_now());
+            added.apply_added(&mut resp);
+            if let Some(cors) = config.location(&req.path).and_then(|l| l.cors.as_ref()) {
+                cors.apply(req, &mut resp);
+            }
+        }
+        let security = match request {
+            Some((req, _)) => config.security_for(&req.path),
+            None => &config.security,
+        };
+        security.apply(&mut resp, stream.is_secure());
+        // 1xx, 204 and 304 responses never have a body and a HEAD response
+        // only states a length the handler knows, RFC 9110 section 8.6.
+        let status = resp.status().as_u16();
+        let head = request.is_some_and(|(req, _)| req.method == "HEAD");
+        let bodiless = status < 200 || status == 204 || status == 304 || head;
+        if status < 200 || status == 204 {
+            resp.headers.retain(|(name, _)| !name.eq_ignore_ascii_case("Content-Length"));
+        }
+        if !bodiless && !resp.has_header("Content-Length") && resp.stream.is_none() {
+            resp.push_header("Content-Length".to_owned(), "0".to_owned());
+        }
+        // HTTP/1.0 has no chunked encoding, a body of unknown length ends
+        // with the connection.
+        if version < Version::HTTP_11 && !bodiless && !resp.has_header("Content-Length") {
+            resp.close_delimited = true;
+            keep_alive = false;
+        }
         resp.setDate();
         resp.setServer("Rust (Unix)");
-        resp.setConnection("close");
+        resp.setConnection(if keep_alive { "keep-alive" } else { "close" });
 
-        resp.send(&stream);
+        match resp.send(stream, config.timeouts.send_min_rate) {
+            Ok(()) if keep_alive => true,
+            Ok(()) => {
+                stream.linger(LINGER_TIME, LINGER_SIZE);
+                false
+            },
+            Err(err) => {
+                println!("Error while send: {}", err);
+                false
+            }
+        }
+    }
+
+    // Gives an error without a body the configured error page, or the
+    // built-in one. Bodies from handlers and backends are kept.
+    fn error_page(mut resp: HTTPResponse, request: Option<(&HTTPRequest, &Client)>, config: &Config) -> HTTPResponse {
+        let status = resp.status();
+        if resp.file.is_some() || resp.stream.is_some() || resp.has_header("Content-Length") {
+            return resp;
+        }
+
+        let head = request.is_some_and(|(req, _)| req.method == "HEAD");
+        let page = request.and_then(|(req, client)| {
+            config.error_pages_for(&req.path).find(status).map(|page| (req, client, page))
+        });
+        match page {
+            Some((_, _, page)) if page.is_redirect() => {
+                resp.setStatus(page.status.filter(|s| s.is_redirection()).unwrap_or(StatusCode::FOUND));
+                resp.push_header("Location".to_owned(), page.uri.clone());
+            },
+            Some((req, client, page)) => match Server::internal_request(req, client, config, &page.uri) {
+                Some(body) => {
+                    for name in ["Content-Type", "Content-Length", "Last-Modified"] {
+                        if let Some(value) = body.header(name) {
+                            resp.push_header(name.to_owned(), value.to_owned());
+                        }
+                    }
+                    resp.file = body.file;
+                    resp.stream = body.stream;
+                    if let Some(status) = page.status {
+                        resp.setStatus(status);
+                    }
+                    return resp;
+                },
+                None => println!("Error page {} for {} is not available", page.uri, status),
+            },
+            None if status.is_error() => {},
+            None => return resp,
+        }
+
+        let body = default_page(resp.status());
+        resp.push_header("Content-Type".to_owned(), "text/html; charset=utf-8".to_owned());
+        resp.push_header("Content-Length".to_owned(), body.len().to_string());
+        if !head {
+            resp.setStream(Box::new(Cursor::new(body.into_bytes())));
+        }
+        resp
+    }
+
+    // Serves `uri` for an error page as a GET without a body, None unless
+    // it succeeds. Errors of the internal request get no error page.
+    fn internal_request(req: &HTTPRequest, client: &Client, config: &Config, uri: &str) -> Option<HTTPResponse> {
+        let method = if req.method == "HEAD" { "HEAD" } else { "GET" };
+        let mut internal = HTTPRequest::parse(format!("{} {} HTTP/1.1\r\n\r\n", method, uri).as_bytes()).ok()?;
+        internal.version = req.version;
+        internal.headers = req.headers.iter()
+            .filter(|(name, _)| !INTERNAL_DROPPED_HEADERS.iter().any(|h| h.eq_ignore_ascii_case(name)) && !name.to_ascii_lowercase().starts_with("if-"))
+            .cloned()
+            .collect();
+
+        match Server::handle_request(&internal, config, client.clone(), &mut io::empty()) {
+            Ok(resp) if resp.status().is_success() => Some(resp),
+            _ => None,
+        }
     }
 
-    fn handle_request(req: HTTPRequest, root: &String) -> Result<HTTPResponse, ()> {
-        let path = req.path;
-        let method = req.method;
+    fn handle_request(req: &HTTPRequest, config: &Config, mut client: Client, body: &mut dyn Read) -> Result<HTTPResponse, ()> {
+        client.addr = config.real_ip.client_addr(client.addr, req);
+        if req.path == "*" {
+            return Ok(Server::handle_options(&Server::server_methods(config)));
+        }
+
+        // Rewritten requests and the files try_files picks are served, and
+        // access checked, as if they were sent with the new target. After
+        // `break` the request stays in the location of the rule.
+        let rewritten = match rewrite(config, req, client.secure) {
+            Ok(rewritten) => rewritten,
+            Err(resp) => return Ok(resp),
+        };
+        let (req, location) = match rewritten {
+            Some(ref rewritten) => (&rewritten.req, rewritten.location.or_else(|| config.location(&rewritten.req.path))),
+            None => (req, config.location(&req.path)),
+        };
+        let tried = match config.try_files_in(location).resolve(req, &config.dir_root, &Vars::new(req, client.secure)) {
+            Ok(tried) => tried,
+            Err(status) => return Ok(Server::handle_status(status)),
+        };
+        let (req, location) = match tried {
+            Some(ref tried) => (tried, config.location(&tried.path)),
+            None => (req, location),
+        };
+
+        if !config.access_in(location).is_allowed(client.addr) {
+            println!("Access denied for {} to {}", client.addr, req.path);
+            return Ok(Server::handle_forbidden());
+        }
+
+        // Preflights carry no credentials and are answered before auth.
+        if let Some(cors) = location.and_then(|l| l.cors.as_ref()).filter(|c| c.is_preflight(req)) {
+            return Ok(cors.preflight(req));
+        }
+
+        if let Some(auth) = location.and_then(|l| l.auth.as_ref()) {
+            client.user = auth.user(req);
+            if client.user.is_none() {
+                return Ok(Server::handle_unauthorized(auth.challenge()));
+            }
+        }
+
+        if let Some(proxy) = location.and_then(|l| l.proxy.as_ref()) {
+            return Ok(proxy.forward(req, &client, body));
+        }
+
+        if let Some(cgi) = location.and_then(|l| l.cgi.as_ref()) {
+            return Ok(cgi.execute(req, &client, &config.dir_root, body));
+        }
+
+        if let Some(fastcgi) = location.and_then(|l| l.fastcgi.as_ref()) {
+            return Ok(fastcgi.execute(req, &client, &config.dir_root, body));
+        }
+
+        if let Some(dav) = location.and_then(|l| l.dav.as_ref()).filter(|d| d.allows(&req.method)) {
+            return Ok(dav.handle(req, &config.dir_root, body));
+        }
+
+        let root = &config.dir_root;
+        let path = req.path.clone();
+        let method = &req.method;
+        let allowed = Server::allowed_methods(location);
+        let content_type = config.file_content_type(&path, Path::new(&format!("{}{}", root, path)));
         println!("{}{}",&root, &path);
-        let resp = match &method[..] {
-            "GET" => Server::handle_get(path, &root, req.isAutoIndex),
-            "HEAD" => Server::handle_head(path, &root, req.isAutoIndex),
-            _ => Server::handle_other(),
+        let mut resp = match &method[..] {
+            "GET" => Server::handle_get(path, &root, req.isAutoIndex, &content_type),
+            "HEAD" => Server::handle_head(path, &root, req.isAutoIndex, &content_type),
+            "OPTIONS" => Server::handle_options(&allowed),
+            m if KNOWN_METHODS.contains(&m) => Server::handle_other(&allowed),
+            _ => Server::handle_status(StatusCode::NOT_IMPLEMENTED),
         };
+        // Browsers must not second-guess a type the server sniffed.
+        if config.sniffs(&req.path) {
+            resp.push_header("X-Content-Type-Options".to_owned(), "nosniff".to_owned());
+        }
 
         Ok(resp)
     }
@@ -108,26 +554,53 @@ impl Server {
         println!("Handle bad");
         let mut resp = HTTPResponse::new();
 
-        resp.setBadRequest();
+        resp.setStatus(StatusCode::BAD_REQUEST);
+        return resp;
+    }
+
+    fn handle_status(status: StatusCode) -> HTTPResponse {
+        println!("Handle {}", status);
+        HTTPResponse::builder().status(status).build()
+    }
+
+    fn handle_forbidden() -> HTTPResponse {
+        let mut resp = HTTPResponse::new();
+
+        resp.setStatus(StatusCode::FORBIDDEN);
+        return resp;
+    }
+
+    fn handle_unauthorized(challenge: String) -> HTTPResponse {
+        HTTPResponse::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header("WWW-Authenticate", &challenge)
+            .build()
+    }
+
+    fn handle_timeout() -> HTTPResponse {
+        println!("Handle timeout");
+        let mut resp = HTTPResponse::new();
+
+        resp.setStatus(StatusCode::REQUEST_TIMEOUT);
         return resp;
     }
 
-    fn handle_get(path: String, root: &String, isAutoIndex: bool) -> HTTPResponse {
+    fn handle_get(path: String, root: &String, isAutoIndex: bool, content_type: &str) -> HTTPResponse {
         let path = format!("{}{}", root, path);
         let mut resp = HTTPResponse::new();
 
         match File::open(&path) {
             Ok(file) => {
                 let p = Path::new(&path);
-                resp.setContentType(&p);
+                resp.setContentType(content_type);
                 resp.setContentLength(&p);
-                resp.setOk(Some(file));
+                resp.setFile(file);
             },
             Err(err) => {
                 if (isAutoIndex) {
-                    resp.set403()
+                    resp.setStatus(StatusCode::FORBIDDEN)
                 } else {
-                    resp.setNotFound();
+                    resp.setStatus(StatusCode::NOT_FOUND);
                 }
             }
         };
@@ -135,7 +608,7 @@ impl Server {
         return resp;
     }
 
-    fn handle_head(path: String, root: &String, isAutoIndex: bool) -> HTTPResponse {
+    fn handle_head(path: String, root: &String, isAutoIndex: bool, content_type: &str) -> HTTPResponse {
         let path = format!("{}{}", root, path);
         
         let mut resp = HTTPResponse::new();
@@ -144,15 +617,14 @@ impl Server {
 
         match p.exists() {
             true => {
-                resp.setContentType(&p);
+                resp.setContentType(content_type);
                 resp.setContentLength(&p);
-                resp.setOk(None);
             },
             false => {
                 if (isAutoIndex) {
-                    resp.set403()
+                    resp.setStatus(StatusCode::FORBIDDEN)
                 } else {
-                    resp.setNotFound();
+                    resp.setStatus(StatusCode::NOT_FOUND);
                 }
             }
         };
@@ -160,11 +632,39 @@ impl Server {
         return resp;
     }
 
-    fn handle_other() -> HTTPResponse {
+    fn handle_other(allowed: &[&str]) -> HTTPResponse {
         println!("Handle other");
-        let mut resp = HTTPResponse::new();
+        HTTPResponse::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .header("Allow", &allowed.join(", "))
+            .build()
+    }
 
-        resp.setNotAllowed();
-        return resp;
+    fn handle_options(allowed: &[&str]) -> HTTPResponse {
+        HTTPResponse::builder()
+            .header("Allow", &allowed.join(", "))
+            .build()
+    }
+
+    // Methods the static and DAV handlers accept for a location. Proxied
+    // and script locations pass every method on instead.
+    fn allowed_methods(location: Option<&Location>) -> Vec<&'static str> {
+        match location.and_then(|l| l.dav.as_ref()) {
+            Some(dav) => dav.allowed_methods(),
+            None => STATIC_METHODS.to_vec(),
+        }
+    }
+
+    // What `OPTIONS *` reports, the methods of all locations together.
+    fn server_methods(config: &Config) -> Vec<&'static str> {
+        let mut methods = STATIC_METHODS.to_vec();
+        for location in &config.locations {
+            if location.proxy.is_some() || location.cgi.is_some() || location.fastcgi.is_some() {
+                methods.push("POST");
+            }
+            methods.extend(Server::allowed_methods(Some(location)));
+        }
+
+        KNOWN_METHODS.iter().copied().filter(|m| methods.contains(m)).collect()
     }
 }
\ No newline at end of file
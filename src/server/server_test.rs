@@ -0,0 +1,128 @@
+use std::io::prelude::*;
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use super::server::{Server, State};
+use super::stream::{Socket, Stream};
+use crate::config::config::Config;
+use crate::http::reader::RequestReader;
+use crate::proxy::proxy::ProxyBuilder;
+
+fn config(path: &str) -> Config {
+    match Config::read(path) {
+        Ok(cfg) => cfg,
+        Err(err) => panic!("Unexcpected error {}", err),
+    }
+}
+
+const UPSTREAM_OK: &str = "HTTP/1.1 200 OK\r\nContent-Length: 8\r\nConnection: close\r\n\r\nupstream";
+
+// Points the proxy of the location with `prefix` to an upstream that
+// answers each request on its own connection with the next of
+// `responses`, and sends the request heads back to the test.
+fn upstream(cfg: &mut Config, prefix: &str, responses: Vec<&'static str>) -> Receiver<String> {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut builder = ProxyBuilder::default();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    builder.parse_directive(&[String::from("proxy_pass"), url]).unwrap();
+    let location = cfg.locations.iter_mut().find(|l| l.prefix == prefix).unwrap();
+    location.proxy = builder.build(prefix).unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for resp in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let head = RequestReader::new().read_head(&mut stream, None, Duration::from_secs(5)).unwrap();
+            tx.send(String::from_utf8(head).unwrap()).unwrap();
+            stream.write_all(resp.as_bytes()).unwrap();
+        }
+    });
+    rx
+}
+
+// Sends `raw` on a fresh connection served with `cfg` and returns all the
+// server sent back before closing it. With `close` the client sends
+// nothing more, otherwise it keeps its side open.
+fn send(cfg: &Config, raw: &[u8], close: bool) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (conn, _) = listener.accept().unwrap();
+
+    thread::scope(|scope| {
+        scope.spawn(|| Server::handle_connection(Stream::Plain(Socket::Tcp(conn)), cfg, &State::default()));
+        client.write_all(raw).unwrap();
+        if close {
+            client.shutdown(Shutdown::Write).unwrap();
+        }
+        let mut resp = Vec::new();
+        client.read_to_end(&mut resp).unwrap();
+        let _ = client.shutdown(Shutdown::Write);
+        String::from_utf8_lossy(&resp).into_owned()
+    })
+}
+
+fn exchange(cfg: &Config, raw: &[u8]) -> String {
+    send(cfg, raw, true)
+}
+
+#[test]
+fn break_keeps_location() {
+    let mut cfg = config("test/test_server.txt");
+    let heads = upstream(&mut cfg, "/api/", vec![UPSTREAM_OK]);
+
+    // Proxied with the new path rather than served from the document root.
+    let resp = exchange(&cfg, b"GET /api/test.txt HTTP/1.1\r\nHost: a\r\n\r\n");
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{}", resp);
+    assert!(resp.ends_with("upstream"), "{}", resp);
+    assert!(heads.recv().unwrap().starts_with("GET /test.txt HTTP/1.1\r\n"));
+
+    // The location's own rules still apply.
+    let resp = exchange(&cfg, b"GET /private/test.txt HTTP/1.1\r\nHost: a\r\n\r\n");
+    assert!(resp.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{}", resp);
+}
+
+#[test]
+fn refused_body_is_drained() {
+    let cfg = config("test/test_server.txt");
+    // The body is never read, the response must still reach the client.
+    let mut raw = b"PUT /upload HTTP/1.1\r\nHost: a\r\nContent-Length: 65536\r\n\r\n".to_vec();
+    raw.resize(raw.len() + 65536, b'x');
+    let resp = send(&cfg, &raw, false);
+    assert!(resp.starts_with("HTTP/1.1 413 Content Too Large\r\n"), "{}", resp);
+    assert!(resp.contains("Connection: close\r\n"), "{}", resp);
+}
+
+#[test]
+fn malformed_field_is_bad_request() {
+    let cfg = config("test/test_server.txt");
+    let resp = exchange(&cfg, b"POST /upload HTTP/1.1\r\nHost: a\r\nTransfer-Encoding : chunked\r\n\r\n0\r\n\r\n");
+    assert!(resp.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", resp);
+}
+
+#[test]
+fn bodiless_responses_have_no_zero_length() {
+    let mut cfg = config("test/test_server.txt");
+    let _heads = upstream(&mut cfg, "/api/", vec![
+        "HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n",
+        "HTTP/1.1 304 Not Modified\r\nETag: \"a\"\r\nConnection: close\r\n\r\n",
+    ]);
+
+    let resp = exchange(&cfg, b"HEAD /api/a HTTP/1.1\r\nHost: a\r\n\r\n");
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{}", resp);
+    assert!(!resp.contains("Content-Length"), "{}", resp);
+
+    let resp = exchange(&cfg, b"GET /api/a HTTP/1.1\r\nHost: a\r\nIf-None-Match: \"a\"\r\n\r\n");
+    assert!(resp.starts_with("HTTP/1.1 304 Not Modified\r\n"), "{}", resp);
+    assert!(!resp.contains("Content-Length"), "{}", resp);
+
+    let preflight = "OPTIONS /cors/a HTTP/1.1\r\nHost: a\r\nOrigin: https://app.example.com\r\nAccess-Control-Request-Method: GET\r\n\r\n";
+    let resp = exchange(&cfg, preflight.as_bytes());
+    assert!(resp.starts_with("HTTP/1.1 204 No Content\r\n"), "{}", resp);
+    assert!(!resp.contains("Content-Length"), "{}", resp);
+
+    // Other bodiless responses still state their length.
+    let resp = exchange(&cfg, b"GET /private/a HTTP/1.1\r\nHost: a\r\n\r\n");
+    assert!(resp.starts_with("HTTP/1.1 403 Forbidden\r\n") && resp.contains("Content-Length: "), "{}", resp);
+}
@@ -0,0 +1,190 @@
+use std::io;
+use std::io::prelude::*;
+use std::net::{Shutdown, TcpStream};
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
+
+use rustls::{ServerConnection, StreamOwned};
+
+use crate::access::access::ClientAddr;
+
+// An accepted socket before any TLS.
+#[derive(Debug)]
+pub enum Socket {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Socket {
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.set_read_timeout(timeout),
+            Socket::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.set_write_timeout(timeout),
+            Socket::Unix(stream) => stream.set_write_timeout(timeout),
+        }
+    }
+
+    pub fn shutdown_write(&self) -> io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.shutdown(Shutdown::Write),
+            Socket::Unix(stream) => stream.shutdown(Shutdown::Write),
+        }
+    }
+
+    // None for unix sockets.
+    pub fn local_port(&self) -> Option<u16> {
+        match self {
+            Socket::Tcp(stream) => stream.local_addr().ok().map(|addr| addr.port()),
+            Socket::Unix(_) => None,
+        }
+    }
+
+    // Unix socket clients are only matched by `unix:` rules, not as
+    // loopback.
+    pub fn peer_addr(&self) -> io::Result<ClientAddr> {
+        match self {
+            Socket::Tcp(stream) => stream.peer_addr().map(|addr| ClientAddr::Ip(addr.ip())),
+            Socket::Unix(_) => Ok(ClientAddr::Unix),
+        }
+    }
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Socket::Tcp(stream) => stream.read(buf),
+            Socket::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Socket::Tcp(stream) => stream.write(buf),
+            Socket::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.flush(),
+            Socket::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+// An accepted client connection, either plain or with TLS terminated.
+pub enum Stream {
+    Plain(Socket),
+    Tls(Box<StreamOwned<ServerConnection, Socket>>),
+}
+
+impl Stream {
+    fn socket(&self) -> &Socket {
+        match self {
+            Stream::Plain(socket) => socket,
+            Stream::Tls(stream) => &stream.sock,
+        }
+    }
+
+    // Ends the sending side, then reads and drops what the client still
+    // sends, up to `limit` bytes for `time` at most. Closing with unread
+    // input makes the kernel reset the connection, and the client may lose
+    // the last response before reading it.
+    pub fn linger(&mut self, time: Duration, limit: usize) {
+        if let Stream::Tls(stream) = self {
+            stream.conn.send_close_notify();
+            if stream.flush().is_err() {
+                return;
+            }
+        }
+        let socket = match self {
+            Stream::Plain(socket) => socket,
+            Stream::Tls(stream) => &mut stream.sock,
+        };
+        if socket.shutdown_write().is_err() {
+            return;
+        }
+
+        let deadline = Instant::now() + time;
+        let mut chunk = [0; 4096];
+        let mut discarded = 0;
+        while discarded < limit {
+            let now = Instant::now();
+            if now >= deadline || socket.set_read_timeout(Some(deadline - now)).is_err() {
+                return;
+            }
+            match socket.read(&mut chunk) {
+                Ok(0) | Err(_) => return,
+                Ok(n) => discarded += n,
+            }
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.socket().set_read_timeout(timeout)
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.socket().set_write_timeout(timeout)
+    }
+
+    pub fn peer_addr(&self) -> io::Result<ClientAddr> {
+        self.socket().peer_addr()
+    }
+
+    pub fn is_secure(&self) -> bool {
+        match self {
+            Stream::Plain(_) => false,
+            Stream::Tls(_) => true,
+        }
+    }
+
+    pub fn local_port(&self) -> Option<u16> {
+        self.socket().local_port()
+    }
+}
+
+// The client side of a request as seen by the handlers.
+#[derive(Clone)]
+pub struct Client {
+    // After real ip rules.
+    pub addr: ClientAddr,
+    pub secure: bool,
+    // Port the request came in on, None for unix sockets.
+    pub port: Option<u16>,
+    // Set once basic auth accepted the credentials.
+    pub user: Option<String>,
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.read(buf),
+            Stream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.write(buf),
+            Stream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(stream) => stream.flush(),
+            Stream::Tls(stream) => stream.flush(),
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::env;
+use std::io;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+use crate::server::listener::Listener;
+use crate::systemd::systemd::{self, LISTEN_FDS_START, STATE_READY};
+
+// Number of listening sockets handed over, starting at fd 3 like with
+// socket activation. LISTEN_PID can't be used as the child pid is unknown
+// until after the fork.
+const UPGRADE_FDS_VAR: &str = "DZ1_UPGRADE_FDS";
+// Abstract socket where the new process reports READY=1 to the old one.
+const UPGRADE_NOTIFY_VAR: &str = "DZ1_UPGRADE_NOTIFY";
+
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+// Handed over fds are first moved above this so that they can't collide
+// with their targets 3, 4, ...
+const FD_SHUFFLE_BASE: RawFd = 64;
+
+// Starts the current executable with the same arguments, passing it the
+// listening sockets, and waits until it reports that it is serving.
+pub fn spawn(listeners: &[&Listener]) -> io::Result<Child> {
+    let exe = env::current_exe()?;
+    let name = format!("dz1-upgrade-{}", std::process::id());
+    let notify = UnixDatagram::bind_addr(&SocketAddr::from_abstract_name(&name)?)?;
+
+    let mut fds = Vec::with_capacity(listeners.len());
+    for listener in listeners {
+        let fd = unsafe { libc::fcntl(listener.as_raw_fd(), libc::F_DUPFD_CLOEXEC, FD_SHUFFLE_BASE) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        fds.push(unsafe { OwnedFd::from_raw_fd(fd) });
+    }
+    let raw: Vec<RawFd> = fds.iter().map(|fd| fd.as_raw_fd()).collect();
+
+    let mut command = Command::new(exe);
+    command.args(env::args_os().skip(1))
+        .env(UPGRADE_FDS_VAR, raw.len().to_string())
+        .env(UPGRADE_NOTIFY_VAR, format!("@{}", name));
+    unsafe {
+        // dup2 leaves FD_CLOEXEC unset on the copies, so exactly fds 3.. survive exec.
+        command.pre_exec(move || {
+            for (i, fd) in raw.iter().enumerate() {
+                if libc::dup2(*fd, LISTEN_FDS_START + i as RawFd) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+
+    let mut child = command.spawn()?;
+    drop(fds);
+
+    match wait_ready(&notify, &mut child) {
+        Ok(()) => Ok(child),
+        Err(err) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            Err(err)
+        }
+    }
+}
+
+fn wait_ready(notify: &UnixDatagram, child: &mut Child) -> io::Result<()> {
+    let deadline = Instant::now() + READY_TIMEOUT;
+    let mut buf = [0; 256];
+
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Err(io::Error::other(format!("new process exited with {}", status)));
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "new process did not become ready"));
+        }
+        notify.set_read_timeout(Some((deadline - now).min(Duration::from_millis(200))))?;
+
+        match notify.recv(&mut buf) {
+            Ok(n) if String::from_utf8_lossy(&buf[..n]).lines().any(|l| l == STATE_READY) => return Ok(()),
+            Ok(_) => {},
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut => {},
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+// Listening sockets passed by an old process during an upgrade.
+pub fn inherited_fds() -> Vec<RawFd> {
+    let count = env::var(UPGRADE_FDS_VAR).ok().and_then(|n| n.parse::<RawFd>().ok()).unwrap_or(0);
+    env::remove_var(UPGRADE_FDS_VAR);
+
+    (0..count).map(|i| LISTEN_FDS_START + i).collect()
+}
+
+// Tells the old process that this one serves requests now.
+pub fn notify_ready() {
+    if let Ok(path) = env::var(UPGRADE_NOTIFY_VAR) {
+        env::remove_var(UPGRADE_NOTIFY_VAR);
+        if let Err(err) = systemd::notify_to(&path, STATE_READY) {
+            println!("Error while notify upgrading process: {}", err);
+        }
+    }
+}
@@ -0,0 +1,3 @@
+pub mod systemd;
+#[cfg(test)]
+mod systemd_test;
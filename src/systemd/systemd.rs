@@ -0,0 +1,92 @@
+use std::env;
+use std::io;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::io::RawFd;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+// The first descriptor passed by the service manager, see sd_listen_fds(3).
+pub const LISTEN_FDS_START: RawFd = 3;
+
+pub const STATE_READY: &str = "READY=1";
+pub const STATE_STOPPING: &str = "STOPPING=1";
+
+#[derive(Debug, PartialEq)]
+pub struct InheritedFd {
+    pub fd: RawFd,
+    // From LISTEN_FDNAMES, set by FileDescriptorName= in the socket unit.
+    pub name: Option<String>,
+}
+
+// Decodes the socket activation variables. They only apply when LISTEN_PID
+// names this process, otherwise they were meant for a parent.
+pub fn parse_listen_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, names: Option<&str>, pid: u32) -> Vec<InheritedFd> {
+    match listen_pid.and_then(|p| p.trim().parse::<u32>().ok()) {
+        Some(listen_pid) if listen_pid == pid => {},
+        _ => return Vec::new(),
+    }
+
+    let count = match listen_fds.and_then(|n| n.trim().parse::<RawFd>().ok()) {
+        Some(count) if count > 0 => count,
+        _ => return Vec::new(),
+    };
+
+    let names: Vec<&str> = names.map(|n| n.split(':').collect()).unwrap_or_default();
+
+    (0..count)
+        .map(|i| InheritedFd {
+            fd: LISTEN_FDS_START + i,
+            name: names.get(i as usize).filter(|n| !n.is_empty()).map(|n| n.to_string()),
+        })
+        .collect()
+}
+
+// Takes the listening sockets passed by the service manager. The variables
+// are removed so that child processes do not pick them up again.
+pub fn listen_fds() -> Vec<InheritedFd> {
+    let listen_pid = env::var("LISTEN_PID").ok();
+    let listen_fds = env::var("LISTEN_FDS").ok();
+    let names = env::var("LISTEN_FDNAMES").ok();
+
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    parse_listen_fds(listen_pid.as_deref(), listen_fds.as_deref(), names.as_deref(), std::process::id())
+}
+
+// Sends a state such as READY=1 to the socket in NOTIFY_SOCKET, see
+// sd_notify(3). Without the variable this does nothing.
+pub fn notify(state: &str) {
+    let path = match env::var("NOTIFY_SOCKET") {
+        Ok(path) => path,
+        Err(_) => return,
+    };
+
+    if let Err(err) = notify_to(&path, state) {
+        println!("Error while notify {}: {}", path, err);
+    }
+}
+
+// A leading `@` refers to the abstract socket namespace.
+pub fn notify_to(path: &str, state: &str) -> io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+
+    match path.strip_prefix('@') {
+        Some(name) => socket.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)?,
+        None => socket.send_to(state.as_bytes(), path)?,
+    };
+
+    Ok(())
+}
+
+// RELOADING=1 has to carry the CLOCK_MONOTONIC time of the reload for
+// services with Type=notify-reload.
+pub fn reloading_state() -> String {
+    let mut now = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now);
+    }
+    let usec = now.tv_sec as u64 * 1_000_000 + now.tv_nsec as u64 / 1_000;
+
+    format!("RELOADING=1\nMONOTONIC_USEC={}", usec)
+}
@@ -0,0 +1,111 @@
+use std::env;
+use std::io::prelude::*;
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, IntoRawFd};
+use std::os::unix::net::{UnixDatagram, UnixListener, UnixStream};
+use std::process;
+
+use super::systemd::{self, InheritedFd};
+use crate::config::listen::{Listen, ListenAddress};
+use crate::server::listener::Listener;
+
+fn temp_path(name: &str) -> String {
+    env::temp_dir()
+        .join(format!("dz1_{}_{}", name, process::id()))
+        .to_str()
+        .unwrap()
+        .to_owned()
+}
+
+#[test]
+fn listen_fds_for_this_process() {
+    let fds = systemd::parse_listen_fds(Some("42"), Some("2"), Some("http:https"), 42);
+    assert_eq!(fds, vec![
+        InheritedFd { fd: 3, name: Some(String::from("http")) },
+        InheritedFd { fd: 4, name: Some(String::from("https")) },
+    ]);
+}
+
+#[test]
+fn listen_fds_without_names() {
+    let fds = systemd::parse_listen_fds(Some("42"), Some("1"), None, 42);
+    assert_eq!(fds, vec![InheritedFd { fd: 3, name: None }]);
+}
+
+#[test]
+fn listen_fds_for_other_process() {
+    assert!(systemd::parse_listen_fds(Some("41"), Some("2"), None, 42).is_empty());
+    assert!(systemd::parse_listen_fds(None, Some("2"), None, 42).is_empty());
+    assert!(systemd::parse_listen_fds(Some("42"), Some("zero"), None, 42).is_empty());
+}
+
+#[test]
+fn notify_path_socket() {
+    let path = temp_path("notify");
+    let _ = std::fs::remove_file(&path);
+    let receiver = UnixDatagram::bind(&path).unwrap();
+
+    systemd::notify_to(&path, systemd::STATE_READY).unwrap();
+    systemd::notify_to(&path, &systemd::reloading_state()).unwrap();
+
+    let mut buf = [0; 128];
+    let n = receiver.recv(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"READY=1");
+    let n = receiver.recv(&mut buf).unwrap();
+    assert!(std::str::from_utf8(&buf[..n]).unwrap().starts_with("RELOADING=1\nMONOTONIC_USEC="));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn notify_abstract_socket() {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::SocketAddr;
+
+    let name = format!("dz1_notify_{}", process::id());
+    let receiver = UnixDatagram::bind_addr(&SocketAddr::from_abstract_name(&name).unwrap()).unwrap();
+
+    systemd::notify_to(&format!("@{}", name), systemd::STATE_STOPPING).unwrap();
+
+    let mut buf = [0; 64];
+    let n = receiver.recv(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"STOPPING=1");
+}
+
+#[test]
+fn adopt_inherited_tcp_socket() {
+    let original = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = original.local_addr().unwrap();
+    let listener = Listener::from_fd(original.into_raw_fd()).unwrap();
+
+    assert!(listener.matches(&Listen::tcp(addr.to_string())));
+    assert!(!listener.matches(&Listen::tcp(String::from("127.0.0.1:1"))));
+
+    let mut client = TcpStream::connect(addr).unwrap();
+    let mut socket = listener.accept().unwrap();
+    client.write_all(b"ping").unwrap();
+    let mut buf = [0; 4];
+    socket.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"ping");
+}
+
+#[test]
+fn adopt_inherited_unix_socket() {
+    let path = temp_path("inherited.sock");
+    let _ = std::fs::remove_file(&path);
+    let original = UnixListener::bind(&path).unwrap();
+    let fd = original.as_raw_fd();
+    std::mem::forget(original);
+
+    let listener = Listener::from_fd(fd).unwrap();
+    assert_eq!(listener.listen.address, ListenAddress::Unix(path.clone()));
+
+    let mut client = UnixStream::connect(&path).unwrap();
+    let mut socket = listener.accept().unwrap();
+    client.write_all(b"pong").unwrap();
+    let mut buf = [0; 4];
+    socket.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"pong");
+
+    std::fs::remove_file(&path).unwrap();
+}
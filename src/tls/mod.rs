@@ -0,0 +1,3 @@
+pub mod tls;
+#[cfg(test)]
+mod tls_test;
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::{Arc, RwLock};
+
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+use crate::server::stream::Socket;
+
+pub const SSL_INVALID_FORMAT: &str = "Invalid ssl directive format";
+pub const SSL_NO_CERTIFICATE: &str = "ssl listener requires ssl_certificate and ssl_certificate_key";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CertificatePaths {
+    pub certificate: String,
+    pub key: String,
+}
+
+// Certificate directives of the config:
+//
+//   ssl_certificate /etc/dz1/default.pem
+//   ssl_certificate_key /etc/dz1/default.key
+//   ssl_sni example.com /etc/dz1/example.pem /etc/dz1/example.key
+#[derive(Debug, Clone, Default)]
+pub struct TlsSettings {
+    pub certificate: Option<String>,
+    pub key: Option<String>,
+    pub sni: Vec<(String, CertificatePaths)>,
+}
+
+impl TlsSettings {
+    // Returns false for directives that are not about TLS.
+    pub fn parse_directive(&mut self, tokens: &[String]) -> Result<bool, String> {
+        match (tokens[0].as_str(), tokens.len()) {
+            ("ssl_certificate", 2) => self.certificate = Some(tokens[1].clone()),
+            ("ssl_certificate_key", 2) => self.key = Some(tokens[1].clone()),
+            ("ssl_sni", 4) => self.sni.push((tokens[1].to_ascii_lowercase(), CertificatePaths {
+                certificate: tokens[2].clone(),
+                key: tokens[3].clone(),
+            })),
+            ("ssl_certificate", _) | ("ssl_certificate_key", _) | ("ssl_sni", _) => {
+                return Err(String::from(SSL_INVALID_FORMAT));
+            },
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    fn default_paths(&self) -> Option<CertificatePaths> {
+        match (&self.certificate, &self.key) {
+            (Some(certificate), Some(key)) => Some(CertificatePaths {
+                certificate: certificate.clone(),
+                key: key.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Keys {
+    default: Option<Arc<CertifiedKey>>,
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+}
+
+// Picks a certificate by the SNI name of the client hello, falling back to
+// the default certificate. Keys can be swapped at runtime by `reload`.
+#[derive(Debug)]
+pub struct CertResolver {
+    settings: TlsSettings,
+    provider: Arc<CryptoProvider>,
+    keys: RwLock<Keys>,
+}
+
+impl CertResolver {
+    fn new(settings: &TlsSettings, provider: Arc<CryptoProvider>) -> Result<CertResolver, String> {
+        let keys = load_keys(settings, &provider)?;
+
+        Ok(CertResolver {
+            settings: settings.clone(),
+            provider,
+            keys: RwLock::new(keys),
+        })
+    }
+
+    // Certificates that fail to load leave the current ones in place.
+    pub fn reload(&self) -> Result<(), String> {
+        let keys = load_keys(&self.settings, &self.provider)?;
+        *self.keys.write().unwrap() = keys;
+        Ok(())
+    }
+
+    fn lookup(&self, server_name: Option<&str>) -> Option<Arc<CertifiedKey>> {
+        let keys = self.keys.read().unwrap();
+
+        if let Some(name) = server_name {
+            let name = name.to_ascii_lowercase();
+            if let Some(key) = keys.by_name.get(&name) {
+                return Some(key.clone());
+            }
+
+            if let Some(dot) = name.find('.') {
+                if let Some(key) = keys.by_name.get(&format!("*{}", &name[dot..])) {
+                    return Some(key.clone());
+                }
+            }
+        }
+
+        keys.default.clone()
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.lookup(client_hello.server_name())
+    }
+}
+
+// Terminates TLS on accepted sockets.
+pub struct Tls {
+    config: Arc<ServerConfig>,
+    resolver: Arc<CertResolver>,
+}
+
+impl Tls {
+    pub fn new(settings: &TlsSettings) -> Result<Tls, String> {
+        if settings.default_paths().is_none() && settings.sni.is_empty() {
+            return Err(String::from(SSL_NO_CERTIFICATE));
+        }
+
+        let provider = Arc::new(ring::default_provider());
+        let resolver = Arc::new(CertResolver::new(settings, provider.clone())?);
+
+        let mut config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|err| err.to_string())?
+            .with_no_client_auth()
+            .with_cert_resolver(resolver.clone());
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        Ok(Tls {
+            config: Arc::new(config),
+            resolver,
+        })
+    }
+
+    pub fn reload(&self) {
+        match self.resolver.reload() {
+            Ok(()) => println!("Reloaded tls certificates"),
+            Err(err) => println!("Error while reload tls certificates: {}", err),
+        }
+    }
+
+    // The handshake itself runs on the first read or write of the stream.
+    pub fn accept(&self, stream: Socket) -> Result<StreamOwned<ServerConnection, Socket>, String> {
+        let conn = ServerConnection::new(self.config.clone()).map_err(|err| err.to_string())?;
+        Ok(StreamOwned::new(conn, stream))
+    }
+}
+
+fn load_keys(settings: &TlsSettings, provider: &CryptoProvider) -> Result<Keys, String> {
+    let default = match settings.default_paths() {
+        Some(paths) => Some(load_certified_key(&paths, provider)?),
+        None => None,
+    };
+
+    let mut by_name = HashMap::new();
+    for (name, paths) in &settings.sni {
+        by_name.insert(name.clone(), load_certified_key(paths, provider)?);
+    }
+
+    Ok(Keys {
+        default,
+        by_name,
+    })
+}
+
+fn load_certified_key(paths: &CertificatePaths, provider: &CryptoProvider) -> Result<Arc<CertifiedKey>, String> {
+    let open = |path: &str| File::open(path)
+        .map(BufReader::new)
+        .map_err(|err| format!("{}: {}", path, err));
+
+    let chain = rustls_pemfile::certs(&mut open(&paths.certificate)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("{}: {}", paths.certificate, err))?;
+    if chain.is_empty() {
+        return Err(format!("{}: no certificates found", paths.certificate));
+    }
+
+    let key = match rustls_pemfile::private_key(&mut open(&paths.key)?) {
+        Ok(Some(key)) => key,
+        Ok(None) => return Err(format!("{}: no private key found", paths.key)),
+        Err(err) => return Err(format!("{}: {}", paths.key, err)),
+    };
+
+    CertifiedKey::from_der(chain, key, provider)
+        .map(Arc::new)
+        .map_err(|err| format!("{}: {}", paths.key, err))
+}
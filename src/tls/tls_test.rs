@@ -0,0 +1,132 @@
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::io::prelude::*;
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use super::tls::{CertificatePaths, Tls, TlsSettings};
+use crate::server::stream::Socket;
+
+fn paths(name: &str) -> CertificatePaths {
+    CertificatePaths {
+        certificate: format!("test/tls/{}.pem", name),
+        key: format!("test/tls/{}.key", name),
+    }
+}
+
+fn settings() -> TlsSettings {
+    let localhost = paths("localhost");
+    TlsSettings {
+        certificate: Some(localhost.certificate),
+        key: Some(localhost.key),
+        sni: vec![(String::from("example.com"), paths("example.com"))],
+    }
+}
+
+fn cert_der(name: &str) -> CertificateDer<'static> {
+    let pem = fs::read(format!("test/tls/{}.pem", name)).unwrap();
+    let cert = rustls_pemfile::certs(&mut &pem[..]).next().unwrap().unwrap();
+    cert
+}
+
+// Runs one request over TLS against `tls` and returns the certificate the
+// server presented and the response body.
+fn exchange(tls: Arc<Tls>, server_name: &str) -> (CertificateDer<'static>, String) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut stream = tls.accept(Socket::Tcp(stream)).unwrap();
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).unwrap();
+        stream.write_all(&buf).unwrap();
+        stream.flush().unwrap();
+    });
+
+    let mut roots = RootCertStore::empty();
+    roots.add(cert_der("localhost")).unwrap();
+    roots.add(cert_der("example.com")).unwrap();
+    let config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let name = ServerName::try_from(server_name.to_owned()).unwrap();
+    let conn = ClientConnection::new(Arc::new(config), name).unwrap();
+    let mut stream = StreamOwned::new(conn, TcpStream::connect(addr).unwrap());
+
+    stream.write_all(b"ping").unwrap();
+    let mut buf = [0; 4];
+    stream.read_exact(&mut buf).unwrap();
+    server.join().unwrap();
+
+    let presented = stream.conn.peer_certificates().unwrap()[0].clone().into_owned();
+    (presented, String::from_utf8(buf.to_vec()).unwrap())
+}
+
+#[test]
+fn handshake_default_certificate() {
+    let tls = Arc::new(Tls::new(&settings()).unwrap());
+    let (cert, body) = exchange(tls, "localhost");
+    assert_eq!(cert, cert_der("localhost"));
+    assert_eq!(body, "ping");
+}
+
+#[test]
+fn handshake_sni_certificate() {
+    let tls = Arc::new(Tls::new(&settings()).unwrap());
+    let (cert, _) = exchange(tls, "example.com");
+    assert_eq!(cert, cert_der("example.com"));
+}
+
+#[test]
+fn reload_certificate() {
+    let dir = env::temp_dir().join(format!("dz1_tls_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let certificate = dir.join("cert.pem");
+    let key = dir.join("cert.key");
+    fs::copy("test/tls/localhost.pem", &certificate).unwrap();
+    fs::copy("test/tls/localhost.key", &key).unwrap();
+
+    let tls = Arc::new(Tls::new(&TlsSettings {
+        certificate: Some(certificate.to_str().unwrap().to_owned()),
+        key: Some(key.to_str().unwrap().to_owned()),
+        sni: Vec::new(),
+    }).unwrap());
+
+    fs::copy("test/tls/example.com.pem", &certificate).unwrap();
+    fs::copy("test/tls/example.com.key", &key).unwrap();
+    tls.reload();
+
+    let (cert, _) = exchange(tls, "example.com");
+    assert_eq!(cert, cert_der("example.com"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn missing_certificate() {
+    assert!(Tls::new(&TlsSettings::default()).is_err());
+    assert!(Tls::new(&TlsSettings {
+        certificate: Some(String::from("test/tls/none.pem")),
+        key: Some(String::from("test/tls/none.key")),
+        sni: Vec::new(),
+    }).is_err());
+}
+
+#[test]
+fn parse_directives() {
+    let mut tls = TlsSettings::default();
+    let tokens = |line: &str| line.split(' ').map(String::from).collect::<Vec<_>>();
+
+    assert_eq!(tls.parse_directive(&tokens("ssl_certificate a.pem")), Ok(true));
+    assert_eq!(tls.parse_directive(&tokens("ssl_sni Example.COM b.pem b.key")), Ok(true));
+    assert_eq!(tls.parse_directive(&tokens("thread_limit 4")), Ok(false));
+    assert!(tls.parse_directive(&tokens("ssl_sni example.com b.pem")).is_err());
+    assert_eq!(tls.sni[0].0, "example.com");
+}
@@ -0,0 +1,15 @@
+#!/bin/sh
+printf 'Content-Type: text/plain\r\nX-Script: env\r\nContent-Length: 1\r\n\r\n'
+echo "method=$REQUEST_METHOD"
+echo "script=$SCRIPT_NAME"
+echo "path_info=$PATH_INFO"
+echo "query=$QUERY_STRING"
+echo "remote=$REMOTE_ADDR"
+echo "user=$REMOTE_USER"
+echo "accept=$HTTP_ACCEPT"
+echo "proxy=$HTTP_PROXY"
+echo "gateway=$GATEWAY_INTERFACE"
+echo "server=$SERVER_NAME:$SERVER_PORT"
+echo "length=$CONTENT_LENGTH"
+printf 'body='
+cat
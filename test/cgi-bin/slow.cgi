@@ -0,0 +1,5 @@
+#!/bin/sh
+if [ "$QUERY_STRING" = "body" ]; then
+    printf 'Content-Type: text/plain\n\nstarted'
+fi
+sleep 10
@@ -0,0 +1,2 @@
+#!/bin/sh
+printf 'Status: 404 Missing\nContent-Type: text/plain\n\nnope'